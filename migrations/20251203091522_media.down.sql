@@ -0,0 +1,9 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS idx_post_media_media_id;
+DROP INDEX IF EXISTS idx_media_variant_media_id;
+DROP INDEX IF EXISTS idx_media_hash;
+
+DROP TABLE IF EXISTS post_media;
+DROP TABLE IF EXISTS media_variant;
+DROP TABLE IF EXISTS media;
@@ -0,0 +1,36 @@
+-- Add up migration script here
+
+-- Every uploaded image (the full-size file)
+-- id doubles as the base file name: "<id>.jpg", "<id>-480w.webp", ...
+CREATE TABLE media (
+    id UUID PRIMARY KEY,
+    uploader_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    file_name TEXT NOT NULL UNIQUE,
+    hash CHAR(64) NOT NULL, -- SHA-256 (hex) of the uploaded bytes
+    mime_type VARCHAR(50) NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    blurhash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Resized / re-encoded files generated from a media row
+CREATE TABLE media_variant (
+    file_name TEXT PRIMARY KEY,
+    media_id UUID NOT NULL REFERENCES media(id) ON DELETE CASCADE,
+    mime_type VARCHAR(50) NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL
+);
+
+-- Which posts reference which media (rebuilt every time a post is saved)
+CREATE TABLE post_media (
+    post_id INTEGER NOT NULL REFERENCES post(id) ON DELETE CASCADE,
+    media_id UUID NOT NULL REFERENCES media(id) ON DELETE CASCADE,
+    PRIMARY KEY (post_id, media_id)
+);
+
+CREATE INDEX idx_media_hash ON media(hash);
+CREATE INDEX idx_media_variant_media_id ON media_variant(media_id);
+CREATE INDEX idx_post_media_media_id ON post_media(media_id);
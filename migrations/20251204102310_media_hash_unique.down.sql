@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS idx_media_hash;
+CREATE INDEX idx_media_hash ON media(hash);
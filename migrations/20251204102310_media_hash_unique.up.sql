@@ -0,0 +1,5 @@
+-- Add up migration script here
+
+-- Identical uploads are deduplicated by content hash, so each hash may only appear once
+DROP INDEX IF EXISTS idx_media_hash;
+CREATE UNIQUE INDEX idx_media_hash ON media(hash);
@@ -0,0 +1,14 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS idx_comment_root;
+DROP INDEX IF EXISTS idx_comment_parent_id;
+DROP INDEX IF EXISTS idx_comment_post_id;
+
+-- Replies can't be represented without parent_id - drop them and any placeholders
+DELETE FROM comment WHERE parent_id IS NOT NULL OR deleted_at IS NOT NULL;
+
+ALTER TABLE comment
+    DROP COLUMN deleted_at,
+    DROP COLUMN path,
+    DROP COLUMN depth,
+    DROP COLUMN parent_id;
@@ -0,0 +1,21 @@
+-- Add up migration script here
+
+-- Threaded replies
+-- parent_id: direct parent (NULL for top-level comments)
+-- depth:     0 for top-level, parent.depth + 1 for replies
+-- path:      ids from the root down to this comment, e.g. {12, 40, 41}
+--            ordering by path yields a flattened, depth-first thread
+-- deleted_at: set when a comment with replies is deleted; the row stays
+--            as a "[deleted]" placeholder so the replies keep their place
+ALTER TABLE comment
+ADD COLUMN parent_id INTEGER REFERENCES comment(id) ON DELETE CASCADE,
+ADD COLUMN depth INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN path INTEGER[] NOT NULL DEFAULT '{}',
+ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- Existing comments are all top-level
+UPDATE comment SET path = ARRAY[id];
+
+CREATE INDEX idx_comment_post_id ON comment(post_id);
+CREATE INDEX idx_comment_parent_id ON comment(parent_id);
+CREATE INDEX idx_comment_root ON comment((path[1]));
@@ -0,0 +1,11 @@
+-- Add down migration script here
+
+DROP FUNCTION IF EXISTS comment_visible(comment);
+
+DROP INDEX IF EXISTS idx_comment_moderation_log_comment_id;
+DROP TABLE IF EXISTS comment_moderation_log;
+
+DROP INDEX IF EXISTS idx_comment_status;
+ALTER TABLE comment DROP COLUMN status;
+
+DROP TYPE IF EXISTS comment_status;
@@ -0,0 +1,46 @@
+-- Add up migration script here
+
+CREATE TYPE comment_status AS ENUM ('pending', 'approved', 'rejected', 'spam');
+
+-- Existing comments were published immediately, so they start out approved
+ALTER TABLE comment
+ADD COLUMN status comment_status NOT NULL DEFAULT 'approved';
+
+CREATE INDEX idx_comment_status ON comment(status);
+
+-- Audit trail of every moderation decision
+-- comment_id has no foreign key on purpose: entries must outlive deleted comments
+-- moderator_id is NULL for automatic decisions
+CREATE TABLE comment_moderation_log (
+    id BIGSERIAL PRIMARY KEY,
+    comment_id INTEGER NOT NULL,
+    moderator_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    action VARCHAR(20) NOT NULL, -- 'status_change' or 'delete'
+    previous_status comment_status,
+    new_status comment_status,
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_comment_moderation_log_comment_id ON comment_moderation_log(comment_id);
+
+-- Whether a comment shows up in public listings
+-- Approved, live comments are visible. Deleted or unapproved comments are only
+-- kept visible (as a "[deleted]" placeholder) while they still have a visible
+-- reply somewhere below them, so threads don't lose their context.
+CREATE OR REPLACE FUNCTION comment_visible(c comment)
+RETURNS BOOLEAN
+LANGUAGE SQL
+STABLE
+AS $$
+SELECT (c.status = 'approved' AND c.deleted_at IS NULL)
+    OR EXISTS (
+        SELECT 1
+        FROM comment d
+        WHERE d.path[1] = c.path[1]
+            AND d.path @> ARRAY[c.id]
+            AND d.id <> c.id
+            AND d.status = 'approved'
+            AND d.deleted_at IS NULL
+    )
+$$;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS idx_comment_user_created_at;
+DROP TABLE IF EXISTS spam_corpus;
+DROP TABLE IF EXISTS spam_token;
@@ -0,0 +1,26 @@
+-- Add up migration script here
+
+-- Naive Bayes spam filter model, rebuilt periodically from moderation decisions
+-- (approved comments = ham, spam comments = spam)
+-- Counts are per document: a token appearing twice in one comment counts once.
+CREATE TABLE spam_token (
+    token TEXT PRIMARY KEY,
+    spam_count INTEGER NOT NULL DEFAULT 0,
+    ham_count INTEGER NOT NULL DEFAULT 0
+);
+
+-- Single-row table holding the corpus size the token counts were computed from
+CREATE TABLE spam_corpus (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    spam_docs INTEGER NOT NULL DEFAULT 0,
+    ham_docs INTEGER NOT NULL DEFAULT 0,
+    trained_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO spam_corpus (id) VALUES (TRUE);
+
+-- Speeds up the per-user rate heuristic
+CREATE INDEX idx_comment_user_created_at ON comment(user_id, created_at);
+
+-- Automatic classification of new comments is logged with action 'classify'
+-- (moderator_id NULL) next to 'status_change' and 'delete'
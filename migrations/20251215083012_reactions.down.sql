@@ -0,0 +1,12 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS comment_reaction_count;
+DROP TABLE IF EXISTS post_reaction_count;
+
+DROP INDEX IF EXISTS idx_comment_reaction_user_id;
+DROP INDEX IF EXISTS idx_post_reaction_user_id;
+
+DROP TABLE IF EXISTS comment_reaction;
+DROP TABLE IF EXISTS post_reaction;
+
+DROP TYPE IF EXISTS reaction_type;
@@ -0,0 +1,43 @@
+-- Add up migration script here
+
+CREATE TYPE reaction_type AS ENUM ('like', 'love', 'insightful', 'funny', 'celebrate');
+
+-- One row per user per reaction type: a user can leave several
+-- different reactions on the same post, but each type only once
+CREATE TABLE post_reaction (
+    post_id INTEGER NOT NULL REFERENCES post(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    reaction reaction_type NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (post_id, user_id, reaction)
+);
+
+CREATE TABLE comment_reaction (
+    comment_id INTEGER NOT NULL REFERENCES comment(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    reaction reaction_type NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (comment_id, user_id, reaction)
+);
+
+-- "Which reactions did this viewer leave" lookups
+CREATE INDEX idx_post_reaction_user_id ON post_reaction(user_id);
+CREATE INDEX idx_comment_reaction_user_id ON comment_reaction(user_id);
+
+-- Aggregated counts, flushed periodically from the Redis hot counters
+-- Lets queries sort/filter by reactions without going through Redis
+CREATE TABLE post_reaction_count (
+    post_id INTEGER NOT NULL REFERENCES post(id) ON DELETE CASCADE,
+    reaction reaction_type NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (post_id, reaction)
+);
+
+CREATE TABLE comment_reaction_count (
+    comment_id INTEGER NOT NULL REFERENCES comment(id) ON DELETE CASCADE,
+    reaction reaction_type NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (comment_id, reaction)
+);
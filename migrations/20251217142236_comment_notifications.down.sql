@@ -0,0 +1,6 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS idx_comment_notification_unsent;
+DROP TABLE IF EXISTS comment_notification;
+DROP TABLE IF EXISTS notification_preference;
+DROP TYPE IF EXISTS notification_frequency;
@@ -0,0 +1,25 @@
+-- Add up migration script here
+
+CREATE TYPE notification_frequency AS ENUM ('instant', 'daily', 'off');
+
+-- Users without a row get the default (instant)
+CREATE TABLE notification_preference (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    comment_notifications notification_frequency NOT NULL DEFAULT 'instant',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Outbox of comment notification emails
+-- Rows are picked up by the scheduler (instant: every minute, daily: digest),
+-- and only once the comment is approved, so held comments notify after moderation.
+CREATE TABLE comment_notification (
+    id BIGSERIAL PRIMARY KEY,
+    recipient_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    comment_id INTEGER NOT NULL REFERENCES comment(id) ON DELETE CASCADE,
+    reason VARCHAR(20) NOT NULL, -- 'post_comment' or 'reply'
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    sent_at TIMESTAMPTZ,
+    UNIQUE (recipient_id, comment_id)
+);
+
+CREATE INDEX idx_comment_notification_unsent ON comment_notification(created_at) WHERE sent_at IS NULL;
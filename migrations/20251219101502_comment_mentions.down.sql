@@ -0,0 +1,9 @@
+-- Add down migration script here
+
+DELETE FROM comment_notification WHERE reason = 'mention';
+
+DROP INDEX IF EXISTS idx_comment_mention_user_id;
+DROP TABLE IF EXISTS comment_mention;
+
+ALTER TABLE comment
+DROP COLUMN IF EXISTS content_html;
@@ -0,0 +1,25 @@
+-- Add up migration script here
+
+-- Comment rendered to HTML at write time (escaped text, @mentions as profile links)
+ALTER TABLE comment
+ADD COLUMN content_html TEXT NOT NULL DEFAULT '';
+
+-- Existing comments have no mentions, escaping and line breaks are enough
+UPDATE comment
+SET content_html = replace(
+    replace(replace(replace(replace(replace(content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;'),
+    E'\n', '<br>'
+);
+
+ALTER TABLE comment ALTER COLUMN content_html DROP DEFAULT;
+
+-- Users mentioned with @username in a comment
+CREATE TABLE comment_mention (
+    comment_id INTEGER NOT NULL REFERENCES comment(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (comment_id, user_id)
+);
+
+CREATE INDEX idx_comment_mention_user_id ON comment_mention(user_id);
+
+-- comment_notification.reason can now also be 'mention'
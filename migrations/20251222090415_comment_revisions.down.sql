@@ -0,0 +1,7 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS idx_comment_revision_comment_id;
+DROP TABLE IF EXISTS comment_revision;
+
+ALTER TABLE comment
+DROP COLUMN IF EXISTS edit_count;
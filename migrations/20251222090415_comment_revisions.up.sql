@@ -0,0 +1,18 @@
+-- Add up migration script here
+
+-- Number of times the text of a comment was changed
+ALTER TABLE comment
+ADD COLUMN edit_count INTEGER NOT NULL DEFAULT 0;
+
+-- Previous versions of edited comments (kept for moderators investigating reports)
+-- written_at: when this version was written, replaced_at: when an edit replaced it
+CREATE TABLE comment_revision (
+    id BIGSERIAL PRIMARY KEY,
+    comment_id INTEGER NOT NULL REFERENCES comment(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    content_html TEXT NOT NULL,
+    written_at TIMESTAMPTZ NOT NULL,
+    replaced_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_comment_revision_comment_id ON comment_revision(comment_id);
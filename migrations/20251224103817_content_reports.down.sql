@@ -0,0 +1,12 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS idx_content_report_status;
+DROP INDEX IF EXISTS idx_content_report_comment_reporter;
+DROP INDEX IF EXISTS idx_content_report_post_reporter;
+DROP TABLE IF EXISTS content_report;
+
+ALTER TABLE post
+DROP COLUMN IF EXISTS hidden_at;
+
+DROP TYPE IF EXISTS report_status;
+DROP TYPE IF EXISTS report_reason;
@@ -0,0 +1,33 @@
+-- Add up migration script here
+
+CREATE TYPE report_reason AS ENUM ('spam', 'harassment', 'hate', 'sexual', 'violence', 'misinformation', 'off_topic', 'other');
+
+-- open: waiting for an admin, dismissed: content is fine, upheld: content was removed
+CREATE TYPE report_status AS ENUM ('open', 'dismissed', 'upheld');
+
+-- Hidden posts are left out of listings and search, only admins can still open them
+ALTER TABLE post
+ADD COLUMN hidden_at TIMESTAMPTZ;
+
+-- User reports of posts and comments
+-- Exactly one of post_id / comment_id is set
+-- Reporters are only ever shown to admins
+CREATE TABLE content_report (
+    id BIGSERIAL PRIMARY KEY,
+    post_id INTEGER REFERENCES post(id) ON DELETE CASCADE,
+    comment_id INTEGER REFERENCES comment(id) ON DELETE CASCADE,
+    reporter_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    reason report_reason NOT NULL,
+    details TEXT,
+    status report_status NOT NULL DEFAULT 'open',
+    resolved_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    resolved_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (num_nonnulls(post_id, comment_id) = 1)
+);
+
+-- A user can report the same post/comment only once
+CREATE UNIQUE INDEX idx_content_report_post_reporter ON content_report(post_id, reporter_id) WHERE post_id IS NOT NULL;
+CREATE UNIQUE INDEX idx_content_report_comment_reporter ON content_report(comment_id, reporter_id) WHERE comment_id IS NOT NULL;
+
+CREATE INDEX idx_content_report_status ON content_report(status);
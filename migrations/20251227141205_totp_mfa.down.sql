@@ -0,0 +1,6 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS role_mfa_policy;
+DROP INDEX IF EXISTS idx_mfa_recovery_code_user_id;
+DROP TABLE IF EXISTS mfa_recovery_code;
+DROP TABLE IF EXISTS user_totp;
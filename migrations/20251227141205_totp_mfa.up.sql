@@ -0,0 +1,31 @@
+-- Add up migration script here
+
+-- TOTP authenticator of a user (at most one)
+-- enabled_at is NULL while enrolment is pending (secret shown, no code confirmed yet)
+-- last_used_step: time step of the last accepted code, older codes are refused (replay protection)
+CREATE TABLE user_totp (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    secret TEXT NOT NULL,
+    enabled_at TIMESTAMPTZ,
+    last_used_step BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Single-use recovery codes, only the SHA-256 of each code is stored
+CREATE TABLE mfa_recovery_code (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_mfa_recovery_code_user_id ON mfa_recovery_code(user_id);
+
+-- Roles whose members must use two-factor authentication, set by admins
+CREATE TABLE role_mfa_policy (
+    role user_role PRIMARY KEY,
+    mfa_required BOOLEAN NOT NULL DEFAULT false,
+    updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS idx_passkey_user_id;
+DROP TABLE IF EXISTS passkey;
@@ -0,0 +1,15 @@
+-- Add up migration script here
+
+-- WebAuthn credentials (passkeys) of users, a user can register several
+-- credential: serialized webauthn-rs Passkey (JSON), updated after each use (signature counter)
+CREATE TABLE passkey (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    credential_id BYTEA NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    credential TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_passkey_user_id ON passkey(user_id);
@@ -0,0 +1,16 @@
+-- Add up migration script here
+
+-- Accounts at external login providers (OIDC issuers, GitHub) linked to users
+-- provider: name from OAUTH_PROVIDERS, subject: stable user id at the provider
+-- email: address the provider reported when the identity was linked
+CREATE TABLE user_identity (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    email TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ,
+    UNIQUE (provider, subject),
+    UNIQUE (user_id, provider)
+);
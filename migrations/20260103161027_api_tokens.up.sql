@@ -0,0 +1,20 @@
+-- Add up migration script here
+
+-- Personal access tokens for scripts calling the API on behalf of a user
+-- token_hash: SHA-256 of the token (hex), the token itself is only shown once
+-- token_prefix: start of the token, so users can tell their tokens apart
+-- scopes: e.g. {posts:write,comments:write}, see ApiScope
+-- expires_at: NULL = never expires
+CREATE TABLE api_token (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    token_prefix TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_api_token_user_id ON api_token(user_id);
@@ -0,0 +1,5 @@
+-- Add up migration script here
+
+-- Embedded in every access and refresh token (`ver` claim), bumped to
+-- invalidate all tokens of a user at once (password change, reset, email change, forced logout)
+ALTER TABLE users ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP INDEX IF EXISTS idx_pending_email_change_user_id;
+DROP TABLE IF EXISTS pending_email_change;
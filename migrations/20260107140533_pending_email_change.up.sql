@@ -0,0 +1,23 @@
+-- Add up migration script here
+
+-- Email changes waiting for confirmation by the new address, and for a while
+-- after that, revertible from the old one
+-- token_hash: SHA-256 of the token in the confirmation link sent to new_email
+-- revert_token_hash: SHA-256 of the token in the revert link sent to old_email
+-- expires_at: the confirmation link stops working
+-- revert_expires_at: the revert link stops working, the row can be deleted
+-- confirmed_at: NULL until the new address is confirmed
+CREATE TABLE pending_email_change (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    old_email VARCHAR(255) NOT NULL,
+    new_email VARCHAR(255) NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    revert_token_hash TEXT NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revert_expires_at TIMESTAMPTZ NOT NULL,
+    confirmed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_pending_email_change_user_id ON pending_email_change(user_id);
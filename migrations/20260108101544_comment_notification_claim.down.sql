@@ -0,0 +1,5 @@
+-- Add down migration script here
+
+ALTER TABLE comment_notification
+    DROP COLUMN claimed_at,
+    DROP COLUMN attempts;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+
+-- claimed_at: when a scheduler run picked the notification up; other runs
+-- skip it until the claim expires (the email failed or the run crashed)
+-- attempts: sends tried so far, given up after a few (see NotificationExt)
+ALTER TABLE comment_notification
+    ADD COLUMN claimed_at TIMESTAMPTZ,
+    ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+-- Nothing to undo: the counts are kept up to date by the flush job anyway
+SELECT 1;
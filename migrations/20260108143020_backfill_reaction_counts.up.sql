@@ -0,0 +1,17 @@
+-- Add up migration script here
+
+-- The count tables are read while Redis is down, start them from the
+-- reaction rows (the flush keeps them up to date from then on)
+INSERT INTO post_reaction_count (post_id, reaction, count)
+SELECT post_id, reaction, COUNT(*)
+FROM post_reaction
+GROUP BY post_id, reaction
+ON CONFLICT (post_id, reaction)
+DO UPDATE SET count = EXCLUDED.count, updated_at = NOW();
+
+INSERT INTO comment_reaction_count (comment_id, reaction, count)
+SELECT comment_id, reaction, COUNT(*)
+FROM comment_reaction
+GROUP BY comment_id, reaction
+ON CONFLICT (comment_id, reaction)
+DO UPDATE SET count = EXCLUDED.count, updated_at = NOW();
@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+ALTER TABLE media DROP COLUMN IF EXISTS last_used_at;
@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+-- Last upload or dedup hit of the image, the orphan cleanup's grace period
+-- starts from it (a dedup hit hands the URLs out again)
+ALTER TABLE media ADD COLUMN last_used_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE media SET last_used_at = created_at;
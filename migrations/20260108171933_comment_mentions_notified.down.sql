@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+ALTER TABLE comment DROP COLUMN IF EXISTS mentions_notified;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+
+-- Users notified of a mention in the comment so far (creation and edits),
+-- capped at MAX_MENTIONS_PER_COMMENT over the comment's lifetime
+ALTER TABLE comment ADD COLUMN mentions_notified INTEGER NOT NULL DEFAULT 0;
+
+UPDATE comment c
+SET mentions_notified = (SELECT COUNT(*) FROM comment_mention m WHERE m.comment_id = c.id);
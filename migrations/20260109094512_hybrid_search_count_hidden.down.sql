@@ -0,0 +1,40 @@
+-- Add down migration script here
+
+CREATE OR REPLACE FUNCTION hybrid_search_count(
+    query_text TEXT DEFAULT NULL,                -- SIMPLIFIED: SINGLE TEXT INPUT
+    query_embedding vector(768) DEFAULT NULL, 
+    full_text_weight FLOAT = 1,
+    semantic_weight FLOAT = 1,
+    rrf_k INT = 50
+)
+RETURNS BIGINT
+LANGUAGE SQL
+AS $$
+-- Full-text search matches (English)
+WITH full_text_en AS (
+    SELECT id
+    FROM post
+    WHERE query_text IS NOT NULL AND content_tsv @@ websearch_to_tsquery(query_text)
+),
+-- Full-text search matches (Korean)
+full_text_ko AS (
+    SELECT id
+    FROM post
+    WHERE query_text IS NOT NULL AND content_tsv_ko @@ websearch_to_tsquery('simple', query_text)
+),
+-- Vector similarity search matches (Semantic)
+semantic AS (
+    SELECT id
+    FROM post
+    WHERE query_embedding IS NOT NULL AND embedding <=> query_embedding < 0.8
+)
+-- Count unique posts from all three search methods
+SELECT COUNT(DISTINCT id) AS total_count
+FROM (
+    SELECT id FROM full_text_en
+    UNION
+    SELECT id FROM full_text_ko
+    UNION
+    SELECT id FROM semantic
+) AS combined_ids;
+$$;
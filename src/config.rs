@@ -1,7 +1,15 @@
+use crate::oauth::OAuthProvider;
+use crate::spam::SpamBackend;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub database_url: String,
     pub jwt_secret: String,
+    pub jwt_signing_key: Option<String>,
+    pub jwt_retired_keys: Vec<String>,
+    pub jwt_secret_retired_at: Option<String>,
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
     pub jwt_maxage: i64,
     pub refresh_token_maxage: i64,
     pub redis_url: String,
@@ -10,6 +18,14 @@ pub struct Config {
     pub model_name: String,
     pub grpc_url: String,
     pub frontend_url: String,
+    pub api_url: String,
+    pub comment_premoderation: bool,
+    pub spam_classifiers: Vec<SpamBackend>,
+    pub report_hide_threshold: i64,
+    pub totp_issuer: String,
+    pub webauthn_rp_id: String,
+    pub webauthn_rp_origin: String,
+    pub oauth_providers: Vec<OAuthProvider>,
 }
 
 impl Config {
@@ -17,6 +33,17 @@ impl Config {
     pub fn init() -> Config {
         let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
         let jwt_secret = std::env::var("JWT_SECRET_KEY").expect("JWT_SECRET_KEY must be set");
+        // Optional: kid=/path/to/key.pem of an RSA or Ed25519 key signing tokens instead of JWT_SECRET_KEY (HS256)
+        let jwt_signing_key = std::env::var("JWT_SIGNING_KEY").ok().filter(|s| !s.trim().is_empty());
+        // Optional: comma separated kid=/path/to/key.pem@retired-at (RFC 3339) of keys replaced by JWT_SIGNING_KEY
+        let jwt_retired_keys = std::env::var("JWT_RETIRED_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(str::to_string)
+            .collect();
+        // Required with JWT_SIGNING_KEY: when it replaced JWT_SECRET_KEY (RFC 3339), HS256 tokens issued before are accepted for REFRESH_TOKEN_MAXAGE after it
+        let jwt_secret_retired_at = std::env::var("JWT_SECRET_RETIRED_AT").ok().filter(|s| !s.trim().is_empty());
         let jwt_maxage = std::env::var("JWT_MAXAGE").expect("JWT_MAXAGE must be set");
         let refresh_token_maxage = std::env::var("REFRESH_TOKEN_MAXAGE").expect("REFRESH_TOKEN_MAXAGE must be set");
         let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
@@ -24,11 +51,47 @@ impl Config {
         let model_name = std::env::var("MODEL_NAME").expect("MODEL_NAME must be set");
         let grpc_url = std::env::var("GRPC_URL").expect("GRPC_URL must be set");
         let frontend_url = std::env::var("FRONTEND_URL").expect("FRONTEND_URL must be set");
+        // Optional: hold first comments of new commenters for review (default off)
+        let comment_premoderation = std::env::var("COMMENT_PREMODERATION").map(|v| v == "true").unwrap_or(false);
+        // Optional: comma separated spam classifier backends (heuristic, bayes, llm), empty disables
+        let spam_classifiers = std::env::var("SPAM_CLASSIFIERS")
+            .unwrap_or("heuristic,bayes".to_string())
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.parse::<SpamBackend>().expect("SPAM_CLASSIFIERS contains an unknown classifier"))
+            .collect();
+        // Optional: open user reports after which a post/comment is hidden until an admin reviews it, 0 disables
+        let report_hide_threshold = std::env::var("REPORT_HIDE_THRESHOLD").unwrap_or("3".to_string()).parse::<i64>().expect("REPORT_HIDE_THRESHOLD must be number");
+        // Optional: name shown for this site in authenticator apps and passkey prompts
+        let totp_issuer = std::env::var("TOTP_ISSUER").unwrap_or("Blog".to_string());
+        // Optional: passkey relying party, the domain passkeys are bound to (default: host of FRONTEND_URL)
+        let webauthn_rp_id = std::env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| {
+            reqwest::Url::parse(&frontend_url).ok().and_then(|u| u.host_str().map(str::to_string)).expect("FRONTEND_URL must be a URL with a host")
+        });
+        // Optional: origin passkey ceremonies run on (default: FRONTEND_URL)
+        let webauthn_rp_origin = std::env::var("WEBAUTHN_RP_ORIGIN").unwrap_or(frontend_url.clone());
+        // Optional: comma separated social login providers (e.g. github,google), each configured with OAUTH_{NAME}_* variables
+        let oauth_providers = std::env::var("OAUTH_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(OAuthProvider::from_env)
+            .collect();
         let port = std::env::var("PORT").expect("PORT must be set").parse::<u16>().expect("PORT must be number");
+        // Optional: public URL of this API, used for links that must reach the backend directly (e.g. one-click unsubscribe)
+        let api_url = std::env::var("API_URL").unwrap_or(format!("http://localhost:{}", port));
+        // Optional: iss and aud claims of issued tokens (default: API_URL), tokens with others are rejected
+        let jwt_issuer = std::env::var("JWT_ISSUER").unwrap_or(api_url.clone());
+        let jwt_audience = std::env::var("JWT_AUDIENCE").unwrap_or(api_url.clone());
 
         Config {
             database_url,
             jwt_secret,
+            jwt_signing_key,
+            jwt_retired_keys,
+            jwt_secret_retired_at,
+            jwt_issuer,
+            jwt_audience,
             jwt_maxage: jwt_maxage.parse::<i64>().unwrap(),
             refresh_token_maxage: refresh_token_maxage.parse::<i64>().unwrap(),
             redis_url,
@@ -37,6 +100,14 @@ impl Config {
             model_name,
             grpc_url,
             frontend_url,
+            api_url,
+            comment_premoderation,
+            spam_classifiers,
+            report_hide_threshold,
+            totp_issuer,
+            webauthn_rp_id,
+            webauthn_rp_origin,
+            oauth_providers,
         }
     }
     
@@ -14,6 +14,39 @@ pub use post::PostExt;
 mod comment;
 pub use comment::CommentExt;
 
+mod media;
+pub use media::{MediaDeletion, MediaExt, NewMedia};
+
+mod moderation;
+pub use moderation::ModerationExt;
+
+mod spam;
+pub use spam::SpamFilterExt;
+
+mod reaction;
+pub use reaction::ReactionExt;
+
+mod notification;
+pub use notification::NotificationExt;
+
+mod report;
+pub use report::ReportExt;
+
+mod mfa;
+pub use mfa::MfaExt;
+
+mod passkey;
+pub use passkey::PasskeyExt;
+
+mod identity;
+pub use identity::IdentityExt;
+
+mod api_token;
+pub use api_token::ApiTokenExt;
+
+mod email_change;
+pub use email_change::EmailChangeExt;
+
 #[derive(Debug, Clone)]
 pub struct DBClient {
     pool: Pool<Postgres>,
@@ -0,0 +1,123 @@
+use super::DBClient;
+use crate::models::ApiToken;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+pub trait ApiTokenExt {
+    async fn create_api_token(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        token_hash: &str,
+        token_prefix: &str,
+        scopes: &[String],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ApiToken, sqlx::Error>;
+
+    async fn get_user_api_tokens(&self, user_id: Uuid) -> Result<Vec<ApiToken>, sqlx::Error>;
+
+    async fn get_api_token_by_hash(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<ApiToken>, sqlx::Error>;
+
+    async fn touch_api_token(&self, token_id: i64) -> Result<(), sqlx::Error>;
+
+    async fn delete_api_token(&self, user_id: Uuid, token_id: i64) -> Result<bool, sqlx::Error>;
+}
+
+impl ApiTokenExt for DBClient {
+    async fn create_api_token(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        token_hash: &str,
+        token_prefix: &str,
+        scopes: &[String],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<ApiToken, sqlx::Error> {
+        sqlx::query_as!(
+            ApiToken,
+            r#"
+            INSERT INTO api_token (user_id, name, token_hash, token_prefix, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, user_id, name, token_prefix, scopes, expires_at, last_used_at, created_at
+            "#,
+            user_id,
+            name,
+            token_hash,
+            token_prefix,
+            scopes,
+            expires_at
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Newest first, expired tokens included (so users see why a script stopped working)
+    async fn get_user_api_tokens(&self, user_id: Uuid) -> Result<Vec<ApiToken>, sqlx::Error> {
+        sqlx::query_as!(
+            ApiToken,
+            r#"
+            SELECT id, user_id, name, token_prefix, scopes, expires_at, last_used_at, created_at
+            FROM api_token
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Token presented by a request, expired or not (the caller checks `expires_at`)
+    async fn get_api_token_by_hash(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<ApiToken>, sqlx::Error> {
+        sqlx::query_as!(
+            ApiToken,
+            r#"
+            SELECT id, user_id, name, token_prefix, scopes, expires_at, last_used_at, created_at
+            FROM api_token
+            WHERE token_hash = $1
+            "#,
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Record a use of a token
+    ///
+    /// At most once a minute per token, so scripts making many requests
+    /// don't turn every request into a write.
+    async fn touch_api_token(&self, token_id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE api_token
+            SET last_used_at = NOW()
+            WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')
+            "#,
+            token_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// # Returns
+    /// false if the user has no such token
+    async fn delete_api_token(&self, user_id: Uuid, token_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM api_token WHERE id = $1 AND user_id = $2",
+            token_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
@@ -1,8 +1,13 @@
 use super::DBClient;
 use crate::dtos::CommentDto;
+use crate::models::{Comment, CommentStatus};
+use crate::utils::comment_content::MAX_MENTIONS_PER_COMMENT;
+use sqlx::PgConnection;
 use uuid::Uuid;
 
 pub trait CommentExt {
+    async fn get_comment(&self, comment_id: i32) -> Result<Option<Comment>, sqlx::Error>;
+
     async fn get_comments(
         &self,
         post_id: i32,
@@ -15,7 +20,11 @@ pub trait CommentExt {
         &self,
         user_id: Uuid,
         post_id: i32,
+        parent_id: Option<i32>,
         content: &str,
+        content_html: &str,
+        mentions: &[Uuid],
+        status: CommentStatus,
     ) -> Result<CommentDto, sqlx::Error>;
 
     async fn edit_comment(
@@ -23,16 +32,52 @@ pub trait CommentExt {
         user_id: Uuid,
         comment_id: i32,
         content: &str,
-    ) -> Result<CommentDto, sqlx::Error>;
+        content_html: &str,
+        mentions: &[Uuid],
+        status: Option<CommentStatus>,
+    ) -> Result<(CommentDto, Vec<Uuid>), sqlx::Error>;
 
     async fn delete_comment(&self, user_id: Uuid, comment_id: i32) -> Result<(), sqlx::Error>;
 
     async fn get_post_comment_count(&self, post_id: i32) -> Result<i64, sqlx::Error>;
 
+    async fn get_post_thread_count(&self, post_id: i32) -> Result<i64, sqlx::Error>;
+
     async fn get_user_comment_count(&self, user_id: &Uuid) -> Result<i64, sqlx::Error>;
+
+    async fn has_approved_comment(&self, user_id: Uuid) -> Result<bool, sqlx::Error>;
+
+    async fn get_user_recent_comment_count(
+        &self,
+        user_id: Uuid,
+        minutes: i32,
+    ) -> Result<i64, sqlx::Error>;
 }
 
 impl CommentExt for DBClient {
+    async fn get_comment(&self, comment_id: i32) -> Result<Option<Comment>, sqlx::Error> {
+        let comment = sqlx::query_as!(
+            Comment,
+            r#"
+            SELECT id, user_id, post_id, parent_id, depth, path, content, content_html, edit_count,
+                   status as "status: CommentStatus", deleted_at, created_at, updated_at
+            FROM comment
+            WHERE id = $1
+            "#,
+            comment_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(comment)
+    }
+
+    /// Pagination is by thread: `limit` top-level comments per page,
+    /// each returned together with all of its replies.
+    ///
+    /// Only approved comments are listed. Deleted or unapproved comments that
+    /// still have visible replies are returned as "[deleted]" placeholders
+    /// (see the `comment_visible` SQL function).
     async fn get_comments(
         &self,
         post_id: i32,
@@ -43,21 +88,45 @@ impl CommentExt for DBClient {
         let offset = (page - 1) * limit;
 
         let order_by = if sort == "created_at_asc" {
-            "r.created_at ASC"
+            "created_at ASC"
         } else {
-            "r.created_at DESC"
+            "created_at DESC"
         };
 
         let query = format!(
             r#"
-            SELECT r.id, u.username as "user_username", r.post_id, r.content, r.created_at, r.updated_at
-            FROM comment r
+            WITH roots AS (
+                SELECT id, created_at
+                FROM comment
+                WHERE post_id = $1 AND parent_id IS NULL AND comment_visible(comment)
+                ORDER BY {order_by}
+                LIMIT $2 OFFSET $3
+            )
+            SELECT
+                r.id,
+                CASE WHEN r.deleted_at IS NULL AND r.status = 'approved' THEN u.username ELSE '[deleted]' END as "user_username",
+                r.post_id,
+                r.parent_id,
+                r.depth,
+                CASE WHEN r.deleted_at IS NULL AND r.status = 'approved' THEN r.content ELSE '[deleted]' END as "content",
+                CASE WHEN r.deleted_at IS NULL AND r.status = 'approved' THEN r.content_html ELSE '[deleted]' END as "content_html",
+                CASE WHEN r.deleted_at IS NULL AND r.status = 'approved' THEN ARRAY(
+                    SELECT mu.username FROM comment_mention m JOIN users mu ON mu.id = m.user_id
+                    WHERE m.comment_id = r.id ORDER BY mu.username
+                ) ELSE '{{}}' END as "mentions",
+                (SELECT COUNT(*) FROM comment c WHERE c.parent_id = r.id AND comment_visible(c)) as "reply_count",
+                NOT (r.deleted_at IS NULL AND r.status = 'approved') as "deleted",
+                r.edit_count > 0 as "edited",
+                r.edit_count,
+                r.status,
+                r.created_at,
+                r.updated_at
+            FROM roots t
+            INNER JOIN comment r ON r.path[1] = t.id
             INNER JOIN users u ON r.user_id = u.id
-            WHERE r.post_id = $1
-            ORDER BY {}
-            LIMIT $2 OFFSET $3
-            "#,
-            order_by
+            WHERE comment_visible(r)
+            ORDER BY t.{order_by}, r.path
+            "#
         );
 
         let comments = sqlx::query_as(&query)
@@ -74,21 +143,51 @@ impl CommentExt for DBClient {
         &self,
         user_id: Uuid,
         post_id: i32,
+        parent_id: Option<i32>,
         content: &str,
+        content_html: &str,
+        mentions: &[Uuid],
+        status: CommentStatus,
     ) -> Result<CommentDto, sqlx::Error> {
+        // The id is drawn from the sequence up front because the comment's
+        // own id is the last element of its path
         let comment = sqlx::query_as!(
             CommentDto,
             r#"
-            WITH new_comment AS (
-                INSERT INTO comment (user_id, post_id, content)
-                VALUES ($1, $2, $3)
+            WITH parent AS (
+                SELECT path, depth FROM comment WHERE id = $4
+            ),
+            new_id AS (
+                SELECT nextval(pg_get_serial_sequence('comment', 'id'))::int AS id
+            ),
+            new_comment AS (
+                INSERT INTO comment (id, user_id, post_id, content, content_html, parent_id, status, mentions_notified, depth, path)
+                SELECT
+                    n.id, $1, $2, $3, $6, $4, $5, cardinality($7::uuid[]),
+                    COALESCE((SELECT depth + 1 FROM parent), 0),
+                    COALESCE((SELECT path FROM parent), '{}') || n.id
+                FROM new_id n
                 RETURNING *
+            ),
+            new_mentions AS (
+                INSERT INTO comment_mention (comment_id, user_id)
+                SELECT nr.id, m.user_id
+                FROM new_comment nr, UNNEST($7::uuid[]) AS m(user_id)
             )
             SELECT
                 nr.id,
                 u.username as "user_username",
                 nr.post_id,
+                nr.parent_id,
+                nr.depth,
                 nr.content,
+                nr.content_html,
+                ARRAY(SELECT mu.username FROM users mu WHERE mu.id = ANY($7) ORDER BY mu.username) as "mentions!",
+                0::bigint as "reply_count!",
+                false as "deleted!",
+                false as "edited!",
+                nr.edit_count,
+                nr.status as "status: CommentStatus",
                 nr.created_at,
                 nr.updated_at
             FROM new_comment nr
@@ -96,7 +195,11 @@ impl CommentExt for DBClient {
             "#,
             user_id,
             post_id,
-            content
+            content,
+            parent_id,
+            status as CommentStatus,
+            content_html,
+            mentions
         )
         .fetch_one(&self.pool)
         .await?;
@@ -104,54 +207,139 @@ impl CommentExt for DBClient {
         Ok(comment)
     }
 
+    /// Update the text of a comment and its mentions
+    ///
+    /// If the text changed, the previous version is kept in `comment_revision`
+    /// and `edit_count` goes up. Saving the same text again is not an edit.
+    /// `status` is the verdict of the spam check on the new text, None keeps
+    /// the current status.
+    ///
+    /// # Returns
+    /// The updated comment, and the users newly mentioned by this edit who
+    /// are to be notified (users mentioned before were already notified, and
+    /// at most `MAX_MENTIONS_PER_COMMENT` are notified over the comment's lifetime)
     async fn edit_comment(
         &self,
         user_id: Uuid,
         comment_id: i32,
         content: &str,
-    ) -> Result<CommentDto, sqlx::Error> {
-        let comment = sqlx::query_as!(
-            CommentDto,
+        content_html: &str,
+        mentions: &[Uuid],
+        status: Option<CommentStatus>,
+    ) -> Result<(CommentDto, Vec<Uuid>), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let mentions_notified = sqlx::query_scalar!(
             r#"
-            WITH updated_comment AS (
-                UPDATE comment
-                SET content = $1, updated_at = NOW()
-                WHERE id = $2 AND user_id = $3
-                RETURNING *
+            WITH old AS (
+                SELECT id, content, content_html, updated_at
+                FROM comment
+                WHERE id = $3 AND user_id = $4 AND deleted_at IS NULL
+                    AND status IN ('pending', 'approved')
+                FOR UPDATE
+            ),
+            revision AS (
+                INSERT INTO comment_revision (comment_id, content, content_html, written_at)
+                SELECT id, content, content_html, updated_at
+                FROM old
+                WHERE content <> $1
             )
-            SELECT
-                ur.id,
-                u.username as "user_username",
-                ur.post_id,
-                ur.content,
-                ur.created_at,
-                ur.updated_at
-            FROM updated_comment ur
-            JOIN users u ON ur.user_id = u.id
+            UPDATE comment c
+            SET content = $1,
+                content_html = $2,
+                edit_count = c.edit_count + CASE WHEN c.content <> $1 THEN 1 ELSE 0 END,
+                status = COALESCE($5, c.status),
+                updated_at = NOW()
+            FROM old
+            WHERE c.id = old.id
+            RETURNING c.mentions_notified
             "#,
             content,
+            content_html,
             comment_id,
-            user_id
+            user_id,
+            status as Option<CommentStatus>
         )
-        .fetch_one(&self.pool)
-        .await?;
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
 
-        Ok(comment)
-    }
+        sqlx::query!(
+            "DELETE FROM comment_mention WHERE comment_id = $1 AND NOT (user_id = ANY($2))",
+            comment_id,
+            mentions
+        )
+        .execute(&mut *tx)
+        .await?;
 
-    async fn delete_comment(&self, user_id: Uuid, comment_id: i32) -> Result<(), sqlx::Error> {
-        let result = sqlx::query!(
-            "DELETE FROM comment WHERE id = $1 AND user_id = $2",
+        let mut new_mentions = sqlx::query_scalar!(
+            r#"
+            INSERT INTO comment_mention (comment_id, user_id)
+            SELECT $1, m.user_id FROM UNNEST($2::uuid[]) AS m(user_id)
+            ON CONFLICT DO NOTHING
+            RETURNING user_id
+            "#,
             comment_id,
-            user_id
+            mentions
         )
-        .execute(&self.pool)
+        .fetch_all(&mut *tx)
         .await?;
 
-        if result.rows_affected() == 0 {
-            return Err(sqlx::Error::RowNotFound);
+        // Removing mentions and adding others doesn't buy new notifications
+        let notifiable = (MAX_MENTIONS_PER_COMMENT as i32 - mentions_notified).max(0);
+        new_mentions.truncate(notifiable as usize);
+
+        if !new_mentions.is_empty() {
+            sqlx::query!(
+                "UPDATE comment SET mentions_notified = mentions_notified + $2 WHERE id = $1",
+                comment_id,
+                new_mentions.len() as i32
+            )
+            .execute(&mut *tx)
+            .await?;
         }
 
+        let comment = sqlx::query_as!(
+            CommentDto,
+            r#"
+            SELECT
+                c.id,
+                u.username as "user_username",
+                c.post_id,
+                c.parent_id,
+                c.depth,
+                c.content,
+                c.content_html,
+                ARRAY(
+                    SELECT mu.username FROM comment_mention m JOIN users mu ON mu.id = m.user_id
+                    WHERE m.comment_id = c.id ORDER BY mu.username
+                ) as "mentions!",
+                (SELECT COUNT(*) FROM comment r WHERE r.parent_id = c.id AND comment_visible(r)) as "reply_count!",
+                false as "deleted!",
+                c.edit_count > 0 as "edited!",
+                c.edit_count,
+                c.status as "status: CommentStatus",
+                c.created_at,
+                c.updated_at
+            FROM comment c
+            JOIN users u ON c.user_id = u.id
+            WHERE c.id = $1
+            "#,
+            comment_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok((comment, new_mentions))
+    }
+
+    async fn delete_comment(&self, user_id: Uuid, comment_id: i32) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        delete_comment_in_tx(&mut *tx, Some(user_id), comment_id).await?;
+        tx.commit().await?;
+
         Ok(())
     }
 
@@ -160,7 +348,22 @@ impl CommentExt for DBClient {
             r#"
             SELECT COUNT(id)
             FROM comment
-            WHERE post_id = $1
+            WHERE post_id = $1 AND status = 'approved' AND deleted_at IS NULL
+            "#,
+            post_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    async fn get_post_thread_count(&self, post_id: i32) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(id)
+            FROM comment
+            WHERE post_id = $1 AND parent_id IS NULL AND comment_visible(comment)
             "#,
             post_id
         )
@@ -175,7 +378,7 @@ impl CommentExt for DBClient {
             r#"
             SELECT COUNT(*)
             FROM comment
-            WHERE user_id = $1
+            WHERE user_id = $1 AND status = 'approved' AND deleted_at IS NULL
             "#,
             user_id
         )
@@ -184,4 +387,135 @@ impl CommentExt for DBClient {
 
         Ok(count.unwrap_or(0))
     }
+
+    async fn has_approved_comment(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM comment WHERE user_id = $1 AND status = 'approved')",
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(exists.unwrap_or(false))
+    }
+
+    /// Comments the user created in the last `minutes`, whatever their status
+    async fn get_user_recent_comment_count(
+        &self,
+        user_id: Uuid,
+        minutes: i32,
+    ) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*)
+            FROM comment
+            WHERE user_id = $1 AND created_at > NOW() - make_interval(mins => $2)
+            "#,
+            user_id,
+            minutes
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+}
+
+/// Delete a comment without destroying the thread below it
+///
+/// - Comment has replies or open reports: soft delete (content wiped, `deleted_at` set),
+///   it is rendered as "[deleted]" and the replies stay where they are. The
+///   wiped text is kept in `comment_revision` for moderators.
+/// - Otherwise: hard delete. If that leaves a "[deleted]" parent without any
+///   replies or open reports, the placeholder is removed as well, walking up
+///   the thread until a live comment or a branch that has to stay is reached.
+///
+/// `owner` restricts the delete to the author's own comment;
+/// `None` deletes regardless of author (moderators).
+/// Runs inside the caller's transaction.
+pub(super) async fn delete_comment_in_tx(
+    conn: &mut PgConnection,
+    owner: Option<Uuid>,
+    comment_id: i32,
+) -> Result<(), sqlx::Error> {
+    // A hard delete would cascade to the revisions and reports moderators still need
+    let keep_row = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM comment WHERE parent_id = $1)
+            OR EXISTS(SELECT 1 FROM content_report WHERE comment_id = $1 AND status = 'open')
+        "#,
+        comment_id
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    .unwrap_or(false);
+
+    if keep_row {
+        let result = sqlx::query!(
+            r#"
+            WITH old AS (
+                SELECT id, content, content_html, updated_at
+                FROM comment
+                WHERE id = $1 AND ($2::uuid IS NULL OR user_id = $2) AND deleted_at IS NULL
+                FOR UPDATE
+            ),
+            revision AS (
+                INSERT INTO comment_revision (comment_id, content, content_html, written_at)
+                SELECT id, content, content_html, updated_at
+                FROM old
+            )
+            UPDATE comment c
+            SET content = '', content_html = '', deleted_at = NOW(), updated_at = NOW()
+            FROM old
+            WHERE c.id = old.id
+            "#,
+            comment_id,
+            owner
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+
+        // The text is gone, so are the mentions in it
+        sqlx::query!(
+            "DELETE FROM comment_mention WHERE comment_id = $1",
+            comment_id
+        )
+        .execute(&mut *conn)
+        .await?;
+    } else {
+        let mut parent_id = sqlx::query_scalar!(
+            "DELETE FROM comment WHERE id = $1 AND ($2::uuid IS NULL OR user_id = $2) RETURNING parent_id",
+            comment_id,
+            owner
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+
+        // Prune placeholders that no longer have any replies
+        while let Some(id) = parent_id {
+            parent_id = sqlx::query_scalar!(
+                r#"
+                DELETE FROM comment
+                WHERE id = $1
+                    AND deleted_at IS NOT NULL
+                    AND NOT EXISTS (SELECT 1 FROM comment c WHERE c.parent_id = $1)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM content_report r WHERE r.comment_id = $1 AND r.status = 'open'
+                    )
+                RETURNING parent_id
+                "#,
+                id
+            )
+            .fetch_optional(&mut *conn)
+            .await?
+            .flatten();
+        }
+    }
+
+    Ok(())
 }
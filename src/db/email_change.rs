@@ -0,0 +1,234 @@
+use super::DBClient;
+use crate::models::PendingEmailChange;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+pub trait EmailChangeExt {
+    async fn create_pending_email_change(
+        &self,
+        user_id: Uuid,
+        new_email: &str,
+        token_hash: &str,
+        revert_token_hash: &str,
+        expires_at: DateTime<Utc>,
+        revert_expires_at: DateTime<Utc>,
+    ) -> Result<PendingEmailChange, sqlx::Error>;
+
+    async fn get_pending_email_change_by_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PendingEmailChange>, sqlx::Error>;
+
+    async fn get_pending_email_change_by_revert_token(
+        &self,
+        revert_token_hash: &str,
+    ) -> Result<Option<PendingEmailChange>, sqlx::Error>;
+
+    async fn confirm_pending_email_change(&self, change_id: i64) -> Result<bool, sqlx::Error>;
+
+    async fn revert_pending_email_change(
+        &self,
+        change_id: i64,
+    ) -> Result<Option<PendingEmailChange>, sqlx::Error>;
+
+    async fn delete_pending_email_change(&self, change_id: i64) -> Result<bool, sqlx::Error>;
+}
+
+impl EmailChangeExt for DBClient {
+    /// Start an email change from the user's current address, replacing
+    /// their unconfirmed one if any
+    ///
+    /// Confirmed changes are kept, their revert link has to keep working.
+    async fn create_pending_email_change(
+        &self,
+        user_id: Uuid,
+        new_email: &str,
+        token_hash: &str,
+        revert_token_hash: &str,
+        expires_at: DateTime<Utc>,
+        revert_expires_at: DateTime<Utc>,
+    ) -> Result<PendingEmailChange, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM pending_email_change WHERE user_id = $1 AND confirmed_at IS NULL",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let change = sqlx::query_as!(
+            PendingEmailChange,
+            r#"
+            INSERT INTO pending_email_change
+                (user_id, old_email, new_email, token_hash, revert_token_hash, expires_at, revert_expires_at)
+            SELECT id, email, $2, $3, $4, $5, $6
+            FROM users
+            WHERE id = $1
+            RETURNING id, user_id, old_email, new_email, expires_at, revert_expires_at, confirmed_at, created_at
+            "#,
+            user_id,
+            new_email,
+            token_hash,
+            revert_token_hash,
+            expires_at,
+            revert_expires_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(change)
+    }
+
+    /// Change of a confirmation link, expired or not (the caller checks `expires_at`)
+    async fn get_pending_email_change_by_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PendingEmailChange>, sqlx::Error> {
+        sqlx::query_as!(
+            PendingEmailChange,
+            r#"
+            SELECT id, user_id, old_email, new_email, expires_at, revert_expires_at, confirmed_at, created_at
+            FROM pending_email_change
+            WHERE token_hash = $1
+            "#,
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Change of a revert link, expired or not (the caller checks `revert_expires_at`)
+    async fn get_pending_email_change_by_revert_token(
+        &self,
+        revert_token_hash: &str,
+    ) -> Result<Option<PendingEmailChange>, sqlx::Error> {
+        sqlx::query_as!(
+            PendingEmailChange,
+            r#"
+            SELECT id, user_id, old_email, new_email, expires_at, revert_expires_at, confirmed_at, created_at
+            FROM pending_email_change
+            WHERE revert_token_hash = $1
+            "#,
+            revert_token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Claim an unconfirmed change and move the user to its new address
+    ///
+    /// Like any email change, this invalidates the user's tokens and deletes
+    /// their personal access tokens. The change is kept, confirmed, so the
+    /// revert link of the old address keeps working.
+    ///
+    /// # Returns
+    /// false if the change was already confirmed (or deleted) by a concurrent request
+    async fn confirm_pending_email_change(&self, change_id: i64) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let claimed = sqlx::query!(
+            r#"
+            UPDATE pending_email_change
+            SET confirmed_at = NOW()
+            WHERE id = $1 AND confirmed_at IS NULL
+            RETURNING user_id, new_email
+            "#,
+            change_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(claimed) = claimed else {
+            return Ok(false);
+        };
+
+        // A unique violation (the address was taken since) rolls the claim back
+        sqlx::query!(
+            r#"
+            WITH revoked AS (DELETE FROM api_token WHERE user_id = $2)
+            UPDATE users
+            SET email = $1, token_version = token_version + 1, updated_at = Now()
+            WHERE id = $2
+            "#,
+            claimed.new_email,
+            claimed.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Cancel an unconfirmed change, or restore the old address of a
+    /// confirmed one, and drop every email change of the user
+    ///
+    /// Restoring invalidates the user's tokens and deletes their personal
+    /// access tokens, like any email change.
+    ///
+    /// # Returns
+    /// The change as it was when reverted, None if a concurrent request
+    /// already reverted it
+    async fn revert_pending_email_change(
+        &self,
+        change_id: i64,
+    ) -> Result<Option<PendingEmailChange>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Locked, so a concurrent confirmation can't slip in between
+        let change = sqlx::query_as!(
+            PendingEmailChange,
+            r#"
+            SELECT id, user_id, old_email, new_email, expires_at, revert_expires_at, confirmed_at, created_at
+            FROM pending_email_change
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            change_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(change) = change else {
+            return Ok(None);
+        };
+
+        if change.confirmed_at.is_some() {
+            // A unique violation (the address was taken since) rolls everything back
+            sqlx::query!(
+                r#"
+                WITH revoked AS (DELETE FROM api_token WHERE user_id = $2)
+                UPDATE users
+                SET email = $1, token_version = token_version + 1, updated_at = Now()
+                WHERE id = $2
+                "#,
+                change.old_email,
+                change.user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query!(
+            "DELETE FROM pending_email_change WHERE user_id = $1",
+            change.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(change))
+    }
+
+    /// # Returns
+    /// false if the change was already deleted by a concurrent request
+    async fn delete_pending_email_change(&self, change_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!("DELETE FROM pending_email_change WHERE id = $1", change_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
@@ -0,0 +1,253 @@
+use super::DBClient;
+use crate::models::{User, UserIdentity, UserRole};
+use uuid::Uuid;
+
+pub trait IdentityExt {
+    async fn get_identity_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<User>, sqlx::Error>;
+
+    async fn create_user_with_identity(
+        &self,
+        username: &str,
+        email: &str,
+        password: &str,
+        verified: bool,
+        provider: &str,
+        subject: &str,
+    ) -> Result<User, sqlx::Error>;
+
+    async fn link_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn link_identity_by_email(
+        &self,
+        user: &User,
+        provider: &str,
+        subject: &str,
+        unusable_password: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn touch_identity(&self, provider: &str, subject: &str) -> Result<(), sqlx::Error>;
+
+    async fn get_user_identities(&self, user_id: Uuid) -> Result<Vec<UserIdentity>, sqlx::Error>;
+
+    async fn delete_identity(&self, user_id: Uuid, identity_id: i64) -> Result<bool, sqlx::Error>;
+}
+
+impl IdentityExt for DBClient {
+    /// User an external account is linked to
+    async fn get_identity_user(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT u.id, u.username, u.email, u.password, u.verified, u.created_at, u.updated_at,
+                u.verification_token, u.token_expires_at, u.token_version, u.role as "role: UserRole"
+            FROM user_identity i
+            JOIN users u ON u.id = i.user_id
+            WHERE i.provider = $1 AND i.subject = $2
+            "#,
+            provider,
+            subject
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Create the account of someone signing in with a provider for the first time
+    ///
+    /// Fails with a unique violation if the username or email is taken.
+    async fn create_user_with_identity(
+        &self,
+        username: &str,
+        email: &str,
+        password: &str,
+        verified: bool,
+        provider: &str,
+        subject: &str,
+    ) -> Result<User, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let user = sqlx::query_as!(
+            User,
+            r#"
+            INSERT INTO users (username, email, password, verified)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole"
+            "#,
+            username,
+            email,
+            password,
+            verified
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO user_identity (user_id, provider, subject, email, last_used_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            "#,
+            user.id,
+            provider,
+            subject,
+            email
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    /// Link an external account to a signed-in user
+    ///
+    /// Fails with a unique violation if the external account is linked to
+    /// someone else, or the user already linked an account of this provider.
+    async fn link_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_identity (user_id, provider, subject, email)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            user_id,
+            provider,
+            subject,
+            email
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Link an external account to the user with the same (provider verified) email
+    ///
+    /// The email is marked as verified. If it wasn't verified before, the
+    /// password is replaced with `unusable_password`, the second factors,
+    /// personal access tokens and other linked accounts are removed and
+    /// existing tokens stop working (`token_version`): whoever registered it never
+    /// proved owning the address, so they mustn't keep access to the account
+    /// of the person who does.
+    async fn link_identity_by_email(
+        &self,
+        user: &User,
+        provider: &str,
+        subject: &str,
+        unusable_password: &str,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        if !user.verified {
+            sqlx::query!(
+                r#"
+                UPDATE users
+                SET verified = true, password = $2, verification_token = NULL, token_expires_at = NULL,
+                    token_version = token_version + 1, updated_at = NOW()
+                WHERE id = $1
+                "#,
+                user.id,
+                unusable_password
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user.id)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query!("DELETE FROM mfa_recovery_code WHERE user_id = $1", user.id)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query!("DELETE FROM api_token WHERE user_id = $1", user.id)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query!("DELETE FROM passkey WHERE user_id = $1", user.id)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query!("DELETE FROM user_identity WHERE user_id = $1", user.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO user_identity (user_id, provider, subject, email, last_used_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            "#,
+            user.id,
+            provider,
+            subject,
+            user.email
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn touch_identity(&self, provider: &str, subject: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE user_identity SET last_used_at = NOW() WHERE provider = $1 AND subject = $2",
+            provider,
+            subject
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Oldest first
+    async fn get_user_identities(&self, user_id: Uuid) -> Result<Vec<UserIdentity>, sqlx::Error> {
+        sqlx::query_as!(
+            UserIdentity,
+            r#"
+            SELECT id, user_id, provider, subject, email, created_at, last_used_at
+            FROM user_identity
+            WHERE user_id = $1
+            ORDER BY created_at ASC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// # Returns
+    /// false if the user has no such identity
+    async fn delete_identity(&self, user_id: Uuid, identity_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM user_identity WHERE id = $1 AND user_id = $2",
+            identity_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
@@ -0,0 +1,279 @@
+use super::DBClient;
+use crate::dtos::MediaDto;
+use crate::models::{Media, MediaVariant};
+use crate::utils::uploads::PUBLIC_UPLOAD_URL;
+use uuid::Uuid;
+
+/// Insert payload for a new media row
+pub struct NewMedia<'a> {
+    pub id: Uuid,
+    pub uploader_id: Uuid,
+    pub file_name: &'a str,
+    pub hash: &'a str,
+    pub mime_type: &'a str,
+    pub width: i32,
+    pub height: i32,
+    pub size_bytes: i64,
+    pub blurhash: &'a str,
+}
+
+/// Outcome of deleting a media entry, see `MediaExt::delete_media`
+#[derive(Debug)]
+pub enum MediaDeletion {
+    Deleted(Vec<String>), // File names of the media and its variants, to remove from disk
+    InUse(i64),           // Number of posts still referencing the media, nothing deleted
+}
+
+pub trait MediaExt {
+    async fn save_media(
+        &self,
+        media: NewMedia<'_>,
+        variants: &[MediaVariant],
+    ) -> Result<Media, sqlx::Error>;
+
+    async fn use_media_by_hash(&self, hash: &str) -> Result<Option<Media>, sqlx::Error>;
+
+    async fn get_media_variants(&self, media_id: Uuid) -> Result<Vec<MediaVariant>, sqlx::Error>;
+
+    async fn get_media_list(
+        &self,
+        page: i32,
+        limit: i32,
+        orphaned_only: bool,
+    ) -> Result<Vec<MediaDto>, sqlx::Error>;
+
+    async fn get_media_count(&self, orphaned_only: bool) -> Result<i64, sqlx::Error>;
+
+    async fn delete_media(&self, media_id: Uuid) -> Result<MediaDeletion, sqlx::Error>;
+
+    async fn sync_post_media(&self, post_id: i32) -> Result<(), sqlx::Error>;
+}
+
+impl MediaExt for DBClient {
+    async fn save_media(
+        &self,
+        media: NewMedia<'_>,
+        variants: &[MediaVariant],
+    ) -> Result<Media, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let saved = sqlx::query_as!(
+            Media,
+            r#"
+            INSERT INTO media (id, uploader_id, file_name, hash, mime_type, width, height, size_bytes, blurhash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id, uploader_id, file_name, hash, mime_type, width, height, size_bytes, blurhash, created_at
+            "#,
+            media.id,
+            media.uploader_id,
+            media.file_name,
+            media.hash,
+            media.mime_type,
+            media.width,
+            media.height,
+            media.size_bytes,
+            media.blurhash,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Insert all variants in one round-trip by unnesting parallel arrays
+        let file_names: Vec<String> = variants.iter().map(|v| v.file_name.clone()).collect();
+        let mime_types: Vec<String> = variants.iter().map(|v| v.mime_type.clone()).collect();
+        let widths: Vec<i32> = variants.iter().map(|v| v.width).collect();
+        let heights: Vec<i32> = variants.iter().map(|v| v.height).collect();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO media_variant (media_id, file_name, mime_type, width, height)
+            SELECT $1, * FROM UNNEST($2::text[], $3::text[], $4::int[], $5::int[])
+            "#,
+            media.id,
+            &file_names,
+            &mime_types,
+            &widths,
+            &heights,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(saved)
+    }
+
+    /// Find a previous upload with the same content hash, for reuse
+    ///
+    /// Its `last_used_at` is bumped: the URLs are handed out again, so the
+    /// orphan cleanup must give them a new grace period.
+    async fn use_media_by_hash(&self, hash: &str) -> Result<Option<Media>, sqlx::Error> {
+        let media = sqlx::query_as!(
+            Media,
+            r#"
+            UPDATE media
+            SET last_used_at = NOW()
+            WHERE hash = $1
+            RETURNING id, uploader_id, file_name, hash, mime_type, width, height, size_bytes, blurhash, created_at
+            "#,
+            hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(media)
+    }
+
+    /// In the order `process_image` generates them: by width, then the
+    /// original format, WebP and AVIF
+    async fn get_media_variants(&self, media_id: Uuid) -> Result<Vec<MediaVariant>, sqlx::Error> {
+        let variants = sqlx::query_as!(
+            MediaVariant,
+            r#"
+            SELECT v.file_name, v.media_id, v.mime_type, v.width, v.height
+            FROM media_variant v
+            JOIN media m ON m.id = v.media_id
+            WHERE v.media_id = $1
+            ORDER BY v.width,
+                CASE v.mime_type
+                    WHEN m.mime_type THEN 0
+                    WHEN 'image/webp' THEN 1
+                    ELSE 2
+                END
+            "#,
+            media_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(variants)
+    }
+
+    async fn get_media_list(
+        &self,
+        page: i32,
+        limit: i32,
+        orphaned_only: bool,
+    ) -> Result<Vec<MediaDto>, sqlx::Error> {
+        let offset = (page - 1) * limit;
+
+        let media = sqlx::query_as!(
+            MediaDto,
+            r#"
+            SELECT m.id, $4 || '/' || m.file_name as "url!", m.mime_type, m.width, m.height, m.size_bytes, m.blurhash,
+                   u.username as "uploader_username?",
+                   COUNT(pm.post_id) as "reference_count!",
+                   m.created_at
+            FROM media m
+            LEFT JOIN users u ON m.uploader_id = u.id
+            LEFT JOIN post_media pm ON pm.media_id = m.id
+            GROUP BY m.id, u.username
+            HAVING NOT $1 OR COUNT(pm.post_id) = 0
+            ORDER BY m.created_at DESC
+            LIMIT $2 OFFSET $3
+            "#,
+            orphaned_only,
+            limit as i64,
+            offset as i64,
+            PUBLIC_UPLOAD_URL
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(media)
+    }
+
+    async fn get_media_count(&self, orphaned_only: bool) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*)
+            FROM media m
+            WHERE NOT $1 OR NOT EXISTS (SELECT 1 FROM post_media pm WHERE pm.media_id = m.id)
+            "#,
+            orphaned_only
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    async fn delete_media(&self, media_id: Uuid) -> Result<MediaDeletion, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Locking the row makes a concurrent post_media insert wait for us
+        // (its foreign key check needs a share lock), so the reference count
+        // below stays true until the delete commits.
+        let file_name = sqlx::query_scalar!(
+            "SELECT file_name FROM media WHERE id = $1 FOR UPDATE",
+            media_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+
+        let references = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM post_media WHERE media_id = $1",
+            media_id
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .unwrap_or(0);
+
+        if references > 0 {
+            return Ok(MediaDeletion::InUse(references));
+        }
+
+        let mut file_names = sqlx::query_scalar!(
+            "SELECT file_name FROM media_variant WHERE media_id = $1",
+            media_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // Variants are removed by ON DELETE CASCADE
+        sqlx::query!("DELETE FROM media WHERE id = $1", media_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        file_names.push(file_name);
+        Ok(MediaDeletion::Deleted(file_names))
+    }
+
+    async fn sync_post_media(&self, post_id: i32) -> Result<(), sqlx::Error> {
+        // Scan both language versions and the thumbnail for upload URLs
+        // (src, srcset, <source> ...), map the file names back to media rows,
+        // and replace this post's references with the result.
+        sqlx::query!(
+            r#"
+            WITH refs AS (
+                SELECT DISTINCT (regexp_matches(
+                    p.content || ' ' || p.content_ko || ' ' || p.thumbnail_url,
+                    '/static/uploads/([A-Za-z0-9._-]+)',
+                    'g'
+                ))[1] AS file_name
+                FROM post p
+                WHERE p.id = $1
+            ),
+            referenced AS (
+                SELECT m.id FROM media m JOIN refs r ON r.file_name = m.file_name
+                UNION
+                SELECT v.media_id FROM media_variant v JOIN refs r ON r.file_name = v.file_name
+            ),
+            removed AS (
+                DELETE FROM post_media
+                WHERE post_id = $1 AND media_id NOT IN (SELECT id FROM referenced)
+            )
+            INSERT INTO post_media (post_id, media_id)
+            SELECT $1, id FROM referenced
+            ON CONFLICT DO NOTHING
+            "#,
+            post_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
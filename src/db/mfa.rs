@@ -0,0 +1,292 @@
+use super::DBClient;
+use crate::models::{UserRole, UserTotp};
+use uuid::Uuid;
+
+pub trait MfaExt {
+    async fn get_totp(&self, user_id: Uuid) -> Result<Option<UserTotp>, sqlx::Error>;
+
+    async fn start_totp_enrolment(&self, user_id: Uuid, secret: &str) -> Result<bool, sqlx::Error>;
+
+    async fn enable_totp(
+        &self,
+        user_id: Uuid,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn disable_totp(&self, user_id: Uuid) -> Result<bool, sqlx::Error>;
+
+    async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool, sqlx::Error>;
+
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool, sqlx::Error>;
+
+    async fn replace_recovery_codes(
+        &self,
+        user_id: Uuid,
+        recovery_code_hashes: &[String],
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_unused_recovery_code_count(&self, user_id: Uuid) -> Result<i64, sqlx::Error>;
+
+    async fn get_mfa_required_roles(&self) -> Result<Vec<UserRole>, sqlx::Error>;
+
+    async fn set_mfa_policy(
+        &self,
+        admin_id: Uuid,
+        role: UserRole,
+        mfa_required: bool,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn mfa_setup_required(&self, user_id: Uuid, role: UserRole) -> Result<bool, sqlx::Error>;
+}
+
+impl MfaExt for DBClient {
+    async fn get_totp(&self, user_id: Uuid) -> Result<Option<UserTotp>, sqlx::Error> {
+        sqlx::query_as!(
+            UserTotp,
+            r#"
+            SELECT user_id, secret, enabled_at, last_used_step, created_at
+            FROM user_totp
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Store a new secret while enrolment is pending
+    ///
+    /// Starting again replaces the pending secret (e.g. the QR code was never scanned).
+    ///
+    /// # Returns
+    /// false if the user already has an enabled authenticator (left untouched)
+    async fn start_totp_enrolment(&self, user_id: Uuid, secret: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO user_totp (user_id, secret)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id)
+            DO UPDATE SET secret = EXCLUDED.secret, last_used_step = 0, created_at = NOW()
+            WHERE user_totp.enabled_at IS NULL
+            "#,
+            user_id,
+            secret
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Finish enrolment with the step of the first valid code and a fresh set of recovery codes
+    ///
+    /// # Returns
+    /// false if there is no pending enrolment (none started, or already enabled)
+    async fn enable_totp(
+        &self,
+        user_id: Uuid,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_totp
+            SET enabled_at = NOW(), last_used_step = $2
+            WHERE user_id = $1 AND enabled_at IS NULL
+            "#,
+            user_id,
+            step
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query!("DELETE FROM mfa_recovery_code WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO mfa_recovery_code (user_id, code_hash)
+            SELECT $1, UNNEST($2::text[])
+            "#,
+            user_id,
+            recovery_code_hashes
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Remove the authenticator (pending or enabled) and all recovery codes
+    ///
+    /// # Returns
+    /// false if the user had no authenticator
+    async fn disable_totp(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!("DELETE FROM mfa_recovery_code WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Record the step of an accepted code
+    ///
+    /// Done with a conditional update, so two requests racing with the same
+    /// code can't both succeed.
+    ///
+    /// # Returns
+    /// false if this step (or a later one) was already used
+    async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE user_totp
+            SET last_used_step = $2
+            WHERE user_id = $1 AND enabled_at IS NOT NULL AND last_used_step < $2
+            "#,
+            user_id,
+            step
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Mark a recovery code as used
+    ///
+    /// # Returns
+    /// false if the code doesn't exist or was already used
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE mfa_recovery_code
+            SET used_at = NOW()
+            WHERE id = (
+                SELECT id FROM mfa_recovery_code
+                WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            user_id,
+            code_hash
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Invalidate all recovery codes of a user and store a new set
+    async fn replace_recovery_codes(
+        &self,
+        user_id: Uuid,
+        recovery_code_hashes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!("DELETE FROM mfa_recovery_code WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO mfa_recovery_code (user_id, code_hash)
+            SELECT $1, UNNEST($2::text[])
+            "#,
+            user_id,
+            recovery_code_hashes
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_unused_recovery_code_count(&self, user_id: Uuid) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM mfa_recovery_code WHERE user_id = $1 AND used_at IS NULL",
+            user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Roles whose members must use two-factor authentication
+    async fn get_mfa_required_roles(&self) -> Result<Vec<UserRole>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT role as "role: UserRole"
+            FROM role_mfa_policy
+            WHERE mfa_required
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn set_mfa_policy(
+        &self,
+        admin_id: Uuid,
+        role: UserRole,
+        mfa_required: bool,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO role_mfa_policy (role, mfa_required, updated_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (role)
+            DO UPDATE SET mfa_required = EXCLUDED.mfa_required, updated_by = EXCLUDED.updated_by, updated_at = NOW()
+            "#,
+            role as UserRole,
+            mfa_required,
+            admin_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether the user's role requires two-factor authentication and the user hasn't set it up yet
+    ///
+    /// An enabled authenticator app or a passkey both count.
+    async fn mfa_setup_required(&self, user_id: Uuid, role: UserRole) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT
+                EXISTS (SELECT 1 FROM role_mfa_policy WHERE role = $2 AND mfa_required)
+                AND NOT EXISTS (
+                    SELECT 1 FROM user_totp WHERE user_id = $1 AND enabled_at IS NOT NULL
+                )
+                AND NOT EXISTS (SELECT 1 FROM passkey WHERE user_id = $1) as "required!"
+            "#,
+            user_id,
+            role as UserRole
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+}
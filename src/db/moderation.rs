@@ -0,0 +1,284 @@
+use super::DBClient;
+use super::comment::delete_comment_in_tx;
+use crate::dtos::{ModerationCommentDto, ModerationLogDto};
+use crate::models::{CommentRevision, CommentStatus};
+use uuid::Uuid;
+
+pub trait ModerationExt {
+    async fn get_moderation_queue(
+        &self,
+        status: CommentStatus,
+        page: i32,
+        limit: i32,
+    ) -> Result<Vec<ModerationCommentDto>, sqlx::Error>;
+
+    async fn get_moderation_queue_count(&self, status: CommentStatus) -> Result<i64, sqlx::Error>;
+
+    async fn set_comment_status(
+        &self,
+        moderator_id: Option<Uuid>,
+        comment_ids: &[i32],
+        status: CommentStatus,
+        reason: Option<&str>,
+    ) -> Result<u64, sqlx::Error>;
+
+    async fn moderator_delete_comment(
+        &self,
+        moderator_id: Uuid,
+        comment_id: i32,
+        reason: Option<&str>,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn log_classification(
+        &self,
+        comment_id: i32,
+        status: CommentStatus,
+        reason: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn get_moderation_log(
+        &self,
+        comment_id: Option<i32>,
+        page: i32,
+        limit: i32,
+    ) -> Result<Vec<ModerationLogDto>, sqlx::Error>;
+
+    async fn get_moderation_log_count(&self, comment_id: Option<i32>) -> Result<i64, sqlx::Error>;
+
+    async fn get_comment_revisions(
+        &self,
+        comment_id: i32,
+    ) -> Result<Vec<CommentRevision>, sqlx::Error>;
+}
+
+impl ModerationExt for DBClient {
+    /// Oldest first, so the queue is worked through in arrival order
+    async fn get_moderation_queue(
+        &self,
+        status: CommentStatus,
+        page: i32,
+        limit: i32,
+    ) -> Result<Vec<ModerationCommentDto>, sqlx::Error> {
+        let offset = (page - 1) * limit;
+
+        let comments = sqlx::query_as!(
+            ModerationCommentDto,
+            r#"
+            SELECT
+                c.id,
+                c.post_id,
+                p.title as "post_title",
+                c.parent_id,
+                u.username as "user_username",
+                c.content,
+                c.status as "status: CommentStatus",
+                c.deleted_at IS NOT NULL as "deleted!",
+                c.edit_count,
+                c.created_at,
+                c.updated_at
+            FROM comment c
+            INNER JOIN users u ON c.user_id = u.id
+            INNER JOIN post p ON c.post_id = p.id
+            WHERE c.status = $1
+            ORDER BY c.created_at ASC
+            LIMIT $2 OFFSET $3
+            "#,
+            status as CommentStatus,
+            limit as i64,
+            offset as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(comments)
+    }
+
+    async fn get_moderation_queue_count(&self, status: CommentStatus) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM comment WHERE status = $1",
+            status as CommentStatus
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Change the status of one or more comments and record it in the audit trail
+    ///
+    /// Comments already in the target status are skipped (no log entry).
+    /// `moderator_id` is None for automatic decisions.
+    ///
+    /// # Returns
+    /// Number of comments whose status actually changed
+    async fn set_comment_status(
+        &self,
+        moderator_id: Option<Uuid>,
+        comment_ids: &[i32],
+        status: CommentStatus,
+        reason: Option<&str>,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            WITH previous AS (
+                SELECT id, status
+                FROM comment
+                WHERE id = ANY($1) AND status <> $2
+                FOR UPDATE
+            ),
+            updated AS (
+                UPDATE comment c
+                SET status = $2
+                FROM previous p
+                WHERE c.id = p.id
+                RETURNING c.id, p.status AS previous_status
+            )
+            INSERT INTO comment_moderation_log (comment_id, moderator_id, action, previous_status, new_status, reason)
+            SELECT id, $3, 'status_change', previous_status, $2, $4
+            FROM updated
+            "#,
+            comment_ids,
+            status as CommentStatus,
+            moderator_id,
+            reason
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Delete any comment regardless of author and record it in the audit trail
+    ///
+    /// Same thread handling as a regular delete: comments with replies
+    /// become "[deleted]" placeholders.
+    async fn moderator_delete_comment(
+        &self,
+        moderator_id: Uuid,
+        comment_id: i32,
+        reason: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let previous_status = sqlx::query_scalar!(
+            r#"
+            SELECT status as "status: CommentStatus"
+            FROM comment
+            WHERE id = $1 AND deleted_at IS NULL
+            FOR UPDATE
+            "#,
+            comment_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+
+        delete_comment_in_tx(&mut *tx, None, comment_id).await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO comment_moderation_log (comment_id, moderator_id, action, previous_status, reason)
+            VALUES ($1, $2, 'delete', $3, $4)
+            "#,
+            comment_id,
+            moderator_id,
+            previous_status as CommentStatus,
+            reason
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Record the status the spam classifier gave a new comment
+    async fn log_classification(
+        &self,
+        comment_id: i32,
+        status: CommentStatus,
+        reason: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO comment_moderation_log (comment_id, action, new_status, reason)
+            VALUES ($1, 'classify', $2, $3)
+            "#,
+            comment_id,
+            status as CommentStatus,
+            reason
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Newest first
+    async fn get_moderation_log(
+        &self,
+        comment_id: Option<i32>,
+        page: i32,
+        limit: i32,
+    ) -> Result<Vec<ModerationLogDto>, sqlx::Error> {
+        let offset = (page - 1) * limit;
+
+        let entries = sqlx::query_as!(
+            ModerationLogDto,
+            r#"
+            SELECT
+                l.id,
+                l.comment_id,
+                u.username as "moderator_username?",
+                l.action,
+                l.previous_status as "previous_status: CommentStatus",
+                l.new_status as "new_status: CommentStatus",
+                l.reason,
+                l.created_at
+            FROM comment_moderation_log l
+            LEFT JOIN users u ON l.moderator_id = u.id
+            WHERE $1::int IS NULL OR l.comment_id = $1
+            ORDER BY l.created_at DESC, l.id DESC
+            LIMIT $2 OFFSET $3
+            "#,
+            comment_id,
+            limit as i64,
+            offset as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    async fn get_moderation_log_count(&self, comment_id: Option<i32>) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM comment_moderation_log WHERE $1::int IS NULL OR comment_id = $1",
+            comment_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Previous versions of a comment, oldest first
+    async fn get_comment_revisions(
+        &self,
+        comment_id: i32,
+    ) -> Result<Vec<CommentRevision>, sqlx::Error> {
+        sqlx::query_as!(
+            CommentRevision,
+            r#"
+            SELECT id, comment_id, content, content_html, written_at, replaced_at
+            FROM comment_revision
+            WHERE comment_id = $1
+            ORDER BY id
+            "#,
+            comment_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
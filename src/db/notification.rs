@@ -0,0 +1,284 @@
+use super::DBClient;
+use crate::models::{CommentNotification, NotificationFrequency};
+use uuid::Uuid;
+
+/// Days after which notifications are removed from the queue (sent or not)
+const NOTIFICATION_RETENTION_DAYS: i32 = 30;
+
+/// Seconds a claimed notification is skipped by other scheduler runs; a
+/// failed send is retried once the claim expires
+const NOTIFICATION_CLAIM_SECS: f64 = 600.0;
+
+/// Sends tried before a notification is given up (and pruned)
+const NOTIFICATION_MAX_ATTEMPTS: i32 = 5;
+
+pub trait NotificationExt {
+    async fn get_notification_preference(
+        &self,
+        user_id: Uuid,
+    ) -> Result<NotificationFrequency, sqlx::Error>;
+
+    async fn set_notification_preference(
+        &self,
+        user_id: Uuid,
+        comment_notifications: NotificationFrequency,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn enqueue_comment_notifications(&self, comment_id: i32) -> Result<u64, sqlx::Error>;
+
+    async fn enqueue_mention_notifications(
+        &self,
+        comment_id: i32,
+        user_ids: &[Uuid],
+    ) -> Result<u64, sqlx::Error>;
+
+    async fn claim_due_comment_notifications(
+        &self,
+        frequency: NotificationFrequency,
+        limit: i64,
+    ) -> Result<Vec<CommentNotification>, sqlx::Error>;
+
+    async fn mark_comment_notifications_sent(&self, ids: &[i64]) -> Result<(), sqlx::Error>;
+
+    async fn prune_comment_notifications(&self) -> Result<u64, sqlx::Error>;
+}
+
+impl NotificationExt for DBClient {
+    /// Users who never changed their preference get instant notifications
+    async fn get_notification_preference(
+        &self,
+        user_id: Uuid,
+    ) -> Result<NotificationFrequency, sqlx::Error> {
+        let frequency = sqlx::query_scalar!(
+            r#"
+            SELECT comment_notifications as "comment_notifications: NotificationFrequency"
+            FROM notification_preference
+            WHERE user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(frequency.unwrap_or(NotificationFrequency::Instant))
+    }
+
+    async fn set_notification_preference(
+        &self,
+        user_id: Uuid,
+        comment_notifications: NotificationFrequency,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO notification_preference (user_id, comment_notifications)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id)
+            DO UPDATE SET comment_notifications = EXCLUDED.comment_notifications, updated_at = NOW()
+            "#,
+            user_id,
+            comment_notifications as NotificationFrequency
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Queue notifications for a new comment
+    ///
+    /// Recipients are the post author, the users mentioned in the comment and,
+    /// for replies, the author of the parent comment. The commenter is never
+    /// notified about their own comment, and everyone gets a single notification,
+    /// with the most specific reason (reply, then mention, then post_comment).
+    /// Users who turned notifications off are skipped.
+    ///
+    /// # Returns
+    /// Number of notifications queued
+    async fn enqueue_comment_notifications(&self, comment_id: i32) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO comment_notification (recipient_id, comment_id, reason)
+            SELECT DISTINCT ON (r.recipient_id) r.recipient_id, c.id, r.reason
+            FROM comment c
+            CROSS JOIN LATERAL (
+                SELECT pc.user_id AS recipient_id, 'reply' AS reason, 1 AS priority
+                FROM comment pc
+                WHERE pc.id = c.parent_id AND pc.deleted_at IS NULL
+                UNION ALL
+                SELECT m.user_id, 'mention', 2
+                FROM comment_mention m
+                WHERE m.comment_id = c.id
+                UNION ALL
+                SELECT p.user_id, 'post_comment', 3
+                FROM post p
+                WHERE p.id = c.post_id
+            ) r
+            LEFT JOIN notification_preference np ON np.user_id = r.recipient_id
+            WHERE c.id = $1
+                AND r.recipient_id <> c.user_id
+                AND COALESCE(np.comment_notifications, 'instant') <> 'off'
+            ORDER BY r.recipient_id, r.priority
+            ON CONFLICT (recipient_id, comment_id) DO NOTHING
+            "#,
+            comment_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Queue "mention" notifications for users newly mentioned by an edit
+    ///
+    /// Users who already got a notification for this comment are skipped,
+    /// as are the commenter and users who turned notifications off.
+    ///
+    /// # Returns
+    /// Number of notifications queued
+    async fn enqueue_mention_notifications(
+        &self,
+        comment_id: i32,
+        user_ids: &[Uuid],
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO comment_notification (recipient_id, comment_id, reason)
+            SELECT m.user_id, c.id, 'mention'
+            FROM comment c
+            CROSS JOIN UNNEST($2::uuid[]) AS m(user_id)
+            LEFT JOIN notification_preference np ON np.user_id = m.user_id
+            WHERE c.id = $1
+                AND m.user_id <> c.user_id
+                AND COALESCE(np.comment_notifications, 'instant') <> 'off'
+            ON CONFLICT (recipient_id, comment_id) DO NOTHING
+            "#,
+            comment_id,
+            user_ids
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Claim the unsent notifications of users with the given preference,
+    /// oldest first, and return them for sending
+    ///
+    /// Only comments that are approved and not deleted are returned, so
+    /// comments held for moderation stay queued until a moderator approves them.
+    /// Claimed rows are skipped by other runs (and other instances) for
+    /// `NOTIFICATION_CLAIM_SECS`, so an email isn't sent twice. Every claim
+    /// counts as an attempt, after `NOTIFICATION_MAX_ATTEMPTS` the
+    /// notification is left to `prune_comment_notifications`, so a
+    /// notification that always fails doesn't hold up the queue.
+    async fn claim_due_comment_notifications(
+        &self,
+        frequency: NotificationFrequency,
+        limit: i64,
+    ) -> Result<Vec<CommentNotification>, sqlx::Error> {
+        sqlx::query_as!(
+            CommentNotification,
+            r#"
+            WITH due AS (
+                SELECT n.id
+                FROM comment_notification n
+                JOIN users u ON u.id = n.recipient_id
+                JOIN comment c ON c.id = n.comment_id
+                LEFT JOIN notification_preference np ON np.user_id = n.recipient_id
+                WHERE n.sent_at IS NULL
+                    AND n.attempts < $3
+                    AND (n.claimed_at IS NULL OR n.claimed_at < NOW() - make_interval(secs => $4))
+                    AND u.verified = true
+                    AND c.status = 'approved'
+                    AND c.deleted_at IS NULL
+                    AND COALESCE(np.comment_notifications, 'instant') = $1
+                ORDER BY n.created_at
+                LIMIT $2
+                FOR UPDATE OF n SKIP LOCKED
+            ),
+            claimed AS (
+                UPDATE comment_notification n
+                SET claimed_at = NOW(), attempts = n.attempts + 1
+                FROM due
+                WHERE n.id = due.id
+                RETURNING n.id, n.recipient_id, n.comment_id, n.reason, n.created_at
+            )
+            SELECT
+                n.id as "id!",
+                n.recipient_id as "recipient_id!",
+                u.email as recipient_email,
+                u.username as recipient_username,
+                n.reason as "reason!",
+                c.id as comment_id,
+                c.content as comment_content,
+                cu.username as commenter_username,
+                p.id as post_id,
+                p.title as post_title,
+                n.created_at as "created_at!"
+            FROM claimed n
+            JOIN users u ON u.id = n.recipient_id
+            JOIN comment c ON c.id = n.comment_id
+            JOIN users cu ON cu.id = c.user_id
+            JOIN post p ON p.id = c.post_id
+            ORDER BY n.created_at
+            "#,
+            frequency as NotificationFrequency,
+            limit,
+            NOTIFICATION_MAX_ATTEMPTS,
+            NOTIFICATION_CLAIM_SECS
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn mark_comment_notifications_sent(&self, ids: &[i64]) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE comment_notification SET sent_at = NOW() WHERE id = ANY($1)",
+            ids
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Drop notifications that will never be sent, and old sent ones
+    ///
+    /// - The comment was rejected, marked as spam or deleted
+    /// - The recipient turned notifications off after they were queued
+    /// - Sending failed `NOTIFICATION_MAX_ATTEMPTS` times
+    /// - Older than the retention period (e.g. a comment never moderated)
+    ///
+    /// # Returns
+    /// Number of notifications removed
+    async fn prune_comment_notifications(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM comment_notification n
+            USING comment c
+            WHERE c.id = n.comment_id
+                AND (
+                    n.created_at < NOW() - make_interval(days => $1)
+                    OR (
+                        n.sent_at IS NULL
+                        AND (
+                            c.status IN ('rejected', 'spam')
+                            OR c.deleted_at IS NOT NULL
+                            OR n.attempts >= $2
+                            OR EXISTS (
+                                SELECT 1 FROM notification_preference np
+                                WHERE np.user_id = n.recipient_id AND np.comment_notifications = 'off'
+                            )
+                        )
+                    )
+                )
+            "#,
+            NOTIFICATION_RETENTION_DAYS,
+            NOTIFICATION_MAX_ATTEMPTS
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,108 @@
+use super::DBClient;
+use crate::models::UserPasskey;
+use uuid::Uuid;
+
+pub trait PasskeyExt {
+    async fn save_passkey(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        credential_id: &[u8],
+        credential: &str,
+    ) -> Result<i64, sqlx::Error>;
+
+    async fn get_user_passkeys(&self, user_id: Uuid) -> Result<Vec<UserPasskey>, sqlx::Error>;
+
+    async fn get_passkey_count(&self, user_id: Uuid) -> Result<i64, sqlx::Error>;
+
+    async fn update_passkey_credential(
+        &self,
+        passkey_id: i64,
+        credential: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn delete_passkey(&self, user_id: Uuid, passkey_id: i64) -> Result<bool, sqlx::Error>;
+}
+
+impl PasskeyExt for DBClient {
+    /// Store a newly registered passkey
+    ///
+    /// Credential ids are unique across all users, registering the same
+    /// authenticator twice fails with a unique violation.
+    async fn save_passkey(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        credential_id: &[u8],
+        credential: &str,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            INSERT INTO passkey (user_id, name, credential_id, credential)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+            user_id,
+            name,
+            credential_id,
+            credential
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Oldest first
+    async fn get_user_passkeys(&self, user_id: Uuid) -> Result<Vec<UserPasskey>, sqlx::Error> {
+        sqlx::query_as!(
+            UserPasskey,
+            r#"
+            SELECT id, user_id, name, credential, created_at, last_used_at
+            FROM passkey
+            WHERE user_id = $1
+            ORDER BY created_at ASC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn get_passkey_count(&self, user_id: Uuid) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!("SELECT COUNT(*) FROM passkey WHERE user_id = $1", user_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Store the credential after a login (new signature counter) and mark it as used
+    async fn update_passkey_credential(
+        &self,
+        passkey_id: i64,
+        credential: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE passkey SET credential = $2, last_used_at = NOW() WHERE id = $1",
+            passkey_id,
+            credential
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// # Returns
+    /// false if the user has no such passkey
+    async fn delete_passkey(&self, user_id: Uuid, passkey_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM passkey WHERE id = $1 AND user_id = $2",
+            passkey_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
@@ -4,7 +4,12 @@ use pgvector::Vector;
 use uuid::Uuid;
 
 pub trait PostExt {
-    async fn get_post(&self, post_id: i32, lang: Lang) -> Result<PostDto, sqlx::Error>;
+    async fn get_post(
+        &self,
+        post_id: i32,
+        lang: Lang,
+        include_hidden: bool,
+    ) -> Result<PostDto, sqlx::Error>;
 
     async fn get_posts(
         &self,
@@ -65,7 +70,13 @@ pub trait PostExt {
 }
 
 impl PostExt for DBClient {
-    async fn get_post(&self, post_id: i32, lang: Lang) -> Result<PostDto, sqlx::Error> {
+    /// Posts hidden after user reports are only found with `include_hidden` (admins)
+    async fn get_post(
+        &self,
+        post_id: i32,
+        lang: Lang,
+        include_hidden: bool,
+    ) -> Result<PostDto, sqlx::Error> {
         let post = if lang == Lang::En {
             sqlx::query_as!(
                 PostDto,
@@ -73,9 +84,10 @@ impl PostExt for DBClient {
                 SELECT p.id, u.username as "user_username", p.content, p.summary, p.title, p.thumbnail_url, p.created_at, p.updated_at
                 FROM post p
                 INNER JOIN users u ON p.user_id = u.id
-                WHERE p.id = $1
+                WHERE p.id = $1 AND (p.hidden_at IS NULL OR $2)
                 "#,
-                post_id
+                post_id,
+                include_hidden
             )
             .fetch_one(&self.pool)
             .await?
@@ -86,9 +98,10 @@ impl PostExt for DBClient {
                 SELECT p.id, u.username as "user_username", p.content_ko as "content", p.summary_ko as "summary", p.title_ko as "title", p.thumbnail_url, p.created_at, p.updated_at
                 FROM post p
                 INNER JOIN users u ON p.user_id = u.id
-                WHERE p.id = $1
+                WHERE p.id = $1 AND (p.hidden_at IS NULL OR $2)
                 "#,
-                post_id
+                post_id,
+                include_hidden
             )
             .fetch_one(&self.pool)
             .await?
@@ -113,7 +126,7 @@ impl PostExt for DBClient {
                 SELECT p.id, u.username as "user_username", p.summary, p.title, p.thumbnail_url, p.created_at, p.updated_at
                 FROM post p
                 INNER JOIN users u ON p.user_id = u.id
-                WHERE u.username = $1
+                WHERE u.username = $1 AND p.hidden_at IS NULL
                 ORDER BY p.created_at DESC
                 LIMIT $2 OFFSET $3
                 "#,
@@ -130,7 +143,7 @@ impl PostExt for DBClient {
                 SELECT p.id, u.username as "user_username", p.summary_ko as "summary", p.title_ko as "title", p.thumbnail_url, p.created_at, p.updated_at
                 FROM post p
                 INNER JOIN users u ON p.user_id = u.id
-                WHERE u.username = $1
+                WHERE u.username = $1 AND p.hidden_at IS NULL
                 ORDER BY p.created_at DESC
                 LIMIT $2 OFFSET $3
                 "#,
@@ -296,7 +309,7 @@ impl PostExt for DBClient {
             SELECT COUNT(p.id)
             FROM post p
             INNER JOIN users u ON p.user_id = u.id
-            WHERE u.username = $1
+            WHERE u.username = $1 AND p.hidden_at IS NULL
             "#,
             user_username
         )
@@ -306,6 +319,8 @@ impl PostExt for DBClient {
         Ok(count.unwrap_or(0))
     }
 
+    /// Posts hidden after user reports are dropped after ranking,
+    /// so a page can come out short (they are rare and soon resolved)
     async fn hybrid_search_posts(
         &self,
         query_text: &str,
@@ -324,6 +339,7 @@ impl PostExt for DBClient {
                 SELECT p.id as "id!", u.username as "user_username!", p.summary as "summary!", p.title as "title!", p.thumbnail_url as "thumbnail_url!", p.created_at as "created_at!", p.updated_at as "updated_at!"
                 FROM hybrid_search($1::text, $2::vector(768), $3::int, $4::int) p
                 JOIN users u ON p.user_id = u.id
+                WHERE p.hidden_at IS NULL
                 "#,
                 query_text,
                 embedding as _,
@@ -339,6 +355,7 @@ impl PostExt for DBClient {
                 SELECT p.id as "id!", u.username as "user_username!", p.summary_ko as "summary!", p.title_ko as "title!", p.thumbnail_url as "thumbnail_url!", p.created_at as "created_at!", p.updated_at as "updated_at!"
                 FROM hybrid_search($1::text, $2::vector(768), $3::int, $4::int) p
                 JOIN users u ON p.user_id = u.id
+                WHERE p.hidden_at IS NULL
                 "#,
                 query_text,
                 embedding as _,
@@ -352,6 +369,7 @@ impl PostExt for DBClient {
         Ok(posts)
     }
 
+    /// Hidden posts are not counted, like in `hybrid_search_posts`
     async fn hybrid_search_posts_count(
         &self,
         query_text: &str,
@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+
+use super::DBClient;
+use crate::models::{ReactionTarget, ReactionType};
+use uuid::Uuid;
+
+pub trait ReactionExt {
+    async fn add_reaction(
+        &self,
+        user_id: Uuid,
+        target: ReactionTarget,
+        reaction: ReactionType,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn remove_reaction(
+        &self,
+        user_id: Uuid,
+        target: ReactionTarget,
+        reaction: ReactionType,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn get_reaction_counts(
+        &self,
+        targets: &[ReactionTarget],
+    ) -> Result<HashMap<ReactionTarget, Vec<(ReactionType, i64)>>, sqlx::Error>;
+
+    async fn get_flushed_reaction_counts(
+        &self,
+        targets: &[ReactionTarget],
+    ) -> Result<HashMap<ReactionTarget, Vec<(ReactionType, i64)>>, sqlx::Error>;
+
+    async fn get_user_reactions(
+        &self,
+        user_id: Uuid,
+        targets: &[ReactionTarget],
+    ) -> Result<HashMap<ReactionTarget, Vec<ReactionType>>, sqlx::Error>;
+
+    async fn save_reaction_counts(
+        &self,
+        counts: &[(ReactionTarget, ReactionType, i64)],
+    ) -> Result<(), sqlx::Error>;
+}
+
+/// Split targets into (post ids, comment ids)
+fn split_targets(targets: &[ReactionTarget]) -> (Vec<i32>, Vec<i32>) {
+    let mut post_ids = Vec::new();
+    let mut comment_ids = Vec::new();
+    for target in targets {
+        match target {
+            ReactionTarget::Post(id) => post_ids.push(*id),
+            ReactionTarget::Comment(id) => comment_ids.push(*id),
+        }
+    }
+    (post_ids, comment_ids)
+}
+
+impl ReactionExt for DBClient {
+    /// # Returns
+    /// true if the reaction was added, false if the user had already left it
+    async fn add_reaction(
+        &self,
+        user_id: Uuid,
+        target: ReactionTarget,
+        reaction: ReactionType,
+    ) -> Result<bool, sqlx::Error> {
+        let result = match target {
+            ReactionTarget::Post(post_id) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO post_reaction (post_id, user_id, reaction)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT DO NOTHING
+                    "#,
+                    post_id,
+                    user_id,
+                    reaction as ReactionType
+                )
+                .execute(&self.pool)
+                .await?
+            }
+            ReactionTarget::Comment(comment_id) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO comment_reaction (comment_id, user_id, reaction)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT DO NOTHING
+                    "#,
+                    comment_id,
+                    user_id,
+                    reaction as ReactionType
+                )
+                .execute(&self.pool)
+                .await?
+            }
+        };
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// # Returns
+    /// true if the reaction was removed, false if the user hadn't left it
+    async fn remove_reaction(
+        &self,
+        user_id: Uuid,
+        target: ReactionTarget,
+        reaction: ReactionType,
+    ) -> Result<bool, sqlx::Error> {
+        let result = match target {
+            ReactionTarget::Post(post_id) => {
+                sqlx::query!(
+                    "DELETE FROM post_reaction WHERE post_id = $1 AND user_id = $2 AND reaction = $3",
+                    post_id,
+                    user_id,
+                    reaction as ReactionType
+                )
+                .execute(&self.pool)
+                .await?
+            }
+            ReactionTarget::Comment(comment_id) => {
+                sqlx::query!(
+                    "DELETE FROM comment_reaction WHERE comment_id = $1 AND user_id = $2 AND reaction = $3",
+                    comment_id,
+                    user_id,
+                    reaction as ReactionType
+                )
+                .execute(&self.pool)
+                .await?
+            }
+        };
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Count reactions straight from the reaction rows (exact, used on Redis cache misses)
+    ///
+    /// Targets without any reaction are absent from the map.
+    async fn get_reaction_counts(
+        &self,
+        targets: &[ReactionTarget],
+    ) -> Result<HashMap<ReactionTarget, Vec<(ReactionType, i64)>>, sqlx::Error> {
+        let (post_ids, comment_ids) = split_targets(targets);
+        let mut counts: HashMap<ReactionTarget, Vec<(ReactionType, i64)>> = HashMap::new();
+
+        if !post_ids.is_empty() {
+            let rows = sqlx::query!(
+                r#"
+                SELECT post_id, reaction as "reaction: ReactionType", COUNT(*) as "count!"
+                FROM post_reaction
+                WHERE post_id = ANY($1)
+                GROUP BY post_id, reaction
+                "#,
+                &post_ids
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            for row in rows {
+                counts
+                    .entry(ReactionTarget::Post(row.post_id))
+                    .or_default()
+                    .push((row.reaction, row.count));
+            }
+        }
+
+        if !comment_ids.is_empty() {
+            let rows = sqlx::query!(
+                r#"
+                SELECT comment_id, reaction as "reaction: ReactionType", COUNT(*) as "count!"
+                FROM comment_reaction
+                WHERE comment_id = ANY($1)
+                GROUP BY comment_id, reaction
+                "#,
+                &comment_ids
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            for row in rows {
+                counts
+                    .entry(ReactionTarget::Comment(row.comment_id))
+                    .or_default()
+                    .push((row.reaction, row.count));
+            }
+        }
+
+        Ok(counts)
+    }
+
+    /// Counts as of the last flush from Redis (a minute behind at most)
+    ///
+    /// Cheaper than counting the reaction rows, used while Redis is down.
+    /// Targets without any reaction are absent from the map.
+    async fn get_flushed_reaction_counts(
+        &self,
+        targets: &[ReactionTarget],
+    ) -> Result<HashMap<ReactionTarget, Vec<(ReactionType, i64)>>, sqlx::Error> {
+        let (post_ids, comment_ids) = split_targets(targets);
+        let mut counts: HashMap<ReactionTarget, Vec<(ReactionType, i64)>> = HashMap::new();
+
+        if !post_ids.is_empty() {
+            let rows = sqlx::query!(
+                r#"
+                SELECT post_id, reaction as "reaction: ReactionType", count
+                FROM post_reaction_count
+                WHERE post_id = ANY($1) AND count > 0
+                "#,
+                &post_ids
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            for row in rows {
+                counts
+                    .entry(ReactionTarget::Post(row.post_id))
+                    .or_default()
+                    .push((row.reaction, row.count));
+            }
+        }
+
+        if !comment_ids.is_empty() {
+            let rows = sqlx::query!(
+                r#"
+                SELECT comment_id, reaction as "reaction: ReactionType", count
+                FROM comment_reaction_count
+                WHERE comment_id = ANY($1) AND count > 0
+                "#,
+                &comment_ids
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            for row in rows {
+                counts
+                    .entry(ReactionTarget::Comment(row.comment_id))
+                    .or_default()
+                    .push((row.reaction, row.count));
+            }
+        }
+
+        Ok(counts)
+    }
+
+    /// Reactions the user left on the given targets
+    async fn get_user_reactions(
+        &self,
+        user_id: Uuid,
+        targets: &[ReactionTarget],
+    ) -> Result<HashMap<ReactionTarget, Vec<ReactionType>>, sqlx::Error> {
+        let (post_ids, comment_ids) = split_targets(targets);
+        let mut reactions: HashMap<ReactionTarget, Vec<ReactionType>> = HashMap::new();
+
+        if !post_ids.is_empty() {
+            let rows = sqlx::query!(
+                r#"
+                SELECT post_id, reaction as "reaction: ReactionType"
+                FROM post_reaction
+                WHERE user_id = $1 AND post_id = ANY($2)
+                ORDER BY created_at
+                "#,
+                user_id,
+                &post_ids
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            for row in rows {
+                reactions
+                    .entry(ReactionTarget::Post(row.post_id))
+                    .or_default()
+                    .push(row.reaction);
+            }
+        }
+
+        if !comment_ids.is_empty() {
+            let rows = sqlx::query!(
+                r#"
+                SELECT comment_id, reaction as "reaction: ReactionType"
+                FROM comment_reaction
+                WHERE user_id = $1 AND comment_id = ANY($2)
+                ORDER BY created_at
+                "#,
+                user_id,
+                &comment_ids
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            for row in rows {
+                reactions
+                    .entry(ReactionTarget::Comment(row.comment_id))
+                    .or_default()
+                    .push(row.reaction);
+            }
+        }
+
+        Ok(reactions)
+    }
+
+    /// Upsert aggregated counts (flushed from the Redis hot counters)
+    ///
+    /// Counts for posts/comments deleted in the meantime are dropped.
+    async fn save_reaction_counts(
+        &self,
+        counts: &[(ReactionTarget, ReactionType, i64)],
+    ) -> Result<(), sqlx::Error> {
+        let mut post_ids = Vec::new();
+        let mut post_reactions = Vec::new();
+        let mut post_counts = Vec::new();
+        let mut comment_ids = Vec::new();
+        let mut comment_reactions = Vec::new();
+        let mut comment_counts = Vec::new();
+
+        for (target, reaction, count) in counts {
+            match target {
+                ReactionTarget::Post(id) => {
+                    post_ids.push(*id);
+                    post_reactions.push(reaction.to_str().to_string());
+                    post_counts.push(*count);
+                }
+                ReactionTarget::Comment(id) => {
+                    comment_ids.push(*id);
+                    comment_reactions.push(reaction.to_str().to_string());
+                    comment_counts.push(*count);
+                }
+            }
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        if !post_ids.is_empty() {
+            sqlx::query!(
+                r#"
+                INSERT INTO post_reaction_count (post_id, reaction, count)
+                SELECT u.post_id, u.reaction::reaction_type, u.count
+                FROM UNNEST($1::int[], $2::text[], $3::bigint[]) AS u(post_id, reaction, count)
+                WHERE EXISTS (SELECT 1 FROM post p WHERE p.id = u.post_id)
+                ON CONFLICT (post_id, reaction)
+                DO UPDATE SET count = EXCLUDED.count, updated_at = NOW()
+                "#,
+                &post_ids,
+                &post_reactions,
+                &post_counts
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if !comment_ids.is_empty() {
+            sqlx::query!(
+                r#"
+                INSERT INTO comment_reaction_count (comment_id, reaction, count)
+                SELECT u.comment_id, u.reaction::reaction_type, u.count
+                FROM UNNEST($1::int[], $2::text[], $3::bigint[]) AS u(comment_id, reaction, count)
+                WHERE EXISTS (SELECT 1 FROM comment c WHERE c.id = u.comment_id)
+                ON CONFLICT (comment_id, reaction)
+                DO UPDATE SET count = EXCLUDED.count, updated_at = NOW()
+                "#,
+                &comment_ids,
+                &comment_reactions,
+                &comment_counts
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+use super::DBClient;
+use crate::dtos::ReportDto;
+use crate::models::{ReportReason, ReportStatus, ReportTarget};
+use uuid::Uuid;
+
+pub trait ReportExt {
+    async fn create_report(
+        &self,
+        reporter_id: Uuid,
+        target: ReportTarget,
+        reason: ReportReason,
+        details: Option<&str>,
+    ) -> Result<Option<i64>, sqlx::Error>;
+
+    async fn get_open_report_target(
+        &self,
+        report_id: i64,
+    ) -> Result<Option<ReportTarget>, sqlx::Error>;
+
+    async fn get_reports(
+        &self,
+        status: ReportStatus,
+        page: i32,
+        limit: i32,
+    ) -> Result<Vec<ReportDto>, sqlx::Error>;
+
+    async fn get_reports_count(&self, status: ReportStatus) -> Result<i64, sqlx::Error>;
+
+    async fn resolve_reports(
+        &self,
+        moderator_id: Uuid,
+        target: ReportTarget,
+        status: ReportStatus,
+    ) -> Result<u64, sqlx::Error>;
+
+    async fn set_post_hidden(&self, post_id: i32, hidden: bool) -> Result<bool, sqlx::Error>;
+}
+
+impl ReportExt for DBClient {
+    /// File a report, at most one per user and post/comment
+    ///
+    /// # Returns
+    /// Number of open reports of the post/comment after this one,
+    /// or None if the user had already reported it
+    async fn create_report(
+        &self,
+        reporter_id: Uuid,
+        target: ReportTarget,
+        reason: ReportReason,
+        details: Option<&str>,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        let (post_id, comment_id) = match target {
+            ReportTarget::Post(id) => (Some(id), None),
+            ReportTarget::Comment(id) => (None, Some(id)),
+        };
+
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO content_report (post_id, comment_id, reporter_id, reason, details)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO NOTHING
+            "#,
+            post_id,
+            comment_id,
+            reporter_id,
+            reason as ReportReason,
+            details
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let open = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!"
+            FROM content_report
+            WHERE status = 'open'
+                AND (post_id = $1 OR comment_id = $2)
+            "#,
+            post_id,
+            comment_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(Some(open))
+    }
+
+    /// None if there is no such report or it was already resolved
+    async fn get_open_report_target(
+        &self,
+        report_id: i64,
+    ) -> Result<Option<ReportTarget>, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT post_id, comment_id FROM content_report WHERE id = $1 AND status = 'open'",
+            report_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.and_then(|r| match (r.post_id, r.comment_id) {
+            (Some(id), _) => Some(ReportTarget::Post(id)),
+            (_, Some(id)) => Some(ReportTarget::Comment(id)),
+            _ => None,
+        }))
+    }
+
+    /// Oldest first, so the reports are triaged in arrival order
+    async fn get_reports(
+        &self,
+        status: ReportStatus,
+        page: i32,
+        limit: i32,
+    ) -> Result<Vec<ReportDto>, sqlx::Error> {
+        let offset = (page - 1) * limit;
+
+        let reports = sqlx::query_as!(
+            ReportDto,
+            r#"
+            SELECT
+                r.id,
+                CASE WHEN r.comment_id IS NULL THEN 'post' ELSE 'comment' END as "target_type!",
+                p.id as "post_id!",
+                p.title as "post_title!",
+                r.comment_id,
+                c.content as "comment_content?",
+                r.reason as "reason: ReportReason",
+                r.details,
+                r.status as "status: ReportStatus",
+                u.username as "reporter_username!",
+                (
+                    SELECT COUNT(*)
+                    FROM content_report o
+                    WHERE o.status = 'open'
+                        AND (o.post_id = r.post_id OR o.comment_id = r.comment_id)
+                ) as "open_reports!",
+                m.username as "resolved_by_username?",
+                r.resolved_at,
+                r.created_at
+            FROM content_report r
+            LEFT JOIN comment c ON c.id = r.comment_id
+            INNER JOIN post p ON p.id = COALESCE(r.post_id, c.post_id)
+            INNER JOIN users u ON u.id = r.reporter_id
+            LEFT JOIN users m ON m.id = r.resolved_by
+            WHERE r.status = $1
+            ORDER BY r.created_at ASC
+            LIMIT $2 OFFSET $3
+            "#,
+            status as ReportStatus,
+            limit as i64,
+            offset as i64
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(reports)
+    }
+
+    async fn get_reports_count(&self, status: ReportStatus) -> Result<i64, sqlx::Error> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM content_report WHERE status = $1",
+            status as ReportStatus
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Close every open report of a post/comment with the admin's decision
+    ///
+    /// # Returns
+    /// Number of reports resolved
+    async fn resolve_reports(
+        &self,
+        moderator_id: Uuid,
+        target: ReportTarget,
+        status: ReportStatus,
+    ) -> Result<u64, sqlx::Error> {
+        let (post_id, comment_id) = match target {
+            ReportTarget::Post(id) => (Some(id), None),
+            ReportTarget::Comment(id) => (None, Some(id)),
+        };
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE content_report
+            SET status = $3, resolved_by = $4, resolved_at = NOW()
+            WHERE status = 'open'
+                AND (post_id = $1 OR comment_id = $2)
+            "#,
+            post_id,
+            comment_id,
+            status as ReportStatus,
+            moderator_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Hide a post from listings and search, or show it again
+    ///
+    /// # Returns
+    /// true if the post changed, false if it was already in that state (or doesn't exist)
+    async fn set_post_hidden(&self, post_id: i32, hidden: bool) -> Result<bool, sqlx::Error> {
+        let result = if hidden {
+            sqlx::query!(
+                "UPDATE post SET hidden_at = NOW() WHERE id = $1 AND hidden_at IS NULL",
+                post_id
+            )
+            .execute(&self.pool)
+            .await?
+        } else {
+            sqlx::query!(
+                "UPDATE post SET hidden_at = NULL WHERE id = $1 AND hidden_at IS NOT NULL",
+                post_id
+            )
+            .execute(&self.pool)
+            .await?
+        };
+
+        Ok(result.rows_affected() == 1)
+    }
+}
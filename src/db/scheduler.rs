@@ -1,11 +1,53 @@
-use super::DBClient;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{DBClient, NotificationExt, ReactionExt, SpamFilterExt};
+use crate::config::Config;
+use crate::mail::mails::{send_comment_digest_email, send_comment_notification_email};
+use crate::models::{CommentNotification, NotificationFrequency, ReactionTarget, ReactionType};
+use crate::redisdb::RedisClient;
+use crate::utils::signature::sign_unsubscribe;
+use crate::utils::uploads::remove_upload_files;
 use tokio_cron_scheduler::{Job, JobScheduler};
+use uuid::Uuid;
+
+/// How long an upload may stay unreferenced before it is garbage-collected
+const MEDIA_GC_GRACE_DAYS: i32 = 7;
+
+/// Reaction counters written to Postgres per batch
+const REACTION_FLUSH_BATCH: usize = 500;
+
+/// Instant notification emails sent per run (every minute)
+const INSTANT_NOTIFICATION_BATCH: i64 = 100;
+
+/// Notifications put into digests per run (daily), the rest waits for the next day
+const DIGEST_NOTIFICATION_BATCH: i64 = 5000;
+
+/// Build the unsubscribe links of a user
+///
+/// # Returns
+/// (frontend page linked in the email body, API endpoint for the `List-Unsubscribe` header)
+fn unsubscribe_links(config: &Config, user_id: Uuid) -> (String, String) {
+    let signature = sign_unsubscribe(user_id, config.jwt_secret.as_bytes());
+    let query = format!("user={}&sig={}", user_id, signature);
+    (
+        format!(
+            "{}/notifications/unsubscribe?{}",
+            config.frontend_url, query
+        ),
+        format!("{}/api/notifications/unsubscribe?{}", config.api_url, query),
+    )
+}
 
 impl DBClient {
     /// Start background cleanup task that runs on a schedule
     ///
     /// Removes unverified users whose verification tokens have expired.
     /// This prevents accumulation of inactive registration attempts.
+    /// Also removes email changes whose revert link expired.
+    ///
+    /// Also garbage-collects uploaded media that no post references,
+    /// and retrains the comment spam filter.
     pub async fn start_cleanup_task(&self) {
         // Create a new job scheduler for managing cron jobs
         let sched = JobScheduler::new().await.unwrap();
@@ -57,14 +99,374 @@ impl DBClient {
                         tracing::error!("Cleanup job {:?} failed: {}", uuid, e);
                     }
                 }
+
+                // Email changes whose revert link expired have nothing left to do
+                let result = sqlx::query!(
+                    "DELETE FROM pending_email_change WHERE revert_expires_at < NOW()"
+                )
+                .execute(&pool)
+                .await;
+
+                match result {
+                    Ok(r) => {
+                        tracing::info!(
+                            "Cleanup job {:?} deleted {} expired email changes",
+                            uuid,
+                            r.rows_affected()
+                        );
+                    }
+                    Err(e) => {
+                        tracing::error!("Cleanup job {:?} failed on email changes: {}", uuid, e);
+                    }
+                }
             })
         })
         .unwrap();
 
         // Add the job to the scheduler
         sched.add(job).await.unwrap();
+
+        // Media garbage collection, daily at 3 AM
+        // Deletes uploads no post references once they were last used (uploaded, or
+        // handed out again by a duplicate upload) longer ago than the grace period.
+        // The grace period covers images uploaded while a post is still being drafted.
+        let pool = self.pool.clone();
+        let media_job = Job::new_async("0 0 3 * * *", move |uuid, _l| {
+            let pool = pool.clone();
+
+            Box::pin(async move {
+                tracing::info!("Running media cleanup job {:?}", uuid);
+
+                // Besides post_media, also check the post text directly (file names
+                // start with the media id) so a failed reference sync can never
+                // cause an image that is actually in use to be deleted.
+                let result = sqlx::query_scalar!(
+                    r#"
+                    WITH orphans AS (
+                        SELECT m.id
+                        FROM media m
+                        WHERE m.last_used_at < NOW() - make_interval(days => $1)
+                            AND NOT EXISTS (SELECT 1 FROM post_media pm WHERE pm.media_id = m.id)
+                            AND NOT EXISTS (
+                                SELECT 1 FROM post p
+                                WHERE strpos(p.content || ' ' || p.content_ko || ' ' || p.thumbnail_url, m.id::text) > 0
+                            )
+                    ),
+                    -- last_used_at is checked again on the row: a duplicate upload
+                    -- reusing the media right now keeps it
+                    deleted_media AS (
+                        DELETE FROM media m USING orphans o
+                        WHERE m.id = o.id AND m.last_used_at < NOW() - make_interval(days => $1)
+                        RETURNING m.id, m.file_name
+                    ),
+                    deleted_variants AS (
+                        DELETE FROM media_variant v USING deleted_media d
+                        WHERE v.media_id = d.id
+                        RETURNING v.file_name
+                    )
+                    SELECT file_name as "file_name!" FROM deleted_variants
+                    UNION ALL
+                    SELECT file_name as "file_name!" FROM deleted_media
+                    "#,
+                    MEDIA_GC_GRACE_DAYS
+                )
+                .fetch_all(&pool)
+                .await;
+
+                match result {
+                    Ok(file_names) => {
+                        let removed = remove_upload_files(&file_names);
+                        tracing::info!(
+                            "Media cleanup job {:?} finished successfully, removed {} files",
+                            uuid,
+                            removed
+                        );
+                    }
+                    Err(e) => {
+                        tracing::error!("Media cleanup job {:?} failed: {}", uuid, e);
+                    }
+                }
+            })
+        })
+        .unwrap();
+
+        sched.add(media_job).await.unwrap();
+
+        // Spam filter retraining, hourly
+        // Picks up the moderation decisions made since the last run.
+        let db_client = self.clone();
+        let spam_job = Job::new_async("0 30 * * * *", move |uuid, _l| {
+            let db_client = db_client.clone();
+
+            Box::pin(async move {
+                match db_client.retrain_spam_filter().await {
+                    Ok((spam_docs, ham_docs)) => {
+                        tracing::info!(
+                            "Spam filter job {:?} finished successfully, trained on {} spam / {} ham comments",
+                            uuid,
+                            spam_docs,
+                            ham_docs
+                        );
+                    }
+                    Err(e) => {
+                        tracing::error!("Spam filter job {:?} failed: {}", uuid, e);
+                    }
+                }
+            })
+        })
+        .unwrap();
+
+        sched.add(spam_job).await.unwrap();
         // Start the scheduler (runs in background, doesn't block)
         // The job will execute repeatedly according to the cron schedule
         sched.start().await.unwrap();
     }
+
+    /// Start the job that flushes Redis reaction counters to Postgres
+    ///
+    /// Reactions update hot counters in Redis (see `RedisClient::incr_reaction_count`).
+    /// Every minute, the counters changed (or loaded) since the last run are copied into
+    /// the `post_reaction_count` / `comment_reaction_count` tables.
+    /// Separate from `start_cleanup_task` because it needs Redis, which is
+    /// connected after the database.
+    pub async fn start_reaction_flush_task(&self, redis_client: RedisClient) {
+        let sched = JobScheduler::new().await.unwrap();
+
+        let db_client = self.clone();
+        let job = Job::new_async("0 * * * * *", move |uuid, _l| {
+            let db_client = db_client.clone();
+            let redis_client = redis_client.clone();
+
+            Box::pin(async move {
+                let mut flushed = 0;
+
+                loop {
+                    let target_keys = match redis_client
+                        .pop_dirty_reaction_targets(REACTION_FLUSH_BATCH)
+                        .await
+                    {
+                        Ok(keys) if !keys.is_empty() => keys,
+                        Ok(_) => break,
+                        Err(e) => {
+                            tracing::error!("Reaction flush job {:?} failed: {}", uuid, e);
+                            break;
+                        }
+                    };
+
+                    let targets: Vec<ReactionTarget> = target_keys
+                        .iter()
+                        .filter_map(|key| ReactionTarget::from_key(key))
+                        .collect();
+
+                    let result = match redis_client.get_reaction_counts(&targets).await {
+                        Ok(cached) => {
+                            // Counters that expired in the meantime are skipped,
+                            // Postgres keeps the value of the last flush
+                            let counts: Vec<(ReactionTarget, ReactionType, i64)> = targets
+                                .iter()
+                                .zip(cached)
+                                .filter_map(|(target, counts)| Some((*target, counts?)))
+                                .flat_map(|(target, counts)| {
+                                    counts.into_iter().filter_map(move |(name, count)| {
+                                        Some((target, ReactionType::from_name(&name)?, count))
+                                    })
+                                })
+                                .collect();
+
+                            db_client
+                                .save_reaction_counts(&counts)
+                                .await
+                                .map_err(|e| e.to_string())
+                        }
+                        Err(e) => Err(e.to_string()),
+                    };
+
+                    if let Err(e) = result {
+                        tracing::error!("Reaction flush job {:?} failed: {}", uuid, e);
+                        // Put them back so the next run retries
+                        if let Err(e) = redis_client.mark_reactions_dirty(&target_keys).await {
+                            tracing::error!("Failed to re-mark reaction counters dirty: {}", e);
+                        }
+                        break;
+                    }
+
+                    flushed += targets.len();
+                }
+
+                if flushed > 0 {
+                    tracing::info!(
+                        "Reaction flush job {:?} finished successfully, flushed {} counters",
+                        uuid,
+                        flushed
+                    );
+                }
+            })
+        })
+        .unwrap();
+
+        sched.add(job).await.unwrap();
+        sched.start().await.unwrap();
+    }
+
+    /// Start the jobs that send comment notification emails
+    ///
+    /// `create_comment` only queues notifications (see `enqueue_comment_notifications`),
+    /// so a slow SMTP server never delays posting a comment.
+    /// - Every minute: one email per comment for users with the instant preference
+    /// - Daily at 8 AM: one digest per user with the daily preference,
+    ///   then notifications that will never be sent are pruned
+    ///
+    /// A notification is only marked as sent once its email went out. Due
+    /// notifications are claimed first, so overlapping runs don't send them
+    /// twice, and failed sends are retried once the claim expires (a few
+    /// times at most, see `claim_due_comment_notifications`).
+    pub async fn start_notification_task(&self, config: Arc<Config>) {
+        let sched = JobScheduler::new().await.unwrap();
+
+        let db_client = self.clone();
+        let env = config.clone();
+        let instant_job = Job::new_async("0 * * * * *", move |uuid, _l| {
+            let db_client = db_client.clone();
+            let env = env.clone();
+
+            Box::pin(async move {
+                let notifications = match db_client
+                    .claim_due_comment_notifications(
+                        NotificationFrequency::Instant,
+                        INSTANT_NOTIFICATION_BATCH,
+                    )
+                    .await
+                {
+                    Ok(notifications) => notifications,
+                    Err(e) => {
+                        tracing::error!("Notification job {:?} failed: {}", uuid, e);
+                        return;
+                    }
+                };
+
+                let mut sent = Vec::new();
+                for notification in &notifications {
+                    let (page, url) = unsubscribe_links(&env, notification.recipient_id);
+                    match send_comment_notification_email(
+                        notification,
+                        &env.frontend_url,
+                        &page,
+                        &url,
+                    )
+                    .await
+                    {
+                        Ok(_) => sent.push(notification.id),
+                        Err(e) => {
+                            tracing::error!(
+                                "Failed to send comment notification {}: {}",
+                                notification.id,
+                                e
+                            );
+                        }
+                    }
+                }
+
+                if sent.is_empty() {
+                    return;
+                }
+
+                match db_client.mark_comment_notifications_sent(&sent).await {
+                    Ok(_) => {
+                        tracing::info!(
+                            "Notification job {:?} finished successfully, sent {} emails",
+                            uuid,
+                            sent.len()
+                        );
+                    }
+                    Err(e) => {
+                        tracing::error!("Notification job {:?} failed: {}", uuid, e);
+                    }
+                }
+            })
+        })
+        .unwrap();
+
+        sched.add(instant_job).await.unwrap();
+
+        let db_client = self.clone();
+        let env = config;
+        let digest_job = Job::new_async("0 0 8 * * *", move |uuid, _l| {
+            let db_client = db_client.clone();
+            let env = env.clone();
+
+            Box::pin(async move {
+                match db_client
+                    .claim_due_comment_notifications(
+                        NotificationFrequency::Daily,
+                        DIGEST_NOTIFICATION_BATCH,
+                    )
+                    .await
+                {
+                    Ok(notifications) => {
+                        let mut by_recipient: HashMap<Uuid, Vec<CommentNotification>> =
+                            HashMap::new();
+                        for notification in notifications {
+                            by_recipient
+                                .entry(notification.recipient_id)
+                                .or_default()
+                                .push(notification);
+                        }
+
+                        let mut sent = Vec::new();
+                        for (recipient_id, notifications) in &by_recipient {
+                            let (page, url) = unsubscribe_links(&env, *recipient_id);
+                            match send_comment_digest_email(
+                                notifications,
+                                &env.frontend_url,
+                                &page,
+                                &url,
+                            )
+                            .await
+                            {
+                                Ok(_) => sent.extend(notifications.iter().map(|n| n.id)),
+                                Err(e) => {
+                                    tracing::error!(
+                                        "Failed to send comment digest to {}: {}",
+                                        recipient_id,
+                                        e
+                                    );
+                                }
+                            }
+                        }
+
+                        if !sent.is_empty() {
+                            match db_client.mark_comment_notifications_sent(&sent).await {
+                                Ok(_) => {
+                                    tracing::info!(
+                                        "Digest job {:?} finished successfully, sent {} digests",
+                                        uuid,
+                                        by_recipient.len()
+                                    );
+                                }
+                                Err(e) => {
+                                    tracing::error!("Digest job {:?} failed: {}", uuid, e);
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Digest job {:?} failed: {}", uuid, e);
+                    }
+                }
+
+                match db_client.prune_comment_notifications().await {
+                    Ok(pruned) => {
+                        tracing::info!("Pruned {} comment notifications", pruned);
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to prune comment notifications: {}", e);
+                    }
+                }
+            })
+        })
+        .unwrap();
+
+        sched.add(digest_job).await.unwrap();
+        sched.start().await.unwrap();
+    }
 }
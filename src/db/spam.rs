@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use super::DBClient;
+use crate::models::SpamToken;
+use crate::spam::bayes::tokenize;
+
+pub trait SpamFilterExt {
+    async fn get_spam_model(
+        &self,
+        tokens: &[String],
+    ) -> Result<(i64, i64, Vec<SpamToken>), sqlx::Error>;
+
+    async fn retrain_spam_filter(&self) -> Result<(i64, i64), sqlx::Error>;
+}
+
+impl SpamFilterExt for DBClient {
+    /// Load the corpus size and the statistics of the given tokens
+    ///
+    /// # Returns
+    /// (spam_docs, ham_docs, known tokens)
+    async fn get_spam_model(
+        &self,
+        tokens: &[String],
+    ) -> Result<(i64, i64, Vec<SpamToken>), sqlx::Error> {
+        let corpus = sqlx::query!("SELECT spam_docs, ham_docs FROM spam_corpus")
+            .fetch_one(&self.pool)
+            .await?;
+
+        let known = sqlx::query_as!(
+            SpamToken,
+            r#"
+            SELECT token, spam_count, ham_count
+            FROM spam_token
+            WHERE token = ANY($1)
+            "#,
+            tokens
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok((corpus.spam_docs as i64, corpus.ham_docs as i64, known))
+    }
+
+    /// Rebuild the spam filter from scratch out of the current moderation state
+    ///
+    /// Approved comments are ham, comments marked as spam are spam.
+    /// Rejected comments are left out - rejection is about tone or topic, not spam.
+    /// Rebuilding (instead of updating counts on every decision) means a
+    /// moderator correcting a status fixes the model on the next run.
+    ///
+    /// # Returns
+    /// (spam_docs, ham_docs) the model was trained on
+    async fn retrain_spam_filter(&self) -> Result<(i64, i64), sqlx::Error> {
+        let comments = sqlx::query!(
+            r#"
+            SELECT content, status = 'spam' as "is_spam!"
+            FROM comment
+            WHERE status IN ('approved', 'spam') AND deleted_at IS NULL
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut counts: HashMap<String, (i32, i32)> = HashMap::new();
+        let (mut spam_docs, mut ham_docs) = (0, 0);
+
+        for comment in &comments {
+            if comment.is_spam {
+                spam_docs += 1;
+            } else {
+                ham_docs += 1;
+            }
+
+            for token in tokenize(&comment.content) {
+                let entry = counts.entry(token).or_default();
+                if comment.is_spam {
+                    entry.0 += 1;
+                } else {
+                    entry.1 += 1;
+                }
+            }
+        }
+
+        // Tokens seen only once are never used for scoring, no need to store them
+        counts.retain(|_, (spam, ham)| *spam + *ham > 1);
+
+        let mut tokens = Vec::with_capacity(counts.len());
+        let mut spam_counts = Vec::with_capacity(counts.len());
+        let mut ham_counts = Vec::with_capacity(counts.len());
+        for (token, (spam, ham)) in counts {
+            tokens.push(token);
+            spam_counts.push(spam);
+            ham_counts.push(ham);
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!("DELETE FROM spam_token")
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO spam_token (token, spam_count, ham_count)
+            SELECT * FROM UNNEST($1::text[], $2::int[], $3::int[])
+            "#,
+            &tokens,
+            &spam_counts,
+            &ham_counts
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE spam_corpus
+            SET spam_docs = $1, ham_docs = $2, trained_at = NOW()
+            "#,
+            spam_docs,
+            ham_docs
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok((spam_docs as i64, ham_docs as i64))
+    }
+}
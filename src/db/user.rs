@@ -27,6 +27,11 @@ pub trait UserExt {
 
     async fn get_user_count(&self) -> Result<i64, sqlx::Error>;
 
+    async fn get_user_ids_by_usernames(
+        &self,
+        usernames: &[String],
+    ) -> Result<Vec<(Uuid, String)>, sqlx::Error>;
+
     async fn update_user_name<T: Into<String> + Send>(
         &self,
         user_id: Uuid,
@@ -43,6 +48,8 @@ pub trait UserExt {
 
     async fn update_user_email(&self, user_id: Uuid, new_email: &str) -> Result<User, sqlx::Error>;
 
+    async fn bump_token_version(&self, user_id: Uuid) -> Result<bool, sqlx::Error>;
+
     async fn verifed_token(&self, token: &str) -> Result<(), sqlx::Error>;
 
     async fn add_verifed_token(
@@ -72,26 +79,26 @@ impl UserExt for DBClient {
         if let Some(user_id) = user_id {
             user = sqlx::query_as!(
                 User,
-                r#"SELECT id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole" FROM users WHERE id = $1"#,
+                r#"SELECT id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole" FROM users WHERE id = $1"#,
                 user_id
             ).fetch_optional(&self.pool).await?;
         } else if let Some(username) = username {
             user = sqlx::query_as!(
                 User,
-                r#"SELECT id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole" FROM users WHERE username = $1"#,
+                r#"SELECT id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole" FROM users WHERE username = $1"#,
                 username
             ).fetch_optional(&self.pool).await?;
         } else if let Some(email) = email {
             user = sqlx::query_as!(
                 User,
-                r#"SELECT id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole" FROM users WHERE email = $1"#,
+                r#"SELECT id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole" FROM users WHERE email = $1"#,
                 email
             ).fetch_optional(&self.pool).await?;
         } else if let Some(token) = token {
             user = sqlx::query_as!(
                 User,
                 r#"
-                SELECT id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole" 
+                SELECT id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole" 
                 FROM users 
                 WHERE verification_token = $1"#,
                 token
@@ -108,7 +115,7 @@ impl UserExt for DBClient {
 
         let users = sqlx::query_as!(
             User,
-            r#"SELECT id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole" FROM users 
+            r#"SELECT id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole" FROM users 
             ORDER BY created_at DESC LIMIT $1 OFFSET $2"#,
             limit as i64,
             offset as i64,
@@ -131,7 +138,7 @@ impl UserExt for DBClient {
             r#"
             INSERT INTO users (username, email, password,verification_token, token_expires_at) 
             VALUES ($1, $2, $3, $4, $5) 
-            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole"
+            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole"
             "#,
             username.into(),
             email.into(),
@@ -163,6 +170,26 @@ impl UserExt for DBClient {
         Ok(count.unwrap_or(0))
     }
 
+    /// Resolve usernames (e.g. @mentions) to user ids
+    ///
+    /// Usernames that don't exist are left out.
+    ///
+    /// # Returns
+    /// (id, username) of each existing user
+    async fn get_user_ids_by_usernames(
+        &self,
+        usernames: &[String],
+    ) -> Result<Vec<(Uuid, String)>, sqlx::Error> {
+        let users = sqlx::query!(
+            "SELECT id, username FROM users WHERE username = ANY($1)",
+            usernames
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users.into_iter().map(|u| (u.id, u.username)).collect())
+    }
+
     async fn update_user_name<T: Into<String> + Send>(
         &self,
         user_id: Uuid,
@@ -174,7 +201,7 @@ impl UserExt for DBClient {
             UPDATE users
             SET username = $1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole"
+            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole"
             "#,
             new_username.into(),
             user_id
@@ -195,7 +222,7 @@ impl UserExt for DBClient {
             UPDATE users
             SET role = $1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole"
+            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole"
             "#,
             new_role as UserRole,
             user_id
@@ -213,10 +240,11 @@ impl UserExt for DBClient {
         let user = sqlx::query_as!(
             User,
             r#"
+            WITH revoked AS (DELETE FROM api_token WHERE user_id = $2)
             UPDATE users
-            SET password = $1, updated_at = Now()
+            SET password = $1, token_version = token_version + 1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole"
+            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole"
             "#,
             new_password,
             user_id
@@ -230,10 +258,11 @@ impl UserExt for DBClient {
         let user = sqlx::query_as!(
             User,
             r#"
+            WITH revoked AS (DELETE FROM api_token WHERE user_id = $2)
             UPDATE users
-            SET email = $1, updated_at = Now()
+            SET email = $1, token_version = token_version + 1, updated_at = Now()
             WHERE id = $2
-            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, role as "role: UserRole"
+            RETURNING id, username, email, password, verified, created_at, updated_at, verification_token, token_expires_at, token_version, role as "role: UserRole"
             "#,
             new_email,
             user_id
@@ -243,6 +272,27 @@ impl UserExt for DBClient {
         Ok(user)
     }
 
+    /// Invalidate every access and refresh token of a user, and delete their
+    /// personal access tokens
+    ///
+    /// Changing the password or email does this too.
+    ///
+    /// # Returns
+    /// false if the user doesn't exist
+    async fn bump_token_version(&self, user_id: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            WITH revoked AS (DELETE FROM api_token WHERE user_id = $1)
+            UPDATE users SET token_version = token_version + 1 WHERE id = $1
+            "#,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
     async fn verifed_token(&self, token: &str) -> Result<(), sqlx::Error> {
         let _ = sqlx::query!(
             r#"
@@ -1,7 +1,16 @@
-use crate::models::{User, UserRole};
+use crate::models::{
+    ApiScope, ApiToken, CommentStatus, NotificationFrequency, ReactionType, ReportReason,
+    ReportStatus, User, UserRole,
+};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
 use validator::Validate;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, PublicKeyCredential, RegisterPublicKeyCredential,
+    RequestChallengeResponse,
+};
 
 // DTOs (Data Transfer Objects) define the structure of data exchanged with clients
 // They are separate from database models to control exactly what data is exposed
@@ -143,6 +152,7 @@ pub struct UserListResponseDto {
 pub struct UserLoginResponseDto {
     pub status: String,
     pub access_token: String,
+    pub csrf_token: String, // Send back in `X-CSRF-Token` on cookie-authenticated writes
     pub username: String,
 }
 
@@ -151,6 +161,27 @@ pub struct UserLoginResponseDto {
 pub struct RefreshResponseDto {
     pub status: String,
     pub access_token: String,
+    pub csrf_token: String,
+}
+
+/// Signed-in device, as listed to the user
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionDto {
+    pub id: String,
+    #[serde(rename = "userAgent")]
+    pub user_agent: String,
+    pub ip: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: DateTime<Utc>,
+    pub current: bool, // The device making this request
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionListResponseDto {
+    pub status: String,
+    pub data: Vec<SessionDto>,
 }
 
 /// Generic success response
@@ -201,6 +232,310 @@ pub struct UserPasswordUpdateDto {
     pub old_password: String,
 }
 
+// ============================================================================
+// Two-Factor Authentication DTOs
+// ============================================================================
+
+/// Login response when the password was right but a second factor is needed
+///
+/// No cookies are set yet, the token is exchanged for them at `/auth/login/mfa`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaChallengeResponseDto {
+    pub status: String, // Always "mfa_required"
+    #[serde(rename = "mfaToken")]
+    pub mfa_token: String,
+    #[serde(rename = "expiresIn")]
+    pub expires_in: i64, // Seconds
+    pub methods: Vec<String>, // Second factors the user has: "totp", "passkey"
+}
+
+/// Second login step: a code from the authenticator app, a recovery code,
+/// or the answer of a passkey (options from `/auth/login/mfa/passkey`)
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct LoginMfaDto {
+    #[validate(length(min = 1, message = "MFA token is required"))]
+    #[serde(rename = "mfaToken")]
+    pub mfa_token: String,
+
+    #[validate(length(max = 20, message = "Code is too long"))]
+    pub code: Option<String>,
+
+    #[validate(length(max = 20, message = "Recovery code is too long"))]
+    #[serde(rename = "recoveryCode")]
+    pub recovery_code: Option<String>,
+
+    pub credential: Option<PublicKeyCredential>,
+}
+
+/// Request for the passkey options of a second login step
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct MfaTokenDto {
+    #[validate(length(min = 1, message = "MFA token is required"))]
+    #[serde(rename = "mfaToken")]
+    pub mfa_token: String,
+}
+
+/// Code from the authenticator app, to confirm enrolment or sensitive changes
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct TotpCodeDto {
+    #[validate(length(min = 1, max = 20, message = "Code is required"))]
+    pub code: String,
+}
+
+/// Turning two-factor authentication off needs the password and a second factor
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct DisableTotpDto {
+    #[validate(length(min = 6, message = "Password must be at least 6 characters"))]
+    pub password: String,
+
+    #[validate(length(max = 20, message = "Code is too long"))]
+    pub code: Option<String>,
+
+    #[validate(length(max = 20, message = "Recovery code is too long"))]
+    #[serde(rename = "recoveryCode")]
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaStatusDto {
+    #[serde(rename = "totpEnabled")]
+    pub totp_enabled: bool,
+    #[serde(rename = "enabledAt")]
+    pub enabled_at: Option<DateTime<Utc>>,
+    #[serde(rename = "recoveryCodesLeft")]
+    pub recovery_codes_left: i64,
+    pub passkeys: i64,
+    pub required: bool, // Required for the user's role
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaStatusResponseDto {
+    pub status: String,
+    pub data: MfaStatusDto,
+}
+
+/// Secret of a pending enrolment, shown once (as a QR code of `otpauthUri`)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpSetupResponseDto {
+    pub status: String,
+    pub secret: String, // For manual entry
+    #[serde(rename = "otpauthUri")]
+    pub otpauth_uri: String,
+}
+
+/// Recovery codes in plain text, shown once
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodesResponseDto {
+    pub status: String,
+    #[serde(rename = "recoveryCodes")]
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct MfaPolicyDto {
+    pub role: UserRole,
+    pub required: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaPolicyResponseDto {
+    pub status: String,
+    #[serde(rename = "requiredRoles")]
+    pub required_roles: Vec<UserRole>,
+}
+
+// ============================================================================
+// Passkey DTOs
+// ============================================================================
+
+/// Options for `navigator.credentials.create()`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyRegistrationOptionsDto {
+    pub status: String,
+    pub options: CreationChallengeResponse,
+}
+
+/// Answer of the browser to the registration options
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct PasskeyRegistrationDto {
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "Name must be between 1 and 100 characters"
+    ))]
+    pub name: String,
+
+    pub credential: RegisterPublicKeyCredential,
+}
+
+/// Options for `navigator.credentials.get()`
+///
+/// `ceremonyId` identifies the login attempt and is sent back with the answer
+/// (not set for the second login step, which uses the MFA token).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyLoginOptionsDto {
+    pub status: String,
+    #[serde(rename = "ceremonyId", skip_serializing_if = "Option::is_none")]
+    pub ceremony_id: Option<String>,
+    pub options: RequestChallengeResponse,
+}
+
+/// Answer of the browser to the login options (passwordless login)
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct PasskeyLoginDto {
+    #[validate(length(min = 1, message = "Ceremony id is required"))]
+    #[serde(rename = "ceremonyId")]
+    pub ceremony_id: String,
+
+    pub credential: PublicKeyCredential,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyDto {
+    pub id: i64,
+    pub name: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyListResponseDto {
+    pub status: String,
+    pub data: Vec<PasskeyDto>,
+}
+
+// ============================================================================
+// Social Login DTOs
+// ============================================================================
+
+/// Query parameters the provider redirects back with
+///
+/// Either `code` and `state`, or `error` if the user declined or the provider failed.
+#[derive(Debug, Deserialize)]
+pub struct OAuthCallbackQueryDto {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OAuthProvidersResponseDto {
+    pub status: String,
+    pub providers: Vec<String>,
+}
+
+/// External account linked to the user
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentityDto {
+    pub id: i64,
+    pub provider: String,
+    pub email: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentityListResponseDto {
+    pub status: String,
+    pub data: Vec<IdentityDto>,
+}
+
+// ============================================================================
+// API Token DTOs
+// ============================================================================
+
+/// Create a personal access token
+#[derive(Validate, Debug, Clone, Serialize, Deserialize)]
+pub struct CreateApiTokenDto {
+    #[validate(length(min = 1, max = 100, message = "Name must be 1-100 characters"))]
+    pub name: String,
+
+    #[validate(length(min = 1, message = "At least one scope is required"))]
+    pub scopes: Vec<ApiScope>,
+
+    #[serde(rename = "expiresInDays")]
+    #[validate(range(min = 1, max = 365, message = "Expiry must be 1-365 days"))]
+    pub expires_in_days: Option<i64>, // None = never expires
+}
+
+/// Personal access token, without the secret
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiTokenDto {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String, // First characters of the token, to tell tokens apart
+    pub scopes: Vec<ApiScope>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiTokenDto {
+    /// Convert a stored token, dropping scope names this version doesn't know
+    pub fn from_token(token: &ApiToken) -> Self {
+        ApiTokenDto {
+            id: token.id,
+            name: token.name.to_owned(),
+            prefix: token.token_prefix.to_owned(),
+            scopes: token.scopes(),
+            expires_at: token.expires_at,
+            last_used_at: token.last_used_at,
+            created_at: token.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiTokenListResponseDto {
+    pub status: String,
+    pub data: Vec<ApiTokenDto>,
+}
+
+/// Response to creating a token, the only time the token itself is returned
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiTokenCreatedResponseDto {
+    pub status: String,
+    pub token: String,
+    pub data: ApiTokenDto,
+}
+
+// ============================================================================
+// Account Lockout DTOs
+// ============================================================================
+
+/// Token from the unlock link, sent by the frontend page the link opens
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AccountUnlockDto {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+}
+
+/// Locked account, as listed to admins
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountLockDto {
+    #[serde(rename = "userId")]
+    pub user_id: Uuid,
+    pub username: Option<String>, // None if the user was deleted since
+    #[serde(rename = "lockedUntil")]
+    pub locked_until: DateTime<Utc>,
+    pub failures: u32,
+    #[serde(rename = "lastIp")]
+    pub last_ip: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountLockListResponseDto {
+    pub status: String,
+    pub data: Vec<AccountLockDto>,
+}
+
 // ============================================================================
 // Email Verification & Password Reset DTOs
 // ============================================================================
@@ -211,12 +546,36 @@ pub struct VerifyEmailQueryDto {
     pub token: String,
 }
 
+/// Token from an email change link (confirmation or revert), sent by the frontend page the link opens
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EmailChangeTokenDto {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+}
+
 #[derive(Deserialize, Serialize, Validate, Debug, Clone)]
 pub struct ForgotPasswordRequestDto {
     #[validate(length(min = 1, message = "Identifier is required"))]
     pub identifier: String,
 }
 
+/// Request a passwordless login link by email
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MagicLinkRequestDto {
+    #[validate(
+        length(min = 1, message = "Email is required"),
+        email(message = "Email is invalid")
+    )]
+    pub email: String,
+}
+
+/// Token from the login link, sent by the frontend page the link opens
+#[derive(Validate, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MagicLinkLoginDto {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Validate, Clone)]
 pub struct ResetPasswordRequestDto {
     #[validate(length(min = 1, message = "Token is required."))]
@@ -303,7 +662,7 @@ pub struct PostPaginationDto {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PostsPaginationResponseDto {
     pub status: String,
-    pub data: Vec<PostPaginationDto>,
+    pub data: Vec<WithReactions<PostPaginationDto>>,
     pub pagination: Option<PaginationDto>,
 }
 
@@ -311,7 +670,7 @@ pub struct PostsPaginationResponseDto {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PostResponseDto {
     pub status: String,
-    pub data: PostDto,
+    pub data: WithReactions<PostDto>,
 }
 
 /// Allowed languages for queries
@@ -345,10 +704,13 @@ pub struct PostsQueryParams {
 pub struct InputcommentRequest {
     #[validate(length(
         min = 1,
-        max = 1000,
-        message = "Content must be between 1 and 1000 characters"
+        max = 5000,
+        message = "Content must be between 1 and 5000 characters"
     ))]
-    pub content: String,
+    pub content: String, // Markdown (restricted subset, see utils::comment_content)
+
+    #[validate(range(min = 1))]
+    pub parent_id: Option<i32>, // Comment being replied to (create only, ignored on edit)
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -372,13 +734,38 @@ fn validate_sort(sort: &String) -> Result<(), validator::ValidationError> {
     }
 }
 
+/// Comment as returned to clients
+///
+/// Lists are flattened threads ordered by path: each top-level comment is
+/// followed by its replies (depth-first), `depth` tells the client how far to indent.
+/// Deleted comments that still have replies come back with `deleted: true`
+/// and "[deleted]" in place of the author and content. The same goes for
+/// comments removed by a moderator.
+/// `status` is "pending" when a new comment is held for moderation.
+/// `content` is the Markdown source (for editing), `contentHtml` the sanitized
+/// HTML ready for display, with `@mentions` of existing users linked to their
+/// profile; `mentions` lists those users.
+/// `edited` / `editCount` tell whether (and how often) the text was changed.
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct CommentDto {
     pub id: i32,
     #[serde(rename = "userUsername")]
     pub user_username: String,
     pub post_id: i32,
+    #[serde(rename = "parentId")]
+    pub parent_id: Option<i32>,
+    pub depth: i32,
     pub content: String,
+    #[serde(rename = "contentHtml")]
+    pub content_html: String,
+    pub mentions: Vec<String>, // Usernames of mentioned users
+    #[serde(rename = "replyCount")]
+    pub reply_count: i64, // Number of direct replies
+    pub deleted: bool,
+    pub edited: bool,
+    #[serde(rename = "editCount")]
+    pub edit_count: i32,
+    pub status: CommentStatus,
     #[serde(rename = "createdAt")]
     pub created_at: DateTime<Utc>,
     #[serde(rename = "updatedAt")]
@@ -388,14 +775,279 @@ pub struct CommentDto {
 #[derive(Debug, Serialize)]
 pub struct CommentListResponse {
     pub status: String,
-    pub data: Vec<CommentDto>,
+    pub data: Vec<WithReactions<CommentDto>>,
     pub pagination: PaginationDto,
 }
 
 #[derive(Debug, Serialize)]
 pub struct SinglecommentResponse {
     pub status: String,
-    pub data: CommentDto,
+    pub data: WithReactions<CommentDto>,
+}
+
+// ============================================================================
+// Reaction DTOs
+// ============================================================================
+
+/// Reaction counts of a post or comment, plus the viewer's own reactions
+///
+/// Example: `{ "counts": { "like": 12, "insightful": 3 }, "mine": ["like"] }`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ReactionSummaryDto {
+    pub counts: HashMap<ReactionType, i64>, // Types with no reactions are left out
+    pub mine: Vec<ReactionType>,            // Always empty for anonymous viewers
+}
+
+/// A post or comment together with its reactions
+///
+/// Flattened: clients get the usual fields plus a `reactions` object.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WithReactions<T> {
+    #[serde(flatten)]
+    pub item: T,
+    pub reactions: ReactionSummaryDto,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReactionResponseDto {
+    pub status: String,
+    pub data: ReactionSummaryDto,
+}
+
+// ============================================================================
+// Notification DTOs
+// ============================================================================
+
+/// Email notification preferences of the current user
+///
+/// Example: `{ "commentNotifications": "daily" }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationPreferencesDto {
+    #[serde(rename = "commentNotifications")]
+    pub comment_notifications: NotificationFrequency,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotificationPreferencesResponseDto {
+    pub status: String,
+    pub data: NotificationPreferencesDto,
+}
+
+/// Signed one-click unsubscribe link: `?user=<uuid>&sig=<hex>`
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeQuery {
+    pub user: Uuid,
+    pub sig: String,
+}
+
+// ============================================================================
+// Comment Moderation DTOs
+// ============================================================================
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ModerationQueueQuery {
+    pub status: Option<CommentStatus>, // Defaults to pending
+
+    #[validate(range(min = 1, message = "Page must be greater than 0"))]
+    pub page: Option<i32>,
+
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<i32>,
+}
+
+/// Set the status of a single comment
+#[derive(Debug, Deserialize, Validate)]
+pub struct ModerateCommentDto {
+    pub status: CommentStatus,
+
+    #[validate(length(max = 500, message = "Reason must be at most 500 characters"))]
+    pub reason: Option<String>,
+}
+
+/// Set the status of several comments at once (e.g. bulk approve)
+#[derive(Debug, Deserialize, Validate)]
+pub struct BulkModerateCommentsDto {
+    #[validate(length(min = 1, max = 100, message = "Between 1 and 100 comment ids required"))]
+    pub ids: Vec<i32>,
+
+    pub status: CommentStatus,
+
+    #[validate(length(max = 500, message = "Reason must be at most 500 characters"))]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ModerationDeleteQuery {
+    #[validate(length(max = 500, message = "Reason must be at most 500 characters"))]
+    pub reason: Option<String>,
+}
+
+/// Comment as seen by moderators: real author and content, whatever the status
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ModerationCommentDto {
+    pub id: i32,
+    #[serde(rename = "postId")]
+    pub post_id: i32,
+    #[serde(rename = "postTitle")]
+    pub post_title: String,
+    #[serde(rename = "parentId")]
+    pub parent_id: Option<i32>,
+    #[serde(rename = "userUsername")]
+    pub user_username: String,
+    pub content: String,
+    pub status: CommentStatus,
+    pub deleted: bool,
+    #[serde(rename = "editCount")]
+    pub edit_count: i32, // See the history endpoint for previous versions
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModerationQueueResponseDto {
+    pub status: String,
+    pub data: Vec<ModerationCommentDto>,
+    pub pagination: PaginationDto,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ModerationLogQuery {
+    pub comment_id: Option<i32>, // Only entries for this comment
+
+    #[validate(range(min = 1, message = "Page must be greater than 0"))]
+    pub page: Option<i32>,
+
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<i32>,
+}
+
+/// Audit trail entry: who moderated which comment, and how
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ModerationLogDto {
+    pub id: i64,
+    #[serde(rename = "commentId")]
+    pub comment_id: i32,
+    #[serde(rename = "moderatorUsername")]
+    pub moderator_username: Option<String>, // None for automatic decisions or deleted moderators
+    pub action: String, // "status_change", "delete" or "classify" (automatic)
+    #[serde(rename = "previousStatus")]
+    pub previous_status: Option<CommentStatus>,
+    #[serde(rename = "newStatus")]
+    pub new_status: Option<CommentStatus>,
+    pub reason: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModerationLogResponseDto {
+    pub status: String,
+    pub data: Vec<ModerationLogDto>,
+    pub pagination: PaginationDto,
+}
+
+/// One version of a comment in its edit history
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommentVersionDto {
+    pub content: String,
+    #[serde(rename = "contentHtml")]
+    pub content_html: String,
+    #[serde(rename = "writtenAt")]
+    pub written_at: DateTime<Utc>,
+    #[serde(rename = "replacedAt")]
+    pub replaced_at: Option<DateTime<Utc>>, // None for the current version
+}
+
+/// Edit history of a comment, oldest version first, the current one last
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommentHistoryDto {
+    #[serde(rename = "commentId")]
+    pub comment_id: i32,
+    #[serde(rename = "editCount")]
+    pub edit_count: i32,
+    pub versions: Vec<CommentVersionDto>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommentHistoryResponseDto {
+    pub status: String,
+    pub data: CommentHistoryDto,
+}
+
+// ============================================================================
+// Report DTOs
+// ============================================================================
+
+/// Report a post or comment
+///
+/// Example: `{ "reason": "other", "details": "Copied from my blog" }`
+#[derive(Debug, Deserialize, Validate)]
+pub struct ReportContentDto {
+    pub reason: ReportReason,
+
+    #[validate(length(max = 1000, message = "Details must be at most 1000 characters"))]
+    pub details: Option<String>, // Required when the reason is "other"
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ReportQuery {
+    pub status: Option<ReportStatus>, // Defaults to open
+
+    #[validate(range(min = 1, message = "Page must be greater than 0"))]
+    pub page: Option<i32>,
+
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<i32>,
+}
+
+/// Resolve all open reports of the reported post/comment
+///
+/// `upheld` takes the content down (comment rejected, post hidden),
+/// `dismissed` keeps it and restores it if the reports had hidden it.
+#[derive(Debug, Deserialize, Validate)]
+pub struct ResolveReportDto {
+    pub status: ReportStatus,
+
+    #[validate(length(max = 500, message = "Reason must be at most 500 characters"))]
+    pub reason: Option<String>,
+}
+
+/// Report as seen by admins, including who filed it
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ReportDto {
+    pub id: i64,
+    #[serde(rename = "targetType")]
+    pub target_type: String, // "post" or "comment"
+    #[serde(rename = "postId")]
+    pub post_id: i32, // For comments: the post they belong to
+    #[serde(rename = "postTitle")]
+    pub post_title: String,
+    #[serde(rename = "commentId")]
+    pub comment_id: Option<i32>,
+    #[serde(rename = "commentContent")]
+    pub comment_content: Option<String>,
+    pub reason: ReportReason,
+    pub details: Option<String>,
+    pub status: ReportStatus,
+    #[serde(rename = "reporterUsername")]
+    pub reporter_username: String,
+    #[serde(rename = "openReports")]
+    pub open_reports: i64, // Open reports of the same post/comment, this one included
+    #[serde(rename = "resolvedByUsername")]
+    pub resolved_by_username: Option<String>,
+    #[serde(rename = "resolvedAt")]
+    pub resolved_at: Option<DateTime<Utc>>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportListResponseDto {
+    pub status: String,
+    pub data: Vec<ReportDto>,
+    pub pagination: PaginationDto,
 }
 
 // ============================================================================
@@ -418,10 +1070,70 @@ pub struct LLMReqeustTextInput {
     pub input: String,
 }
 
+/// One encoded file generated from an uploaded image
+#[derive(Serialize)]
+pub struct ImageVariantDto {
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+}
+
 /// Image upload response
+///
+/// `location` stays the full-size image so editors that only understand
+/// `{ location }` keep working; `variants` lets the editor build `srcset`/`<picture>`.
 #[derive(Serialize)]
 pub struct UploadResponse {
     pub location: String, // URL of uploaded image
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String, // Placeholder shown while the image loads
+    pub variants: Vec<ImageVariantDto>,
+}
+
+// ============================================================================
+// Media Library DTOs
+// ============================================================================
+
+/// Query parameters for the admin media listing
+#[derive(Debug, Deserialize, Validate)]
+pub struct MediaQueryParams {
+    #[validate(range(min = 1))]
+    pub page: Option<i32>,
+
+    #[validate(range(min = 1, max = 100))]
+    pub limit: Option<i32>,
+
+    pub orphaned: Option<bool>, // Only media not referenced by any post
+}
+
+/// Media library entry (admin view)
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct MediaDto {
+    pub id: Uuid,
+    pub url: String,
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+    pub width: i32,
+    pub height: i32,
+    #[serde(rename = "sizeBytes")]
+    pub size_bytes: i64,
+    pub blurhash: String,
+    #[serde(rename = "uploaderUsername")]
+    pub uploader_username: Option<String>,
+    #[serde(rename = "referenceCount")]
+    pub reference_count: i64, // Number of posts using this media
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MediaListResponseDto {
+    pub status: String,
+    pub data: Vec<MediaDto>,
+    pub pagination: PaginationDto,
 }
 
 /// Newsletter subscription request
@@ -88,6 +88,9 @@ pub enum ErrorMessage {
 
     // Authorization errors
     PermissionDenied,
+    MfaSetupRequired,
+    TokenScopeMissing,
+    CsrfTokenInvalid,
 
     // User management errors
     UserNoLongerExist,
@@ -119,6 +122,15 @@ impl fmt::Display for ErrorMessage {
             ErrorMessage::PermissionDenied => {
                 "You are not allowed to perform this action".to_string()
             }
+            ErrorMessage::MfaSetupRequired => {
+                "Two-factor authentication must be enabled for this action".to_string()
+            }
+            ErrorMessage::TokenScopeMissing => {
+                "This API token is not allowed to perform this action".to_string()
+            }
+            ErrorMessage::CsrfTokenInvalid => {
+                "Missing or invalid CSRF token (X-CSRF-Token header)".to_string()
+            }
             ErrorMessage::UserNotAuthenticated => {
                 "Authentication required. Please log in.".to_string()
             }
@@ -1,7 +1,18 @@
+pub mod api_token;
 pub mod auth;
 pub mod users;
 
 pub mod newsletter;
 pub mod post;
 pub mod comment;
-pub mod search;
\ No newline at end of file
+pub mod lockout;
+pub mod media;
+pub mod mfa;
+pub mod moderation;
+pub mod notification;
+pub mod oauth;
+pub mod passkey;
+pub mod reaction;
+pub mod report;
+pub mod search;
+pub mod well_known;
\ No newline at end of file
@@ -0,0 +1,117 @@
+use crate::AppState;
+use crate::db::ApiTokenExt;
+use crate::dtos::{
+    ApiTokenCreatedResponseDto, ApiTokenDto, ApiTokenListResponseDto, CreateApiTokenDto, Response,
+};
+use crate::error::{ErrorMessage, HttpError};
+use crate::middleware::JWTAuthMiddleware;
+use crate::utils::{api_token, token_hash};
+use axum::Extension;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use chrono::{Duration, Utc};
+use tracing::instrument;
+use validator::Validate;
+
+/// Personal access tokens of the current user, expired ones included
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn get_api_tokens(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let tokens = app_state
+        .db_client
+        .get_user_api_tokens(jwt.user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting API tokens: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!("get_api_tokens successful");
+    Ok(Json(ApiTokenListResponseDto {
+        status: "success".to_string(),
+        data: tokens.iter().map(ApiTokenDto::from_token).collect(),
+    }))
+}
+
+/// Create a personal access token
+///
+/// The token is only stored as a hash, so this is the only response that
+/// contains it.
+#[instrument(skip(app_state, jwt, body), fields(username = %jwt.user.username))]
+pub async fn create_api_token(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<CreateApiTokenDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid create_api_token input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let mut scopes: Vec<String> = body.scopes.iter().map(|s| s.to_str().to_string()).collect();
+    scopes.sort();
+    scopes.dedup();
+
+    let expires_at = body
+        .expires_in_days
+        .map(|days| Utc::now() + Duration::days(days));
+
+    let token = api_token::generate();
+
+    let created = app_state
+        .db_client
+        .create_api_token(
+            jwt.user.id,
+            body.name.trim(),
+            &token_hash::hash(&token),
+            &api_token::display_prefix(&token),
+            &scopes,
+            expires_at,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, creating API token: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!(token_id = created.id, "create_api_token successful");
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiTokenCreatedResponseDto {
+            status: "success".to_string(),
+            token,
+            data: ApiTokenDto::from_token(&created),
+        }),
+    ))
+}
+
+/// Revoke a personal access token, it stops working immediately
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn delete_api_token(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Path(token_id): Path<i64>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state
+        .db_client
+        .delete_api_token(jwt.user.id, token_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, deleting API token: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if !deleted {
+        tracing::error!(token_id, "API token not found");
+        return Err(HttpError::not_found("API token not found".to_string()));
+    }
+
+    tracing::info!("delete_api_token successful");
+    Ok(Json(Response {
+        status: "success",
+        message: "API token revoked".to_string(),
+    }))
+}
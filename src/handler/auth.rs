@@ -1,27 +1,71 @@
 use crate::{
     AppState,
-    db::UserExt,
+    db::{EmailChangeExt, MfaExt, PasskeyExt, UserExt},
     dtos::{
-        ForgotPasswordRequestDto, LoginUserDto, RefreshResponseDto, RegisterUserDto,
-        ResetPasswordRequestDto, Response, UserLoginResponseDto, VerifyEmailQueryDto,
+        AccountUnlockDto, EmailChangeTokenDto, ForgotPasswordRequestDto, LoginMfaDto, LoginUserDto,
+        MagicLinkLoginDto, MagicLinkRequestDto, MfaChallengeResponseDto, RefreshResponseDto,
+        RegisterUserDto, ResetPasswordRequestDto, Response, UserLoginResponseDto,
+        VerifyEmailQueryDto,
     },
     error::{ErrorMessage, HttpError},
-    mail::mails::{send_forgot_password_email, send_verification_email, send_welcome_email},
-    utils::{password, token},
+    handler::{
+        mfa::check_second_factor,
+        oauth::{get_oauth_providers, oauth_callback, start_oauth_link, start_oauth_login},
+        passkey::{
+            finish_passkey_login, finish_passkey_registration, start_passkey_login,
+            start_passkey_mfa, start_passkey_registration, verify_passkey_mfa,
+        },
+    },
+    mail::mails::{
+        send_account_locked_email, send_forgot_password_email, send_magic_link_email,
+        send_verification_email, send_welcome_email,
+    },
+    middleware::{CSRF_COOKIE, auth, role_check},
+    models::{AccountLock, MfaChallenge, Session, User, UserRole},
+    redisdb::TokenRotation,
+    utils::{email_change, password, signature, token, token_hash},
 };
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 use axum::{
     Json, Router,
     extract::{Query, State},
     http::{HeaderMap, StatusCode, header},
+    middleware,
     response::IntoResponse,
     routing::{get, post},
 };
-use axum_extra::extract::cookie::{Cookie, CookieJar};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
 use chrono::{Duration, Utc};
+use std::net::IpAddr;
 use validator::Validate;
 
 use axum_client_ip::ClientIp;
 
+/// Longest User-Agent kept for the session list
+const MAX_USER_AGENT_CHARS: usize = 255;
+
+/// Seconds a user has to enter the second factor after the password
+const MFA_CHALLENGE_TTL_SECS: i64 = 300;
+
+/// Attempts (codes or passkey prompts) per MFA token
+const MFA_MAX_ATTEMPTS: u32 = 5;
+
+/// Seconds a magic login link is valid
+const MAGIC_LINK_TTL_SECS: i64 = 900;
+
+/// Identifier invalid magic link tokens are counted under (per IP)
+const MAGIC_LINK_IDENTIFIER: &str = "magic-link";
+
+/// Failed passwords, or failed second factors (within 24 hours) that lock
+/// an account; every further multiple of it locks the account again
+const ACCOUNT_LOCK_THRESHOLD: u32 = 5;
+
+/// Seconds of the first lock of an account, doubled for every further lock
+const ACCOUNT_LOCK_BASE_SECS: i64 = 900;
+
+/// Longest an account is locked at once
+const ACCOUNT_LOCK_MAX_SECS: i64 = 86400;
+
 use tracing::instrument;
 
 pub fn auth_handler(app_state: AppState) -> Router<AppState> {
@@ -29,11 +73,59 @@ pub fn auth_handler(app_state: AppState) -> Router<AppState> {
         .route("/register", post(register))
         .route(
             "/login",
-            post(login).layer(app_state.ip_extraction.into_extension()),
+            post(login).layer(app_state.ip_extraction.clone().into_extension()),
+        )
+        .route("/login/mfa", post(login_mfa))
+        .route("/login/mfa/passkey", post(start_passkey_mfa))
+        .route(
+            "/magic-link",
+            post(request_magic_link).layer(app_state.ip_extraction.clone().into_extension()),
+        )
+        .route(
+            "/magic-link/login",
+            post(magic_link_login).layer(app_state.ip_extraction.clone().into_extension()),
+        )
+        .route("/passkey/login/start", post(start_passkey_login))
+        .route(
+            "/passkey/login/finish",
+            post(finish_passkey_login).layer(app_state.ip_extraction.clone().into_extension()),
+        )
+        .route(
+            "/passkey/register/start",
+            post(start_passkey_registration)
+                .route_layer(middleware::from_fn(|req, next| {
+                    role_check(req, next, vec![UserRole::Admin, UserRole::User], None)
+                }))
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/passkey/register/finish",
+            post(finish_passkey_registration)
+                .route_layer(middleware::from_fn(|req, next| {
+                    role_check(req, next, vec![UserRole::Admin, UserRole::User], None)
+                }))
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route("/oauth/providers", get(get_oauth_providers))
+        .route("/oauth/{provider}", get(start_oauth_login))
+        .route(
+            "/oauth/{provider}/callback",
+            get(oauth_callback).layer(app_state.ip_extraction.clone().into_extension()),
+        )
+        .route(
+            "/oauth/{provider}/link",
+            get(start_oauth_link)
+                .route_layer(middleware::from_fn(|req, next| {
+                    role_check(req, next, vec![UserRole::Admin, UserRole::User], None)
+                }))
+                .route_layer(middleware::from_fn_with_state(app_state, auth)),
         )
         .route("/verify", get(verify_email))
+        .route("/email-change/confirm", post(confirm_email_change))
+        .route("/email-change/revert", post(revert_email_change))
         .route("/forgot-password", post(forgot_password))
         .route("/reset-password", post(reset_password))
+        .route("/unlock", post(unlock_account))
         .route("/refresh", post(refresh))
 }
 
@@ -115,6 +207,7 @@ pub async fn register(
 pub async fn login(
     ClientIp(ip): ClientIp,
     State(app_state): State<AppState>,
+    headers: HeaderMap,
     Json(body): Json<LoginUserDto>,
 ) -> Result<impl IntoResponse, HttpError> {
     let ip_attempts = app_state
@@ -146,14 +239,17 @@ pub async fn login(
         return Err(HttpError::server_error("Login failed"));
     }
 
-    match authenticate_process(State(app_state.clone()), &body).await {
-        Ok(response) => {
-            if let Err(e) = app_state
-                .redis_client
-                .delete_identifier_ip_attempts(ip, &body.identifier)
-                .await
-            {
-                tracing::warn!("Failed to clear rate limit: {:?}", e);
+    match authenticate_process(State(app_state.clone()), &body, ip, user_agent(&headers)).await {
+        Ok((response, session_started)) => {
+            // With a second factor, the counters are only cleared once it passes (see login_mfa)
+            if session_started {
+                let cleared = app_state
+                    .redis_client
+                    .delete_identifier_ip_attempts(ip, &body.identifier)
+                    .await;
+                if let Err(e) = cleared {
+                    tracing::warn!("Failed to clear rate limit: {:?}", e);
+                }
             }
             tracing::info!(identifier = %body.identifier, ip = %ip, "Login Successful");
             Ok(response)
@@ -171,10 +267,23 @@ pub async fn login(
     }
 }
 
+/// User-Agent of the request, truncated, as kept for the session list
+pub(crate) fn user_agent(headers: &HeaderMap) -> String {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_USER_AGENT_CHARS)
+        .collect()
+}
+
 async fn authenticate_process(
     State(app_state): State<AppState>,
     body: &LoginUserDto,
-) -> Result<impl IntoResponse + use<>, HttpError> {
+    ip: IpAddr,
+    user_agent: String,
+) -> Result<(axum::response::Response, bool), HttpError> {
     body.validate().map_err(|e| {
         tracing::error!("Invalid login input: {}", e);
         HttpError::server_error("Login failed")
@@ -205,83 +314,773 @@ async fn authenticate_process(
         HttpError::server_error("Login failed")
     })?;
 
+    // While locked, the password isn't even checked: guesses can't be confirmed
+    check_account_lock(&app_state, &user).await?;
+
     let password_matched = password::compare(&body.password, &user.password).map_err(|e| {
         tracing::error!("Password error: {}", e);
         HttpError::server_error("Login failed")
     })?;
 
-    if password_matched {
-        let access_token = token::create_token(
-            &user.id.to_string(),
-            &app_state.env.jwt_secret.as_bytes(),
-            app_state.env.jwt_maxage,
-        )
+    if !password_matched {
+        tracing::error!("password mismatch");
+        register_failed_login(&app_state, &user, &ip.to_string(), FailedFactor::Password).await;
+        return Err(HttpError::server_error("Login failed"));
+    }
+
+    let (response, session_started) =
+        complete_login(&app_state, &user, ip.to_string(), user_agent).await?;
+    if session_started {
+        let cleared = app_state
+            .redis_client
+            .delete_account_failures(user.id)
+            .await;
+        if let Err(e) = cleared {
+            tracing::warn!("Failed to clear account failures: {:?}", e);
+        }
+    }
+    tracing::info!("authenticate_process succesful");
+    Ok((response, session_started))
+}
+
+/// Reject logins (and second factor checks) to a locked account
+///
+/// With the same generic failure as a wrong password or an unknown
+/// identifier, so locking an account doesn't confirm that it exists.
+/// The owner learns about the lock from the email `register_failed_login` sends.
+pub(crate) async fn check_account_lock(app_state: &AppState, user: &User) -> Result<(), HttpError> {
+    let lock = app_state
+        .redis_client
+        .get_account_lock(user.id)
+        .await
         .map_err(|e| {
-            tracing::error!("Access token creation error: {}", e);
+            tracing::error!("RedisDB error, getting account lock: {}", e);
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
-        let access_cookie = Cookie::build(("access_token", access_token.clone()))
-            .path("/")
-            .http_only(true)
-            .secure(true)
-            .build();
+    match lock {
+        Some(lock) => {
+            tracing::warn!(user_id = %user.id, locked_until = %lock.locked_until, "Login to a locked account");
+            Err(HttpError::server_error("Login failed"))
+        }
+        None => Ok(()),
+    }
+}
 
-        let response = axum::response::Json(UserLoginResponseDto {
-            status: "success".to_string(),
-            access_token,
-            username: user.username,
-        });
+/// What a failed login attempt got wrong, see `register_failed_login`
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FailedFactor {
+    Password,
+    SecondFactor, // TOTP or recovery code
+}
 
-        let refresh_token = token::create_token(
-            &user.id.to_string(),
-            &app_state.env.jwt_secret.as_bytes(),
-            app_state.env.refresh_token_maxage,
-        )
+/// Count a wrong password or second factor against the account, and lock it
+/// every `ACCOUNT_LOCK_THRESHOLD` failures of that kind
+///
+/// Locks are progressive: 15 minutes, then 30, 60... up to a day. The owner
+/// is emailed about the failed logins, with a link that lifts the lock.
+/// Errors are only logged, the login fails either way.
+pub(crate) async fn register_failed_login(
+    app_state: &AppState,
+    user: &User,
+    ip: &str,
+    factor: FailedFactor,
+) {
+    let counted = match factor {
+        FailedFactor::Password => {
+            app_state
+                .redis_client
+                .increment_account_failures(user.id)
+                .await
+        }
+        FailedFactor::SecondFactor => {
+            app_state
+                .redis_client
+                .increment_account_mfa_failures(user.id)
+                .await
+        }
+    };
+    let failures = match counted {
+        Ok(failures) => failures,
+        Err(e) => {
+            tracing::warn!("Failed to increment account failures: {:?}", e);
+            return;
+        }
+    };
+
+    if failures % ACCOUNT_LOCK_THRESHOLD != 0 {
+        return;
+    }
+
+    let previous_locks = (failures / ACCOUNT_LOCK_THRESHOLD - 1).min(16);
+    let lock_secs = (ACCOUNT_LOCK_BASE_SECS << previous_locks).min(ACCOUNT_LOCK_MAX_SECS);
+
+    let mut nonce_bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let lock = AccountLock {
+        user_id: user.id,
+        locked_until: Utc::now() + Duration::seconds(lock_secs),
+        failures,
+        last_ip: ip.to_string(),
+        unlock_nonce: hex::encode(nonce_bytes),
+    };
+
+    if let Err(e) = app_state.redis_client.lock_account(&lock).await {
+        tracing::error!(user_id = %user.id, "RedisDB error, locking account: {}", e);
+        return;
+    }
+    tracing::warn!(user_id = %user.id, failures, ip = %ip, ?factor, lock_secs, "Account locked after failed logins");
+
+    let sig = signature::sign_account_unlock(
+        user.id,
+        &lock.unlock_nonce,
+        app_state.env.jwt_secret.as_bytes(),
+    );
+    // Like magic links, the link opens a frontend page that posts the token to /unlock
+    let unlock_link = format!(
+        "{}/auth/unlock?token={}.{}.{}",
+        app_state.env.frontend_url, user.id, lock.unlock_nonce, sig
+    );
+
+    if let Err(e) = send_account_locked_email(
+        &user.email,
+        &user.username,
+        match factor {
+            FailedFactor::Password => "password",
+            FailedFactor::SecondFactor => "two-factor authentication code",
+        },
+        failures,
+        &lock.last_ip,
+        &lock.locked_until.format("%Y-%m-%d %H:%M UTC").to_string(),
+        &unlock_link,
+    )
+    .await
+    {
+        tracing::error!("Failed to send account locked email: {}", e);
+    }
+}
+
+/// Lift an account lock with the link emailed to the owner
+///
+/// The token (`{user_id}.{nonce}.{signature}`) only works for the lock it
+/// was sent for. Failed passwords are forgotten too.
+#[instrument(skip(app_state, body))]
+pub async fn unlock_account(
+    State(app_state): State<AppState>,
+    Json(body): Json<AccountUnlockDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid unlock input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let invalid = || HttpError::bad_request("Invalid or expired unlock link".to_string());
+
+    let parts: Vec<&str> = body.token.split('.').collect();
+    let [user_id, nonce, sig] = parts[..] else {
+        tracing::error!("Malformed unlock token");
+        return Err(invalid());
+    };
+    let Ok(user_id) = user_id.parse::<uuid::Uuid>() else {
+        tracing::error!("Malformed unlock token");
+        return Err(invalid());
+    };
+
+    if !signature::verify_account_unlock(user_id, nonce, sig, app_state.env.jwt_secret.as_bytes()) {
+        tracing::error!("Invalid unlock signature");
+        return Err(invalid());
+    }
+
+    let lock = app_state
+        .redis_client
+        .get_account_lock(user_id)
+        .await
         .map_err(|e| {
-            tracing::error!("Refresh token creation error: {}", e);
+            tracing::error!("RedisDB error, getting account lock: {}", e);
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
-        let refresh_cookie = Cookie::build(("refresh_token", &refresh_token))
-            .path("/")
-            .http_only(true)
-            .secure(true)
-            .build();
+    // The lock ended, or the account was locked again since (with a new link)
+    if lock.is_none_or(|lock| lock.unlock_nonce != nonce) {
+        tracing::error!(user_id = %user_id, "Unlock link doesn't match the current lock");
+        return Err(invalid());
+    }
+
+    app_state
+        .redis_client
+        .delete_account_lock(user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, deleting account lock: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
 
-        let mut headers = HeaderMap::new();
+    tracing::info!(user_id = %user_id, "Account unlocked");
+    Ok(Json(Response {
+        status: "success",
+        message: "Your account is unlocked, you can log in again.".to_string(),
+    }))
+}
 
-        headers.append(
-            header::SET_COOKIE,
-            access_cookie.to_string().parse().unwrap(),
-        );
+/// Finish the first login step (password or magic link)
+///
+/// Answers with the token cookies, or with an MFA token if the user has a
+/// second factor (then the cookies are set by `/login/mfa`).
+///
+/// # Returns
+/// The response, and whether a session was started (false while the second
+/// factor is pending)
+async fn complete_login(
+    app_state: &AppState,
+    user: &User,
+    ip: String,
+    user_agent: String,
+) -> Result<(axum::response::Response, bool), HttpError> {
+    match create_mfa_challenge(app_state, user, ip.clone(), user_agent.clone()).await? {
+        Some(challenge) => Ok((Json(challenge).into_response(), false)),
+        None => Ok((start_session(app_state, user, ip, user_agent).await?, true)),
+    }
+}
 
-        headers.append(
-            header::SET_COOKIE,
-            refresh_cookie.to_string().parse().unwrap(),
-        );
+/// Start the second login step for a user who passed the first one (password or social login)
+///
+/// # Returns
+/// The MFA token to send to the client, None if the user has no second factor
+/// (then the caller starts the session right away)
+pub(crate) async fn create_mfa_challenge(
+    app_state: &AppState,
+    user: &User,
+    ip: String,
+    user_agent: String,
+) -> Result<Option<MfaChallengeResponseDto>, HttpError> {
+    let totp_enabled = app_state
+        .db_client
+        .get_totp(user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting TOTP: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .is_some_and(|t| t.enabled_at.is_some());
 
-        app_state
+    let passkey_count = app_state
+        .db_client
+        .get_passkey_count(user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, counting passkeys: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let mut methods = Vec::new();
+    if totp_enabled {
+        methods.push("totp".to_string());
+    }
+    if passkey_count > 0 {
+        methods.push("passkey".to_string());
+    }
+
+    if methods.is_empty() {
+        return Ok(None);
+    }
+
+    // First step passed, but no cookies until the second factor is checked at /login/mfa
+    let mut token_bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut token_bytes);
+    let challenge = MfaChallenge {
+        id: hex::encode(token_bytes),
+        user_id: user.id,
+        user_agent,
+        ip,
+    };
+
+    app_state
+        .redis_client
+        .save_mfa_challenge(&challenge, MFA_CHALLENGE_TTL_SECS)
+        .await
+        .map_err(|e| {
+            tracing::error!(user_id = %user.id, "RedisDB error, saving MFA challenge: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    Ok(Some(MfaChallengeResponseDto {
+        status: "mfa_required".to_string(),
+        mfa_token: challenge.id,
+        expires_in: MFA_CHALLENGE_TTL_SECS,
+        methods,
+    }))
+}
+
+/// Reject requests from an IP (or IP and identifier) over the failed login limits
+///
+/// Same limits as `/login`: 100 failures per IP and 10 per identifier and IP.
+async fn check_attempt_limits(
+    app_state: &AppState,
+    ip: IpAddr,
+    identifier: &str,
+) -> Result<(), HttpError> {
+    let ip_attempts = app_state
+        .redis_client
+        .get_ip_attempts(ip)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, getting ip attempts: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .unwrap_or(0);
+
+    let identifier_ip_attempts = app_state
+        .redis_client
+        .get_identifier_ip_attempts(ip, identifier)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, getting identifier+ip attempts: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .unwrap_or(0);
+
+    if ip_attempts >= 100 || identifier_ip_attempts >= 10 {
+        tracing::error!("Login attempt exceeded the limit");
+        return Err(HttpError::new(
+            "Too many attempts, please try again later".to_string(),
+            StatusCode::TOO_MANY_REQUESTS,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Email a single-use login link (passwordless login)
+///
+/// Always answers the same, whether an account exists or not, so the
+/// endpoint can't be used to find out who has an account. Every request
+/// counts as a failed login attempt for the email, which limits how many
+/// emails one IP can trigger for an address.
+#[instrument(skip(app_state, body), fields(email = %body.email))]
+pub async fn request_magic_link(
+    ClientIp(ip): ClientIp,
+    State(app_state): State<AppState>,
+    Json(body): Json<MagicLinkRequestDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid magic link input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    check_attempt_limits(&app_state, ip, &body.email).await?;
+
+    if let Err(e) = app_state
+        .redis_client
+        .increment_attempts(ip, &body.email)
+        .await
+    {
+        tracing::warn!("Failed to increment the rate {:?}", e);
+    }
+
+    let response = Json(Response {
+        status: "success",
+        message: "If an account exists for this email, a login link has been sent.".to_string(),
+    });
+
+    let user = app_state
+        .db_client
+        .get_user(None, None, Some(&body.email), None)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting user: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let Some(user) = user else {
+        tracing::warn!("Magic link requested for an unknown email");
+        return Ok(response);
+    };
+
+    let mut nonce_bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let nonce = hex::encode(nonce_bytes);
+    let expires_at = Utc::now().timestamp() + MAGIC_LINK_TTL_SECS;
+    let sig = signature::sign_magic_link(
+        user.id,
+        &nonce,
+        expires_at,
+        app_state.env.jwt_secret.as_bytes(),
+    );
+
+    app_state
+        .redis_client
+        .save_magic_link(&nonce, user.id, MAGIC_LINK_TTL_SECS)
+        .await
+        .map_err(|e| {
+            tracing::error!(user_id = %user.id, "RedisDB error, saving magic link: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    // The link opens a frontend page that posts the token to /magic-link/login,
+    // so mail scanners following links don't use it up
+    let login_link = format!(
+        "{}/auth/magic-link?token={}.{}.{}.{}",
+        app_state.env.frontend_url, user.id, nonce, expires_at, sig
+    );
+
+    if let Err(e) = send_magic_link_email(&user.email, &user.username, &login_link).await {
+        tracing::error!("Failed to send magic link email: {}", e);
+        return Err(HttpError::server_error("Failed to send email".to_string()));
+    }
+
+    tracing::info!(user_id = %user.id, "Magic link sent");
+    Ok(response)
+}
+
+/// Sign in with the token of a magic link
+///
+/// Same answer as `/login`: the token cookies, or an MFA token if the user
+/// has a second factor. Invalid tokens count as failed login attempts, and
+/// a locked account can't sign in with a link either.
+#[instrument(skip(app_state, headers, body))]
+pub async fn magic_link_login(
+    ClientIp(ip): ClientIp,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<MagicLinkLoginDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid magic link login input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    check_attempt_limits(&app_state, ip, MAGIC_LINK_IDENTIFIER).await?;
+
+    match consume_magic_link(&app_state, &body.token).await {
+        Ok(user) => {
+            if let Err(e) = app_state
+                .redis_client
+                .delete_identifier_ip_attempts(ip, MAGIC_LINK_IDENTIFIER)
+                .await
+            {
+                tracing::warn!("Failed to clear rate limit: {:?}", e);
+            }
+            check_account_lock(&app_state, &user).await?;
+            let (response, _) =
+                complete_login(&app_state, &user, ip.to_string(), user_agent(&headers)).await?;
+            tracing::info!(user_id = %user.id, ip = %ip, "Magic link login successful");
+            Ok(response)
+        }
+        Err(e) => {
+            if let Err(e) = app_state
+                .redis_client
+                .increment_attempts(ip, MAGIC_LINK_IDENTIFIER)
+                .await
+            {
+                tracing::warn!("Failed to increment the rate {:?}", e);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Check a magic link token (`{user_id}.{nonce}.{expires_at}.{signature}`) and use it up
+async fn consume_magic_link(app_state: &AppState, token: &str) -> Result<User, HttpError> {
+    let invalid = || HttpError::unauthorized(ErrorMessage::InvalidToken.to_string());
+
+    let parts: Vec<&str> = token.split('.').collect();
+    let [user_id, nonce, expires_at, sig] = parts[..] else {
+        tracing::error!("Malformed magic link token");
+        return Err(invalid());
+    };
+    let (Ok(user_id), Ok(expires_at)) = (user_id.parse::<uuid::Uuid>(), expires_at.parse::<i64>())
+    else {
+        tracing::error!("Malformed magic link token");
+        return Err(invalid());
+    };
+
+    if !signature::verify_magic_link(
+        user_id,
+        nonce,
+        expires_at,
+        sig,
+        app_state.env.jwt_secret.as_bytes(),
+    ) {
+        tracing::error!("Invalid magic link signature");
+        return Err(invalid());
+    }
+
+    if expires_at < Utc::now().timestamp() {
+        tracing::error!("Magic link expired");
+        return Err(invalid());
+    }
+
+    let link_user_id = app_state
+        .redis_client
+        .take_magic_link(nonce)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, getting magic link: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if link_user_id != Some(user_id) {
+        tracing::error!("Magic link already used");
+        return Err(invalid());
+    }
+
+    app_state
+        .db_client
+        .get_user(Some(user_id), None, None, None)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting user: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .ok_or_else(|| {
+            tracing::error!("User of magic link not found");
+            HttpError::unauthorized(ErrorMessage::UserNoLongerExist.to_string())
+        })
+}
+
+/// Count an attempt against an MFA token and return its challenge
+///
+/// Each MFA token allows `MFA_MAX_ATTEMPTS` attempts, then the password
+/// has to be entered again.
+pub(crate) async fn attempt_mfa_challenge(
+    app_state: &AppState,
+    mfa_token: &str,
+) -> Result<MfaChallenge, HttpError> {
+    let (challenge, attempts) = app_state
+        .redis_client
+        .attempt_mfa_challenge(mfa_token)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, getting MFA challenge: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .ok_or_else(|| {
+            tracing::error!("MFA challenge not found or expired");
+            HttpError::unauthorized(ErrorMessage::InvalidToken.to_string())
+        })?;
+
+    if attempts > MFA_MAX_ATTEMPTS {
+        if let Err(e) = app_state
             .redis_client
-            .save_refresh_token(
-                &user.id.to_string(),
-                &refresh_token,
-                app_state.env.refresh_token_maxage,
+            .delete_mfa_challenge(&challenge.id)
+            .await
+        {
+            tracing::warn!("Failed to delete MFA challenge: {:?}", e);
+        }
+        tracing::warn!(user_id = %challenge.user_id, "MFA attempts exceeded the limit");
+        return Err(HttpError::unauthorized(
+            "Too many attempts, please log in again".to_string(),
+        ));
+    }
+
+    Ok(challenge)
+}
+
+/// Second login step: exchange the MFA token and a second factor for a session
+///
+/// The second factor is a code from the authenticator app, a recovery code,
+/// or the answer to the passkey prompt from `/login/mfa/passkey`. Wrong codes
+/// count towards the account lock, whichever MFA token they came with.
+#[instrument(skip(app_state, body))]
+pub async fn login_mfa(
+    State(app_state): State<AppState>,
+    Json(body): Json<LoginMfaDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid login_mfa input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let challenge = attempt_mfa_challenge(&app_state, &body.mfa_token).await?;
+
+    let user = app_state
+        .db_client
+        .get_user(Some(challenge.user_id), None, None, None)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting user: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .ok_or_else(|| {
+            tracing::error!("User of MFA challenge not found");
+            HttpError::unauthorized(ErrorMessage::UserNoLongerExist.to_string())
+        })?;
+
+    let verified = match &body.credential {
+        Some(credential) => {
+            check_account_lock(&app_state, &user).await?;
+            verify_passkey_mfa(&app_state, &challenge, credential).await?
+        }
+        None => {
+            check_second_factor(
+                &app_state,
+                &user,
+                &challenge.ip,
+                body.code.as_deref(),
+                body.recovery_code.as_deref(),
             )
+            .await?
+        }
+    };
+
+    if !verified {
+        tracing::error!(user_id = %challenge.user_id, "Invalid MFA code");
+        return Err(HttpError::unauthorized(
+            "Invalid authentication code".to_string(),
+        ));
+    }
+
+    // Single use: of two requests completing the same challenge, only one gets a session
+    let consumed = app_state
+        .redis_client
+        .delete_mfa_challenge(&challenge.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, deleting MFA challenge: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if !consumed {
+        tracing::error!("MFA challenge already used");
+        return Err(HttpError::unauthorized(
+            ErrorMessage::InvalidToken.to_string(),
+        ));
+    }
+
+    let response = start_session(
+        &app_state,
+        &user,
+        challenge.ip.clone(),
+        challenge.user_agent,
+    )
+    .await?;
+    clear_login_failures(&app_state, &user, &challenge.ip).await;
+    tracing::info!(user_id = %user.id, "Login MFA successful");
+    Ok(response)
+}
+
+/// Forget the failed logins of a user who just passed every login step
+///
+/// The login identifier isn't kept with the MFA token, so the counters of
+/// both the username and the email are cleared for the IP of the login.
+async fn clear_login_failures(app_state: &AppState, user: &User, ip: &str) {
+    if let Err(e) = app_state
+        .redis_client
+        .delete_account_failures(user.id)
+        .await
+    {
+        tracing::warn!("Failed to clear account failures: {:?}", e);
+    }
+
+    let Ok(ip) = ip.parse::<IpAddr>() else {
+        return;
+    };
+    for identifier in [&user.username, &user.email] {
+        if let Err(e) = app_state
+            .redis_client
+            .delete_identifier_ip_attempts(ip, identifier)
             .await
-            .map_err(|e| {
-                tracing::error!(user_id = %user.id, "RedisDB error, saving refresh token: {}", e);
-                HttpError::server_error(ErrorMessage::ServerError.to_string())
-            })?;
+        {
+            tracing::warn!("Failed to clear rate limit: {:?}", e);
+        }
+    }
+}
 
-        let mut response = response.into_response();
-        response.headers_mut().extend(headers);
-        tracing::info!("authenticate_process succesful");
-        Ok(response)
-    } else {
-        tracing::error!("password mismatch");
-        Err(HttpError::server_error("Login failed"))
+/// Create a session for a user who passed every login step, and set the token cookies
+///
+/// Every login is a new session, other devices stay signed in.
+pub(crate) async fn start_session(
+    app_state: &AppState,
+    user: &User,
+    ip: String,
+    user_agent: String,
+) -> Result<axum::response::Response, HttpError> {
+    let session_id = uuid::Uuid::new_v4().to_string();
+
+    let access_token = token::create_access_token(
+        user,
+        &session_id,
+        &app_state.jwt_keys,
+        app_state.env.jwt_maxage,
+    )
+    .map_err(|e| {
+        tracing::error!("Access token creation error: {}", e);
+        HttpError::server_error(ErrorMessage::ServerError.to_string())
+    })?;
+
+    let refresh_token = token::create_refresh_token(
+        user,
+        &session_id,
+        &app_state.jwt_keys,
+        app_state.env.refresh_token_maxage,
+    )
+    .map_err(|e| {
+        tracing::error!("Refresh token creation error: {}", e);
+        HttpError::server_error(ErrorMessage::ServerError.to_string())
+    })?;
+
+    let csrf_token = signature::sign_csrf_token(&session_id, app_state.env.jwt_secret.as_bytes());
+    let headers = session_cookies(&access_token, &refresh_token, &csrf_token);
+
+    let response = axum::response::Json(UserLoginResponseDto {
+        status: "success".to_string(),
+        access_token,
+        csrf_token,
+        username: user.username.clone(),
+    });
+
+    let now = Utc::now();
+    let session = Session {
+        id: session_id,
+        user_id: user.id,
+        refresh_token,
+        user_agent,
+        ip,
+        created_at: now,
+        last_used_at: now,
+    };
+
+    app_state
+        .redis_client
+        .save_session(&session, app_state.env.refresh_token_maxage)
+        .await
+        .map_err(|e| {
+            tracing::error!(user_id = %user.id, "RedisDB error, saving session: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let mut response = response.into_response();
+    response.headers_mut().extend(headers);
+    Ok(response)
+}
+
+/// Set-Cookie headers of a session's tokens
+///
+/// The token cookies are `HttpOnly`, the CSRF token cookie isn't: the frontend
+/// reads it and echoes it in the `X-CSRF-Token` header. All of them are
+/// `SameSite=Strict`, browsers don't send them along with cross-site requests.
+pub(crate) fn session_cookies(
+    access_token: &str,
+    refresh_token: &str,
+    csrf_token: &str,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    for (name, value, http_only) in [
+        ("access_token", access_token, true),
+        ("refresh_token", refresh_token, true),
+        (CSRF_COOKIE, csrf_token, false),
+    ] {
+        let cookie = Cookie::build((name, value))
+            .path("/")
+            .http_only(http_only)
+            .secure(true)
+            .same_site(SameSite::Strict)
+            .build();
+        headers.append(header::SET_COOKIE, cookie.to_string().parse().unwrap());
     }
+
+    headers
 }
 
 #[instrument(skip(app_state))]
@@ -331,22 +1130,12 @@ pub async fn verify_email(
             HttpError::server_error(e.to_string())
         })?;
 
-    if query_params.token.contains('+') {
-        let new_email = &query_params.token[37..];
-        app_state
-            .db_client
-            .update_user_email(user.id, new_email)
-            .await
-            .map_err(|e| {
-                tracing::error!(user_id = %user.id, new_email = %new_email, "Failed to update user email: {}", e);
-                HttpError::server_error(e.to_string())})?;
-    } else {
-        let send_welcome_email_result = send_welcome_email(&user.email, &user.username).await;
+    let send_welcome_email_result = send_welcome_email(&user.email, &user.username).await;
 
-        if let Err(e) = send_welcome_email_result {
-            tracing::error!("Failed to send welcome email: {}", e);
-        }
+    if let Err(e) = send_welcome_email_result {
+        tracing::error!("Failed to send welcome email: {}", e);
     }
+
     tracing::info!(user_id = %user.id, "Email verification successful");
     Ok((
         StatusCode::OK,
@@ -357,6 +1146,149 @@ pub async fn verify_email(
     ))
 }
 
+/// Confirm an email change with the link sent to the new address
+///
+/// Changing the email bumps the token version, so every device is signed out.
+#[instrument(skip(app_state, body))]
+pub async fn confirm_email_change(
+    State(app_state): State<AppState>,
+    Json(body): Json<EmailChangeTokenDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid confirm_email_change input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let change = app_state
+        .db_client
+        .get_pending_email_change_by_token(&token_hash::hash(&body.token))
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting pending email change: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .filter(|change| change.confirmed_at.is_none() && change.expires_at > Utc::now())
+        .ok_or_else(|| {
+            tracing::error!("Email change not found, already confirmed or expired");
+            HttpError::bad_request(ErrorMessage::InvalidToken.to_string())
+        })?;
+
+    // Claims the link and changes the email at once, so it can only be used once
+    let confirmed = app_state
+        .db_client
+        .confirm_pending_email_change(change.id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                tracing::error!(user_id = %change.user_id, "New email was taken in the meantime");
+                HttpError::unique_constraint_violation("Email already exists".to_string())
+            }
+            e => {
+                tracing::error!(user_id = %change.user_id, "DB error, confirming pending email change: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }
+        })?;
+
+    if !confirmed {
+        tracing::error!(user_id = %change.user_id, "Email change confirmed by a concurrent request");
+        return Err(HttpError::bad_request(
+            ErrorMessage::InvalidToken.to_string(),
+        ));
+    }
+
+    // The email change bumped the token version, sessions can't be refreshed anymore either
+    app_state
+        .redis_client
+        .delete_user_sessions(change.user_id, None)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, deleting sessions: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!(user_id = %change.user_id, "Email change confirmed");
+    Ok(Json(Response {
+        status: "success",
+        message: "Your email address was changed, please log in again.".to_string(),
+    }))
+}
+
+/// Cancel or revert an email change with the link sent to the old address
+///
+/// Unconfirmed changes are cancelled. Confirmed ones are reverted: the old
+/// address is restored and every device signed out, whatever the email was
+/// changed to since. Either way all email changes of the user are dropped,
+/// so links sent to an address the account was moved to stop working.
+#[instrument(skip(app_state, body))]
+pub async fn revert_email_change(
+    State(app_state): State<AppState>,
+    Json(body): Json<EmailChangeTokenDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid revert_email_change input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let change = app_state
+        .db_client
+        .get_pending_email_change_by_revert_token(&token_hash::hash(&body.token))
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting pending email change: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .filter(|change| change.revert_expires_at > Utc::now())
+        .ok_or_else(|| {
+            tracing::error!("Email change not found or revert link expired");
+            HttpError::bad_request(ErrorMessage::InvalidToken.to_string())
+        })?;
+
+    // Checks again and reverts under a lock, a concurrent confirmation or
+    // revert of the same change can't interleave
+    let change = app_state
+        .db_client
+        .revert_pending_email_change(change.id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                tracing::error!(user_id = %change.user_id, "Old email was taken in the meantime");
+                HttpError::unique_constraint_violation("Email already exists".to_string())
+            }
+            e => {
+                tracing::error!(user_id = %change.user_id, "DB error, reverting email change: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }
+        })?
+        .ok_or_else(|| {
+            tracing::error!(user_id = %change.user_id, "Email change reverted by a concurrent request");
+            HttpError::bad_request(ErrorMessage::InvalidToken.to_string())
+        })?;
+
+    if change.confirmed_at.is_some() {
+        app_state
+            .redis_client
+            .delete_user_sessions(change.user_id, None)
+            .await
+            .map_err(|e| {
+                tracing::error!("RedisDB error, deleting sessions: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?;
+    }
+
+    let message = match change.confirmed_at {
+        Some(_) => {
+            "Your email address was changed back, please log in again and choose a new password."
+        }
+        None => "The email change was cancelled.",
+    };
+
+    tracing::info!(user_id = %change.user_id, reverted = change.confirmed_at.is_some(), "Email change reverted");
+    Ok(Json(Response {
+        status: "success",
+        message: message.to_string(),
+    }))
+}
+
 #[instrument(skip(app_state))]
 pub async fn forgot_password(
     State(app_state): State<AppState>,
@@ -488,6 +1420,22 @@ pub async fn reset_password(
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
+    // The old password may be what leaked, sign out every device
+    // (the password update already deleted the personal access tokens)
+    app_state
+        .redis_client
+        .delete_user_sessions(user.id, None)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, deleting sessions: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    // Whoever was guessing the old password is out of luck now
+    if let Err(e) = app_state.redis_client.delete_account_lock(user.id).await {
+        tracing::warn!("Failed to delete account lock: {:?}", e);
+    }
+
     let response = Response {
         message: "Password has been successfully reset.".to_string(),
         status: "success",
@@ -510,8 +1458,8 @@ pub async fn refresh(
         HttpError::unauthorized(ErrorMessage::TokenNotProvided.to_string())
     })?;
 
-    let token_details = match token::decode_token(&token, app_state.env.jwt_secret.as_bytes()) {
-        Ok(token_details) => token_details,
+    let claims = match token::decode_refresh_token(&token, &app_state.jwt_keys) {
+        Ok(claims) => claims,
         Err(e) => {
             tracing::error!("Invalid refresh token: {}", e);
             return Err(HttpError::unauthorized(
@@ -519,26 +1467,83 @@ pub async fn refresh(
             ));
         }
     };
+    let session_id = claims.sid;
 
-    let stored_refresh_token = app_state
-        .redis_client
-        .get_refresh_token(&token_details)
+    let user_uuid = uuid::Uuid::parse_str(&claims.sub).map_err(|e| {
+        tracing::error!("Invalid user id in refresh token: {}", e);
+        HttpError::unauthorized(ErrorMessage::InvalidToken.to_string())
+    })?;
+
+    // The access token carries the current role, and deleted users get no new token
+    let user = app_state
+        .db_client
+        .get_user(Some(user_uuid), None, None, None)
         .await
         .map_err(|e| {
-            tracing::error!("RedisDB error, getting refresh token: {}", e);
+            tracing::error!("DB error, getting user: {}", e);
             HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .ok_or_else(|| {
+            tracing::error!("User of refresh token not found");
+            HttpError::unauthorized(ErrorMessage::UserNoLongerExist.to_string())
         })?;
 
-    if stored_refresh_token.is_none() || stored_refresh_token.unwrap() != token {
-        tracing::error!("Refresh token mismatch or not found in Redis");
-        return Err(HttpError::server_error(
-            "Refresh token mismatch".to_string(),
+    // Refresh tokens issued before a password change, email change or forced logout
+    if claims.ver != user.token_version {
+        tracing::error!("Refresh token version is outdated");
+        return Err(HttpError::unauthorized(
+            ErrorMessage::InvalidToken.to_string(),
         ));
     }
 
-    let access_token = token::create_token(
-        &token_details,
-        &app_state.env.jwt_secret.as_bytes(),
+    // Refresh tokens are single use: every refresh hands out a new one
+    let new_refresh_token = token::create_refresh_token(
+        &user,
+        &session_id,
+        &app_state.jwt_keys,
+        app_state.env.refresh_token_maxage,
+    )
+    .map_err(|e| {
+        tracing::error!("Refresh token creation error: {}", e);
+        HttpError::server_error(ErrorMessage::ServerError.to_string())
+    })?;
+
+    let rotation = app_state
+        .redis_client
+        .rotate_refresh_token(
+            user_uuid,
+            &session_id,
+            &token,
+            &new_refresh_token,
+            app_state.env.refresh_token_maxage,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, rotating refresh token: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let refresh_token = match rotation {
+        TokenRotation::Rotated => new_refresh_token,
+        TokenRotation::Grace(latest) => latest,
+        TokenRotation::Reused => {
+            tracing::warn!(user_id = %user_uuid, session_id = %session_id, "Refresh token reuse detected, session revoked");
+            return Err(HttpError::unauthorized(
+                ErrorMessage::InvalidToken.to_string(),
+            ));
+        }
+        TokenRotation::NotFound => {
+            tracing::error!("Session of refresh token not found in Redis");
+            return Err(HttpError::unauthorized(
+                ErrorMessage::InvalidToken.to_string(),
+            ));
+        }
+    };
+
+    let access_token = token::create_access_token(
+        &user,
+        &session_id,
+        &app_state.jwt_keys,
         app_state.env.jwt_maxage,
     )
     .map_err(|e| {
@@ -546,24 +1551,15 @@ pub async fn refresh(
         HttpError::server_error(ErrorMessage::ServerError.to_string())
     })?;
 
-    let access_cookie = Cookie::build(("access_token", access_token.clone()))
-        .path("/")
-        .http_only(true)
-        .secure(true)
-        .build();
+    let csrf_token = signature::sign_csrf_token(&session_id, app_state.env.jwt_secret.as_bytes());
+    let headers = session_cookies(&access_token, &refresh_token, &csrf_token);
 
     let response = axum::response::Json(RefreshResponseDto {
         status: "access_token recreated".to_string(),
         access_token,
+        csrf_token,
     });
 
-    let mut headers = HeaderMap::new();
-
-    headers.append(
-        header::SET_COOKIE,
-        access_cookie.to_string().parse().unwrap(),
-    );
-
     let mut response = response.into_response();
     response.headers_mut().extend(headers);
     tracing::info!("Access token refreshed successfully");
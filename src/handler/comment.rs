@@ -1,12 +1,17 @@
 use crate::AppState;
-use crate::db::CommentExt;
+use crate::db::{CommentExt, ModerationExt, NotificationExt, UserExt};
 use crate::dtos::{
     CommentListResponse, GetcommentsQuery, InputcommentRequest, PaginationDto,
-    SinglecommentResponse,
+    SinglecommentResponse, WithReactions,
 };
 use crate::error::{ErrorMessage, HttpError};
+use crate::handler::reaction::{add_comment_reaction, reaction_summaries, remove_comment_reaction};
+use crate::handler::report::report_comment;
 use crate::middleware::JWTAuthMiddleware;
-use crate::middleware::auth;
+use crate::middleware::{auth, optional_auth, role_check};
+use crate::models::{ApiScope, CommentStatus, ReactionTarget, UserRole};
+use crate::spam;
+use crate::utils::comment_content::{MAX_MENTIONS_PER_COMMENT, extract_mentions, render_html};
 use axum::Extension;
 use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
@@ -14,29 +19,112 @@ use axum::response::{IntoResponse, Json};
 use axum::routing::{get, post, put};
 use axum::{Router, middleware};
 use tracing::instrument;
+use uuid::Uuid;
 use validator::Validate;
 
+/// Deepest reply level allowed (top-level comments are depth 0)
+///
+/// Keeps threads readable on narrow screens, where every level adds indentation.
+const MAX_COMMENT_DEPTH: i32 = 4;
+
+/// Resolve the @mentions of a comment and render it to HTML
+///
+/// Mentions of usernames that don't exist are left as plain text.
+///
+/// # Returns
+/// (rendered HTML, ids of the mentioned users)
+async fn render_comment(
+    app_state: &AppState,
+    content: &str,
+) -> Result<(String, Vec<Uuid>), HttpError> {
+    let names = extract_mentions(content);
+
+    if names.len() > MAX_MENTIONS_PER_COMMENT {
+        tracing::error!("Too many mentions in comment: {}", names.len());
+        return Err(HttpError::bad_request(format!(
+            "A comment can mention at most {} users",
+            MAX_MENTIONS_PER_COMMENT
+        )));
+    }
+
+    let users = if names.is_empty() {
+        Vec::new()
+    } else {
+        app_state
+            .db_client
+            .get_user_ids_by_usernames(&names)
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, resolving mentions: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?
+    };
+
+    let (user_ids, usernames): (Vec<Uuid>, Vec<String>) = users.into_iter().unzip();
+    Ok((render_html(content, &usernames), user_ids))
+}
+
 pub fn comment_handler(app_state: AppState) -> Router<AppState> {
     Router::new()
-        .route("/", get(get_comments))
+        .route(
+            "/",
+            get(get_comments).route_layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                optional_auth,
+            )),
+        )
         .route(
             "/",
             post(create_comment)
+                .route_layer(middleware::from_fn(|req, next| {
+                    role_check(
+                        req,
+                        next,
+                        vec![UserRole::Admin, UserRole::User],
+                        Some(ApiScope::CommentsWrite),
+                    )
+                }))
                 .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
         )
         .route(
             "/{comment_id}",
             put(edit_comment)
                 .delete(delete_comment)
+                .route_layer(middleware::from_fn(|req, next| {
+                    role_check(
+                        req,
+                        next,
+                        vec![UserRole::Admin, UserRole::User],
+                        Some(ApiScope::CommentsWrite),
+                    )
+                }))
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/{comment_id}/reactions/{reaction}",
+            put(add_comment_reaction)
+                .delete(remove_comment_reaction)
+                .route_layer(middleware::from_fn(|req, next| {
+                    role_check(req, next, vec![UserRole::Admin, UserRole::User], None)
+                }))
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/{comment_id}/report",
+            post(report_comment)
+                .route_layer(middleware::from_fn(|req, next| {
+                    role_check(req, next, vec![UserRole::Admin, UserRole::User], None)
+                }))
                 .route_layer(middleware::from_fn_with_state(app_state, auth)),
         )
 }
 
-#[instrument(skip(app_state))]
+#[instrument(skip(app_state, viewer))]
 pub async fn get_comments(
     Query(params): Query<GetcommentsQuery>,
     Path(post_id): Path<i32>,
     State(app_state): State<AppState>,
+    viewer: Option<Extension<JWTAuthMiddleware>>,
 ) -> Result<impl IntoResponse, HttpError> {
     params.validate().map_err(|e| {
         tracing::error!("Invalid get_comments input: {}", e);
@@ -56,17 +144,38 @@ pub async fn get_comments(
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
+    // Pages are made of threads (top-level comments), not individual comments
     let total = app_state
         .db_client
-        .get_post_comment_count(post_id)
+        .get_post_thread_count(post_id)
         .await
         .map_err(|e| {
-            tracing::error!("DB error, getting post comment count: {}", e);
+            tracing::error!("DB error, getting post thread count: {}", e);
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
     let total_pages = (total as f64 / limit as f64).ceil() as i32;
 
+    let targets: Vec<ReactionTarget> = comments
+        .iter()
+        .map(|c| ReactionTarget::Comment(c.id))
+        .collect();
+    let viewer_id = viewer.map(|Extension(jwt)| jwt.user.id);
+    let mut reactions = reaction_summaries(&app_state, &targets, viewer_id).await?;
+
+    let comments = comments
+        .into_iter()
+        .map(|comment| {
+            let reactions = reactions
+                .remove(&ReactionTarget::Comment(comment.id))
+                .unwrap_or_default();
+            WithReactions {
+                item: comment,
+                reactions,
+            }
+        })
+        .collect();
+
     let response = Json(CommentListResponse {
         status: "success".to_string(),
         data: comments,
@@ -95,20 +204,123 @@ pub async fn create_comment(
 
     let user_id = jwt.user.id;
 
+    if let Some(parent_id) = body.parent_id {
+        let parent = app_state
+            .db_client
+            .get_comment(parent_id)
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, getting parent comment: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?
+            .filter(|parent| parent.post_id == post_id)
+            .ok_or_else(|| {
+                tracing::error!("Parent comment {} not found on post {}", parent_id, post_id);
+                HttpError::not_found("Parent comment not found".to_string())
+            })?;
+
+        if parent.deleted_at.is_some() || parent.status != CommentStatus::Approved {
+            tracing::error!("Reply to deleted or unapproved comment {}", parent_id);
+            return Err(HttpError::bad_request(
+                "Cannot reply to a deleted comment".to_string(),
+            ));
+        }
+
+        if parent.depth >= MAX_COMMENT_DEPTH {
+            tracing::error!("Reply depth limit reached under comment {}", parent_id);
+            return Err(HttpError::bad_request(format!(
+                "Replies cannot be nested more than {} levels deep",
+                MAX_COMMENT_DEPTH
+            )));
+        }
+    }
+
+    let (content_html, mentions) = render_comment(&app_state, &body.content).await?;
+
+    // Pre-moderation: a user's comments are held for review until one has been approved
+    let mut status = if app_state.env.comment_premoderation && jwt.user.role != UserRole::Admin {
+        let trusted = app_state
+            .db_client
+            .has_approved_comment(user_id)
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, checking approved comments: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?;
+
+        if trusted {
+            CommentStatus::Approved
+        } else {
+            CommentStatus::Pending
+        }
+    } else {
+        CommentStatus::Approved
+    };
+
+    // Spam check: borderline comments go to the moderation queue, clear spam is marked as such.
+    // Admins are trusted and skip it.
+    let mut classification = None;
+    if !app_state.env.spam_classifiers.is_empty() && jwt.user.role != UserRole::Admin {
+        let verdict = spam::classify_comment(&app_state, user_id, &body.content).await;
+        tracing::debug!(score = verdict.score, reason = %verdict.reason(), "Comment classified");
+
+        if verdict.status != CommentStatus::Approved {
+            status = verdict.status;
+            classification = Some(verdict);
+        }
+    }
+
     let comment = app_state
         .db_client
-        .create_comment(user_id, post_id, &body.content)
+        .create_comment(
+            user_id,
+            post_id,
+            body.parent_id,
+            &body.content,
+            &content_html,
+            &mentions,
+            status,
+        )
         .await
         .map_err(|e| {
             tracing::error!("DB error, creating comment: {}", e);
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
+    if let Some(verdict) = classification {
+        // Comment is already saved, a missing audit entry is not worth failing the request for
+        if let Err(e) = app_state
+            .db_client
+            .log_classification(comment.id, comment.status, &verdict.reason())
+            .await
+        {
+            tracing::error!("DB error, logging comment classification: {}", e);
+        }
+    }
+
+    // Notify the post author, parent commenter and mentioned users. Emails are sent by the
+    // notification job, which waits until a pending comment is approved.
+    if matches!(
+        comment.status,
+        CommentStatus::Approved | CommentStatus::Pending
+    ) {
+        let queued = app_state
+            .db_client
+            .enqueue_comment_notifications(comment.id)
+            .await;
+        if let Err(e) = queued {
+            tracing::error!("DB error, queueing comment notifications: {}", e);
+        }
+    }
+
+    tracing::info!(status = ?comment.status, "create_comment successful");
     let response = Json(SinglecommentResponse {
         status: "success".to_string(),
-        data: comment,
+        data: WithReactions {
+            item: comment,
+            reactions: Default::default(),
+        },
     });
-    tracing::info!("create_comment successful");
     Ok((StatusCode::CREATED, response))
 }
 
@@ -126,21 +338,81 @@ pub async fn edit_comment(
 
     let user_id = jwt.user.id;
 
-    let comment = app_state
+    let (content_html, mentions) = render_comment(&app_state, &body.content).await?;
+
+    // Same spam check as new comments, so an approved comment can't be edited into spam
+    let mut classification = None;
+    if !app_state.env.spam_classifiers.is_empty() && jwt.user.role != UserRole::Admin {
+        let verdict = spam::classify_comment(&app_state, user_id, &body.content).await;
+        tracing::debug!(score = verdict.score, reason = %verdict.reason(), "Comment edit classified");
+
+        if verdict.status != CommentStatus::Approved {
+            classification = Some(verdict);
+        }
+    }
+
+    let (comment, new_mentions) = app_state
         .db_client
-        .edit_comment(user_id, comment_id, &body.content)
+        .edit_comment(
+            user_id,
+            comment_id,
+            &body.content,
+            &content_html,
+            &mentions,
+            classification.as_ref().map(|verdict| verdict.status),
+        )
         .await
-        .map_err(|e| {
-            tracing::error!("DB error, editing comment: {}", e);
-            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                tracing::warn!("Comment {} not found for edit", comment_id);
+                HttpError::not_found("Comment not found".to_string())
+            }
+            _ => {
+                tracing::error!("DB error, editing comment: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }
         })?;
 
-    let response = Json(SinglecommentResponse {
+    if let Some(verdict) = classification {
+        let logged = app_state
+            .db_client
+            .log_classification(comment.id, comment.status, &verdict.reason())
+            .await;
+        if let Err(e) = logged {
+            tracing::error!("DB error, logging comment classification: {}", e);
+        }
+    }
+
+    // Only users mentioned for the first time are notified, not everyone again
+    if !new_mentions.is_empty()
+        && matches!(
+            comment.status,
+            CommentStatus::Approved | CommentStatus::Pending
+        )
+    {
+        let queued = app_state
+            .db_client
+            .enqueue_mention_notifications(comment.id, &new_mentions)
+            .await;
+        if let Err(e) = queued {
+            tracing::error!("DB error, queueing mention notifications: {}", e);
+        }
+    }
+
+    let target = ReactionTarget::Comment(comment.id);
+    let reactions = reaction_summaries(&app_state, &[target], Some(user_id))
+        .await?
+        .remove(&target)
+        .unwrap_or_default();
+
+    tracing::info!(status = ?comment.status, "edit_comment successful");
+    Ok(Json(SinglecommentResponse {
         status: "success".to_string(),
-        data: comment,
-    });
-    tracing::info!("edit_comment successful");
-    Ok(response)
+        data: WithReactions {
+            item: comment,
+            reactions,
+        },
+    }))
 }
 
 #[instrument(skip(app_state, jwt))]
@@ -155,9 +427,15 @@ async fn delete_comment(
         .db_client
         .delete_comment(user_id, comment_id)
         .await
-        .map_err(|e| {
-            tracing::error!("DB error, deleting comment: {}", e);
-            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                tracing::warn!("Comment {} not found for deletion", comment_id);
+                HttpError::not_found("Comment not found".to_string())
+            }
+            _ => {
+                tracing::error!("DB error, deleting comment: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }
         })?;
     tracing::info!("delete_comment successful");
     Ok(StatusCode::NO_CONTENT)
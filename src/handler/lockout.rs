@@ -0,0 +1,81 @@
+use crate::AppState;
+use crate::db::UserExt;
+use crate::dtos::{AccountLockDto, AccountLockListResponseDto, Response};
+use crate::error::{ErrorMessage, HttpError};
+use crate::middleware::JWTAuthMiddleware;
+use axum::Extension;
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Json};
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Accounts locked after too many failed passwords (admin)
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn get_lockouts(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let locks = app_state
+        .redis_client
+        .get_account_locks()
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, getting account locks: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    // Only ever a handful of accounts are locked at once
+    let mut data = Vec::with_capacity(locks.len());
+    for lock in locks {
+        let user = app_state
+            .db_client
+            .get_user(Some(lock.user_id), None, None, None)
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, getting user: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?;
+
+        data.push(AccountLockDto {
+            user_id: lock.user_id,
+            username: user.map(|u| u.username),
+            locked_until: lock.locked_until,
+            failures: lock.failures,
+            last_ip: lock.last_ip,
+        });
+    }
+
+    tracing::info!("get_lockouts successful");
+    Ok(Json(AccountLockListResponseDto {
+        status: "success".to_string(),
+        data,
+    }))
+}
+
+/// Lift the lock of an account and forget its failed passwords (admin)
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn delete_lockout(
+    Path(user_id): Path<Uuid>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state
+        .redis_client
+        .delete_account_lock(user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, deleting account lock: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if !deleted {
+        tracing::error!(user_id = %user_id, "Account lock not found");
+        return Err(HttpError::not_found("Account is not locked".to_string()));
+    }
+
+    tracing::info!(user_id = %user_id, "delete_lockout successful");
+    Ok(Json(Response {
+        status: "success",
+        message: "Account unlocked".to_string(),
+    }))
+}
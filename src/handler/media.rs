@@ -0,0 +1,114 @@
+use crate::AppState;
+use crate::db::{MediaDeletion, MediaExt};
+use crate::dtos::{MediaListResponseDto, MediaQueryParams, PaginationDto};
+use crate::error::{ErrorMessage, HttpError};
+use crate::middleware::{auth, role_check};
+use crate::models::{ApiScope, UserRole};
+use crate::utils::uploads::remove_upload_files;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use axum::routing::{delete, get};
+use axum::{Router, middleware};
+use tracing::instrument;
+use uuid::Uuid;
+use validator::Validate;
+
+/// Media library routes - admin only
+pub fn media_handler(app_state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_media_list))
+        .route("/{media_id}", delete(delete_media))
+        .route_layer(middleware::from_fn(|req, next| {
+            role_check(req, next, vec![UserRole::Admin], Some(ApiScope::MediaWrite))
+        }))
+        .route_layer(middleware::from_fn_with_state(app_state, auth))
+}
+
+#[instrument(skip(app_state))]
+pub async fn get_media_list(
+    Query(params): Query<MediaQueryParams>,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    params.validate().map_err(|e| {
+        tracing::error!("Invalid get_media_list input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let page = params.page.unwrap_or(1);
+    let limit = params.limit.unwrap_or(20);
+    let orphaned_only = params.orphaned.unwrap_or(false);
+
+    let media = app_state
+        .db_client
+        .get_media_list(page, limit, orphaned_only)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting media list: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let total = app_state
+        .db_client
+        .get_media_count(orphaned_only)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting media count: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let total_pages = (total as f64 / limit as f64).ceil() as i32;
+
+    let response = Json(MediaListResponseDto {
+        status: "success".to_string(),
+        data: media,
+        pagination: PaginationDto {
+            page,
+            limit,
+            total: total as i32,
+            total_pages,
+        },
+    });
+    tracing::info!("get_media_list successful");
+    Ok(response)
+}
+
+/// Delete a media entry and all of its files
+///
+/// Refuses with 409 while any post still references the media,
+/// so an admin can't break images in published posts by accident.
+#[instrument(skip(app_state))]
+pub async fn delete_media(
+    Path(media_id): Path<Uuid>,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deletion = app_state
+        .db_client
+        .delete_media(media_id)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                tracing::warn!("Media with id {} not found", media_id);
+                HttpError::not_found("Media not found".to_string())
+            }
+            _ => {
+                tracing::error!("DB error, deleting media: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }
+        })?;
+
+    let file_names = match deletion {
+        MediaDeletion::Deleted(file_names) => file_names,
+        MediaDeletion::InUse(references) => {
+            tracing::warn!(%media_id, references, "Refusing to delete referenced media");
+            return Err(HttpError::new(
+                format!("Media is still used by {} post(s)", references),
+                StatusCode::CONFLICT,
+            ));
+        }
+    };
+
+    let removed = remove_upload_files(&file_names);
+    tracing::info!(%media_id, removed, "delete_media successful");
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,478 @@
+use crate::AppState;
+use crate::db::{MfaExt, PasskeyExt};
+use crate::dtos::{
+    DisableTotpDto, MfaPolicyDto, MfaPolicyResponseDto, MfaStatusDto, MfaStatusResponseDto,
+    RecoveryCodesResponseDto, Response, TotpCodeDto, TotpSetupResponseDto,
+};
+use crate::error::{ErrorMessage, HttpError};
+use crate::handler::auth::{FailedFactor, check_account_lock, register_failed_login};
+use crate::handler::users::current_session_id;
+use crate::middleware::JWTAuthMiddleware;
+use crate::models::{User, UserRole};
+use crate::utils::{password, totp};
+use axum::Extension;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use axum_client_ip::ClientIp;
+use chrono::Utc;
+use tracing::instrument;
+use uuid::Uuid;
+use validator::Validate;
+
+/// Check a second factor and use it up
+///
+/// Exactly one of `code` (authenticator app) and `recovery_code` must be given.
+/// An accepted TOTP code can't be used again, a recovery code is spent.
+///
+/// # Returns
+/// false if the factor is wrong, already used, or the user has no enabled authenticator
+pub async fn verify_second_factor(
+    app_state: &AppState,
+    user_id: Uuid,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<bool, HttpError> {
+    match (code, recovery_code) {
+        (Some(code), None) => {
+            let user_totp = app_state
+                .db_client
+                .get_totp(user_id)
+                .await
+                .map_err(|e| {
+                    tracing::error!("DB error, getting TOTP: {}", e);
+                    HttpError::server_error(ErrorMessage::ServerError.to_string())
+                })?
+                .filter(|t| t.enabled_at.is_some());
+
+            let Some(user_totp) = user_totp else {
+                return Ok(false);
+            };
+
+            let Some(step) = totp::verify_code(
+                &user_totp.secret,
+                code,
+                Utc::now().timestamp(),
+                user_totp.last_used_step,
+            ) else {
+                return Ok(false);
+            };
+
+            app_state
+                .db_client
+                .use_totp_step(user_id, step)
+                .await
+                .map_err(|e| {
+                    tracing::error!("DB error, using TOTP step: {}", e);
+                    HttpError::server_error(ErrorMessage::ServerError.to_string())
+                })
+        }
+        (None, Some(recovery_code)) => app_state
+            .db_client
+            .use_recovery_code(user_id, &totp::hash_recovery_code(recovery_code))
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, using recovery code: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }),
+        _ => Err(HttpError::bad_request(
+            "Provide either a code or a recovery code".to_string(),
+        )),
+    }
+}
+
+/// Check a second factor typed by someone who may only have the password
+/// (login) or a stolen session (settings)
+///
+/// Refused while the account is locked, and a wrong factor counts towards
+/// the lock, so the codes can't be guessed by starting over.
+pub(crate) async fn check_second_factor(
+    app_state: &AppState,
+    user: &User,
+    ip: &str,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<bool, HttpError> {
+    check_account_lock(app_state, user).await?;
+
+    let verified = verify_second_factor(app_state, user.id, code, recovery_code).await?;
+    if !verified {
+        register_failed_login(app_state, user, ip, FailedFactor::SecondFactor).await;
+    }
+    Ok(verified)
+}
+
+/// Generate recovery codes, returning them in plain text and their hashes for storage
+fn new_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let codes = totp::generate_recovery_codes();
+    let hashes = codes.iter().map(|c| totp::hash_recovery_code(c)).collect();
+    (codes, hashes)
+}
+
+async fn get_passkey_count(app_state: &AppState, user_id: Uuid) -> Result<i64, HttpError> {
+    app_state
+        .db_client
+        .get_passkey_count(user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, counting passkeys: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })
+}
+
+async fn get_required_roles(app_state: &AppState) -> Result<Vec<UserRole>, HttpError> {
+    app_state
+        .db_client
+        .get_mfa_required_roles()
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting MFA policy: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })
+}
+
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn get_mfa_status(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let user_totp = app_state
+        .db_client
+        .get_totp(jwt.user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting TOTP: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+    let enabled_at = user_totp.and_then(|t| t.enabled_at);
+
+    let recovery_codes_left = app_state
+        .db_client
+        .get_unused_recovery_code_count(jwt.user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, counting recovery codes: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let required = get_required_roles(&app_state)
+        .await?
+        .contains(&jwt.user.role);
+    let passkeys = get_passkey_count(&app_state, jwt.user.id).await?;
+
+    tracing::info!("get_mfa_status successful");
+    Ok(Json(MfaStatusResponseDto {
+        status: "success".to_string(),
+        data: MfaStatusDto {
+            totp_enabled: enabled_at.is_some(),
+            enabled_at,
+            recovery_codes_left,
+            passkeys,
+            required,
+        },
+    }))
+}
+
+/// Start TOTP enrolment: a new secret, to be confirmed with a code at `enable_totp`
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn setup_totp(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let secret = totp::generate_secret();
+
+    let started = app_state
+        .db_client
+        .start_totp_enrolment(jwt.user.id, &secret)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, starting TOTP enrolment: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if !started {
+        tracing::error!("TOTP already enabled");
+        return Err(HttpError::unique_constraint_violation(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    let otpauth_uri =
+        totp::provisioning_uri(&secret, &app_state.env.totp_issuer, &jwt.user.username);
+
+    tracing::info!("setup_totp successful");
+    Ok(Json(TotpSetupResponseDto {
+        status: "success".to_string(),
+        secret,
+        otpauth_uri,
+    }))
+}
+
+/// Confirm enrolment with a first code from the app
+///
+/// Returns the recovery codes (shown once) and signs out the other devices,
+/// their sessions were opened with the password alone.
+#[instrument(skip(app_state, jwt, body), fields(username = %jwt.user.username))]
+pub async fn enable_totp(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<TotpCodeDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid enable_totp input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let pending = app_state
+        .db_client
+        .get_totp(jwt.user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting TOTP: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .filter(|t| t.enabled_at.is_none())
+        .ok_or_else(|| {
+            tracing::error!("No pending TOTP enrolment");
+            HttpError::bad_request("No pending two-factor setup, start it first".to_string())
+        })?;
+
+    let step = totp::verify_code(&pending.secret, &body.code, Utc::now().timestamp(), 0)
+        .ok_or_else(|| {
+            tracing::error!("Invalid TOTP code at enrolment");
+            HttpError::bad_request("Invalid authentication code".to_string())
+        })?;
+
+    let (recovery_codes, hashes) = new_recovery_codes();
+
+    let enabled = app_state
+        .db_client
+        .enable_totp(jwt.user.id, step, &hashes)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, enabling TOTP: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if !enabled {
+        tracing::error!("TOTP enrolment changed concurrently");
+        return Err(HttpError::unique_constraint_violation(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    let current_session = current_session_id(&jwt);
+    app_state
+        .redis_client
+        .delete_user_sessions(jwt.user.id, current_session.as_deref())
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, deleting sessions: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!("enable_totp successful");
+    Ok((
+        StatusCode::CREATED,
+        Json(RecoveryCodesResponseDto {
+            status: "success".to_string(),
+            recovery_codes,
+        }),
+    ))
+}
+
+/// Replace all recovery codes (e.g. most were used up or the list was lost)
+#[instrument(skip(app_state, jwt, body), fields(username = %jwt.user.username))]
+pub async fn regenerate_recovery_codes(
+    ClientIp(ip): ClientIp,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<TotpCodeDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid regenerate_recovery_codes input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    if !check_second_factor(
+        &app_state,
+        &jwt.user,
+        &ip.to_string(),
+        Some(&body.code),
+        None,
+    )
+    .await?
+    {
+        tracing::error!("Invalid TOTP code for recovery codes");
+        return Err(HttpError::bad_request(
+            "Invalid authentication code".to_string(),
+        ));
+    }
+
+    let (recovery_codes, hashes) = new_recovery_codes();
+
+    app_state
+        .db_client
+        .replace_recovery_codes(jwt.user.id, &hashes)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, replacing recovery codes: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!("regenerate_recovery_codes successful");
+    Ok(Json(RecoveryCodesResponseDto {
+        status: "success".to_string(),
+        recovery_codes,
+    }))
+}
+
+/// Turn TOTP off (or cancel a pending enrolment)
+///
+/// Not allowed while the user's role requires two-factor authentication,
+/// unless a passkey is left as second factor.
+#[instrument(skip(app_state, jwt, body), fields(username = %jwt.user.username))]
+pub async fn disable_totp(
+    ClientIp(ip): ClientIp,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<DisableTotpDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid disable_totp input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    if get_required_roles(&app_state)
+        .await?
+        .contains(&jwt.user.role)
+        && get_passkey_count(&app_state, jwt.user.id).await? == 0
+    {
+        tracing::error!("Tried to disable TOTP required for role");
+        return Err(HttpError::new(
+            "Two-factor authentication is required for your role".to_string(),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+
+    let password_matched = password::compare(&body.password, &jwt.user.password).map_err(|e| {
+        tracing::error!("Password comparison error: {}", e);
+        HttpError::server_error(ErrorMessage::ServerError.to_string())
+    })?;
+
+    if !password_matched {
+        tracing::error!("Password is incorrect");
+        return Err(HttpError::bad_request("Password is incorrect".to_string()));
+    }
+
+    let user_totp = app_state
+        .db_client
+        .get_totp(jwt.user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting TOTP: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .ok_or_else(|| {
+            tracing::error!("TOTP not set up");
+            HttpError::not_found("Two-factor authentication is not set up".to_string())
+        })?;
+
+    // A pending enrolment never protected the account, the password is enough to cancel it
+    if user_totp.enabled_at.is_some()
+        && !check_second_factor(
+            &app_state,
+            &jwt.user,
+            &ip.to_string(),
+            body.code.as_deref(),
+            body.recovery_code.as_deref(),
+        )
+        .await?
+    {
+        tracing::error!("Invalid second factor for disabling TOTP");
+        return Err(HttpError::bad_request(
+            "Invalid authentication code".to_string(),
+        ));
+    }
+
+    app_state
+        .db_client
+        .disable_totp(jwt.user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, disabling TOTP: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!("disable_totp successful");
+    Ok(Json(Response {
+        status: "success",
+        message: "Two-factor authentication disabled".to_string(),
+    }))
+}
+
+#[instrument(skip(app_state))]
+pub async fn get_mfa_policy(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    let required_roles = get_required_roles(&app_state).await?;
+
+    tracing::info!("get_mfa_policy successful");
+    Ok(Json(MfaPolicyResponseDto {
+        status: "success".to_string(),
+        required_roles,
+    }))
+}
+
+/// Require (or stop requiring) two-factor authentication for a role
+///
+/// Members without it keep access to their account but lose the privileges
+/// of the role until they enable it, see `role_check`. Admins can only
+/// require it for their own role once they use it themselves.
+#[instrument(skip(app_state, jwt, body), fields(admin = %jwt.user.username))]
+pub async fn update_mfa_policy(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<MfaPolicyDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid update_mfa_policy input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    if body.required && body.role == jwt.user.role {
+        let totp_enabled = app_state
+            .db_client
+            .get_totp(jwt.user.id)
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, getting TOTP: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?
+            .is_some_and(|t| t.enabled_at.is_some());
+
+        if !totp_enabled && get_passkey_count(&app_state, jwt.user.id).await? == 0 {
+            tracing::error!("Admin without second factor tried to require it for their role");
+            return Err(HttpError::bad_request(
+                "Enable two-factor authentication on your own account first".to_string(),
+            ));
+        }
+    }
+
+    app_state
+        .db_client
+        .set_mfa_policy(jwt.user.id, body.role, body.required)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, updating MFA policy: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let required_roles = get_required_roles(&app_state).await?;
+
+    tracing::info!(role = ?body.role, required = body.required, "update_mfa_policy successful");
+    Ok(Json(MfaPolicyResponseDto {
+        status: "success".to_string(),
+        required_roles,
+    }))
+}
@@ -0,0 +1,306 @@
+use crate::AppState;
+use crate::db::{CommentExt, ModerationExt};
+use crate::dtos::{
+    BulkModerateCommentsDto, CommentHistoryDto, CommentHistoryResponseDto, CommentVersionDto,
+    ModerateCommentDto, ModerationDeleteQuery, ModerationLogQuery, ModerationLogResponseDto,
+    ModerationQueueQuery, ModerationQueueResponseDto, PaginationDto, Response,
+};
+use crate::error::{ErrorMessage, HttpError};
+use crate::handler::report::{get_reports, resolve_report};
+use crate::middleware::{JWTAuthMiddleware, auth, role_check};
+use crate::models::{CommentStatus, UserRole};
+use axum::Extension;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use axum::routing::{get, post, put};
+use axum::{Router, middleware};
+use tracing::instrument;
+use validator::Validate;
+
+/// Comment moderation and report triage routes - admin only
+///
+/// Every comment status change and delete made here is written to the moderation log.
+pub fn moderation_handler(app_state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/comments", get(get_moderation_queue))
+        .route("/comments/bulk", post(bulk_moderate_comments))
+        .route(
+            "/comments/{comment_id}",
+            put(moderate_comment).delete(delete_comment),
+        )
+        .route("/comments/{comment_id}/history", get(get_comment_history))
+        .route("/log", get(get_moderation_log))
+        .route("/reports", get(get_reports))
+        .route("/reports/{report_id}", put(resolve_report))
+        .route_layer(middleware::from_fn(|req, next| {
+            role_check(req, next, vec![UserRole::Admin], None)
+        }))
+        .route_layer(middleware::from_fn_with_state(app_state, auth))
+}
+
+#[instrument(skip(app_state))]
+pub async fn get_moderation_queue(
+    Query(params): Query<ModerationQueueQuery>,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    params.validate().map_err(|e| {
+        tracing::error!("Invalid get_moderation_queue input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let status = params.status.unwrap_or(CommentStatus::Pending);
+    let page = params.page.unwrap_or(1);
+    let limit = params.limit.unwrap_or(20);
+
+    let comments = app_state
+        .db_client
+        .get_moderation_queue(status, page, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting moderation queue: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let total = app_state
+        .db_client
+        .get_moderation_queue_count(status)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting moderation queue count: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let total_pages = (total as f64 / limit as f64).ceil() as i32;
+
+    let response = Json(ModerationQueueResponseDto {
+        status: "success".to_string(),
+        data: comments,
+        pagination: PaginationDto {
+            page,
+            limit,
+            total: total as i32,
+            total_pages,
+        },
+    });
+    tracing::info!("get_moderation_queue successful");
+    Ok(response)
+}
+
+#[instrument(skip(app_state, body, jwt), fields(moderator = %jwt.user.username))]
+pub async fn moderate_comment(
+    Path(comment_id): Path<i32>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<ModerateCommentDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid moderate_comment input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let changed = app_state
+        .db_client
+        .set_comment_status(
+            Some(jwt.user.id),
+            &[comment_id],
+            body.status,
+            body.reason.as_deref(),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, moderating comment: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    // Nothing changed: either the comment doesn't exist or it already has this status
+    if changed == 0 {
+        tracing::warn!(comment_id, status = ?body.status, "Comment not found or unchanged");
+        return Err(HttpError::not_found(
+            "Comment not found or already in this status".to_string(),
+        ));
+    }
+
+    tracing::info!(comment_id, status = ?body.status, "moderate_comment successful");
+    Ok(Json(Response {
+        status: "success",
+        message: "Comment status updated".to_string(),
+    }))
+}
+
+/// Apply one status to many comments (bulk approve / reject from the queue)
+///
+/// Unknown ids and comments already in the target status are skipped.
+#[instrument(skip(app_state, body, jwt), fields(moderator = %jwt.user.username))]
+pub async fn bulk_moderate_comments(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<BulkModerateCommentsDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid bulk_moderate_comments input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let changed = app_state
+        .db_client
+        .set_comment_status(
+            Some(jwt.user.id),
+            &body.ids,
+            body.status,
+            body.reason.as_deref(),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, bulk moderating comments: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!(changed, status = ?body.status, "bulk_moderate_comments successful");
+    Ok(Json(Response {
+        status: "success",
+        message: format!("{} comment(s) updated", changed),
+    }))
+}
+
+/// Delete any comment, whoever wrote it
+#[instrument(skip(app_state, jwt), fields(moderator = %jwt.user.username))]
+pub async fn delete_comment(
+    Path(comment_id): Path<i32>,
+    Query(params): Query<ModerationDeleteQuery>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    params.validate().map_err(|e| {
+        tracing::error!("Invalid moderation delete_comment input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    app_state
+        .db_client
+        .moderator_delete_comment(jwt.user.id, comment_id, params.reason.as_deref())
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                tracing::warn!("Comment {} not found for moderator deletion", comment_id);
+                HttpError::not_found("Comment not found".to_string())
+            }
+            _ => {
+                tracing::error!("DB error, moderator deleting comment: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }
+        })?;
+
+    tracing::info!(comment_id, "moderation delete_comment successful");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[instrument(skip(app_state))]
+pub async fn get_moderation_log(
+    Query(params): Query<ModerationLogQuery>,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    params.validate().map_err(|e| {
+        tracing::error!("Invalid get_moderation_log input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let page = params.page.unwrap_or(1);
+    let limit = params.limit.unwrap_or(20);
+
+    let entries = app_state
+        .db_client
+        .get_moderation_log(params.comment_id, page, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting moderation log: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let total = app_state
+        .db_client
+        .get_moderation_log_count(params.comment_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting moderation log count: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let total_pages = (total as f64 / limit as f64).ceil() as i32;
+
+    let response = Json(ModerationLogResponseDto {
+        status: "success".to_string(),
+        data: entries,
+        pagination: PaginationDto {
+            page,
+            limit,
+            total: total as i32,
+            total_pages,
+        },
+    });
+    tracing::info!("get_moderation_log successful");
+    Ok(response)
+}
+
+/// Edit history of a comment, to see what it said when it was reported
+///
+/// Includes every previous version plus the current one (last, `replacedAt: null`).
+#[instrument(skip(app_state))]
+pub async fn get_comment_history(
+    Path(comment_id): Path<i32>,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    let comment = app_state
+        .db_client
+        .get_comment(comment_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting comment: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .ok_or_else(|| {
+            tracing::warn!("Comment {} not found for history", comment_id);
+            HttpError::not_found("Comment not found".to_string())
+        })?;
+
+    let revisions = app_state
+        .db_client
+        .get_comment_revisions(comment_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting comment revisions: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    // updated_at also changes on moderation, the current text dates from the last edit
+    let current_written_at = revisions
+        .last()
+        .map(|r| r.replaced_at)
+        .unwrap_or(comment.created_at);
+
+    let mut versions: Vec<CommentVersionDto> = revisions
+        .into_iter()
+        .map(|r| CommentVersionDto {
+            content: r.content,
+            content_html: r.content_html,
+            written_at: r.written_at,
+            replaced_at: Some(r.replaced_at),
+        })
+        .collect();
+    versions.push(CommentVersionDto {
+        content: comment.content,
+        content_html: comment.content_html,
+        written_at: current_written_at,
+        replaced_at: None,
+    });
+
+    tracing::info!("get_comment_history successful");
+    Ok(Json(CommentHistoryResponseDto {
+        status: "success".to_string(),
+        data: CommentHistoryDto {
+            comment_id,
+            edit_count: comment.edit_count,
+            versions,
+        },
+    }))
+}
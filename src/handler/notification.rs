@@ -0,0 +1,67 @@
+use crate::{
+    AppState,
+    db::NotificationExt,
+    dtos::{Response, UnsubscribeQuery},
+    error::{ErrorMessage, HttpError},
+    models::NotificationFrequency,
+    utils::signature::verify_unsubscribe,
+};
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    response::IntoResponse,
+    routing::post,
+};
+use tracing::instrument;
+
+/// Public notification routes
+///
+/// Preferences of the signed-in user live under `/api/users/notifications`.
+pub fn notification_handler() -> Router<AppState> {
+    Router::new().route("/unsubscribe", post(unsubscribe))
+}
+
+/// One-click unsubscribe from comment notification emails
+///
+/// Works without logging in: the link in the email (and its `List-Unsubscribe`
+/// header) carries the user id and an HMAC signature of it.
+/// Mail clients POST here with the body `List-Unsubscribe=One-Click` (RFC 8058),
+/// which is ignored - everything needed is in the query string.
+#[instrument(skip(app_state, params), fields(user_id = %params.user))]
+pub async fn unsubscribe(
+    Query(params): Query<UnsubscribeQuery>,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    if !verify_unsubscribe(
+        params.user,
+        &params.sig,
+        app_state.env.jwt_secret.as_bytes(),
+    ) {
+        tracing::error!("Invalid unsubscribe signature");
+        return Err(HttpError::bad_request(
+            "Invalid unsubscribe link".to_string(),
+        ));
+    }
+
+    app_state
+        .db_client
+        .set_notification_preference(params.user, NotificationFrequency::Off)
+        .await
+        .map_err(|e| match e {
+            // Account deleted since the email was sent
+            sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => {
+                tracing::error!("User not found for unsubscribe");
+                HttpError::not_found("User not found".to_string())
+            }
+            _ => {
+                tracing::error!("DB error, unsubscribing: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }
+        })?;
+
+    tracing::info!("unsubscribe successful");
+    Ok(Json(Response {
+        status: "success",
+        message: "You will no longer receive comment notification emails.".to_string(),
+    }))
+}
@@ -0,0 +1,605 @@
+use crate::AppState;
+use crate::db::{IdentityExt, UserExt};
+use crate::dtos::{
+    IdentityDto, IdentityListResponseDto, OAuthCallbackQueryDto, OAuthProvidersResponseDto,
+    Response,
+};
+use crate::error::{ErrorMessage, HttpError};
+use crate::handler::auth::{create_mfa_challenge, start_session, user_agent};
+use crate::mail::mails::send_welcome_email;
+use crate::middleware::JWTAuthMiddleware;
+use crate::models::{OAuthLoginState, User};
+use crate::oauth::{ExternalIdentity, OAuthProvider, pkce_pair};
+use crate::utils::password;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::Extension;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, header};
+use axum::response::{IntoResponse, Json, Redirect};
+use axum_client_ip::ClientIp;
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Seconds the user has to sign in at the provider
+const OAUTH_STATE_TTL_SECS: i64 = 600;
+
+/// Cookie binding a social login to the browser that started it
+const STATE_COOKIE: &str = "oauth_state";
+
+/// Only the callback needs the state cookie
+const STATE_COOKIE_PATH: &str = "/api/auth/oauth";
+
+/// Tries to find a free username before giving up
+const USERNAME_ATTEMPTS: usize = 5;
+
+/// Longest username generated from a provider profile
+const MAX_GENERATED_USERNAME_CHARS: usize = 30;
+
+/// 32 random bytes, hex encoded
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn find_provider<'a>(app_state: &'a AppState, name: &str) -> Result<&'a OAuthProvider, HttpError> {
+    app_state
+        .env
+        .oauth_providers
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| HttpError::not_found("Login provider not found".to_string()))
+}
+
+/// Callback URL of a provider, must be registered at the provider exactly like this
+fn redirect_uri(app_state: &AppState, provider: &OAuthProvider) -> String {
+    format!(
+        "{}/api/auth/oauth/{}/callback",
+        app_state.env.api_url, provider.name
+    )
+}
+
+/// Send the browser back to the frontend with the outcome of a social login
+///
+/// The details are in the URL fragment, so the MFA token doesn't end up in
+/// server logs or `Referer` headers. All values are URL safe (fixed codes,
+/// hex tokens, provider names).
+fn frontend_redirect(app_state: &AppState, fields: &[(&str, &str)]) -> Redirect {
+    let fragment = fields
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&");
+    Redirect::to(&format!(
+        "{}/auth/oauth/callback#{}",
+        app_state.env.frontend_url, fragment
+    ))
+}
+
+/// Password for accounts created through a provider
+///
+/// Random and thrown away, so the account can only be used through the
+/// provider until the user sets a password with "forgot password".
+fn unusable_password() -> Result<String, HttpError> {
+    password::hash(random_token()).map_err(|e| {
+        tracing::error!("Password hashing error: {}", e);
+        HttpError::server_error(ErrorMessage::ServerError.to_string())
+    })
+}
+
+/// Redirect to the provider, remembering the login (or link) in Redis and in a cookie
+async fn redirect_to_provider(
+    app_state: &AppState,
+    provider: &OAuthProvider,
+    link_user_id: Option<Uuid>,
+) -> Result<(CookieJar, Redirect), HttpError> {
+    let endpoints = provider
+        .endpoints(&app_state.http_client.conn)
+        .await
+        .map_err(|e| {
+            tracing::error!(provider = %provider.name, "OAuth error, getting endpoints: {}", e.message);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let (code_verifier, code_challenge) = pkce_pair();
+    let state = OAuthLoginState {
+        id: random_token(),
+        provider: provider.name.clone(),
+        code_verifier,
+        link_user_id,
+    };
+
+    app_state
+        .redis_client
+        .save_oauth_state(&state, OAUTH_STATE_TTL_SECS)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, saving OAuth state: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let authorize_url = provider
+        .authorize_url(
+            &endpoints,
+            &redirect_uri(app_state, provider),
+            &state.id,
+            &code_challenge,
+        )
+        .map_err(|e| {
+            tracing::error!(provider = %provider.name, "OAuth error, building authorize URL: {}", e.message);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    // SameSite=Lax: the cookie must come along on the top-level redirect back from the provider
+    let cookie = Cookie::build((STATE_COOKIE, state.id))
+        .path(STATE_COOKIE_PATH)
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::seconds(OAUTH_STATE_TTL_SECS))
+        .build();
+
+    Ok((CookieJar::new().add(cookie), Redirect::to(&authorize_url)))
+}
+
+/// Providers the frontend can show login buttons for
+pub async fn get_oauth_providers(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(OAuthProvidersResponseDto {
+        status: "success".to_string(),
+        providers: app_state
+            .env
+            .oauth_providers
+            .iter()
+            .map(|p| p.name.clone())
+            .collect(),
+    })
+}
+
+/// Start a social login: redirect the browser to the provider
+///
+/// Authorization code flow with PKCE, the provider sends the browser back to
+/// `/auth/oauth/{provider}/callback`.
+#[instrument(skip(app_state))]
+pub async fn start_oauth_login(
+    State(app_state): State<AppState>,
+    Path(provider): Path<String>,
+) -> Result<impl IntoResponse, HttpError> {
+    let provider = find_provider(&app_state, &provider)?;
+    let response = redirect_to_provider(&app_state, provider, None).await?;
+
+    tracing::info!("start_oauth_login successful");
+    Ok(response)
+}
+
+/// Start linking an account at a provider to the signed-in user
+///
+/// Same flow as the login, the callback links the account instead of signing in.
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn start_oauth_link(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Path(provider): Path<String>,
+) -> Result<impl IntoResponse, HttpError> {
+    let provider = find_provider(&app_state, &provider)?;
+    let response = redirect_to_provider(&app_state, provider, Some(jwt.user.id)).await?;
+
+    tracing::info!("start_oauth_link successful");
+    Ok(response)
+}
+
+/// The provider sent the browser back: sign in (or link) and return to the frontend
+///
+/// Always answers with a redirect to `{FRONTEND_URL}/auth/oauth/callback`, with
+/// `status` in the fragment:
+/// - `success`: signed in, the token cookies are set
+/// - `mfa_required`: the user has a second factor, `mfaToken` and `methods`
+///   are passed on to `/auth/login/mfa` as after a password login
+/// - `linked`: the account was linked to the signed-in user
+/// - `error`: see `error` for the reason
+#[instrument(skip(app_state, headers, cookie_jar, query))]
+pub async fn oauth_callback(
+    ClientIp(ip): ClientIp,
+    State(app_state): State<AppState>,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    cookie_jar: CookieJar,
+    Query(query): Query<OAuthCallbackQueryDto>,
+) -> impl IntoResponse {
+    let cookie_state = cookie_jar.get(STATE_COOKIE).map(|c| c.value().to_string());
+    let cookie_jar = cookie_jar.remove(Cookie::build(STATE_COOKIE).path(STATE_COOKIE_PATH));
+
+    let result = finish_oauth(
+        &app_state,
+        &provider,
+        query,
+        cookie_state,
+        ip.to_string(),
+        user_agent(&headers),
+    )
+    .await;
+
+    match result {
+        Ok(response) => {
+            tracing::info!("oauth_callback successful");
+            (cookie_jar, response).into_response()
+        }
+        Err(error) => (
+            cookie_jar,
+            frontend_redirect(&app_state, &[("status", "error"), ("error", error)]),
+        )
+            .into_response(),
+    }
+}
+
+/// The callback, with errors as codes for the frontend
+async fn finish_oauth(
+    app_state: &AppState,
+    provider_name: &str,
+    query: OAuthCallbackQueryDto,
+    cookie_state: Option<String>,
+    ip: String,
+    user_agent: String,
+) -> Result<axum::response::Response, &'static str> {
+    let provider = find_provider(app_state, provider_name).map_err(|_| "unknown_provider")?;
+
+    if let Some(error) = query.error {
+        tracing::warn!(provider = %provider.name, "Provider returned an error: {}", error);
+        return Err("access_denied");
+    }
+
+    let (Some(code), Some(state_id)) = (query.code, query.state) else {
+        tracing::error!("OAuth callback without code or state");
+        return Err("invalid_request");
+    };
+
+    // The state must come back to the browser that started the login,
+    // otherwise someone could sign a victim into their own account (login CSRF)
+    if cookie_state.as_deref() != Some(state_id.as_str()) {
+        tracing::error!("OAuth state doesn't match the state cookie");
+        return Err("invalid_state");
+    }
+
+    let state = app_state
+        .redis_client
+        .take_oauth_state(&state_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, getting OAuth state: {}", e);
+            "server_error"
+        })?
+        .filter(|s| s.provider == provider.name)
+        .ok_or_else(|| {
+            tracing::error!("OAuth state not found or expired");
+            "invalid_state"
+        })?;
+
+    let client = &app_state.http_client.conn;
+
+    let identity = async {
+        let endpoints = provider.endpoints(client).await?;
+        let access_token = provider
+            .exchange_code(
+                client,
+                &endpoints,
+                &code,
+                &redirect_uri(app_state, provider),
+                &state.code_verifier,
+            )
+            .await?;
+        provider
+            .fetch_identity(client, &endpoints, &access_token)
+            .await
+    }
+    .await
+    .map_err(|e| {
+        tracing::error!(provider = %provider.name, "OAuth error, getting identity: {}", e.message);
+        "provider_error"
+    })?;
+
+    if let Some(user_id) = state.link_user_id {
+        link_identity(app_state, provider, user_id, &identity).await?;
+        return Ok(frontend_redirect(
+            app_state,
+            &[("status", "linked"), ("provider", &provider.name)],
+        )
+        .into_response());
+    }
+
+    let user = find_or_create_user(app_state, provider, &identity).await?;
+
+    if let Some(challenge) = create_mfa_challenge(app_state, &user, ip.clone(), user_agent.clone())
+        .await
+        .map_err(|_| "server_error")?
+    {
+        let methods = challenge.methods.join(",");
+        return Ok(frontend_redirect(
+            app_state,
+            &[
+                ("status", "mfa_required"),
+                ("mfaToken", &challenge.mfa_token),
+                ("methods", &methods),
+            ],
+        )
+        .into_response());
+    }
+
+    let session = start_session(app_state, &user, ip, user_agent)
+        .await
+        .map_err(|_| "server_error")?;
+
+    let mut response = frontend_redirect(app_state, &[("status", "success")]).into_response();
+    for cookie in session.headers().get_all(header::SET_COOKIE) {
+        response
+            .headers_mut()
+            .append(header::SET_COOKIE, cookie.clone());
+    }
+    Ok(response)
+}
+
+/// Link the external account to the user who started the link
+async fn link_identity(
+    app_state: &AppState,
+    provider: &OAuthProvider,
+    user_id: Uuid,
+    identity: &ExternalIdentity,
+) -> Result<(), &'static str> {
+    let linked_user = app_state
+        .db_client
+        .get_identity_user(&provider.name, &identity.subject)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting identity: {}", e);
+            "server_error"
+        })?;
+
+    match linked_user {
+        Some(user) if user.id == user_id => return Ok(()),
+        Some(_) => {
+            tracing::error!(provider = %provider.name, "Identity is linked to another user");
+            return Err("identity_in_use");
+        }
+        None => {}
+    }
+
+    let result = app_state
+        .db_client
+        .link_identity(
+            user_id,
+            &provider.name,
+            &identity.subject,
+            identity.email.as_deref(),
+        )
+        .await;
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            tracing::error!(provider = %provider.name, "User already linked an account of this provider");
+            Err("provider_already_linked")
+        }
+        Err(e) => {
+            tracing::error!("DB error, linking identity: {}", e);
+            Err("server_error")
+        }
+    }
+}
+
+/// User to sign in for an external account
+///
+/// 1. The account is linked already: its user
+/// 2. The provider asserts an email of an existing user: link to that user
+///    (see `link_identity_by_email` for unverified accounts)
+/// 3. Otherwise a new, verified account is created
+///
+/// 2 and 3 need an email the provider has verified, anything else could be
+/// used to take over someone else's account.
+async fn find_or_create_user(
+    app_state: &AppState,
+    provider: &OAuthProvider,
+    identity: &ExternalIdentity,
+) -> Result<User, &'static str> {
+    let linked_user = app_state
+        .db_client
+        .get_identity_user(&provider.name, &identity.subject)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting identity: {}", e);
+            "server_error"
+        })?;
+
+    if let Some(user) = linked_user {
+        if let Err(e) = app_state
+            .db_client
+            .touch_identity(&provider.name, &identity.subject)
+            .await
+        {
+            tracing::warn!("Failed to update identity last use: {:?}", e);
+        }
+        return Ok(user);
+    }
+
+    let Some(email) = identity
+        .email
+        .as_deref()
+        .filter(|_| identity.email_verified)
+    else {
+        tracing::error!(provider = %provider.name, "Provider didn't assert a verified email");
+        return Err("email_not_verified");
+    };
+
+    let unusable_password = unusable_password().map_err(|_| "server_error")?;
+
+    let existing_user = app_state
+        .db_client
+        .get_user(None, None, Some(email), None)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting user: {}", e);
+            "server_error"
+        })?;
+
+    if let Some(user) = existing_user {
+        app_state
+            .db_client
+            .link_identity_by_email(&user, &provider.name, &identity.subject, &unusable_password)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                    tracing::error!(user_id = %user.id, provider = %provider.name, "User linked another account of this provider");
+                    "provider_already_linked"
+                }
+                e => {
+                    tracing::error!("DB error, linking identity: {}", e);
+                    "server_error"
+                }
+            })?;
+
+        // The password was replaced, so whoever signed in with it is signed out
+        if !user.verified {
+            let revoked = app_state
+                .redis_client
+                .delete_user_sessions(user.id, None)
+                .await;
+            if let Err(e) = revoked {
+                tracing::warn!("Failed to revoke sessions: {:?}", e);
+            }
+        }
+
+        tracing::info!(user_id = %user.id, provider = %provider.name, "Identity linked by email");
+        return Ok(user);
+    }
+
+    let username = free_username(app_state, identity, email).await?;
+    let user = app_state
+        .db_client
+        .create_user_with_identity(
+            &username,
+            email,
+            &unusable_password,
+            true,
+            &provider.name,
+            &identity.subject,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, creating user: {}", e);
+            "server_error"
+        })?;
+
+    if let Err(e) = send_welcome_email(&user.email, &user.username).await {
+        tracing::error!("Failed to send welcome email: {}", e);
+    }
+
+    tracing::info!(user_id = %user.id, provider = %provider.name, "User created from identity");
+    Ok(user)
+}
+
+/// Pick a username for a new account, from the provider profile or the email
+///
+/// A random suffix is added while the name is taken.
+async fn free_username(
+    app_state: &AppState,
+    identity: &ExternalIdentity,
+    email: &str,
+) -> Result<String, &'static str> {
+    let source = identity
+        .preferred_username
+        .as_deref()
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default());
+    let mut base: String = source
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        .take(MAX_GENERATED_USERNAME_CHARS)
+        .collect();
+    if base.is_empty() {
+        base = "user".to_string();
+    }
+
+    for attempt in 0..USERNAME_ATTEMPTS {
+        let candidate = if attempt == 0 {
+            base.clone()
+        } else {
+            format!("{}-{}", base, &random_token()[..6])
+        };
+
+        let taken = app_state
+            .db_client
+            .get_user(None, Some(&candidate), None, None)
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, getting user: {}", e);
+                "server_error"
+            })?
+            .is_some();
+
+        if !taken {
+            return Ok(candidate);
+        }
+    }
+
+    tracing::error!("No free username found");
+    Err("server_error")
+}
+
+/// External accounts linked to the current user
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn get_identities(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let identities = app_state
+        .db_client
+        .get_user_identities(jwt.user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting identities: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!("get_identities successful");
+    Ok(Json(IdentityListResponseDto {
+        status: "success".to_string(),
+        data: identities
+            .into_iter()
+            .map(|i| IdentityDto {
+                id: i.id,
+                provider: i.provider,
+                email: i.email,
+                created_at: i.created_at,
+                last_used_at: i.last_used_at,
+            })
+            .collect(),
+    }))
+}
+
+/// Unlink an external account
+///
+/// Always allowed: accounts created through a provider can still be signed
+/// into after setting a password with "forgot password".
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn delete_identity(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Path(identity_id): Path<i64>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state
+        .db_client
+        .delete_identity(jwt.user.id, identity_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, deleting identity: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if !deleted {
+        tracing::error!(identity_id, "Identity not found");
+        return Err(HttpError::not_found("Linked account not found".to_string()));
+    }
+
+    tracing::info!("delete_identity successful");
+    Ok(Json(Response {
+        status: "success",
+        message: "Account unlinked".to_string(),
+    }))
+}
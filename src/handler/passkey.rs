@@ -0,0 +1,493 @@
+use crate::AppState;
+use crate::db::{MfaExt, PasskeyExt, UserExt};
+use crate::dtos::{
+    MfaTokenDto, PasskeyDto, PasskeyListResponseDto, PasskeyLoginDto, PasskeyLoginOptionsDto,
+    PasskeyRegistrationDto, PasskeyRegistrationOptionsDto, Response,
+};
+use crate::error::{ErrorMessage, HttpError};
+use crate::handler::auth::{attempt_mfa_challenge, start_session, user_agent};
+use crate::middleware::JWTAuthMiddleware;
+use crate::models::MfaChallenge;
+use axum::Extension;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Json};
+use axum_client_ip::ClientIp;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tracing::instrument;
+use uuid::Uuid;
+use validator::Validate;
+use webauthn_rs::prelude::{
+    AuthenticationResult, DiscoverableAuthentication, DiscoverableKey, Passkey,
+    PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+};
+
+/// Seconds the browser has to answer a passkey prompt
+const CEREMONY_TTL_SECS: i64 = 300;
+
+/// Keep the state of a passkey ceremony in Redis until the browser answers
+async fn save_ceremony<T: Serialize>(
+    app_state: &AppState,
+    ceremony: &str,
+    state: &T,
+) -> Result<(), HttpError> {
+    let state = serde_json::to_string(state).map_err(|e| {
+        tracing::error!("Passkey state serialization error: {}", e);
+        HttpError::server_error(ErrorMessage::ServerError.to_string())
+    })?;
+
+    app_state
+        .redis_client
+        .save_webauthn_state(ceremony, &state, CEREMONY_TTL_SECS)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, saving passkey state: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })
+}
+
+/// Take the state of a passkey ceremony, None if it expired or was already answered
+async fn take_ceremony<T: DeserializeOwned>(
+    app_state: &AppState,
+    ceremony: &str,
+) -> Result<Option<T>, HttpError> {
+    let state = app_state
+        .redis_client
+        .take_webauthn_state(ceremony)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, getting passkey state: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    state
+        .map(|s| serde_json::from_str(&s))
+        .transpose()
+        .map_err(|e| {
+            tracing::error!("Passkey state deserialization error: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })
+}
+
+/// Passkeys of a user, with the id of their row
+async fn load_passkeys(
+    app_state: &AppState,
+    user_id: Uuid,
+) -> Result<Vec<(i64, Passkey)>, HttpError> {
+    let rows = app_state
+        .db_client
+        .get_user_passkeys(user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting passkeys: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    rows.into_iter()
+        .map(|row| {
+            let passkey = serde_json::from_str(&row.credential).map_err(|e| {
+                tracing::error!(passkey_id = row.id, "Stored passkey is corrupt: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?;
+            Ok((row.id, passkey))
+        })
+        .collect()
+}
+
+/// Store the updated credential (signature counter, backup state) after a login
+async fn record_passkey_use(
+    app_state: &AppState,
+    passkeys: &mut [(i64, Passkey)],
+    result: &AuthenticationResult,
+) -> Result<(), HttpError> {
+    let Some((passkey_id, passkey)) = passkeys
+        .iter_mut()
+        .find(|(_, p)| p.cred_id() == result.cred_id())
+    else {
+        return Ok(());
+    };
+
+    passkey.update_credential(result);
+    let credential = serde_json::to_string(passkey).map_err(|e| {
+        tracing::error!("Passkey serialization error: {}", e);
+        HttpError::server_error(ErrorMessage::ServerError.to_string())
+    })?;
+
+    app_state
+        .db_client
+        .update_passkey_credential(*passkey_id, &credential)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, updating passkey: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })
+}
+
+/// Registration, step 1: options for `navigator.credentials.create()`
+///
+/// The user's existing passkeys are excluded, so the same authenticator
+/// isn't registered twice. One registration per user can be pending.
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn start_passkey_registration(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let existing = load_passkeys(&app_state, jwt.user.id).await?;
+    let exclude = existing.iter().map(|(_, p)| p.cred_id().clone()).collect();
+
+    let (options, state) = app_state
+        .webauthn
+        .start_passkey_registration(
+            jwt.user.id,
+            &jwt.user.username,
+            &jwt.user.username,
+            Some(exclude),
+        )
+        .map_err(|e| {
+            tracing::error!("Passkey registration start error: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    save_ceremony(&app_state, &format!("register:{}", jwt.user.id), &state).await?;
+
+    tracing::info!("start_passkey_registration successful");
+    Ok(Json(PasskeyRegistrationOptionsDto {
+        status: "success".to_string(),
+        options,
+    }))
+}
+
+/// Registration, step 2: check the browser's answer and store the passkey
+#[instrument(skip(app_state, jwt, body), fields(username = %jwt.user.username))]
+pub async fn finish_passkey_registration(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<PasskeyRegistrationDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid finish_passkey_registration input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let state: PasskeyRegistration =
+        take_ceremony(&app_state, &format!("register:{}", jwt.user.id))
+            .await?
+            .ok_or_else(|| {
+                tracing::error!("No pending passkey registration");
+                HttpError::bad_request(
+                    "Passkey registration expired, please start again".to_string(),
+                )
+            })?;
+
+    let passkey = app_state
+        .webauthn
+        .finish_passkey_registration(&body.credential, &state)
+        .map_err(|e| {
+            tracing::error!("Passkey registration failed: {}", e);
+            HttpError::bad_request("Passkey registration failed".to_string())
+        })?;
+
+    let credential = serde_json::to_string(&passkey).map_err(|e| {
+        tracing::error!("Passkey serialization error: {}", e);
+        HttpError::server_error(ErrorMessage::ServerError.to_string())
+    })?;
+
+    app_state
+        .db_client
+        .save_passkey(
+            jwt.user.id,
+            body.name.trim(),
+            passkey.cred_id().as_ref(),
+            &credential,
+        )
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                tracing::error!("Passkey already registered");
+                HttpError::unique_constraint_violation(
+                    "This passkey is already registered".to_string(),
+                )
+            }
+            _ => {
+                tracing::error!("DB error, saving passkey: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }
+        })?;
+
+    tracing::info!("finish_passkey_registration successful");
+    Ok((
+        StatusCode::CREATED,
+        Json(Response {
+            status: "success",
+            message: "Passkey registered".to_string(),
+        }),
+    ))
+}
+
+/// Passwordless login, step 1: options for `navigator.credentials.get()`
+///
+/// No user is named, the browser offers the passkeys it has for this site
+/// and the answer tells which user it belongs to.
+#[instrument(skip(app_state))]
+pub async fn start_passkey_login(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    let (options, state) = app_state
+        .webauthn
+        .start_discoverable_authentication()
+        .map_err(|e| {
+            tracing::error!("Passkey login start error: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let ceremony_id = Uuid::new_v4().to_string();
+    save_ceremony(&app_state, &format!("login:{}", ceremony_id), &state).await?;
+
+    tracing::info!("start_passkey_login successful");
+    Ok(Json(PasskeyLoginOptionsDto {
+        status: "success".to_string(),
+        ceremony_id: Some(ceremony_id),
+        options,
+    }))
+}
+
+/// Passwordless login, step 2: check the browser's answer and start a session
+///
+/// A passkey verifies the user (PIN/biometrics) on top of possession of the
+/// device, so no second factor is asked for.
+#[instrument(skip(app_state, headers, body))]
+pub async fn finish_passkey_login(
+    ClientIp(ip): ClientIp,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<PasskeyLoginDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid finish_passkey_login input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let state: DiscoverableAuthentication =
+        take_ceremony(&app_state, &format!("login:{}", body.ceremony_id))
+            .await?
+            .ok_or_else(|| {
+                tracing::error!("Passkey login ceremony not found or expired");
+                HttpError::unauthorized(ErrorMessage::InvalidToken.to_string())
+            })?;
+
+    let (user_id, _) = app_state
+        .webauthn
+        .identify_discoverable_authentication(&body.credential)
+        .map_err(|e| {
+            tracing::error!("Passkey answer without user handle: {}", e);
+            HttpError::unauthorized("Passkey login failed".to_string())
+        })?;
+
+    let mut passkeys = load_passkeys(&app_state, user_id).await?;
+    let keys: Vec<DiscoverableKey> = passkeys.iter().map(|(_, p)| p.into()).collect();
+
+    let result = app_state
+        .webauthn
+        .finish_discoverable_authentication(&body.credential, state, &keys)
+        .map_err(|e| {
+            tracing::error!(user_id = %user_id, "Passkey login failed: {}", e);
+            HttpError::unauthorized("Passkey login failed".to_string())
+        })?;
+
+    record_passkey_use(&app_state, &mut passkeys, &result).await?;
+
+    let user = app_state
+        .db_client
+        .get_user(Some(user_id), None, None, None)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting user: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .ok_or_else(|| {
+            tracing::error!("User of passkey not found");
+            HttpError::unauthorized("Passkey login failed".to_string())
+        })?;
+
+    let response = start_session(&app_state, &user, ip.to_string(), user_agent(&headers)).await?;
+    tracing::info!(user_id = %user.id, ip = %ip, "Passkey login successful");
+    Ok(response)
+}
+
+/// Second login step with a passkey: options for `navigator.credentials.get()`
+///
+/// The answer is sent to `/auth/login/mfa` as `credential`. Asking for the
+/// options counts as one of the attempts of the MFA token.
+#[instrument(skip(app_state, body))]
+pub async fn start_passkey_mfa(
+    State(app_state): State<AppState>,
+    Json(body): Json<MfaTokenDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid start_passkey_mfa input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let challenge = attempt_mfa_challenge(&app_state, &body.mfa_token).await?;
+
+    let passkeys: Vec<Passkey> = load_passkeys(&app_state, challenge.user_id)
+        .await?
+        .into_iter()
+        .map(|(_, p)| p)
+        .collect();
+    if passkeys.is_empty() {
+        tracing::error!(user_id = %challenge.user_id, "No passkey for MFA");
+        return Err(HttpError::bad_request("No passkey registered".to_string()));
+    }
+
+    let (options, state) = app_state
+        .webauthn
+        .start_passkey_authentication(&passkeys)
+        .map_err(|e| {
+            tracing::error!("Passkey MFA start error: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    save_ceremony(&app_state, &format!("mfa:{}", challenge.id), &state).await?;
+
+    tracing::info!("start_passkey_mfa successful");
+    Ok(Json(PasskeyLoginOptionsDto {
+        status: "success".to_string(),
+        ceremony_id: None,
+        options,
+    }))
+}
+
+/// Check the passkey answer of a second login step
+///
+/// # Returns
+/// false if no passkey prompt is pending for the challenge or the answer is wrong
+pub async fn verify_passkey_mfa(
+    app_state: &AppState,
+    challenge: &MfaChallenge,
+    credential: &PublicKeyCredential,
+) -> Result<bool, HttpError> {
+    let state: Option<PasskeyAuthentication> =
+        take_ceremony(app_state, &format!("mfa:{}", challenge.id)).await?;
+    let Some(state) = state else {
+        return Ok(false);
+    };
+
+    // The prompt only allowed this user's passkeys, so a valid answer is theirs
+    let result = match app_state
+        .webauthn
+        .finish_passkey_authentication(credential, &state)
+    {
+        Ok(result) => result,
+        Err(e) => {
+            tracing::error!(user_id = %challenge.user_id, "Passkey MFA failed: {}", e);
+            return Ok(false);
+        }
+    };
+
+    let mut passkeys = load_passkeys(app_state, challenge.user_id).await?;
+    record_passkey_use(app_state, &mut passkeys, &result).await?;
+
+    Ok(true)
+}
+
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn get_passkeys(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let passkeys = app_state
+        .db_client
+        .get_user_passkeys(jwt.user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting passkeys: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let data = passkeys
+        .into_iter()
+        .map(|p| PasskeyDto {
+            id: p.id,
+            name: p.name,
+            created_at: p.created_at,
+            last_used_at: p.last_used_at,
+        })
+        .collect();
+
+    tracing::info!("get_passkeys successful");
+    Ok(Json(PasskeyListResponseDto {
+        status: "success".to_string(),
+        data,
+    }))
+}
+
+/// Remove a passkey
+///
+/// Not allowed if it's the last second factor of a user whose role requires one.
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn delete_passkey(
+    Path(passkey_id): Path<i64>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let required = app_state
+        .db_client
+        .get_mfa_required_roles()
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting MFA policy: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .contains(&jwt.user.role);
+
+    if required {
+        let totp_enabled = app_state
+            .db_client
+            .get_totp(jwt.user.id)
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, getting TOTP: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?
+            .is_some_and(|t| t.enabled_at.is_some());
+
+        let passkey_count = app_state
+            .db_client
+            .get_passkey_count(jwt.user.id)
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, counting passkeys: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?;
+
+        if !totp_enabled && passkey_count <= 1 {
+            tracing::error!("Tried to delete the last second factor required for role");
+            return Err(HttpError::new(
+                "Two-factor authentication is required for your role".to_string(),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+    }
+
+    let deleted = app_state
+        .db_client
+        .delete_passkey(jwt.user.id, passkey_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, deleting passkey: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if !deleted {
+        tracing::warn!("Passkey {} not found", passkey_id);
+        return Err(HttpError::not_found("Passkey not found".to_string()));
+    }
+
+    tracing::info!("delete_passkey successful");
+    Ok(Json(Response {
+        status: "success",
+        message: "Passkey removed".to_string(),
+    }))
+}
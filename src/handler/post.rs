@@ -1,47 +1,61 @@
 use std::collections::HashSet;
-use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
 
 use crate::AppState;
-use crate::db::PostExt;
+use crate::db::{MediaExt, NewMedia, PostExt};
 use crate::dtos::{
-    InputPostDto, Lang, LangQuery, PaginationDto, PostResponseDto, PostsPaginationResponseDto,
-    PostsQueryParams, UploadResponse,
+    ImageVariantDto, InputPostDto, Lang, LangQuery, PaginationDto, PostPaginationDto,
+    PostResponseDto, PostsPaginationResponseDto, PostsQueryParams, UploadResponse, WithReactions,
 };
 use crate::error::{ErrorMessage, HttpError};
 use crate::handler::comment::comment_handler;
+use crate::handler::reaction::{add_post_reaction, reaction_summaries, remove_post_reaction};
+use crate::handler::report::report_post;
 use crate::middleware::JWTAuthMiddleware;
-use crate::middleware::{auth, role_check};
-use crate::models::UserRole;
+use crate::middleware::{auth, optional_auth, role_check};
+use crate::models::{ApiScope, Media, MediaVariant, ReactionTarget, UserRole};
+use crate::utils::image_processing::{self, EncodedVariant};
+use crate::utils::uploads::{self, remove_upload_files};
 use axum::Extension;
 use axum::extract::{DefaultBodyLimit, Multipart, Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Json};
 use axum::routing::{get, post, put};
 use axum::{Router, middleware};
+use sha2::{Digest, Sha256};
 use tracing::instrument;
 use uuid::Uuid;
 use validator::Validate;
 
 pub fn post_handler(app_state: AppState) -> Router<AppState> {
     Router::new()
-        .route("/", get(get_posts))
+        .route(
+            "/",
+            get(get_posts).route_layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                optional_auth,
+            )),
+        )
         .route(
             "/",
             post(create_post)
                 .route_layer(middleware::from_fn(|req, next| {
-                    role_check(req, next, vec![UserRole::Admin])
+                    role_check(req, next, vec![UserRole::Admin], Some(ApiScope::PostsWrite))
                 }))
                 .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
         )
-        .route("/{post_id}", get(get_post))
+        .route(
+            "/{post_id}",
+            get(get_post).route_layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                optional_auth,
+            )),
+        )
         .route(
             "/{post_id}",
             put(edit_post)
                 .delete(delete_post)
                 .route_layer(middleware::from_fn(|req, next| {
-                    role_check(req, next, vec![UserRole::Admin])
+                    role_check(req, next, vec![UserRole::Admin], Some(ApiScope::PostsWrite))
                 }))
                 .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
         )
@@ -50,17 +64,58 @@ pub fn post_handler(app_state: AppState) -> Router<AppState> {
             post(upload_image)
                 .layer(DefaultBodyLimit::max(10 * 1024 * 1024))
                 .route_layer(middleware::from_fn(|req, next| {
-                    role_check(req, next, vec![UserRole::Admin])
+                    role_check(req, next, vec![UserRole::Admin], Some(ApiScope::MediaWrite))
+                }))
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/{post_id}/reactions/{reaction}",
+            put(add_post_reaction)
+                .delete(remove_post_reaction)
+                .route_layer(middleware::from_fn(|req, next| {
+                    role_check(req, next, vec![UserRole::Admin, UserRole::User], None)
+                }))
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/{post_id}/report",
+            post(report_post)
+                .route_layer(middleware::from_fn(|req, next| {
+                    role_check(req, next, vec![UserRole::Admin, UserRole::User], None)
                 }))
                 .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
         )
         .nest("/{post_id}/comments", comment_handler(app_state))
 }
 
-#[instrument(skip(app_state))]
+/// Attach reaction summaries to the posts of a list view
+pub(crate) async fn posts_with_reactions(
+    app_state: &AppState,
+    posts: Vec<PostPaginationDto>,
+    viewer_id: Option<Uuid>,
+) -> Result<Vec<WithReactions<PostPaginationDto>>, HttpError> {
+    let targets: Vec<ReactionTarget> = posts.iter().map(|p| ReactionTarget::Post(p.id)).collect();
+    let mut reactions = reaction_summaries(app_state, &targets, viewer_id).await?;
+
+    Ok(posts
+        .into_iter()
+        .map(|post| {
+            let reactions = reactions
+                .remove(&ReactionTarget::Post(post.id))
+                .unwrap_or_default();
+            WithReactions {
+                item: post,
+                reactions,
+            }
+        })
+        .collect())
+}
+
+#[instrument(skip(app_state, viewer))]
 pub async fn get_posts(
     Query(params): Query<PostsQueryParams>,
     State(app_state): State<AppState>,
+    viewer: Option<Extension<JWTAuthMiddleware>>,
 ) -> Result<impl IntoResponse, HttpError> {
     params.validate().map_err(|e| {
         tracing::error!("Invalid get_posts input: {}", e);
@@ -98,6 +153,9 @@ pub async fn get_posts(
 
     let total_pages = (total as f64 / limit as f64).ceil() as i32;
 
+    let viewer_id = viewer.map(|Extension(jwt)| jwt.user.id);
+    let posts = posts_with_reactions(&app_state, posts, viewer_id).await?;
+
     let response = Json(PostsPaginationResponseDto {
         status: "success".to_string(),
         data: posts,
@@ -112,16 +170,20 @@ pub async fn get_posts(
     Ok(response)
 }
 
-#[instrument(skip(app_state))]
+#[instrument(skip(app_state, viewer))]
 pub async fn get_post(
     Path(post_id): Path<i32>,
     Query(q): Query<LangQuery>,
     State(app_state): State<AppState>,
+    viewer: Option<Extension<JWTAuthMiddleware>>,
 ) -> Result<impl IntoResponse, HttpError> {
     let lang = q.lang.unwrap_or(Lang::En);
+    let viewer = viewer.map(|Extension(jwt)| jwt.user);
+    // Admins can still open posts hidden after user reports
+    let include_hidden = viewer.as_ref().is_some_and(|u| u.role == UserRole::Admin);
     let post = app_state
         .db_client
-        .get_post(post_id, lang)
+        .get_post(post_id, lang, include_hidden)
         .await
         .map_err(|e| match e {
             sqlx::Error::RowNotFound => {
@@ -134,9 +196,19 @@ pub async fn get_post(
             }
         })?;
 
+    let target = ReactionTarget::Post(post_id);
+    let viewer_id = viewer.map(|u| u.id);
+    let reactions = reaction_summaries(&app_state, &[target], viewer_id)
+        .await?
+        .remove(&target)
+        .unwrap_or_default();
+
     let response = Json(PostResponseDto {
         status: "success".to_string(),
-        data: post,
+        data: WithReactions {
+            item: post,
+            reactions,
+        },
     });
     tracing::info!("get_post successful");
     Ok(response)
@@ -182,6 +254,15 @@ pub async fn create_post(
         })?;
 
     let post_id = result.id;
+
+    // Record which uploads the post uses so the orphan cleanup leaves them alone
+    // The post is already saved, so a failure here must not fail the request
+    // (a retry would create a duplicate); the orphan cleanup also matches
+    // media ids in post content, so it won't remove uploads this misses
+    if let Err(e) = app_state.db_client.sync_post_media(post_id).await {
+        tracing::warn!("DB error, syncing post media: {}", e);
+    }
+
     let app_state_clone = app_state.clone();
     let raw_text_clone = raw_text.clone();
     let title_clone = title.clone();
@@ -213,9 +294,13 @@ pub async fn create_post(
         }
     });
 
+    // A new post has no reactions yet
     let response = Json(PostResponseDto {
         status: "success".to_string(),
-        data: result,
+        data: WithReactions {
+            item: result,
+            reactions: Default::default(),
+        },
     });
     tracing::info!("create_post successful");
     Ok((StatusCode::CREATED, response))
@@ -258,6 +343,16 @@ pub async fn edit_post(
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
+    if let Err(e) = app_state.db_client.sync_post_media(post_id).await {
+        tracing::warn!("DB error, syncing post media: {}", e);
+    }
+
+    let target = ReactionTarget::Post(post_id);
+    let reactions = reaction_summaries(&app_state, &[target], Some(user_id))
+        .await?
+        .remove(&target)
+        .unwrap_or_default();
+
     tokio::spawn(async move {
         let summary = app_state
             .http_client
@@ -289,7 +384,10 @@ pub async fn edit_post(
 
     let response = Json(PostResponseDto {
         status: "success".to_string(),
-        data: result,
+        data: WithReactions {
+            item: result,
+            reactions,
+        },
     });
     tracing::info!("edit_post successful");
     Ok(response)
@@ -315,14 +413,12 @@ pub async fn delete_post(
     Ok(StatusCode::NO_CONTENT)
 }
 
-#[instrument(skip(multipart))]
-pub async fn upload_image(mut multipart: Multipart) -> Result<impl IntoResponse, HttpError> {
-    let upload_dir = PathBuf::from("./uploads");
-    fs::create_dir_all(&upload_dir).map_err(|e| {
-        tracing::error!("Failed to create upload directory: {}", e);
-        HttpError::server_error(format!("Failed to create upload directory: {}", e))
-    })?;
-
+#[instrument(skip(app_state, jwt, multipart))]
+pub async fn upload_image(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, HttpError> {
     if let Some(field) = multipart.next_field().await.map_err(|e| {
         tracing::error!("Invalid multipart data: {}", e);
         HttpError::bad_request(format!("Invalid multipart data: {}", e))
@@ -391,31 +487,175 @@ pub async fn upload_image(mut multipart: Multipart) -> Result<impl IntoResponse,
             ));
         }
 
-        let new_name = format!("{}.{}", Uuid::new_v4(), ext);
+        // Hash the bytes as uploaded, before processing, so the media library
+        // can identify the same source image regardless of how it was re-encoded
+        let hash = hex::encode(Sha256::digest(&bytes));
 
-        let mut path = upload_dir;
-        path.push(&new_name);
+        // Same bytes uploaded before (screenshot pasted twice, client retry):
+        // skip processing and hand back the existing files
+        if let Some(response) = existing_upload(&app_state, &hash).await? {
+            tracing::info!("Duplicate upload, reusing media: {}", response.location);
+            return Ok(Json(response));
+        }
 
-        let mut file = fs::File::create(&path).map_err(|e| {
-            tracing::error!("Failed to create file: {}", e);
-            HttpError::server_error(format!("Failed to create file: {}", e))
-        })?;
-        file.write_all(&bytes).map_err(|e| {
-            tracing::error!("Failed to write to file: {}", e);
-            HttpError::server_error(format!("Failed to write to file: {}", e))
-        })?;
+        // Decode, strip metadata and build responsive variants off the async runtime
+        let processed =
+            tokio::task::spawn_blocking(move || image_processing::process_image(&bytes, &ext))
+                .await
+                .map_err(|e| {
+                    tracing::error!("Image processing task failed: {}", e);
+                    HttpError::server_error(ErrorMessage::ServerError.to_string())
+                })?
+                .map_err(|e| {
+                    tracing::error!("Failed to process image: {}", e);
+                    HttpError::bad_request("Invalid or unsupported image")
+                })?;
+
+        // The media id doubles as the base file name of every file for this upload
+        let media_id = Uuid::new_v4();
+
+        let original_name = write_variant(media_id, &processed.original)?;
+        let mut variants = Vec::with_capacity(processed.variants.len());
+        for variant in &processed.variants {
+            variants.push(MediaVariant {
+                file_name: write_variant(media_id, variant)?,
+                media_id,
+                mime_type: variant.mime_type.to_string(),
+                width: variant.width as i32,
+                height: variant.height as i32,
+            });
+        }
+
+        let result = app_state
+            .db_client
+            .save_media(
+                NewMedia {
+                    id: media_id,
+                    uploader_id: jwt.user.id,
+                    file_name: &original_name,
+                    hash: &hash,
+                    mime_type: processed.original.mime_type,
+                    width: processed.width as i32,
+                    height: processed.height as i32,
+                    size_bytes: processed.original.bytes.len() as i64,
+                    blurhash: &processed.blurhash,
+                },
+                &variants,
+            )
+            .await;
 
-        let public_url = format!("https://theolee.net/static/uploads/{}", new_name);
-        tracing::info!("Image uploaded successfully: {}", public_url);
-        Ok(Json(UploadResponse {
-            location: public_url,
-        }))
+        let media = match result {
+            Ok(media) => media,
+            Err(e) => {
+                // Don't leave untracked files behind - the orphan cleanup only sees the media table
+                let mut file_names: Vec<String> =
+                    variants.iter().map(|v| v.file_name.clone()).collect();
+                file_names.push(original_name);
+                remove_upload_files(&file_names);
+
+                // A concurrent upload of the same bytes was saved first - return that one
+                if matches!(&e, sqlx::Error::Database(db_err) if db_err.is_unique_violation()) {
+                    if let Some(response) = existing_upload(&app_state, &hash).await? {
+                        tracing::info!("Concurrent duplicate upload: {}", response.location);
+                        return Ok(Json(response));
+                    }
+                }
+
+                tracing::error!("DB error, saving media: {}", e);
+                return Err(HttpError::server_error(
+                    ErrorMessage::ServerError.to_string(),
+                ));
+            }
+        };
+
+        let response = upload_response(&media, &variants);
+        tracing::info!(
+            %media_id,
+            variants = variants.len(),
+            "Image uploaded successfully: {}",
+            response.location
+        );
+        Ok(Json(response))
     } else {
         tracing::error!("No file uploaded");
         Err(HttpError::bad_request("No file uploaded"))
     }
 }
 
+/// Look up a previous upload with the same content hash
+///
+/// Returns the response the original upload produced, so `upload_image` is
+/// idempotent: uploading the same bytes twice yields the same URLs. The
+/// reused media counts as freshly uploaded for the orphan cleanup.
+async fn existing_upload(
+    app_state: &AppState,
+    hash: &str,
+) -> Result<Option<UploadResponse>, HttpError> {
+    let media = app_state
+        .db_client
+        .use_media_by_hash(hash)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting media by hash: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let Some(media) = media else {
+        return Ok(None);
+    };
+
+    let variants = app_state
+        .db_client
+        .get_media_variants(media.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting media variants: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    Ok(Some(upload_response(&media, &variants)))
+}
+
+/// Build the upload response (full-size file first, then every variant)
+fn upload_response(media: &Media, variants: &[MediaVariant]) -> UploadResponse {
+    let location = uploads::public_url(&media.file_name);
+
+    let mut variant_dtos = vec![ImageVariantDto {
+        url: location.clone(),
+        width: media.width as u32,
+        height: media.height as u32,
+        mime_type: media.mime_type.clone(),
+    }];
+    variant_dtos.extend(variants.iter().map(|v| ImageVariantDto {
+        url: uploads::public_url(&v.file_name),
+        width: v.width as u32,
+        height: v.height as u32,
+        mime_type: v.mime_type.clone(),
+    }));
+
+    UploadResponse {
+        location,
+        width: media.width as u32,
+        height: media.height as u32,
+        blurhash: media.blurhash.clone(),
+        variants: variant_dtos,
+    }
+}
+
+/// Write one encoded variant to the upload directory and return its file name
+///
+/// File name: "{media_id}{suffix}.{ext}", e.g. "6f1c...-480w.webp"
+fn write_variant(media_id: Uuid, variant: &EncodedVariant) -> Result<String, HttpError> {
+    let file_name = format!("{}{}.{}", media_id, variant.suffix, variant.ext);
+
+    uploads::write_upload_file(&file_name, &variant.bytes).map_err(|e| {
+        tracing::error!("Failed to write upload {}: {}", file_name, e);
+        HttpError::server_error(format!("Failed to write file: {}", e))
+    })?;
+
+    Ok(file_name)
+}
+
 fn verify_image_signature(bytes: &[u8], ext: &str) -> bool {
     if bytes.len() < 4 {
         return false;
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use crate::AppState;
+use crate::db::{CommentExt, ReactionExt};
+use crate::dtos::{ReactionResponseDto, ReactionSummaryDto};
+use crate::error::{ErrorMessage, HttpError};
+use crate::middleware::JWTAuthMiddleware;
+use crate::models::{CommentStatus, ReactionTarget, ReactionType};
+use axum::Extension;
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Json};
+use tracing::instrument;
+use uuid::Uuid;
+
+/// Build reaction summaries (counts + viewer's own reactions) for posts/comments
+///
+/// Counts come from the Redis hot counters. Targets that aren't cached are
+/// counted in Postgres and put into Redis for the next request. If Redis is
+/// unavailable the counts of the last flush are read from the count tables,
+/// so reactions never break reading posts.
+///
+/// # Parameters
+/// - `targets`: Posts/comments to summarize
+/// - `viewer`: Signed-in user, or None for anonymous requests (`mine` stays empty)
+pub async fn reaction_summaries(
+    app_state: &AppState,
+    targets: &[ReactionTarget],
+    viewer: Option<Uuid>,
+) -> Result<HashMap<ReactionTarget, ReactionSummaryDto>, HttpError> {
+    let mut summaries: HashMap<ReactionTarget, ReactionSummaryDto> = HashMap::new();
+
+    let (cached, redis_up) = match app_state.redis_client.get_reaction_counts(targets).await {
+        Ok(cached) => (cached, true),
+        Err(e) => {
+            tracing::warn!("Redis error, getting reaction counts: {}", e);
+            (vec![None; targets.len()], false)
+        }
+    };
+
+    let mut missing = Vec::new();
+    for (target, counts) in targets.iter().zip(cached) {
+        match counts {
+            Some(counts) => {
+                let counts = counts
+                    .into_iter()
+                    .filter(|(_, count)| *count > 0)
+                    .filter_map(|(name, count)| Some((ReactionType::from_name(&name)?, count)))
+                    .collect();
+                summaries.insert(
+                    *target,
+                    ReactionSummaryDto {
+                        counts,
+                        mine: Vec::new(),
+                    },
+                );
+            }
+            None => missing.push(*target),
+        }
+    }
+
+    if !missing.is_empty() {
+        let loaded = if redis_up {
+            app_state.db_client.get_reaction_counts(&missing).await
+        } else {
+            app_state
+                .db_client
+                .get_flushed_reaction_counts(&missing)
+                .await
+        };
+        let mut loaded = loaded.map_err(|e| {
+            tracing::error!("DB error, getting reaction counts: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+        let entries: Vec<(ReactionTarget, Vec<(ReactionType, i64)>)> = missing
+            .iter()
+            .map(|target| (*target, loaded.remove(target).unwrap_or_default()))
+            .collect();
+
+        // Only exact counts are cached, the flushed ones may be behind
+        if redis_up {
+            let cached = app_state.redis_client.cache_reaction_counts(&entries).await;
+            if let Err(e) = cached {
+                tracing::warn!("Redis error, caching reaction counts: {}", e);
+            }
+        }
+
+        for (target, counts) in entries {
+            summaries.insert(
+                target,
+                ReactionSummaryDto {
+                    counts: counts.into_iter().collect(),
+                    mine: Vec::new(),
+                },
+            );
+        }
+    }
+
+    if let Some(user_id) = viewer {
+        let mine = app_state
+            .db_client
+            .get_user_reactions(user_id, targets)
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, getting user reactions: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?;
+
+        for (target, reactions) in mine {
+            if let Some(summary) = summaries.get_mut(&target) {
+                summary.mine = reactions;
+            }
+        }
+    }
+
+    Ok(summaries)
+}
+
+/// Add or remove a reaction and keep the Redis counter in step
+///
+/// Adding a reaction that already exists (or removing one that doesn't)
+/// is a no-op, so retries and double clicks don't skew the counts.
+async fn update_reaction(
+    app_state: &AppState,
+    user_id: Uuid,
+    target: ReactionTarget,
+    reaction: ReactionType,
+    add: bool,
+) -> Result<ReactionSummaryDto, HttpError> {
+    let changed = if add {
+        app_state
+            .db_client
+            .add_reaction(user_id, target, reaction)
+            .await
+    } else {
+        app_state
+            .db_client
+            .remove_reaction(user_id, target, reaction)
+            .await
+    }
+    .map_err(|e| match e {
+        // Post/comment deleted in the meantime (or never existed)
+        sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => {
+            tracing::warn!(?target, "Reaction target not found");
+            HttpError::not_found("Reaction target not found".to_string())
+        }
+        _ => {
+            tracing::error!("DB error, updating reaction: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        }
+    })?;
+
+    if changed {
+        let delta = if add { 1 } else { -1 };
+        if let Err(e) = app_state
+            .redis_client
+            .incr_reaction_count(target, reaction, delta)
+            .await
+        {
+            // The cached counter is now off by one until it expires
+            tracing::warn!("Redis error, updating reaction count: {}", e);
+        }
+    }
+
+    let mut summaries = reaction_summaries(app_state, &[target], Some(user_id)).await?;
+    Ok(summaries.remove(&target).unwrap_or_default())
+}
+
+/// Only visible comments can be reacted to
+async fn ensure_comment_visible(app_state: &AppState, comment_id: i32) -> Result<(), HttpError> {
+    let comment = app_state
+        .db_client
+        .get_comment(comment_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting comment: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    match comment {
+        Some(c) if c.status == CommentStatus::Approved && c.deleted_at.is_none() => Ok(()),
+        _ => {
+            tracing::warn!("Comment {} not found for reaction", comment_id);
+            Err(HttpError::not_found("Comment not found".to_string()))
+        }
+    }
+}
+
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn add_post_reaction(
+    Path((post_id, reaction)): Path<(i32, ReactionType)>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let summary = update_reaction(
+        &app_state,
+        jwt.user.id,
+        ReactionTarget::Post(post_id),
+        reaction,
+        true,
+    )
+    .await?;
+
+    tracing::info!("add_post_reaction successful");
+    Ok(Json(ReactionResponseDto {
+        status: "success".to_string(),
+        data: summary,
+    }))
+}
+
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn remove_post_reaction(
+    Path((post_id, reaction)): Path<(i32, ReactionType)>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let summary = update_reaction(
+        &app_state,
+        jwt.user.id,
+        ReactionTarget::Post(post_id),
+        reaction,
+        false,
+    )
+    .await?;
+
+    tracing::info!("remove_post_reaction successful");
+    Ok(Json(ReactionResponseDto {
+        status: "success".to_string(),
+        data: summary,
+    }))
+}
+
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn add_comment_reaction(
+    Path((comment_id, reaction)): Path<(i32, ReactionType)>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    ensure_comment_visible(&app_state, comment_id).await?;
+
+    let summary = update_reaction(
+        &app_state,
+        jwt.user.id,
+        ReactionTarget::Comment(comment_id),
+        reaction,
+        true,
+    )
+    .await?;
+
+    tracing::info!("add_comment_reaction successful");
+    Ok(Json(ReactionResponseDto {
+        status: "success".to_string(),
+        data: summary,
+    }))
+}
+
+/// Removing works on any comment, so a reaction can still be taken back after moderation
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn remove_comment_reaction(
+    Path((comment_id, reaction)): Path<(i32, ReactionType)>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let summary = update_reaction(
+        &app_state,
+        jwt.user.id,
+        ReactionTarget::Comment(comment_id),
+        reaction,
+        false,
+    )
+    .await?;
+
+    tracing::info!("remove_comment_reaction successful");
+    Ok(Json(ReactionResponseDto {
+        status: "success".to_string(),
+        data: summary,
+    }))
+}
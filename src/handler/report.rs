@@ -0,0 +1,353 @@
+use crate::AppState;
+use crate::db::{CommentExt, ModerationExt, PostExt, ReportExt};
+use crate::dtos::{
+    Lang, PaginationDto, ReportContentDto, ReportListResponseDto, ReportQuery, ResolveReportDto,
+    Response,
+};
+use crate::error::{ErrorMessage, HttpError};
+use crate::middleware::JWTAuthMiddleware;
+use crate::models::{CommentStatus, ReportReason, ReportStatus, ReportTarget};
+use axum::Extension;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use tracing::instrument;
+use uuid::Uuid;
+use validator::Validate;
+
+fn validate_report(body: &ReportContentDto) -> Result<(), HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid report input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let has_details = body
+        .details
+        .as_deref()
+        .is_some_and(|d| !d.trim().is_empty());
+    if body.reason == ReportReason::Other && !has_details {
+        tracing::error!("Report with reason other but no details");
+        return Err(HttpError::bad_request(
+            "Details are required when the reason is other".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Store a report
+///
+/// # Returns
+/// Number of open reports of the post/comment, this one included.
+/// 409 if the user had already reported it.
+async fn file_report(
+    app_state: &AppState,
+    reporter_id: Uuid,
+    target: ReportTarget,
+    body: &ReportContentDto,
+) -> Result<i64, HttpError> {
+    let details = body
+        .details
+        .as_deref()
+        .map(str::trim)
+        .filter(|d| !d.is_empty());
+
+    let open_reports = app_state
+        .db_client
+        .create_report(reporter_id, target, body.reason, details)
+        .await
+        .map_err(|e| match e {
+            // Post/comment deleted in the meantime
+            sqlx::Error::Database(db_err) if db_err.is_foreign_key_violation() => {
+                tracing::warn!(?target, "Report target not found");
+                HttpError::not_found("Report target not found".to_string())
+            }
+            _ => {
+                tracing::error!("DB error, creating report: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }
+        })?;
+
+    open_reports.ok_or_else(|| {
+        tracing::warn!(?target, "Duplicate report");
+        HttpError::unique_constraint_violation("You have already reported this".to_string())
+    })
+}
+
+/// Whether enough users reported something to hide it until an admin looks at it
+///
+/// A threshold of 0 turns auto-hiding off.
+fn reached_hide_threshold(app_state: &AppState, open_reports: i64) -> bool {
+    let threshold = app_state.env.report_hide_threshold;
+    threshold > 0 && open_reports >= threshold
+}
+
+fn report_received() -> impl IntoResponse {
+    (
+        StatusCode::CREATED,
+        Json(Response {
+            status: "success",
+            message: "Thanks, the report will be reviewed by a moderator.".to_string(),
+        }),
+    )
+}
+
+#[instrument(skip(app_state, jwt, body), fields(username = %jwt.user.username))]
+pub async fn report_post(
+    Path(post_id): Path<i32>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<ReportContentDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    validate_report(&body)?;
+
+    // Hidden posts can't be reported again, they are already waiting for an admin
+    let post = app_state
+        .db_client
+        .get_post(post_id, Lang::En, false)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                tracing::warn!("Post with id {} not found for report", post_id);
+                HttpError::not_found(format!("Post with id {} not found", post_id))
+            }
+            _ => {
+                tracing::error!("DB error, getting post: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            }
+        })?;
+
+    if post.user_username == jwt.user.username {
+        tracing::error!("User tried to report their own post {}", post_id);
+        return Err(HttpError::bad_request(
+            "You can't report your own post".to_string(),
+        ));
+    }
+
+    let target = ReportTarget::Post(post_id);
+    let open_reports = file_report(&app_state, jwt.user.id, target, &body).await?;
+
+    if reached_hide_threshold(&app_state, open_reports) {
+        let hidden = app_state
+            .db_client
+            .set_post_hidden(post_id, true)
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, hiding reported post: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?;
+        if hidden {
+            tracing::warn!("Post {} hidden after {} reports", post_id, open_reports);
+        }
+    }
+
+    tracing::info!("report_post successful");
+    Ok(report_received())
+}
+
+#[instrument(skip(app_state, jwt, body), fields(username = %jwt.user.username))]
+pub async fn report_comment(
+    Path(comment_id): Path<i32>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<ReportContentDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    validate_report(&body)?;
+
+    let comment = app_state
+        .db_client
+        .get_comment(comment_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting comment: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    // Only visible comments can be reported
+    let comment = match comment {
+        Some(c) if c.status == CommentStatus::Approved && c.deleted_at.is_none() => c,
+        _ => {
+            tracing::warn!("Comment {} not found for report", comment_id);
+            return Err(HttpError::not_found("Comment not found".to_string()));
+        }
+    };
+
+    if comment.user_id == jwt.user.id {
+        tracing::error!("User tried to report their own comment {}", comment_id);
+        return Err(HttpError::bad_request(
+            "You can't report your own comment".to_string(),
+        ));
+    }
+
+    let target = ReportTarget::Comment(comment_id);
+    let open_reports = file_report(&app_state, jwt.user.id, target, &body).await?;
+
+    // Back to pending: out of public listings and into the moderation queue
+    if reached_hide_threshold(&app_state, open_reports) {
+        let reason = format!("Hidden after {} user reports", open_reports);
+        app_state
+            .db_client
+            .set_comment_status(None, &[comment_id], CommentStatus::Pending, Some(&reason))
+            .await
+            .map_err(|e| {
+                tracing::error!("DB error, hiding reported comment: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?;
+        tracing::warn!(
+            "Comment {} hidden after {} reports",
+            comment_id,
+            open_reports
+        );
+    }
+
+    tracing::info!("report_comment successful");
+    Ok(report_received())
+}
+
+#[instrument(skip(app_state))]
+pub async fn get_reports(
+    Query(params): Query<ReportQuery>,
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    params.validate().map_err(|e| {
+        tracing::error!("Invalid get_reports input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    let status = params.status.unwrap_or(ReportStatus::Open);
+    let page = params.page.unwrap_or(1);
+    let limit = params.limit.unwrap_or(20);
+
+    let reports = app_state
+        .db_client
+        .get_reports(status, page, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting reports: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let total = app_state
+        .db_client
+        .get_reports_count(status)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting reports count: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let total_pages = (total as f64 / limit as f64).ceil() as i32;
+
+    let response = Json(ReportListResponseDto {
+        status: "success".to_string(),
+        data: reports,
+        pagination: PaginationDto {
+            page,
+            limit,
+            total: total as i32,
+            total_pages,
+        },
+    });
+    tracing::info!("get_reports successful");
+    Ok(response)
+}
+
+/// Decide on a report, and with it on every other open report of the same post/comment
+///
+/// - Upheld comment: rejected. Upheld post: hidden.
+/// - Dismissed comment: approved again if the reports had hidden it.
+///   Dismissed post: shown again.
+#[instrument(skip(app_state, jwt, body), fields(moderator = %jwt.user.username))]
+pub async fn resolve_report(
+    Path(report_id): Path<i64>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<ResolveReportDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate().map_err(|e| {
+        tracing::error!("Invalid resolve_report input: {}", e);
+        HttpError::bad_request(e.to_string())
+    })?;
+
+    if body.status == ReportStatus::Open {
+        tracing::error!("Tried to resolve report {} as open", report_id);
+        return Err(HttpError::bad_request(
+            "Status must be dismissed or upheld".to_string(),
+        ));
+    }
+
+    let target = app_state
+        .db_client
+        .get_open_report_target(report_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting report: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .ok_or_else(|| {
+            tracing::warn!("Open report {} not found", report_id);
+            HttpError::not_found("Report not found or already resolved".to_string())
+        })?;
+
+    let upheld = body.status == ReportStatus::Upheld;
+
+    match target {
+        ReportTarget::Post(post_id) => {
+            app_state
+                .db_client
+                .set_post_hidden(post_id, upheld)
+                .await
+                .map_err(|e| {
+                    tracing::error!("DB error, updating reported post: {}", e);
+                    HttpError::server_error(ErrorMessage::ServerError.to_string())
+                })?;
+        }
+        ReportTarget::Comment(comment_id) => {
+            let comment = app_state
+                .db_client
+                .get_comment(comment_id)
+                .await
+                .map_err(|e| {
+                    tracing::error!("DB error, getting comment: {}", e);
+                    HttpError::server_error(ErrorMessage::ServerError.to_string())
+                })?;
+
+            // Only approved comments can be reported, so a pending one was hidden by the reports
+            let status = match comment.map(|c| c.status) {
+                Some(CommentStatus::Approved | CommentStatus::Pending) if upheld => {
+                    Some(CommentStatus::Rejected)
+                }
+                Some(CommentStatus::Pending) => Some(CommentStatus::Approved),
+                _ => None,
+            };
+
+            if let Some(status) = status {
+                let reason = body.reason.clone().unwrap_or(format!(
+                    "Report {} {}",
+                    report_id,
+                    if upheld { "upheld" } else { "dismissed" }
+                ));
+                app_state
+                    .db_client
+                    .set_comment_status(Some(jwt.user.id), &[comment_id], status, Some(&reason))
+                    .await
+                    .map_err(|e| {
+                        tracing::error!("DB error, moderating reported comment: {}", e);
+                        HttpError::server_error(ErrorMessage::ServerError.to_string())
+                    })?;
+            }
+        }
+    }
+
+    let resolved = app_state
+        .db_client
+        .resolve_reports(jwt.user.id, target, body.status)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, resolving reports: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!(resolved, "resolve_report successful");
+    Ok(StatusCode::NO_CONTENT)
+}
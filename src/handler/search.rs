@@ -2,21 +2,28 @@ use crate::AppState;
 use crate::db::PostExt;
 use crate::dtos::{GetSearchQuery, Lang, PaginationDto, PostsPaginationResponseDto};
 use crate::error::{ErrorMessage, HttpError};
-use axum::Router;
+use crate::handler::post::posts_with_reactions;
+use crate::middleware::{JWTAuthMiddleware, optional_auth};
 use axum::extract::{Query, State};
 use axum::response::{IntoResponse, Json};
 use axum::routing::get;
+use axum::{Extension, Router, middleware};
 use tracing::instrument;
 use validator::Validate;
 
-pub fn search_handler() -> Router<AppState> {
-    Router::new().route("/", get(get_hybrid_search))
+pub fn search_handler(app_state: AppState) -> Router<AppState> {
+    Router::new().route(
+        "/",
+        get(get_hybrid_search)
+            .route_layer(middleware::from_fn_with_state(app_state, optional_auth)),
+    )
 }
 
-#[instrument(skip(app_state))]
+#[instrument(skip(app_state, viewer))]
 pub async fn get_hybrid_search(
     Query(params): Query<GetSearchQuery>,
     State(app_state): State<AppState>,
+    viewer: Option<Extension<JWTAuthMiddleware>>,
 ) -> Result<impl IntoResponse, HttpError> {
     params.validate().map_err(|e| {
         tracing::error!("Invalid get_hybrid_search input: {}", e);
@@ -50,6 +57,9 @@ pub async fn get_hybrid_search(
 
     let total_pages = (total as f64 / limit as f64).ceil() as i32;
 
+    let viewer_id = viewer.map(|Extension(jwt)| jwt.user.id);
+    let search_result = posts_with_reactions(&app_state, search_result, viewer_id).await?;
+
     let response = Json(PostsPaginationResponseDto {
         status: "success".to_string(),
         data: search_result,
@@ -1,6 +1,19 @@
-use crate::db::CommentExt;
-use crate::dtos::{EmailUpdateDto, UserMeData};
-use crate::mail::mails::send_verification_email_newemail;
+use crate::db::{CommentExt, EmailChangeExt, NotificationExt};
+use crate::dtos::{
+    EmailUpdateDto, NotificationPreferencesDto, NotificationPreferencesResponseDto, SessionDto,
+    SessionListResponseDto, UserMeData,
+};
+use crate::handler::api_token::{create_api_token, delete_api_token, get_api_tokens};
+use crate::handler::auth::session_cookies;
+use crate::handler::lockout::{delete_lockout, get_lockouts};
+use crate::handler::mfa::{
+    disable_totp, enable_totp, get_mfa_policy, get_mfa_status, regenerate_recovery_codes,
+    setup_totp, update_mfa_policy,
+};
+use crate::handler::oauth::{delete_identity, get_identities};
+use crate::handler::passkey::{delete_passkey, get_passkeys};
+use crate::mail::mails::{send_email_change_notice_email, send_verification_email_newemail};
+use crate::redisdb::TokenRotation;
 use crate::{
     AppState,
     db::PostExt,
@@ -10,35 +23,42 @@ use crate::{
         UserData, UserListResponseDto, UserMeResponseDto, UserPasswordUpdateDto, UserResponseDto,
     },
     error::{ErrorMessage, HttpError},
-    middleware::{JWTAuthMiddleware, role_check},
-    models::UserRole,
-    utils::password,
+    middleware::{CSRF_COOKIE, JWTAuthMiddleware, role_check},
+    models::{User, UserRole},
+    utils::{email_change, password, signature, token, token_hash},
 };
 use axum::{
     Extension, Json, Router,
-    extract::{Query, State},
+    extract::{Path, Query, State},
     http::{HeaderMap, StatusCode, header},
     middleware,
     response::IntoResponse,
     routing::{delete, get, post, put},
 };
-use axum_extra::extract::cookie::Cookie;
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
 use chrono::{Duration, Utc};
 use tracing::instrument;
+use uuid::Uuid;
 use validator::Validate;
 
+/// Hours the new address has to confirm an email change
+const EMAIL_CHANGE_CONFIRM_HOURS: i64 = 24;
+
+/// Days the old address can revert an email change
+const EMAIL_CHANGE_REVERT_DAYS: i64 = 7;
+
 pub fn users_handler() -> Router<AppState> {
     Router::new()
         .route(
             "/me",
             get(get_me).layer(middleware::from_fn(|req, next| {
-                role_check(req, next, vec![UserRole::Admin, UserRole::User])
+                role_check(req, next, vec![UserRole::Admin, UserRole::User], None)
             })),
         )
         .route(
             "/users",
             get(get_users).layer(middleware::from_fn(|req, next| {
-                role_check(req, next, vec![UserRole::Admin])
+                role_check(req, next, vec![UserRole::Admin], None)
             })),
         )
         .route("/username", put(update_user_name))
@@ -46,6 +66,138 @@ pub fn users_handler() -> Router<AppState> {
         .route("/email", put(update_user_email))
         .route("/logout", post(logout))
         .route("/delete-me", delete(delete_me))
+        .route(
+            "/notifications",
+            get(get_notification_preferences).put(update_notification_preferences),
+        )
+        .route("/sessions", get(get_sessions))
+        .route("/sessions/others", delete(revoke_other_sessions))
+        .route("/sessions/{session_id}", delete(revoke_session))
+        .route("/mfa", get(get_mfa_status))
+        .route("/mfa/totp", post(setup_totp).delete(disable_totp))
+        .route("/mfa/totp/enable", post(enable_totp))
+        .route("/mfa/recovery-codes", post(regenerate_recovery_codes))
+        .route("/passkeys", get(get_passkeys))
+        .route("/passkeys/{passkey_id}", delete(delete_passkey))
+        .route("/identities", get(get_identities))
+        .route("/identities/{identity_id}", delete(delete_identity))
+        .route("/tokens", get(get_api_tokens).post(create_api_token))
+        .route("/tokens/{token_id}", delete(delete_api_token))
+        .route(
+            "/users/{user_id}/logout",
+            post(force_logout).layer(middleware::from_fn(|req, next| {
+                role_check(req, next, vec![UserRole::Admin], None)
+            })),
+        )
+        .route(
+            "/lockouts",
+            get(get_lockouts).layer(middleware::from_fn(|req, next| {
+                role_check(req, next, vec![UserRole::Admin], None)
+            })),
+        )
+        .route(
+            "/lockouts/{user_id}",
+            delete(delete_lockout).layer(middleware::from_fn(|req, next| {
+                role_check(req, next, vec![UserRole::Admin], None)
+            })),
+        )
+        .route(
+            "/mfa-policy",
+            get(get_mfa_policy)
+                .put(update_mfa_policy)
+                .layer(middleware::from_fn(|req, next| {
+                    role_check(req, next, vec![UserRole::Admin], None)
+                })),
+        )
+        // Account management is for logged-in users, personal access tokens can't reach it
+        .route_layer(middleware::from_fn(|req, next| {
+            role_check(req, next, vec![UserRole::Admin, UserRole::User], None)
+        }))
+}
+
+/// Session of the device making the request, named by its access token
+///
+/// None for personal access tokens, they don't belong to a session.
+pub(crate) fn current_session_id(jwt: &JWTAuthMiddleware) -> Option<String> {
+    jwt.claims.as_ref().map(|claims| claims.sid.clone())
+}
+
+/// Session of the refresh token cookie sent with the request
+///
+/// None if the cookie is missing, invalid or belongs to another user
+/// (e.g. API clients that only send an access token).
+fn refresh_cookie_session_id(
+    cookie_jar: &CookieJar,
+    app_state: &AppState,
+    user_id: Uuid,
+) -> Option<String> {
+    let refresh_token = cookie_jar.get("refresh_token")?.value().to_string();
+    let claims = token::decode_refresh_token(refresh_token, &app_state.jwt_keys).ok()?;
+    (claims.sub == user_id.to_string()).then_some(claims.sid)
+}
+
+/// Cookies with new tokens for this device's session, after its token version was bumped
+///
+/// Bumping `token_version` invalidates every token of the user, this keeps the
+/// device that made the change signed in. Empty if the request has no valid
+/// refresh token cookie: that device is signed out like the others.
+async fn reissue_session_cookies(
+    cookie_jar: &CookieJar,
+    app_state: &AppState,
+    user: &User,
+) -> Result<HeaderMap, HttpError> {
+    let Some(session_id) = refresh_cookie_session_id(cookie_jar, app_state, user.id) else {
+        return Ok(HeaderMap::new());
+    };
+    let presented_token = cookie_jar
+        .get("refresh_token")
+        .map(|cookie| cookie.value().to_string())
+        .unwrap_or_default();
+
+    let refresh_token = token::create_refresh_token(
+        user,
+        &session_id,
+        &app_state.jwt_keys,
+        app_state.env.refresh_token_maxage,
+    )
+    .map_err(|e| {
+        tracing::error!("Refresh token creation error: {}", e);
+        HttpError::server_error(ErrorMessage::ServerError.to_string())
+    })?;
+
+    let rotation = app_state
+        .redis_client
+        .rotate_refresh_token(
+            user.id,
+            &session_id,
+            &presented_token,
+            &refresh_token,
+            app_state.env.refresh_token_maxage,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, rotating refresh token: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if !matches!(rotation, TokenRotation::Rotated) {
+        tracing::warn!(session_id = %session_id, "Current session not reissued: {:?}", rotation);
+        return Ok(HeaderMap::new());
+    }
+
+    let access_token = token::create_access_token(
+        user,
+        &session_id,
+        &app_state.jwt_keys,
+        app_state.env.jwt_maxage,
+    )
+    .map_err(|e| {
+        tracing::error!("Access token creation error: {}", e);
+        HttpError::server_error(ErrorMessage::ServerError.to_string())
+    })?;
+
+    let csrf_token = signature::sign_csrf_token(&session_id, app_state.env.jwt_secret.as_bytes());
+    Ok(session_cookies(&access_token, &refresh_token, &csrf_token))
 }
 
 #[instrument(skip(user, app_state), fields(username = %user.user.username))]
@@ -201,10 +353,11 @@ pub async fn update_user_role(
     Ok(Json(response))
 }
 
-#[instrument(skip(app_state, user, body), fields(username = %user.user.username))]
+#[instrument(skip(app_state, user, cookie_jar, body), fields(username = %user.user.username))]
 pub async fn update_user_password(
     State(app_state): State<AppState>,
     Extension(user): Extension<JWTAuthMiddleware>,
+    cookie_jar: CookieJar,
     Json(body): Json<UserPasswordUpdateDto>,
 ) -> Result<impl IntoResponse, HttpError> {
     body.validate().map_err(|e| {
@@ -212,6 +365,7 @@ pub async fn update_user_password(
         HttpError::bad_request(e.to_string())
     })?;
 
+    let current_session = current_session_id(&user);
     let user = &user.user;
     let user_id = uuid::Uuid::parse_str(&user.id.to_string()).unwrap();
 
@@ -246,7 +400,8 @@ pub async fn update_user_password(
         HttpError::server_error(ErrorMessage::ServerError.to_string())
     })?;
 
-    app_state
+    // Also bumps the token version: every token issued so far stops working
+    let user = app_state
         .db_client
         .update_user_password(user_id.clone(), hash_password)
         .await
@@ -255,23 +410,77 @@ pub async fn update_user_password(
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
+    // Sign out every other device, whoever knew the old password is locked out
     app_state
         .redis_client
-        .delete_refresh_token(&user_id.to_string())
+        .delete_user_sessions(user_id, current_session.as_deref())
         .await
         .map_err(|e| {
-            tracing::error!("RedisDB error, deleting refresh token: {}", e);
+            tracing::error!("RedisDB error, deleting sessions: {}", e);
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
-    let response = Response {
+    let headers = reissue_session_cookies(&cookie_jar, &app_state, &user).await?;
+
+    let json_response = Json(Response {
         message: "Password updated Successfully".to_string(),
         status: "success",
-    };
+    });
+
+    let mut response = json_response.into_response();
+    response.headers_mut().extend(headers);
     tracing::info!("update_user_password successful");
-    Ok(Json(response))
+    Ok(response)
+}
+
+/// Sign a user out everywhere (admin)
+///
+/// Bumps the user's token version, so their access tokens stop working right
+/// away instead of when they expire, and deletes all their sessions and
+/// personal access tokens.
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn force_logout(
+    Path(user_id): Path<Uuid>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let found = app_state
+        .db_client
+        .bump_token_version(user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, bumping token version: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if !found {
+        tracing::error!(user_id = %user_id, "User not found");
+        return Err(HttpError::not_found(
+            ErrorMessage::UserNoLongerExist.to_string(),
+        ));
+    }
+
+    let revoked = app_state
+        .redis_client
+        .delete_user_sessions(user_id, None)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, deleting sessions: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!(user_id = %user_id, revoked, "force_logout successful");
+    Ok(Json(Response {
+        status: "success",
+        message: format!("User signed out of {} session(s)", revoked),
+    }))
 }
 
+/// Start changing the email address
+///
+/// The new address gets a confirmation link (valid 24 hours), the email only
+/// changes once it is used. The current address gets a notice with a link
+/// that cancels the change, or reverts it for 7 days after the request.
 #[instrument(skip(user, app_state), fields(username = %user.user.username))]
 pub async fn update_user_email(
     Extension(user): Extension<JWTAuthMiddleware>,
@@ -283,32 +492,47 @@ pub async fn update_user_email(
         HttpError::bad_request(e.to_string())
     })?;
 
-    let email_token = format!("{}+{}", uuid::Uuid::new_v4(), &body.email);
-    let expires_at = Utc::now() + Duration::hours(24);
-    let user_id = user.user.id;
+    let user = user.user;
+
+    if body.email == user.email {
+        return Err(HttpError::bad_request(
+            "This is already your email address".to_string(),
+        ));
+    }
 
     app_state
         .db_client
-        .check_email_duplicate(user_id, &body.email)
+        .check_email_duplicate(user.id, &body.email)
         .await
         .map_err(|e| {
             tracing::error!("DB error, checking email duplicate: {}", e);
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
+    let token = email_change::generate_token();
+    let revert_token = email_change::generate_token();
+    let now = Utc::now();
+
     app_state
         .db_client
-        .add_verifed_token(user_id, &email_token, expires_at)
+        .create_pending_email_change(
+            user.id,
+            &body.email,
+            &token_hash::hash(&token),
+            &token_hash::hash(&revert_token),
+            now + Duration::hours(EMAIL_CHANGE_CONFIRM_HOURS),
+            now + Duration::days(EMAIL_CHANGE_REVERT_DAYS),
+        )
         .await
         .map_err(|e| {
-            tracing::error!("DB error, adding verified token: {}", e);
+            tracing::error!("DB error, creating pending email change: {}", e);
             HttpError::server_error(ErrorMessage::ServerError.to_string())
         })?;
 
     send_verification_email_newemail(
         &body.email,
-        &user.user.username,
-        &email_token,
+        &user.username,
+        &token,
         &app_state.env.frontend_url,
     )
     .await
@@ -317,6 +541,16 @@ pub async fn update_user_email(
         HttpError::server_error(ErrorMessage::ServerError.to_string())
     })?;
 
+    let revert_link = format!(
+        "{}/auth/email/change/revert?token={}",
+        app_state.env.frontend_url, revert_token
+    );
+    if let Err(e) =
+        send_email_change_notice_email(&user.email, &user.username, &body.email, &revert_link).await
+    {
+        tracing::error!("Failed to send email change notice: {}", e);
+    }
+
     let response = Response {
         message: "Please verify your email".to_string(),
         status: "success",
@@ -325,43 +559,54 @@ pub async fn update_user_email(
     Ok(Json(response))
 }
 
-#[instrument(skip(user, app_state), fields(username = %user.user.username))]
+/// Sign out this device only, other sessions stay signed in
+#[instrument(skip(user, app_state, cookie_jar), fields(username = %user.user.username))]
 pub async fn logout(
     Extension(user): Extension<JWTAuthMiddleware>,
     State(app_state): State<AppState>,
+    cookie_jar: CookieJar,
 ) -> Result<impl IntoResponse, HttpError> {
-    let user = user.user;
-
-    app_state
-        .redis_client
-        .delete_refresh_token(&user.id.to_string())
-        .await
-        .map_err(|e| {
-            tracing::error!("RedisDB error, deleting refresh token: {}", e);
-            HttpError::server_error(ErrorMessage::ServerError.to_string())
-        })?;
+    // The access token stays valid until it expires, unless it's denylisted
+    if let Some(claims) = &user.claims {
+        let expires_in = claims.exp as i64 - Utc::now().timestamp();
+        app_state
+            .redis_client
+            .revoke_access_token(&claims.jti, expires_in)
+            .await
+            .map_err(|e| {
+                tracing::error!("RedisDB error, revoking access token: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?;
+    }
 
-    let access_cookie = Cookie::build(("access_token", ""))
-        .path("/")
-        .max_age(time::Duration::ZERO)
-        .http_only(true)
-        .build();
+    // Access tokens name their session, the refresh cookie is the fallback
+    let session_id = match &user.claims {
+        Some(claims) => Some(claims.sid.clone()),
+        None => refresh_cookie_session_id(&cookie_jar, &app_state, user.user.id),
+    };
+    let user = user.user;
 
-    let refresh_cookie = Cookie::build(("refresh_token", ""))
-        .path("/")
-        .max_age(time::Duration::ZERO)
-        .http_only(true)
-        .build();
+    if let Some(session_id) = session_id {
+        app_state
+            .redis_client
+            .delete_session(user.id, &session_id)
+            .await
+            .map_err(|e| {
+                tracing::error!("RedisDB error, deleting session: {}", e);
+                HttpError::server_error(ErrorMessage::ServerError.to_string())
+            })?;
+    }
 
     let mut headers = HeaderMap::new();
-    headers.append(
-        header::SET_COOKIE,
-        access_cookie.to_string().parse().unwrap(),
-    );
-    headers.append(
-        header::SET_COOKIE,
-        refresh_cookie.to_string().parse().unwrap(),
-    );
+    for name in ["access_token", "refresh_token", CSRF_COOKIE] {
+        let cookie = Cookie::build((name, ""))
+            .path("/")
+            .max_age(time::Duration::ZERO)
+            .http_only(name != CSRF_COOKIE)
+            .same_site(SameSite::Strict)
+            .build();
+        headers.append(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    }
 
     let json_response = axum::response::Json(Response {
         status: "success",
@@ -414,3 +659,140 @@ pub async fn delete_me(
         Err(HttpError::unauthorized("Invalid password".to_string()))
     }
 }
+
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn get_notification_preferences(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let comment_notifications = app_state
+        .db_client
+        .get_notification_preference(jwt.user.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting notification preference: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!("get_notification_preferences successful");
+    Ok(Json(NotificationPreferencesResponseDto {
+        status: "success".to_string(),
+        data: NotificationPreferencesDto {
+            comment_notifications,
+        },
+    }))
+}
+
+/// Request body: { commentNotifications: "instant" | "daily" | "off" }
+#[instrument(skip(app_state, jwt, body), fields(username = %jwt.user.username))]
+pub async fn update_notification_preferences(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+    Json(body): Json<NotificationPreferencesDto>,
+) -> Result<impl IntoResponse, HttpError> {
+    app_state
+        .db_client
+        .set_notification_preference(jwt.user.id, body.comment_notifications)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, updating notification preference: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!("update_notification_preferences successful");
+    Ok(Json(NotificationPreferencesResponseDto {
+        status: "success".to_string(),
+        data: body,
+    }))
+}
+
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn get_sessions(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let user_id = jwt.user.id;
+
+    let sessions = app_state
+        .redis_client
+        .get_user_sessions(user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, getting sessions: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    let current_session = current_session_id(&jwt);
+    let data = sessions
+        .into_iter()
+        .map(|session| SessionDto {
+            current: current_session.as_deref() == Some(session.id.as_str()),
+            id: session.id,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+        })
+        .collect();
+
+    tracing::info!("get_sessions successful");
+    Ok(Json(SessionListResponseDto {
+        status: "success".to_string(),
+        data,
+    }))
+}
+
+/// Sign out one device
+///
+/// Its tokens stop working right away: access tokens of a session that
+/// no longer exists are rejected by the auth middleware.
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn revoke_session(
+    Path(session_id): Path<String>,
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let deleted = app_state
+        .redis_client
+        .delete_session(jwt.user.id, &session_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, deleting session: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    if !deleted {
+        tracing::warn!("Session {} not found", session_id);
+        return Err(HttpError::not_found("Session not found".to_string()));
+    }
+
+    tracing::info!("revoke_session successful");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sign out every device except the one making the request
+///
+/// The device is the session named by the access token's `sid` claim.
+#[instrument(skip(app_state, jwt), fields(username = %jwt.user.username))]
+pub async fn revoke_other_sessions(
+    State(app_state): State<AppState>,
+    Extension(jwt): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, HttpError> {
+    let user_id = jwt.user.id;
+    let current_session = current_session_id(&jwt);
+
+    let revoked = app_state
+        .redis_client
+        .delete_user_sessions(user_id, current_session.as_deref())
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, deleting sessions: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    tracing::info!(revoked, "revoke_other_sessions successful");
+    Ok(Json(Response {
+        status: "success",
+        message: format!("Signed out of {} other session(s)", revoked),
+    }))
+}
@@ -0,0 +1,24 @@
+use crate::AppState;
+use axum::Router;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::{IntoResponse, Json};
+use axum::routing::get;
+
+/// Discovery documents served outside `/api`, at the paths other services expect
+pub fn well_known_handler() -> Router<AppState> {
+    Router::new().route("/jwks.json", get(get_jwks))
+}
+
+/// Public keys that verify our access and refresh tokens (RFC 7517)
+///
+/// Includes retired keys until their rotation window has passed, so tokens
+/// issued just before a rotation still verify. Empty when tokens are signed
+/// with HS256 (`JWT_SIGNING_KEY` not set).
+pub async fn get_jwks(State(app_state): State<AppState>) -> impl IntoResponse {
+    (
+        // Verifiers can cache the keys, new ones appear before they sign anything
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(app_state.jwt_keys.jwks()),
+    )
+}
@@ -1,4 +1,8 @@
-use super::sendmail::send_email;
+use super::sendmail::{send_email, send_email_with_unsubscribe};
+use crate::models::CommentNotification;
+
+/// Maximum number of characters of a comment quoted in notification emails
+const COMMENT_EXCERPT_CHARS: usize = 300;
 
 /// Send email verification link to new users during registration
 ///
@@ -27,7 +31,8 @@ pub async fn send_verification_email(
 /// Send email verification link when user changes their email address
 ///
 /// Uses a different template (Verification-newemail.html) to indicate
-/// this is for an email change, not initial registration.
+/// this is for an email change, not initial registration. The link opens a
+/// frontend page that posts the token to `/api/auth/email-change/confirm`.
 pub async fn send_verification_email_newemail(
     to_email: &str,
     username: &str,
@@ -36,7 +41,7 @@ pub async fn send_verification_email_newemail(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let subject = "Email Verification";
     let template_path = "src/mail/templates/Verification-newemail.html";
-    let verification_link = format!("{}/auth/email/confirm/{}", frontend_url, token);
+    let verification_link = format!("{}/auth/email/change/confirm?token={}", frontend_url, token);
     let placeholders = vec![
         ("{{username}}".to_string(), username.to_string()),
         ("{{verification_link}}".to_string(), verification_link),
@@ -45,6 +50,29 @@ pub async fn send_verification_email_newemail(
     send_email(to_email, subject, template_path, &placeholders).await
 }
 
+/// Tell the old address of an account that its email is being changed
+///
+/// Sent when the change is requested, so the owner hears about it even if
+/// someone else confirms the new address. The revert_link cancels the
+/// change, or reverts it once confirmed, e.g.
+/// https://example.com/auth/email/change/revert?token={token}
+pub async fn send_email_change_notice_email(
+    to_email: &str,
+    username: &str,
+    new_email: &str,
+    revert_link: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let subject = "Your email address is being changed";
+    let template_path = "src/mail/templates/EmailChange-notice.html";
+    let placeholders = vec![
+        ("{{username}}".to_string(), username.to_string()),
+        ("{{new_email}}".to_string(), new_email.to_string()),
+        ("{{revert_link}}".to_string(), revert_link.to_string()),
+    ];
+
+    send_email(to_email, subject, template_path, &placeholders).await
+}
+
 /// Send welcome email after successful email verification
 ///
 /// Sent immediately after user verifies their email to confirm
@@ -78,3 +106,207 @@ pub async fn send_forgot_password_email(
 
     send_email(to_email, subject, template_path, &placeholders).await
 }
+
+/// Send a passwordless login link
+///
+/// The login_link should be a complete URL including the signed token,
+/// e.g. https://example.com/auth/magic-link?token={token}
+pub async fn send_magic_link_email(
+    to_email: &str,
+    username: &str,
+    login_link: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let subject = "Your login link";
+    let template_path = "src/mail/templates/MagicLink-email.html";
+    let placeholders = vec![
+        ("{{username}}".to_string(), username.to_string()),
+        ("{{login_link}}".to_string(), login_link.to_string()),
+    ];
+
+    send_email(to_email, subject, template_path, &placeholders).await
+}
+
+/// Tell a user their account was locked after too many failed passwords
+/// or second factors (`factor` says which, e.g. "password")
+///
+/// Doubles as the suspicious activity notice: it says how many failures
+/// there were and where the last one came from. The unlock_link lifts the
+/// lock, e.g. https://example.com/auth/unlock?token={token}
+pub async fn send_account_locked_email(
+    to_email: &str,
+    username: &str,
+    factor: &str,
+    failures: u32,
+    ip: &str,
+    locked_until: &str,
+    unlock_link: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let subject = "Your account was locked";
+    let template_path = "src/mail/templates/AccountLocked-email.html";
+    let placeholders = vec![
+        ("{{username}}".to_string(), username.to_string()),
+        ("{{factor}}".to_string(), factor.to_string()),
+        ("{{failures}}".to_string(), failures.to_string()),
+        ("{{ip}}".to_string(), ip.to_string()),
+        ("{{locked_until}}".to_string(), locked_until.to_string()),
+        ("{{unlock_link}}".to_string(), unlock_link.to_string()),
+    ];
+
+    send_email(to_email, subject, template_path, &placeholders).await
+}
+
+/// Shorten a comment for quoting in an email and escape it for HTML
+///
+/// Comments are user input, so they (and usernames/titles) must be escaped
+/// before being put into the HTML templates.
+fn comment_excerpt(content: &str) -> String {
+    let mut excerpt: String = content.chars().take(COMMENT_EXCERPT_CHARS).collect();
+    if content.chars().count() > COMMENT_EXCERPT_CHARS {
+        excerpt.push('…');
+    }
+    ammonia::clean_text(&excerpt)
+}
+
+/// Link to a comment on the frontend: https://example.com/posts/{post_id}#comment-{comment_id}
+fn comment_link(frontend_url: &str, notification: &CommentNotification) -> String {
+    format!(
+        "{}/posts/{}#comment-{}",
+        frontend_url, notification.post_id, notification.comment_id
+    )
+}
+
+/// Send a notification about a single new comment (instant preference)
+///
+/// Uses the Comment-notification.html template. The wording depends on
+/// why the recipient is notified: reply, mention or comment on their post.
+///
+/// # Parameters
+/// - `unsubscribe_page`: Frontend page linked in the email body
+/// - `unsubscribe_url`: API endpoint for the one-click `List-Unsubscribe` header
+pub async fn send_comment_notification_email(
+    notification: &CommentNotification,
+    frontend_url: &str,
+    unsubscribe_page: &str,
+    unsubscribe_url: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let commenter = ammonia::clean_text(&notification.commenter_username);
+    let post_title = ammonia::clean_text(&notification.post_title);
+
+    let (subject, headline) = match notification.reason.as_str() {
+        "reply" => (
+            format!(
+                "{} replied to your comment",
+                notification.commenter_username
+            ),
+            format!(
+                "{} replied to your comment on \"{}\"",
+                commenter, post_title
+            ),
+        ),
+        "mention" => (
+            format!("{} mentioned you", notification.commenter_username),
+            format!(
+                "{} mentioned you in a comment on \"{}\"",
+                commenter, post_title
+            ),
+        ),
+        _ => (
+            format!("New comment on \"{}\"", notification.post_title),
+            format!("{} commented on your post \"{}\"", commenter, post_title),
+        ),
+    };
+    let template_path = "src/mail/templates/Comment-notification.html";
+    let placeholders = vec![
+        (
+            "{{username}}".to_string(),
+            ammonia::clean_text(&notification.recipient_username),
+        ),
+        ("{{headline}}".to_string(), headline),
+        (
+            "{{comment}}".to_string(),
+            comment_excerpt(&notification.comment_content),
+        ),
+        (
+            "{{comment_link}}".to_string(),
+            comment_link(frontend_url, notification),
+        ),
+        (
+            "{{unsubscribe_link}}".to_string(),
+            unsubscribe_page.to_string(),
+        ),
+    ];
+
+    send_email_with_unsubscribe(
+        &notification.recipient_email,
+        &subject,
+        template_path,
+        &placeholders,
+        unsubscribe_url,
+    )
+    .await
+}
+
+/// Send the daily digest of new comments (daily preference)
+///
+/// All notifications must belong to the same recipient.
+/// Each comment becomes one entry of the Comment-digest.html template.
+pub async fn send_comment_digest_email(
+    notifications: &[CommentNotification],
+    frontend_url: &str,
+    unsubscribe_page: &str,
+    unsubscribe_url: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(first) = notifications.first() else {
+        return Ok(());
+    };
+
+    let subject = format!(
+        "{} new comment(s) on your posts and comments",
+        notifications.len()
+    );
+    let template_path = "src/mail/templates/Comment-digest.html";
+
+    let comments: String = notifications
+        .iter()
+        .map(|n| {
+            let action = match n.reason.as_str() {
+                "reply" => "replied to your comment on",
+                "mention" => "mentioned you on",
+                _ => "commented on",
+            };
+            format!(
+                r#"<div style="border-left: 3px solid #dddddd; padding-left: 12px; margin-bottom: 16px;">
+            <p style="color: #333333; margin: 0 0 4px 0;"><strong>{}</strong> {} <a href="{}" style="color: #007bff;">{}</a></p>
+            <p style="color: #555555; margin: 0;">{}</p>
+        </div>"#,
+                ammonia::clean_text(&n.commenter_username),
+                action,
+                comment_link(frontend_url, n),
+                ammonia::clean_text(&n.post_title),
+                comment_excerpt(&n.comment_content)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n        ");
+
+    let placeholders = vec![
+        (
+            "{{username}}".to_string(),
+            ammonia::clean_text(&first.recipient_username),
+        ),
+        ("{{comments}}".to_string(), comments),
+        (
+            "{{unsubscribe_link}}".to_string(),
+            unsubscribe_page.to_string(),
+        ),
+    ];
+
+    send_email_with_unsubscribe(
+        &first.recipient_email,
+        &subject,
+        template_path,
+        &placeholders,
+        unsubscribe_url,
+    )
+    .await
+}
@@ -1,6 +1,9 @@
 use lettre::{
     Message, SmtpTransport, Transport,
-    message::{SinglePart, header},
+    message::{
+        SinglePart,
+        header::{self, HeaderName, HeaderValue},
+    },
     transport::smtp::authentication::Credentials,
 };
 use std::{env, fs};
@@ -20,6 +23,38 @@ pub async fn send_email(
     subject: &str,
     template_path: &str,
     placeholders: &[(String, String)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    deliver(to_email, subject, template_path, placeholders, None).await
+}
+
+/// Send an HTML email with one-click unsubscribe headers
+///
+/// Same as `send_email`, plus the `List-Unsubscribe` and `List-Unsubscribe-Post`
+/// headers (RFC 8058). Mail clients show an "Unsubscribe" button for these and
+/// POST to `unsubscribe_url` directly; Gmail and Yahoo require them for bulk senders.
+pub async fn send_email_with_unsubscribe(
+    to_email: &str,
+    subject: &str,
+    template_path: &str,
+    placeholders: &[(String, String)],
+    unsubscribe_url: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    deliver(
+        to_email,
+        subject,
+        template_path,
+        placeholders,
+        Some(unsubscribe_url),
+    )
+    .await
+}
+
+async fn deliver(
+    to_email: &str,
+    subject: &str,
+    template_path: &str,
+    placeholders: &[(String, String)],
+    unsubscribe_url: Option<&str>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Load SMTP credentials from environment variables
     let smtp_username = env::var("SMTP_USERNAME")?;
@@ -37,16 +72,29 @@ pub async fn send_email(
     }
 
     // Build the email message
-    let email = Message::builder()
+    let mut builder = Message::builder()
         .from(smtp_username.parse()?) // From address (usually same as SMTP username)
         .to(to_email.parse()?) // Recipient address
         .subject(subject) // Email subject
-        .header(header::ContentType::TEXT_HTML)
-        .singlepart(
-            SinglePart::builder()
-                .header(header::ContentType::TEXT_HTML)
-                .body(html_template), // HTML content with placeholders replaced
-        )?;
+        .header(header::ContentType::TEXT_HTML);
+
+    if let Some(url) = unsubscribe_url {
+        builder = builder
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                format!("<{}>", url),
+            ))
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                "List-Unsubscribe=One-Click".to_string(),
+            ));
+    }
+
+    let email = builder.singlepart(
+        SinglePart::builder()
+            .header(header::ContentType::TEXT_HTML)
+            .body(html_template), // HTML content with placeholders replaced
+    )?;
 
     // Configure SMTP transport with STARTTLS encryption
     let creds = Credentials::new(smtp_username.clone(), smtp_password.clone());
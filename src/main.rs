@@ -9,13 +9,18 @@ mod http; // HTTP client wrapper for external API calls
 mod mail; // Email sending functionality
 mod middleware; // Custom middleware (auth, role_check etc.)
 mod models; // Database models representing table structures
+mod oauth; // Social login providers (OpenID Connect, GitHub)
 mod redisdb; // Redis client for session storage and managing login attempts
 mod routes; // Route definitions and router configuration
+mod spam; // Spam classification for comments
 mod tracing_config; //configuring tracing function
 mod utils; // Utility functions and helpers (password, token)
 
+#[cfg(test)]
+mod tests; // End-to-end tests of the API against Postgres and Redis
+
 use axum::http::{
-    HeaderValue, Method,
+    HeaderName, HeaderValue, Method,
     header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
 };
 use config::Config;
@@ -25,7 +30,9 @@ use http::HttpClient;
 use redisdb::RedisClient;
 use sqlx::postgres::PgPoolOptions;
 use std::sync::Arc;
+use webauthn_rs::prelude::{Url, Webauthn, WebauthnBuilder};
 use tower_http::cors::CorsLayer;
+use utils::jwt_keys::JwtKeys;
 
 use axum_client_ip::ClientIpSource;
 use std::net::SocketAddr;
@@ -50,6 +57,8 @@ use embed::embed_service_client::EmbedServiceClient;
 /// - `grpc_client`: Client for vector embedding service
 /// - `http_client`: HTTP client for making external API requests
 /// - `ip_extraction`: Strategy for extracting client IP (varies by deployment)
+/// - `webauthn`: Relying party for passkey registration and login
+/// - `jwt_keys`: Keys signing and verifying access and refresh tokens
 #[derive(Clone)]
 pub struct AppState {
     pub env: Arc<Config>,
@@ -58,6 +67,8 @@ pub struct AppState {
     pub grpc_client: grpc::GRPCClient,
     pub http_client: http::HttpClient,
     pub ip_extraction: ClientIpSource,
+    pub webauthn: Arc<Webauthn>,
+    pub jwt_keys: Arc<JwtKeys>,
 }
 
 #[tokio::main]
@@ -106,7 +117,12 @@ async fn main() {
     // Essential for modern web applications with separate frontend/backend
     let cors = CorsLayer::new()
         .allow_origin(config.frontend_url.parse::<HeaderValue>().unwrap())
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE])
+        .allow_headers([
+            AUTHORIZATION,
+            ACCEPT,
+            CONTENT_TYPE,
+            HeaderName::from_static(middleware::CSRF_HEADER), // CSRF token of cookie-authenticated writes
+        ])
         .allow_credentials(true) // Allow cookies and authorization headers
         .allow_methods([
             Method::GET,
@@ -123,6 +139,11 @@ async fn main() {
     // Example: removing not verified accounts, etc.
     db_client.start_cleanup_task().await;
 
+    // Send queued comment notification emails (instant and daily digest)
+    db_client
+        .start_notification_task(Arc::new(config.clone()))
+        .await;
+
     // Initialize Redis connection
     let manager = redis::Client::open(config.redis_url.clone())
         .unwrap()
@@ -132,6 +153,11 @@ async fn main() {
 
     let redis_client = RedisClient::new(manager);
 
+    // Periodically persist the reaction counters kept in Redis
+    db_client
+        .start_reaction_flush_task(redis_client.clone())
+        .await;
+
     // Initialize gRPC client for embedding service
     // This service converts text to vector embeddings for semantic search
     let embed_client = EmbedServiceClient::connect(config.grpc_url.clone())
@@ -145,6 +171,18 @@ async fn main() {
         conn: reqwest::Client::new(),
     };
 
+    // Passkeys are bound to the relying party id (domain), and ceremonies
+    // are only accepted from the configured origin
+    let rp_origin = Url::parse(&config.webauthn_rp_origin).expect("WEBAUTHN_RP_ORIGIN must be a URL");
+    let webauthn = WebauthnBuilder::new(&config.webauthn_rp_id, &rp_origin)
+        .expect("Invalid WebAuthn relying party configuration")
+        .rp_name(&config.totp_issuer)
+        .build()
+        .expect("Invalid WebAuthn relying party configuration");
+
+    // Load the token signing key and the retired keys still accepted during a rotation
+    let jwt_keys = JwtKeys::init(&config);
+
     // Assemble application state with all initialized components
     // This state will be cloned and passed to each request handler
     let app_state = AppState {
@@ -154,6 +192,8 @@ async fn main() {
         grpc_client,
         http_client,
         ip_extraction: ip_source,
+        webauthn: Arc::new(webauthn),
+        jwt_keys: Arc::new(jwt_keys),
     };
 
     // Create the main router with all routes and apply CORS middleware
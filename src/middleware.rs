@@ -6,16 +6,27 @@ use axum::{
 };
 
 use axum_extra::extract::cookie::CookieJar;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     AppState,
-    db::UserExt,
+    db::{ApiTokenExt, MfaExt, UserExt},
     error::{ErrorMessage, HttpError},
-    models::{User, UserRole},
-    utils::token,
+    models::{ApiScope, User, UserRole},
+    utils::{
+        api_token, signature,
+        token::{self, TokenClaims},
+        token_hash,
+    },
 };
 
+/// Cookie holding the CSRF token of the session, readable by the frontend
+pub const CSRF_COOKIE: &str = "csrf_token";
+
+/// Header cookie-authenticated state-changing requests echo the CSRF token in
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
 /// Middleware extension that stores authenticated user information
 ///
 /// This struct is inserted into the request extensions after successful authentication.
@@ -30,6 +41,9 @@ use crate::{
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct JWTAuthMiddleware {
     pub user: User,
+    pub mfa_setup_required: bool, // Role requires 2FA but the user hasn't enabled it, see `role_check`
+    pub token_scopes: Option<Vec<ApiScope>>, // Scopes of the personal access token used, None for a login (JWT)
+    pub claims: Option<TokenClaims>, // Claims of the access token used, None for a personal access token
 }
 
 /// Authentication middleware that validates JWT tokens
@@ -44,6 +58,12 @@ pub struct JWTAuthMiddleware {
 /// - First: Check `access_token` cookie (for browser-based clients)
 /// - Second: Check `Authorization: Bearer <token>` header (for API clients)
 ///
+/// The bearer token can also be a personal access token (`blog_pat_...`).
+/// Those only get through `role_check` on routes that name one of their scopes.
+///
+/// Requests authenticated by the cookie that aren't GET/HEAD/OPTIONS must send
+/// the session's CSRF token in the `X-CSRF-Token` header (403 otherwise).
+///
 /// # Errors
 /// Returns 401 Unauthorized if:
 /// - No token is provided
@@ -55,38 +75,81 @@ pub async fn auth(
     mut req: Request,
     next: Next,
 ) -> Result<impl IntoResponse, HttpError> {
+    let auth = authenticate(&cookie_jar, &app_state, &req).await?;
+
+    // Insert authenticated user into request extensions
+    // This makes the user available to all downstream handlers and middleware
+    // without needing to re-authenticate or query the database
+    req.extensions_mut().insert(auth);
+
+    // Pass the request to the next middleware/handler in the chain
+    Ok(next.run(req).await)
+}
+
+/// Optional authentication middleware for public endpoints
+///
+/// Same as `auth`, except that requests without a valid token are let
+/// through anonymously instead of being rejected. Handlers extract
+/// `Option<Extension<JWTAuthMiddleware>>` to personalize the response
+/// (e.g. the viewer's own reactions) when the user is signed in.
+pub async fn optional_auth(
+    cookie_jar: CookieJar,
+    State(app_state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> impl IntoResponse {
+    if let Ok(auth) = authenticate(&cookie_jar, &app_state, &req).await {
+        req.extensions_mut().insert(auth);
+    }
+
+    next.run(req).await
+}
+
+/// Resolve the user a request is authenticated as (shared by `auth` and `optional_auth`)
+async fn authenticate(
+    cookie_jar: &CookieJar,
+    app_state: &AppState,
+    req: &Request,
+) -> Result<JWTAuthMiddleware, HttpError> {
     // Attempt to extract JWT token from two possible sources:
     // 1. Cookie (preferred for browser clients with same-origin requests)
     // 2. Authorization header (preferred for API clients and cross-origin requests)
-    let cookies = cookie_jar
+    let cookie_token = cookie_jar
         .get("access_token")
-        .map(|cookie| cookie.value().to_string())
-        .or_else(|| {
-            req.headers()
-                .get(header::AUTHORIZATION)
-                .and_then(|auth_header| auth_header.to_str().ok()) // Convert Result to Option using .ok()
-                .and_then(|auth_value| {
-                    // Extract token from "Bearer <token>" format
-                    if auth_value.starts_with("Bearer ") {
-                        Some(auth_value[7..].to_owned()) // Skip "Bearer " prefix (7 characters)
-                    } else {
-                        None
-                    }
-                })
-        });
+        .map(|cookie| cookie.value().to_string());
+    let from_cookie = cookie_token.is_some();
+
+    let cookies = cookie_token.or_else(|| {
+        req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|auth_header| auth_header.to_str().ok()) // Convert Result to Option using .ok()
+            .and_then(|auth_value| {
+                // Extract token from "Bearer <token>" format
+                if auth_value.starts_with("Bearer ") {
+                    Some(auth_value[7..].to_owned()) // Skip "Bearer " prefix (7 characters)
+                } else {
+                    None
+                }
+            })
+    });
 
     // Convert Option to Result - return error if no token was found
     // .ok_or_else() transforms Option<T> into Result<T, E>
     let token = cookies
         .ok_or_else(|| HttpError::unauthorized(ErrorMessage::TokenNotProvided.to_string()))?;
 
+    if api_token::is_api_token(&token) {
+        return authenticate_api_token(app_state, &token).await;
+    }
+
     // Decode and verify the JWT token
     // This checks:
     // - Token signature is valid (hasn't been tampered with)
     // - Token hasn't expired
-    // - Token was signed with the correct secret key
-    let token_details = match token::decode_token(token, app_state.env.jwt_secret.as_bytes()) {
-        Ok(token_details) => token_details,
+    // - Token was signed with one of our keys, by us (iss) for us (aud)
+    // - Token is an access token, not a refresh token
+    let claims = match token::decode_access_token(token, &app_state.jwt_keys) {
+        Ok(claims) => claims,
         Err(_) => {
             return Err(HttpError::unauthorized(
                 ErrorMessage::InvalidToken.to_string(),
@@ -94,11 +157,64 @@ pub async fn auth(
         }
     };
 
+    // Tokens revoked before they expire (e.g. on logout) are on a denylist
+    let revoked = app_state
+        .redis_client
+        .is_access_token_revoked(&claims.jti)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, checking token denylist: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+    if revoked {
+        return Err(HttpError::unauthorized(
+            ErrorMessage::InvalidToken.to_string(),
+        ));
+    }
+
+    // Browsers attach cookies to cross-site requests too, so state-changing
+    // requests authenticated by the cookie must also carry the session's CSRF token.
+    // Bearer tokens are only ever sent by code that holds them, they're exempt.
+    if from_cookie && !req.method().is_safe() {
+        let csrf_token = req
+            .headers()
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        if !signature::verify_csrf_token(
+            &claims.sid,
+            csrf_token,
+            app_state.env.jwt_secret.as_bytes(),
+        ) {
+            return Err(HttpError::new(
+                ErrorMessage::CsrfTokenInvalid.to_string(),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+    }
+
     // Extract user ID from token claims and parse into UUID format
-    // Token details contain the user ID as a string representation
-    let user_id = uuid::Uuid::parse_str(&token_details.to_string())
+    // The subject contains the user ID as a string representation
+    let user_id = uuid::Uuid::parse_str(&claims.sub)
         .map_err(|_| HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
 
+    // A signed out session (logout, revoked from another device, reuse detected)
+    // takes its access tokens with it
+    let session_active = app_state
+        .redis_client
+        .is_session_active(user_id, &claims.sid)
+        .await
+        .map_err(|e| {
+            tracing::error!("RedisDB error, checking session: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+    if !session_active {
+        return Err(HttpError::unauthorized(
+            ErrorMessage::InvalidToken.to_string(),
+        ));
+    }
+
     // Fetch user from database using the ID from the token
     // We only search by user_id (other parameters are None)
     // This ensures the user still exists and hasn't been deleted
@@ -113,14 +229,73 @@ pub async fn auth(
     let user =
         user.ok_or_else(|| HttpError::unauthorized(ErrorMessage::UserNoLongerExist.to_string()))?;
 
-    // Insert authenticated user into request extensions
-    // This makes the user available to all downstream handlers and middleware
-    // without needing to re-authenticate or query the database
-    req.extensions_mut()
-        .insert(JWTAuthMiddleware { user: user.clone() });
+    // Tokens issued before the password/email changed or a forced logout are void
+    if claims.ver != user.token_version {
+        return Err(HttpError::unauthorized(
+            ErrorMessage::InvalidToken.to_string(),
+        ));
+    }
 
-    // Pass the request to the next middleware/handler in the chain
-    Ok(next.run(req).await)
+    with_mfa_policy(app_state, user, None, Some(claims)).await
+}
+
+/// Resolve the user of a personal access token
+///
+/// Unknown and expired tokens are rejected like an invalid JWT.
+async fn authenticate_api_token(
+    app_state: &AppState,
+    token: &str,
+) -> Result<JWTAuthMiddleware, HttpError> {
+    let api_token = app_state
+        .db_client
+        .get_api_token_by_hash(&token_hash::hash(token))
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, getting API token: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?
+        .filter(|t| {
+            t.expires_at
+                .is_none_or(|expires_at| expires_at > Utc::now())
+        })
+        .ok_or_else(|| HttpError::unauthorized(ErrorMessage::InvalidToken.to_string()))?;
+
+    let user = app_state
+        .db_client
+        .get_user(Some(api_token.user_id), None, None, None)
+        .await
+        .map_err(|_| HttpError::unauthorized(ErrorMessage::UserNoLongerExist.to_string()))?
+        .ok_or_else(|| HttpError::unauthorized(ErrorMessage::UserNoLongerExist.to_string()))?;
+
+    if let Err(e) = app_state.db_client.touch_api_token(api_token.id).await {
+        tracing::warn!("Failed to update API token last use: {:?}", e);
+    }
+
+    with_mfa_policy(app_state, user, Some(api_token.scopes()), None).await
+}
+
+/// Finish authentication: check whether the user still has to set up 2FA for their role
+async fn with_mfa_policy(
+    app_state: &AppState,
+    user: User,
+    token_scopes: Option<Vec<ApiScope>>,
+    claims: Option<TokenClaims>,
+) -> Result<JWTAuthMiddleware, HttpError> {
+    let mfa_setup_required = app_state
+        .db_client
+        .mfa_setup_required(user.id, user.role)
+        .await
+        .map_err(|e| {
+            tracing::error!("DB error, checking MFA policy: {}", e);
+            HttpError::server_error(ErrorMessage::ServerError.to_string())
+        })?;
+
+    Ok(JWTAuthMiddleware {
+        user,
+        mfa_setup_required,
+        token_scopes,
+        claims,
+    })
 }
 
 /// Role-based access control (RBAC) middleware
@@ -134,14 +309,19 @@ pub async fn auth(
 /// - `req`: The incoming request (must have been processed by auth middleware)
 /// - `next`: The next middleware/handler in the chain
 /// - `required_roles`: List of roles allowed to access this route
+/// - `required_scope`: Scope a personal access token needs for this route,
+///   None if the route is only for logged-in users (tokens are refused)
 ///
 /// # Errors
 /// Returns 401 if user is not authenticated
-/// Returns 403 if user doesn't have any of the required roles
+/// Returns 403 if user doesn't have any of the required roles,
+/// or their role requires two-factor authentication and they haven't enabled it,
+/// or the request uses a personal access token without the required scope
 pub async fn role_check(
     req: Request,
     next: Next,
     required_roles: Vec<UserRole>,
+    required_scope: Option<ApiScope>,
 ) -> Result<impl IntoResponse, HttpError> {
     // Extract authenticated user from request extensions
     // This was inserted by the auth middleware earlier in the chain
@@ -159,6 +339,27 @@ pub async fn role_check(
         ));
     }
 
+    // Roles that require two-factor authentication only get their privileges once it's enabled.
+    // Routes open to every user stay reachable, so the account can still be used to set it up.
+    if user.mfa_setup_required && !required_roles.contains(&UserRole::User) {
+        return Err(HttpError::new(
+            ErrorMessage::MfaSetupRequired.to_string(),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+
+    // Personal access tokens only reach routes that name one of their scopes
+    let scope_allowed = match &user.token_scopes {
+        Some(token_scopes) => required_scope.is_some_and(|scope| token_scopes.contains(&scope)),
+        None => true,
+    };
+    if !scope_allowed {
+        return Err(HttpError::new(
+            ErrorMessage::TokenScopeMissing.to_string(),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+
     // User has required role - proceed to the next handler
     Ok(next.run(req).await)
 }
@@ -62,10 +62,180 @@ pub struct User {
     pub verified: bool,                     // Whether email has been verified
     pub verification_token: Option<String>, // Token sent via email for verification (None after verification)
     pub token_expires_at: Option<DateTime<Utc>>,
+    pub token_version: i32, // `ver` claim of valid tokens, bumped to sign the user out everywhere
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
 }
 
+/// Login session, one per signed-in device (stored in Redis, not Postgres)
+///
+/// Created at login. The tokens of a device are only accepted while its
+/// session exists, so deleting the session signs that device out right away.
+///
+/// The refresh token is rotated on every use, which makes the session a token
+/// family: see `RedisClient::rotate_refresh_token` for reuse detection.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: String, // Random UUID, also the `sid` claim of the refresh token
+    pub user_id: Uuid,
+    pub refresh_token: String, // Current refresh token of the session
+    pub user_agent: String,    // As sent at login, truncated
+    pub ip: String,            // Client IP at login
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>, // Last token refresh
+}
+
+/// Pending second login step (stored in Redis, not Postgres)
+///
+/// Created when a user with two-factor authentication enabled enters the
+/// right password. Its id is the short-lived MFA token sent to the client,
+/// which is exchanged for a session together with a code.
+/// The device details of the login are kept for the session created afterwards.
+#[derive(Debug, Clone)]
+pub struct MfaChallenge {
+    pub id: String, // Random token, only valid for a few minutes
+    pub user_id: Uuid,
+    pub user_agent: String,
+    pub ip: String,
+}
+
+/// Temporary lock of an account after too many failed passwords or second
+/// factors (stored in Redis, not Postgres)
+///
+/// Failures are counted per account, whatever IP they come from, so attacks
+/// spread over many IPs are throttled too. Every lock lasts twice as long as
+/// the previous one, see `register_failed_login`. The owner is emailed a link
+/// carrying the unlock nonce, which lifts the lock early.
+#[derive(Debug, Clone)]
+pub struct AccountLock {
+    pub user_id: Uuid,
+    pub locked_until: DateTime<Utc>,
+    pub failures: u32, // Failures (of the kind that locked it) in the last 24 hours
+    pub last_ip: String, // IP of the failure that locked the account
+    pub unlock_nonce: String, // Random value of the emailed unlock link
+}
+
+/// TOTP authenticator of a user
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct UserTotp {
+    pub user_id: Uuid,
+    pub secret: String, // Base32, shared with the authenticator app
+    pub enabled_at: Option<DateTime<Utc>>, // None while enrolment is pending
+    pub last_used_step: i64, // Time step of the last accepted code
+    pub created_at: DateTime<Utc>,
+}
+
+/// WebAuthn credential (passkey) of a user
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct UserPasskey {
+    pub id: i64,
+    pub user_id: Uuid,
+    pub name: String,       // Chosen by the user, e.g. "Phone"
+    pub credential: String, // Serialized `webauthn_rs::prelude::Passkey` (JSON)
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Pending social login, between the redirect to the provider and its callback
+/// (stored in Redis, not Postgres)
+///
+/// Its id is the OAuth `state` parameter, so a callback is only accepted for
+/// a login this API started, and only once.
+#[derive(Debug, Clone)]
+pub struct OAuthLoginState {
+    pub id: String,                 // Random `state` sent to the provider
+    pub provider: String,           // Name from OAUTH_PROVIDERS
+    pub code_verifier: String,      // PKCE verifier, never leaves the server
+    pub link_user_id: Option<Uuid>, // Signed-in user linking an identity, None for a login
+}
+
+/// Account at an external login provider linked to a user
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct UserIdentity {
+    pub id: i64,
+    pub user_id: Uuid,
+    pub provider: String, // Name from OAUTH_PROVIDERS, e.g. "github"
+    pub subject: String,  // User id at the provider
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// What a personal access token may do
+///
+/// Stored as text in `api_token.scopes` (see `to_str`). A token only reaches
+/// routes whose `role_check` names one of its scopes, and only if the
+/// user's role is allowed there too.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ApiScope {
+    #[serde(rename = "posts:write")]
+    PostsWrite, // Create, edit and delete posts
+    #[serde(rename = "comments:write")]
+    CommentsWrite, // Create, edit and delete own comments
+    #[serde(rename = "media:write")]
+    MediaWrite, // Upload images and manage the media library
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 3] = [
+        ApiScope::PostsWrite,
+        ApiScope::CommentsWrite,
+        ApiScope::MediaWrite,
+    ];
+
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            ApiScope::PostsWrite => "posts:write",
+            ApiScope::CommentsWrite => "comments:write",
+            ApiScope::MediaWrite => "media:write",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ApiScope> {
+        ApiScope::ALL.into_iter().find(|s| s.to_str() == name)
+    }
+}
+
+/// Email change of a user, see `update_user_email`
+///
+/// The new address has to be confirmed before it replaces the old one, and
+/// the old address gets a link that cancels the change, or reverts it
+/// if it was already confirmed. Only hashes of both link tokens are stored.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PendingEmailChange {
+    pub id: i64,
+    pub user_id: Uuid,
+    pub old_email: String,
+    pub new_email: String,
+    pub expires_at: DateTime<Utc>,        // Of the confirmation link
+    pub revert_expires_at: DateTime<Utc>, // Of the revert link
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Personal access token of a user (the token itself is never stored)
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: Uuid,
+    pub name: String,         // Chosen by the user, e.g. "publish script"
+    pub token_prefix: String, // Start of the token, shown in the token list
+    pub scopes: Vec<String>,  // `ApiScope` names, see `scopes()`
+    pub expires_at: Option<DateTime<Utc>>, // None = never expires
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiToken {
+    /// Scopes of the token, unknown names (e.g. of a removed scope) are ignored
+    pub fn scopes(&self) -> Vec<ApiScope> {
+        self.scopes
+            .iter()
+            .filter_map(|s| ApiScope::from_name(s))
+            .collect()
+    }
+}
+
 /// Post model representing blog posts/articles
 ///
 /// This struct stores blog post data with multiple representations of content:
@@ -93,6 +263,20 @@ pub struct Post {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Moderation status of a comment
+///
+/// Only `Approved` comments are shown publicly. `Pending` comments wait in
+/// the moderation queue; `Rejected` and `Spam` are kept for the audit trail.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "comment_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum CommentStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Spam,
+}
+
 /// Comment model representing user comments on blog posts
 ///
 /// This creates a one-to-many relationship:
@@ -102,16 +286,191 @@ pub struct Post {
 /// The relationship is established through foreign keys:
 /// - `user_id`: References users.id
 /// - `post_id`: References posts.id
+/// - `parent_id`: References comment.id (replies only)
+///
+/// Threading uses a materialized path: `path` holds the ids from the root
+/// comment down to this one, so sorting by `path` gives a depth-first thread.
+/// Deleting a comment that has replies only sets `deleted_at` (soft delete);
+/// the row is then shown as "[deleted]" so the replies keep their context.
 #[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
 pub struct Comment {
-    pub id: i64,       // Primary key (auto-incrementing)
-    pub user_id: Uuid, // Foreign key: which user wrote this comment
-    pub post_id: i64,  // Foreign key: which post this comment belongs to
-    pub content: String,
+    pub id: i32,                // Primary key (auto-incrementing)
+    pub user_id: Uuid,          // Foreign key: which user wrote this comment
+    pub post_id: i32,           // Foreign key: which post this comment belongs to
+    pub parent_id: Option<i32>, // Foreign key: comment being replied to (None = top-level)
+    pub depth: i32,             // 0 for top-level comments
+    pub path: Vec<i32>,         // Ancestor ids + own id, e.g. [12, 40, 41]
+    pub content: String,        // Markdown source
+    pub content_html: String,   // Rendered at write time, see utils::comment_content
+    pub edit_count: i32,        // Number of edits, previous versions are in comment_revision
+    pub status: CommentStatus,
+    pub deleted_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// Previous version of an edited comment
+///
+/// A row is written every time an edit changes the text, holding the text
+/// as it was before the edit. Only moderators get to see these.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct CommentRevision {
+    pub id: i64,
+    pub comment_id: i32,
+    pub content: String,
+    pub content_html: String,
+    pub written_at: DateTime<Utc>,  // When this version was written
+    pub replaced_at: DateTime<Utc>, // When an edit replaced it
+}
+
+/// Reaction a user can leave on a post or comment
+///
+/// Maps to the "reaction_type" ENUM in PostgreSQL.
+/// Also used as the field name of the Redis counter hashes (see `to_str`).
+#[derive(
+    Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+#[sqlx(type_name = "reaction_type", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReactionType {
+    Like,
+    Love,
+    Insightful,
+    Funny,
+    Celebrate,
+}
+
+impl ReactionType {
+    pub const ALL: [ReactionType; 5] = [
+        ReactionType::Like,
+        ReactionType::Love,
+        ReactionType::Insightful,
+        ReactionType::Funny,
+        ReactionType::Celebrate,
+    ];
+
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            ReactionType::Like => "like",
+            ReactionType::Love => "love",
+            ReactionType::Insightful => "insightful",
+            ReactionType::Funny => "funny",
+            ReactionType::Celebrate => "celebrate",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ReactionType> {
+        ReactionType::ALL.into_iter().find(|r| r.to_str() == name)
+    }
+}
+
+/// What a reaction is attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReactionTarget {
+    Post(i32),
+    Comment(i32),
+}
+
+impl ReactionTarget {
+    /// Short form used in Redis, e.g. "post:12", "comment:40"
+    pub fn to_key(&self) -> String {
+        match self {
+            ReactionTarget::Post(id) => format!("post:{}", id),
+            ReactionTarget::Comment(id) => format!("comment:{}", id),
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<ReactionTarget> {
+        let (kind, id) = key.split_once(':')?;
+        let id = id.parse().ok()?;
+        match kind {
+            "post" => Some(ReactionTarget::Post(id)),
+            "comment" => Some(ReactionTarget::Comment(id)),
+            _ => None,
+        }
+    }
+}
+
+/// Token statistics of the Naive Bayes spam filter
+///
+/// Number of spam / ham comments the token appeared in,
+/// see `spam::bayes` for how they are turned into a score.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct SpamToken {
+    pub token: String,
+    pub spam_count: i32,
+    pub ham_count: i32,
+}
+
+/// How often a user wants to be emailed about comments
+///
+/// Maps to the "notification_frequency" ENUM in PostgreSQL.
+/// Users without a `notification_preference` row are treated as `Instant`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "notification_frequency", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationFrequency {
+    Instant, // One email per comment
+    Daily,   // One digest email per day
+    Off,
+}
+
+/// Queued comment notification, joined with everything needed to write the email
+///
+/// `reason` is "reply" when the recipient wrote the parent comment,
+/// "mention" when they were mentioned, "post_comment" when they wrote the post.
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct CommentNotification {
+    pub id: i64,
+    pub recipient_id: Uuid,
+    pub recipient_email: String,
+    pub recipient_username: String,
+    pub reason: String,
+    pub comment_id: i32,
+    pub comment_content: String,
+    pub commenter_username: String,
+    pub post_id: i32,
+    pub post_title: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Why a post or comment was reported
+///
+/// Maps to the "report_reason" ENUM in PostgreSQL.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "report_reason", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    Spam,
+    Harassment,
+    Hate,
+    Sexual,
+    Violence,
+    Misinformation,
+    OffTopic,
+    Other, // Details required
+}
+
+/// Where a report is in triage
+///
+/// All open reports of a post/comment are resolved together,
+/// since the decision is about the content, not the individual report.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "report_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReportStatus {
+    Open,
+    Dismissed, // Content is fine (restored if it was hidden)
+    Upheld,    // Content was taken down
+}
+
+/// What a report is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportTarget {
+    Post(i32),
+    Comment(i32),
+}
+
 /// Newsletter subscription model
 ///
 /// Stores email addresses of users who subscribed to the newsletter.
@@ -128,3 +487,33 @@ pub struct NewsletterEmail {
     pub email: String,             // Subscriber's email address
     pub created_at: DateTime<Utc>, // When they subscribed
 }
+
+/// Uploaded image tracked in the media library
+///
+/// `id` is also the base file name on disk, so every file belonging to this
+/// upload (`<id>.jpg`, `<id>-480w.webp`, ...) can be found from the row alone.
+///
+/// `hash` is the SHA-256 of the bytes the client sent (before processing).
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct Media {
+    pub id: Uuid,
+    pub uploader_id: Option<Uuid>, // None once the uploader's account is deleted
+    pub file_name: String,         // Full-size file
+    pub hash: String,
+    pub mime_type: String,
+    pub width: i32,
+    pub height: i32,
+    pub size_bytes: i64,
+    pub blurhash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Resized or re-encoded file generated from a Media row
+#[derive(Debug, Deserialize, Serialize, sqlx::FromRow, Clone)]
+pub struct MediaVariant {
+    pub file_name: String, // Primary key
+    pub media_id: Uuid,    // Foreign key: media.id
+    pub mime_type: String,
+    pub width: i32,
+    pub height: i32,
+}
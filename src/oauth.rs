@@ -0,0 +1,344 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use data_encoding::BASE64URL_NOPAD;
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::HttpError;
+
+/// Issuer used for `google` when `OAUTH_GOOGLE_ISSUER` isn't set
+const GOOGLE_ISSUER: &str = "https://accounts.google.com";
+
+/// GitHub doesn't speak OpenID Connect, its OAuth endpoints are fixed
+const GITHUB_AUTHORIZE_URL: &str = "https://github.com/login/oauth/authorize";
+const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+const GITHUB_USER_URL: &str = "https://api.github.com/user";
+const GITHUB_EMAILS_URL: &str = "https://api.github.com/user/emails";
+
+/// GitHub's API refuses requests without a User-Agent
+const USER_AGENT: &str = "blog-backend";
+
+/// How a provider is talked to
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderKind {
+    Github,                  // GitHub OAuth app (not OIDC, claims come from the REST API)
+    Oidc { issuer: String }, // Any OpenID Connect issuer, endpoints come from discovery
+}
+
+/// A social login provider enabled with `OAUTH_PROVIDERS`
+///
+/// Each provider is configured with `OAUTH_{NAME}_CLIENT_ID`,
+/// `OAUTH_{NAME}_CLIENT_SECRET` and, for OIDC issuers other than Google,
+/// `OAUTH_{NAME}_ISSUER`. The name `github` selects GitHub, every other name
+/// is an OpenID Connect issuer (e.g. a local mock issuer for testing).
+#[derive(Debug, Clone)]
+pub struct OAuthProvider {
+    pub name: String, // Used in URLs and stored with linked identities, e.g. "google"
+    pub kind: ProviderKind,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+/// Endpoints of a provider for one login
+#[derive(Debug, Clone)]
+pub struct ProviderEndpoints {
+    pub authorization: String,
+    pub token: String,
+    pub userinfo: String,
+}
+
+/// Who the provider says the user is
+#[derive(Debug, Clone)]
+pub struct ExternalIdentity {
+    pub subject: String, // Stable id at the provider (OIDC `sub`, GitHub user id)
+    pub email: Option<String>,
+    pub email_verified: bool, // Provider asserts the user owns `email`
+    pub preferred_username: Option<String>, // Suggestion for the username of a new account
+}
+
+/// Parts of the OpenID Provider metadata (`/.well-known/openid-configuration`) we use
+#[derive(Debug, Deserialize)]
+struct DiscoveryDocument {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// OIDC UserInfo response
+///
+/// Some issuers send `email_verified` as the string "true", hence the Value.
+#[derive(Debug, Deserialize)]
+struct UserInfo {
+    sub: String,
+    email: Option<String>,
+    email_verified: Option<serde_json::Value>,
+    preferred_username: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubUser {
+    id: i64,
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+impl OAuthProvider {
+    /// Read the configuration of a provider from the environment
+    ///
+    /// # Panics
+    /// If the name is invalid or a required variable is missing, like the rest of `Config::init`
+    pub fn from_env(name: &str) -> Self {
+        let name = name.trim().to_lowercase();
+        assert!(
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "OAUTH_PROVIDERS contains an invalid provider name '{}'",
+            name
+        );
+
+        let prefix = format!("OAUTH_{}", name.to_uppercase().replace('-', "_"));
+        let var = |suffix: &str| std::env::var(format!("{}_{}", prefix, suffix));
+
+        let client_id =
+            var("CLIENT_ID").unwrap_or_else(|_| panic!("{}_CLIENT_ID must be set", prefix));
+        let client_secret =
+            var("CLIENT_SECRET").unwrap_or_else(|_| panic!("{}_CLIENT_SECRET must be set", prefix));
+
+        let kind = match name.as_str() {
+            "github" => ProviderKind::Github,
+            "google" => ProviderKind::Oidc {
+                issuer: var("ISSUER").unwrap_or(GOOGLE_ISSUER.to_string()),
+            },
+            _ => ProviderKind::Oidc {
+                issuer: var("ISSUER").unwrap_or_else(|_| panic!("{}_ISSUER must be set", prefix)),
+            },
+        };
+
+        OAuthProvider {
+            name,
+            kind,
+            client_id,
+            client_secret,
+        }
+    }
+
+    /// Scopes requested at the authorization endpoint
+    fn scopes(&self) -> &str {
+        match self.kind {
+            ProviderKind::Github => "read:user user:email",
+            ProviderKind::Oidc { .. } => "openid email profile",
+        }
+    }
+
+    /// Look up the endpoints of the provider
+    ///
+    /// For OIDC issuers this fetches the discovery document on every login,
+    /// so rotated endpoints are picked up without a restart. The `issuer` in the
+    /// document must match the configured one (OIDC Discovery 1.0, section 4.3).
+    pub async fn endpoints(
+        &self,
+        client: &reqwest::Client,
+    ) -> Result<ProviderEndpoints, HttpError> {
+        let issuer = match &self.kind {
+            ProviderKind::Github => {
+                return Ok(ProviderEndpoints {
+                    authorization: GITHUB_AUTHORIZE_URL.to_string(),
+                    token: GITHUB_TOKEN_URL.to_string(),
+                    userinfo: GITHUB_USER_URL.to_string(),
+                });
+            }
+            ProviderKind::Oidc { issuer } => issuer.trim_end_matches('/'),
+        };
+
+        let document: DiscoveryDocument = client
+            .get(format!("{}/.well-known/openid-configuration", issuer))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| HttpError::server_error(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        if document.issuer.trim_end_matches('/') != issuer {
+            return Err(HttpError::server_error(format!(
+                "Discovery document issuer '{}' doesn't match '{}'",
+                document.issuer, issuer
+            )));
+        }
+
+        Ok(ProviderEndpoints {
+            authorization: document.authorization_endpoint,
+            token: document.token_endpoint,
+            userinfo: document.userinfo_endpoint,
+        })
+    }
+
+    /// URL the browser is sent to for signing in at the provider
+    ///
+    /// # Parameters
+    /// - `redirect_uri`: Callback URL of this API, must be registered at the provider
+    /// - `state`: Random value tying the callback to this login attempt
+    /// - `code_challenge`: S256 PKCE challenge from `pkce_pair`
+    pub fn authorize_url(
+        &self,
+        endpoints: &ProviderEndpoints,
+        redirect_uri: &str,
+        state: &str,
+        code_challenge: &str,
+    ) -> Result<String, HttpError> {
+        let mut url = Url::parse(&endpoints.authorization)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", self.scopes())
+            .append_pair("state", state)
+            .append_pair("code_challenge", code_challenge)
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.to_string())
+    }
+
+    /// Exchange the authorization code from the callback for an access token
+    ///
+    /// The client secret is sent in the body (`client_secret_post`), which
+    /// GitHub requires and OIDC issuers commonly accept.
+    pub async fn exchange_code(
+        &self,
+        client: &reqwest::Client,
+        endpoints: &ProviderEndpoints,
+        code: &str,
+        redirect_uri: &str,
+        code_verifier: &str,
+    ) -> Result<String, HttpError> {
+        let token: TokenResponse = client
+            .post(&endpoints.token)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| HttpError::server_error(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        Ok(token.access_token)
+    }
+
+    /// Fetch who signed in with the access token from `exchange_code`
+    ///
+    /// The claims come from the UserInfo endpoint over TLS with a token we just
+    /// got from the token endpoint, so there is no ID token signature to check.
+    pub async fn fetch_identity(
+        &self,
+        client: &reqwest::Client,
+        endpoints: &ProviderEndpoints,
+        access_token: &str,
+    ) -> Result<ExternalIdentity, HttpError> {
+        match self.kind {
+            ProviderKind::Github => fetch_github_identity(client, endpoints, access_token).await,
+            ProviderKind::Oidc { .. } => {
+                let info: UserInfo = client
+                    .get(&endpoints.userinfo)
+                    .bearer_auth(access_token)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| HttpError::server_error(e.to_string()))?
+                    .json()
+                    .await
+                    .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+                let email_verified = match info.email_verified {
+                    Some(serde_json::Value::Bool(v)) => v,
+                    Some(serde_json::Value::String(v)) => v == "true",
+                    _ => false,
+                };
+
+                Ok(ExternalIdentity {
+                    subject: info.sub,
+                    email: info.email,
+                    email_verified,
+                    preferred_username: info.preferred_username.or(info.name),
+                })
+            }
+        }
+    }
+}
+
+/// GitHub only returns public profile emails on `/user`, the verified
+/// primary address comes from `/user/emails`
+async fn fetch_github_identity(
+    client: &reqwest::Client,
+    endpoints: &ProviderEndpoints,
+    access_token: &str,
+) -> Result<ExternalIdentity, HttpError> {
+    let user: GithubUser = client
+        .get(&endpoints.userinfo)
+        .bearer_auth(access_token)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .json()
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let emails: Vec<GithubEmail> = client
+        .get(GITHUB_EMAILS_URL)
+        .bearer_auth(access_token)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .json()
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let primary = emails.into_iter().find(|e| e.primary);
+
+    Ok(ExternalIdentity {
+        subject: user.id.to_string(),
+        email_verified: primary.as_ref().is_some_and(|e| e.verified),
+        email: primary.map(|e| e.email),
+        preferred_username: Some(user.login),
+    })
+}
+
+/// Generate a PKCE code verifier and its S256 challenge (RFC 7636)
+///
+/// # Returns
+/// (verifier, challenge), the verifier stays on the server until the code exchange
+pub fn pkce_pair() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let verifier = BASE64URL_NOPAD.encode(&bytes);
+    let challenge = BASE64URL_NOPAD.encode(&Sha256::digest(verifier.as_bytes()));
+    (verifier, challenge)
+}
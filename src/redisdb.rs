@@ -1,13 +1,103 @@
+use std::collections::HashMap;
 use std::net::IpAddr;
 
+use chrono::{DateTime, Utc};
 use redis::{AsyncCommands, aio::ConnectionManager};
+use uuid::Uuid;
+
+use crate::models::{
+    AccountLock, MfaChallenge, OAuthLoginState, ReactionTarget, ReactionType, Session,
+};
+
+/// How long reaction counters stay cached after their last write
+const REACTION_COUNTS_TTL_SECS: i64 = 86400;
+
+/// Set of counters changed since the last flush to Postgres
+const REACTION_DIRTY_KEY: &str = "reaction_counts_dirty";
+
+/// Sorted set of locked accounts (user id scored by the end of the lock), for the admin list
+const ACCOUNT_LOCKS_KEY: &str = "account_locks";
+
+/// How long failed passwords count against an account
+const ACCOUNT_FAILURES_TTL_SECS: i64 = 86400;
+
+/// Seconds the refresh token rotated out last is still accepted (concurrent refreshes)
+const REFRESH_REUSE_GRACE_SECS: i64 = 10;
+
+/// Outcome of presenting a refresh token, see `RedisClient::rotate_refresh_token`
+#[derive(Debug)]
+pub enum TokenRotation {
+    Rotated,       // Token was the latest one, the new token replaced it
+    Grace(String), // Token was just rotated out by a concurrent refresh, here is the latest one
+    Reused,        // Token was rotated out earlier: stolen, session deleted
+    NotFound,      // Session expired, revoked, or belongs to another user
+}
+
+/// Build a session from its Redis hash, None if it doesn't exist (or is corrupt)
+fn session_from_hash(session_id: &str, hash: HashMap<String, String>) -> Option<Session> {
+    let timestamp = |field: &str| {
+        hash.get(field)
+            .and_then(|v| v.parse::<i64>().ok())
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+    };
+
+    Some(Session {
+        id: session_id.to_string(),
+        user_id: hash.get("user_id")?.parse().ok()?,
+        refresh_token: hash.get("refresh_token")?.clone(),
+        user_agent: hash.get("user_agent").cloned().unwrap_or_default(),
+        ip: hash.get("ip").cloned().unwrap_or_default(),
+        created_at: timestamp("created_at")?,
+        last_used_at: timestamp("last_used_at")?,
+    })
+}
+
+/// Build an MFA challenge and its attempt count from its Redis hash, None if it doesn't exist
+fn mfa_challenge_from_hash(
+    challenge_id: &str,
+    hash: HashMap<String, String>,
+) -> Option<(MfaChallenge, u32)> {
+    let attempts = hash.get("attempts")?.parse().ok()?;
+    let challenge = MfaChallenge {
+        id: challenge_id.to_string(),
+        user_id: hash.get("user_id")?.parse().ok()?,
+        user_agent: hash.get("user_agent").cloned().unwrap_or_default(),
+        ip: hash.get("ip").cloned().unwrap_or_default(),
+    };
+    Some((challenge, attempts))
+}
+
+/// Build a pending social login from its Redis hash, None if it doesn't exist
+fn oauth_state_from_hash(state_id: &str, hash: HashMap<String, String>) -> Option<OAuthLoginState> {
+    Some(OAuthLoginState {
+        id: state_id.to_string(),
+        provider: hash.get("provider")?.clone(),
+        code_verifier: hash.get("code_verifier")?.clone(),
+        link_user_id: hash.get("link_user_id").and_then(|id| id.parse().ok()),
+    })
+}
+
+/// Build an account lock from its Redis hash, None if it doesn't exist (or expired)
+fn account_lock_from_hash(user_id: Uuid, hash: HashMap<String, String>) -> Option<AccountLock> {
+    Some(AccountLock {
+        user_id,
+        locked_until: DateTime::from_timestamp(hash.get("locked_until")?.parse().ok()?, 0)?,
+        failures: hash.get("failures")?.parse().ok()?,
+        last_ip: hash.get("last_ip").cloned().unwrap_or_default(),
+        unlock_nonce: hash.get("unlock_nonce")?.clone(),
+    })
+}
 
 /// Redis client wrapper for caching and session management
 ///
 /// This client handles:
-/// - JWT refresh token storage (with automatic expiration)
+/// - Login sessions with their refresh tokens (with automatic expiration)
+/// - Pending two-factor login challenges, passkey ceremonies and social logins
+/// - Unused magic login links
+/// - Revoked access tokens (until they expire)
 /// - Login attempt tracking for rate limiting and security
 /// - IP-based and identifier-based (email/username) tracking
+/// - Per-account failed password counters and account locks
 ///
 /// Why use Redis?
 /// - In-memory storage provides extremely fast read/write operations
@@ -31,58 +121,445 @@ impl RedisClient {
         Self { conn }
     }
 
-    /// Store a refresh token for a user with automatic expiration
+    /// Store a login session with automatic expiration
     ///
-    /// Refresh tokens are stored separately from access tokens and have longer lifespans.
-    /// When an access token expires, the client can use the refresh token to obtain
-    /// a new access token without re-authentication.
+    /// Every login creates its own session, so signing in on a new device
+    /// doesn't sign out the others. The session holds the refresh token of
+    /// that device, plus what the user needs to recognize it in the session list.
     ///
-    /// Key pattern: "refresh:{user_id}"
+    /// Key patterns:
+    /// - "session:{session_id}": hash with the session fields
+    /// - "user_sessions:{user_id}": set of the user's session IDs
+    ///
+    /// The index set lives as long as the newest session; IDs of sessions
+    /// that expired before it are dropped when the sessions are listed.
     ///
     /// # Parameters
-    /// - `user_id`: User's unique identifier (typically a UUID string)
-    /// - `refresh_token`: The JWT refresh token to store
-    /// - `expires_in_seconds`: TTL for the token (e.g., 7 days = 604800 seconds)
+    /// - `session`: Session to store
+    /// - `expires_in_seconds`: TTL, same as the refresh token (e.g., 7 days = 604800 seconds)
     ///
     /// # Why clone ConnectionManager?
     /// Redis commands require a mutable reference, but `self` is immutable. (&mut self is impossible since app_state is immutable)
     /// Cloning ConnectionManager is cheap (it's Arc-based internally) and allows
     /// us to get mutable access without requiring &mut self.
-    pub async fn save_refresh_token(
+    pub async fn save_session(
         &self,
-        user_id: &str,
-        refresh_token: &str,
+        session: &Session,
         expires_in_seconds: i64,
     ) -> redis::RedisResult<()> {
-        let key = format!("refresh:{}", user_id);
-        let ttl_secs = expires_in_seconds;
+        let key = format!("session:{}", session.id);
+        let user_key = format!("user_sessions:{}", session.user_id);
         let mut conn = self.conn.clone(); // Cheap clone - ConnectionManager uses Arc internally
 
-        // set_ex: Set key with expiration in one atomic operation
-        // Redis will automatically delete this key after ttl_secs
-        conn.set_ex(key, refresh_token, ttl_secs as u64).await
+        let fields = [
+            ("user_id", session.user_id.to_string()),
+            ("refresh_token", session.refresh_token.clone()),
+            ("user_agent", session.user_agent.clone()),
+            ("ip", session.ip.clone()),
+            ("created_at", session.created_at.timestamp().to_string()),
+            ("last_used_at", session.last_used_at.timestamp().to_string()),
+        ];
+
+        redis::pipe()
+            .atomic()
+            .hset_multiple(&key, &fields)
+            .ignore()
+            .expire(&key, expires_in_seconds)
+            .ignore()
+            .sadd(&user_key, &session.id)
+            .ignore()
+            .expire(&user_key, expires_in_seconds)
+            .ignore()
+            .query_async(&mut conn)
+            .await
     }
 
-    /// Retrieve a user's refresh token from Redis
+    /// Rotate the refresh token of a session (one refresh token = one use)
+    ///
+    /// A session is a token family: every refresh replaces its token with a
+    /// new one, and only the latest token is accepted. If an older token of
+    /// the family shows up again, it was copied by someone, and since there's
+    /// no telling whether the thief or the user is holding the latest one,
+    /// the whole session is deleted.
+    ///
+    /// The token rotated out last stays accepted for `REFRESH_REUSE_GRACE_SECS`,
+    /// so two tabs refreshing at the same moment don't sign the user out;
+    /// the late one gets the latest token instead of a new one.
     ///
-    /// Returns None if:
-    /// - Token was never stored
-    /// - Token has expired (Redis auto-deleted it)
-    /// - Token was manually deleted (logout)
-    pub async fn get_refresh_token(&self, user_id: &str) -> redis::RedisResult<Option<String>> {
-        let key = format!("refresh:{}", user_id);
+    /// Runs as a Lua script so the compare and the swap are atomic.
+    /// Every rotation also renews the TTL of the session.
+    pub async fn rotate_refresh_token(
+        &self,
+        user_id: Uuid,
+        session_id: &str,
+        presented_token: &str,
+        new_token: &str,
+        expires_in_seconds: i64,
+    ) -> redis::RedisResult<TokenRotation> {
+        let script = redis::Script::new(
+            r"
+            if redis.call('HGET', KEYS[1], 'user_id') ~= ARGV[1] then
+                return {'not_found'}
+            end
+            local current = redis.call('HGET', KEYS[1], 'refresh_token')
+            local now = tonumber(ARGV[4])
+            if current == ARGV[2] then
+                redis.call('HSET', KEYS[1], 'refresh_token', ARGV[3],
+                    'previous_refresh_token', ARGV[2], 'rotated_at', ARGV[4], 'last_used_at', ARGV[4])
+                redis.call('EXPIRE', KEYS[1], ARGV[5])
+                redis.call('EXPIRE', KEYS[2], ARGV[5])
+                return {'rotated'}
+            end
+            local previous = redis.call('HGET', KEYS[1], 'previous_refresh_token')
+            local rotated_at = tonumber(redis.call('HGET', KEYS[1], 'rotated_at') or '0')
+            if previous == ARGV[2] and now - rotated_at <= tonumber(ARGV[6]) then
+                return {'grace', current}
+            end
+            redis.call('DEL', KEYS[1])
+            redis.call('SREM', KEYS[2], ARGV[7])
+            return {'reused'}
+            ",
+        );
         let mut conn = self.conn.clone();
-        conn.get(key).await
+
+        let result: Vec<String> = script
+            .key(format!("session:{}", session_id))
+            .key(format!("user_sessions:{}", user_id))
+            .arg(user_id.to_string())
+            .arg(presented_token)
+            .arg(new_token)
+            .arg(Utc::now().timestamp())
+            .arg(expires_in_seconds)
+            .arg(REFRESH_REUSE_GRACE_SECS)
+            .arg(session_id)
+            .invoke_async(&mut conn)
+            .await?;
+
+        Ok(match result.as_slice() {
+            [status] if status == "rotated" => TokenRotation::Rotated,
+            [status, current] if status == "grace" => TokenRotation::Grace(current.clone()),
+            [status] if status == "reused" => TokenRotation::Reused,
+            _ => TokenRotation::NotFound,
+        })
     }
 
-    /// Delete a user's refresh token (used during logout)
+    /// Whether a session still exists and belongs to the user
     ///
-    /// This invalidates the refresh token, forcing re-authentication.
-    /// This is crucial for security when a user logs out.
-    pub async fn delete_refresh_token(&self, user_id: &str) -> redis::RedisResult<()> {
-        let key = format!("refresh:{}", user_id);
+    /// Checked on every request authenticated by an access token, so that
+    /// signing a session out also voids the access tokens issued to it.
+    pub async fn is_session_active(
+        &self,
+        user_id: Uuid,
+        session_id: &str,
+    ) -> redis::RedisResult<bool> {
         let mut conn = self.conn.clone();
-        conn.del(key).await
+        let owner: Option<String> = conn
+            .hget(format!("session:{}", session_id), "user_id")
+            .await?;
+        Ok(owner == Some(user_id.to_string()))
+    }
+
+    /// All live sessions of a user, most recently used first
+    pub async fn get_user_sessions(&self, user_id: Uuid) -> redis::RedisResult<Vec<Session>> {
+        let user_key = format!("user_sessions:{}", user_id);
+        let mut conn = self.conn.clone();
+
+        let session_ids: Vec<String> = conn.smembers(&user_key).await?;
+        if session_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut pipe = redis::pipe();
+        for session_id in &session_ids {
+            pipe.hgetall(format!("session:{}", session_id));
+        }
+        let hashes: Vec<HashMap<String, String>> = pipe.query_async(&mut conn).await?;
+
+        let mut sessions = Vec::new();
+        let mut expired = Vec::new();
+        for (session_id, hash) in session_ids.iter().zip(hashes) {
+            match session_from_hash(session_id, hash) {
+                Some(session) => sessions.push(session),
+                None => expired.push(session_id),
+            }
+        }
+
+        if !expired.is_empty() {
+            let _: () = conn.srem(&user_key, expired).await?;
+        }
+
+        sessions.sort_by(|a, b| b.last_used_at.cmp(&a.last_used_at));
+        Ok(sessions)
+    }
+
+    /// Delete one session of a user (sign that device out)
+    ///
+    /// Runs as a Lua script: the session is only deleted if it is in the
+    /// user's set, so nobody can revoke another user's session by its ID.
+    ///
+    /// # Returns
+    /// true if the session was deleted, false if the user has no such session
+    pub async fn delete_session(
+        &self,
+        user_id: Uuid,
+        session_id: &str,
+    ) -> redis::RedisResult<bool> {
+        let script = redis::Script::new(
+            r"
+            if redis.call('SREM', KEYS[1], ARGV[1]) == 1 then
+                redis.call('DEL', KEYS[2])
+                return 1
+            end
+            return 0
+            ",
+        );
+        let mut conn = self.conn.clone();
+
+        let deleted: i32 = script
+            .key(format!("user_sessions:{}", user_id))
+            .key(format!("session:{}", session_id))
+            .arg(session_id)
+            .invoke_async(&mut conn)
+            .await?;
+
+        Ok(deleted == 1)
+    }
+
+    /// Delete all sessions of a user, except `keep` (e.g. the current device)
+    ///
+    /// # Returns
+    /// Number of sessions deleted
+    pub async fn delete_user_sessions(
+        &self,
+        user_id: Uuid,
+        keep: Option<&str>,
+    ) -> redis::RedisResult<usize> {
+        let user_key = format!("user_sessions:{}", user_id);
+        let mut conn = self.conn.clone();
+
+        let session_ids: Vec<String> = conn.smembers(&user_key).await?;
+        let revoked: Vec<&String> = session_ids
+            .iter()
+            .filter(|id| Some(id.as_str()) != keep)
+            .collect();
+        if revoked.is_empty() {
+            return Ok(0);
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for session_id in &revoked {
+            pipe.del(format!("session:{}", session_id)).ignore();
+        }
+        pipe.srem(&user_key, &revoked).ignore();
+        let _: () = pipe.query_async(&mut conn).await?;
+
+        Ok(revoked.len())
+    }
+
+    /// Store a pending second login step, see `MfaChallenge`
+    ///
+    /// Key pattern: "mfa_challenge:{id}", a hash with the login details
+    /// and the number of codes tried so far.
+    pub async fn save_mfa_challenge(
+        &self,
+        challenge: &MfaChallenge,
+        expires_in_seconds: i64,
+    ) -> redis::RedisResult<()> {
+        let key = format!("mfa_challenge:{}", challenge.id);
+        let mut conn = self.conn.clone();
+
+        let fields = [
+            ("user_id", challenge.user_id.to_string()),
+            ("user_agent", challenge.user_agent.clone()),
+            ("ip", challenge.ip.clone()),
+            ("attempts", "0".to_string()),
+        ];
+
+        redis::pipe()
+            .atomic()
+            .hset_multiple(&key, &fields)
+            .ignore()
+            .expire(&key, expires_in_seconds)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+    }
+
+    /// Count a code attempt against a challenge and return the challenge
+    ///
+    /// Runs as a Lua script, so an expired challenge isn't recreated
+    /// (without TTL) by the attempt counter.
+    ///
+    /// # Returns
+    /// The challenge and the number of attempts including this one,
+    /// None if the challenge expired or was already used
+    pub async fn attempt_mfa_challenge(
+        &self,
+        challenge_id: &str,
+    ) -> redis::RedisResult<Option<(MfaChallenge, u32)>> {
+        let script = redis::Script::new(
+            r"
+            if redis.call('EXISTS', KEYS[1]) == 0 then
+                return {}
+            end
+            redis.call('HINCRBY', KEYS[1], 'attempts', 1)
+            return redis.call('HGETALL', KEYS[1])
+            ",
+        );
+        let mut conn = self.conn.clone();
+
+        let hash: HashMap<String, String> = script
+            .key(format!("mfa_challenge:{}", challenge_id))
+            .invoke_async(&mut conn)
+            .await?;
+
+        Ok(mfa_challenge_from_hash(challenge_id, hash))
+    }
+
+    /// Delete a challenge (used, or too many wrong codes)
+    ///
+    /// # Returns
+    /// true if this call deleted it, so of two requests completing the same
+    /// challenge at once only one gets a session
+    pub async fn delete_mfa_challenge(&self, challenge_id: &str) -> redis::RedisResult<bool> {
+        let mut conn = self.conn.clone();
+        let deleted: i32 = conn.del(format!("mfa_challenge:{}", challenge_id)).await?;
+        Ok(deleted == 1)
+    }
+
+    /// Store the state of a passkey ceremony until the browser answers
+    ///
+    /// Key pattern: "webauthn_state:{ceremony}", the value is the serialized
+    /// webauthn-rs state (it holds the challenge the answer must be signed over).
+    pub async fn save_webauthn_state(
+        &self,
+        ceremony: &str,
+        state: &str,
+        expires_in_seconds: i64,
+    ) -> redis::RedisResult<()> {
+        let mut conn = self.conn.clone();
+        conn.set_ex(
+            format!("webauthn_state:{}", ceremony),
+            state,
+            expires_in_seconds as u64,
+        )
+        .await
+    }
+
+    /// Get and delete the state of a passkey ceremony (GETDEL), so every challenge is used once
+    ///
+    /// # Returns
+    /// None if the ceremony expired or was already finished
+    pub async fn take_webauthn_state(&self, ceremony: &str) -> redis::RedisResult<Option<String>> {
+        let mut conn = self.conn.clone();
+        conn.get_del(format!("webauthn_state:{}", ceremony)).await
+    }
+
+    /// Store a pending social login, see `OAuthLoginState`
+    ///
+    /// Key pattern: "oauth_state:{id}", a hash with the provider, the PKCE
+    /// verifier and the user linking an identity (absent for a login).
+    pub async fn save_oauth_state(
+        &self,
+        state: &OAuthLoginState,
+        expires_in_seconds: i64,
+    ) -> redis::RedisResult<()> {
+        let key = format!("oauth_state:{}", state.id);
+        let mut conn = self.conn.clone();
+
+        let mut fields = vec![
+            ("provider", state.provider.clone()),
+            ("code_verifier", state.code_verifier.clone()),
+        ];
+        if let Some(user_id) = state.link_user_id {
+            fields.push(("link_user_id", user_id.to_string()));
+        }
+
+        redis::pipe()
+            .atomic()
+            .hset_multiple(&key, &fields)
+            .ignore()
+            .expire(&key, expires_in_seconds)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+    }
+
+    /// Get and delete a pending social login in one transaction, so every `state` is used once
+    ///
+    /// # Returns
+    /// None if the login expired or its callback already ran
+    pub async fn take_oauth_state(
+        &self,
+        state_id: &str,
+    ) -> redis::RedisResult<Option<OAuthLoginState>> {
+        let key = format!("oauth_state:{}", state_id);
+        let mut conn = self.conn.clone();
+
+        let (hash, _deleted): (HashMap<String, String>, i32) = redis::pipe()
+            .atomic()
+            .hgetall(&key)
+            .del(&key)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(oauth_state_from_hash(state_id, hash))
+    }
+
+    /// Remember an unused magic login link
+    ///
+    /// Key pattern: "magic_link:{nonce}", the value is the user id the link signs in.
+    pub async fn save_magic_link(
+        &self,
+        nonce: &str,
+        user_id: Uuid,
+        expires_in_seconds: i64,
+    ) -> redis::RedisResult<()> {
+        let mut conn = self.conn.clone();
+        conn.set_ex(
+            format!("magic_link:{}", nonce),
+            user_id.to_string(),
+            expires_in_seconds as u64,
+        )
+        .await
+    }
+
+    /// Get and delete a magic link (GETDEL), so of two requests using it only one signs in
+    ///
+    /// # Returns
+    /// The user id of the link, None if it expired or was already used
+    pub async fn take_magic_link(&self, nonce: &str) -> redis::RedisResult<Option<Uuid>> {
+        let mut conn = self.conn.clone();
+        let user_id: Option<String> = conn.get_del(format!("magic_link:{}", nonce)).await?;
+        Ok(user_id.and_then(|id| id.parse().ok()))
+    }
+
+    /// Revoke a single access token by its `jti`
+    ///
+    /// Key pattern: "revoked_token:{jti}", kept until the token expires anyway
+    /// so the denylist never grows beyond the tokens still in circulation.
+    pub async fn revoke_access_token(
+        &self,
+        jti: &str,
+        expires_in_seconds: i64,
+    ) -> redis::RedisResult<()> {
+        if expires_in_seconds <= 0 {
+            return Ok(());
+        }
+        let mut conn = self.conn.clone();
+        conn.set_ex(
+            format!("revoked_token:{}", jti),
+            1,
+            expires_in_seconds as u64,
+        )
+        .await
+    }
+
+    /// Whether an access token was revoked, checked on every authenticated request
+    pub async fn is_access_token_revoked(&self, jti: &str) -> redis::RedisResult<bool> {
+        let mut conn = self.conn.clone();
+        conn.exists(format!("revoked_token:{}", jti)).await
     }
 
     /// Get total failed login attempts from an IP address
@@ -170,4 +647,305 @@ impl RedisClient {
             .query_async(&mut conn) // Execute pipeline asynchronously
             .await
     }
+
+    /// Count a failed password against an account, whatever IP it came from
+    ///
+    /// Key pattern: "login_fail_account:{user_id}"
+    /// TTL: 24 hours, refreshed by every failure
+    ///
+    /// # Returns
+    /// Failures in the last 24 hours, this one included
+    pub async fn increment_account_failures(&self, user_id: Uuid) -> redis::RedisResult<u32> {
+        let key = format!("login_fail_account:{}", user_id);
+        let mut conn = self.conn.clone();
+
+        let (failures,): (u32,) = redis::pipe()
+            .atomic()
+            .incr(&key, 1)
+            .expire(&key, ACCOUNT_FAILURES_TTL_SECS)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(failures)
+    }
+
+    /// Count a wrong second factor (TOTP or recovery code) against an account
+    ///
+    /// Kept apart from the failed passwords: whoever gets here knows the
+    /// password, and a new MFA token must not give them a fresh set of guesses.
+    ///
+    /// Key pattern: "mfa_fail_account:{user_id}"
+    /// TTL: 24 hours, refreshed by every failure
+    ///
+    /// # Returns
+    /// Failures in the last 24 hours, this one included
+    pub async fn increment_account_mfa_failures(&self, user_id: Uuid) -> redis::RedisResult<u32> {
+        let key = format!("mfa_fail_account:{}", user_id);
+        let mut conn = self.conn.clone();
+
+        let (failures,): (u32,) = redis::pipe()
+            .atomic()
+            .incr(&key, 1)
+            .expire(&key, ACCOUNT_FAILURES_TTL_SECS)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(failures)
+    }
+
+    /// Lock an account until `lock.locked_until`
+    ///
+    /// Key patterns:
+    /// - "account_lock:{user_id}": hash with the lock fields, expires with the lock
+    /// - "account_locks": sorted set of locked user ids, see `get_account_locks`
+    pub async fn lock_account(&self, lock: &AccountLock) -> redis::RedisResult<()> {
+        let key = format!("account_lock:{}", lock.user_id);
+        let mut conn = self.conn.clone();
+
+        let fields = [
+            ("locked_until", lock.locked_until.timestamp().to_string()),
+            ("failures", lock.failures.to_string()),
+            ("last_ip", lock.last_ip.clone()),
+            ("unlock_nonce", lock.unlock_nonce.clone()),
+        ];
+
+        redis::pipe()
+            .atomic()
+            .del(&key)
+            .ignore()
+            .hset_multiple(&key, &fields)
+            .ignore()
+            .expire_at(&key, lock.locked_until.timestamp())
+            .ignore()
+            .zadd(
+                ACCOUNT_LOCKS_KEY,
+                lock.user_id.to_string(),
+                lock.locked_until.timestamp(),
+            )
+            .ignore()
+            .query_async(&mut conn)
+            .await
+    }
+
+    /// Current lock of an account, None if it isn't locked
+    pub async fn get_account_lock(&self, user_id: Uuid) -> redis::RedisResult<Option<AccountLock>> {
+        let mut conn = self.conn.clone();
+        let hash: HashMap<String, String> =
+            conn.hgetall(format!("account_lock:{}", user_id)).await?;
+        Ok(account_lock_from_hash(user_id, hash))
+    }
+
+    /// All accounts locked right now, the longest locked first
+    ///
+    /// Locks that ended are dropped from the index on the way.
+    pub async fn get_account_locks(&self) -> redis::RedisResult<Vec<AccountLock>> {
+        let mut conn = self.conn.clone();
+
+        let _: () = conn
+            .zrembyscore(ACCOUNT_LOCKS_KEY, "-inf", Utc::now().timestamp())
+            .await?;
+        let user_ids: Vec<String> = conn.zrevrange(ACCOUNT_LOCKS_KEY, 0, -1).await?;
+
+        let user_ids: Vec<Uuid> = user_ids.iter().filter_map(|id| id.parse().ok()).collect();
+        if user_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut pipe = redis::pipe();
+        for user_id in &user_ids {
+            pipe.hgetall(format!("account_lock:{}", user_id));
+        }
+        let hashes: Vec<HashMap<String, String>> = pipe.query_async(&mut conn).await?;
+
+        Ok(user_ids
+            .into_iter()
+            .zip(hashes)
+            .filter_map(|(user_id, hash)| account_lock_from_hash(user_id, hash))
+            .collect())
+    }
+
+    /// Lift the lock of an account and forget its failed passwords
+    ///
+    /// # Returns
+    /// Whether the account was locked
+    pub async fn delete_account_lock(&self, user_id: Uuid) -> redis::RedisResult<bool> {
+        let mut conn = self.conn.clone();
+
+        let (deleted,): (u32,) = redis::pipe()
+            .atomic()
+            .del(format!("account_lock:{}", user_id))
+            .del(format!("login_fail_account:{}", user_id))
+            .ignore()
+            .del(format!("mfa_fail_account:{}", user_id))
+            .ignore()
+            .zrem(ACCOUNT_LOCKS_KEY, user_id.to_string())
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(deleted > 0)
+    }
+
+    /// Forget the failed passwords and second factors of an account (after a
+    /// login that passed every step)
+    pub async fn delete_account_failures(&self, user_id: Uuid) -> redis::RedisResult<()> {
+        let mut conn = self.conn.clone();
+        conn.del(&[
+            format!("login_fail_account:{}", user_id),
+            format!("mfa_fail_account:{}", user_id),
+        ])
+        .await
+    }
+
+    /// Get cached reaction counters for several posts/comments in one round-trip
+    ///
+    /// Key pattern: "reaction_counts:{target}" (e.g. "reaction_counts:post:12"),
+    /// a hash of reaction type -> count.
+    ///
+    /// # Returns
+    /// One entry per target, in order. None means not cached (load it from Postgres).
+    pub async fn get_reaction_counts(
+        &self,
+        targets: &[ReactionTarget],
+    ) -> redis::RedisResult<Vec<Option<HashMap<String, i64>>>> {
+        if targets.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        for target in targets {
+            pipe.hgetall(format!("reaction_counts:{}", target.to_key()));
+        }
+
+        let hashes: Vec<HashMap<String, i64>> = pipe.query_async(&mut conn).await?;
+
+        // A cached counter always has every reaction type as a field, so empty = missing
+        Ok(hashes
+            .into_iter()
+            .map(|hash| if hash.is_empty() { None } else { Some(hash) })
+            .collect())
+    }
+
+    /// Cache reaction counters loaded from Postgres
+    ///
+    /// Every reaction type is written (zeros included) so a cached counter is
+    /// never mistaken for a missing one. Counters that are already cached are
+    /// left alone: another request may have filled and incremented them since
+    /// these counts were loaded. Filled counters are marked dirty, so the next
+    /// flush writes them to the count tables.
+    pub async fn cache_reaction_counts(
+        &self,
+        entries: &[(ReactionTarget, Vec<(ReactionType, i64)>)],
+    ) -> redis::RedisResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        // KEYS: one counter per entry, then the dirty set
+        // ARGV: TTL, number of reaction types, their names, then per entry
+        // the dirty set member followed by one count per reaction type
+        let script = redis::Script::new(
+            r"
+            local types = tonumber(ARGV[2])
+            local entries = #KEYS - 1
+            for i = 1, entries do
+                if redis.call('EXISTS', KEYS[i]) == 0 then
+                    local base = 2 + types + (i - 1) * (types + 1)
+                    for j = 1, types do
+                        redis.call('HSET', KEYS[i], ARGV[2 + j], ARGV[base + 1 + j])
+                    end
+                    redis.call('EXPIRE', KEYS[i], ARGV[1])
+                    redis.call('SADD', KEYS[#KEYS], ARGV[base + 1])
+                end
+            end
+            return 0
+            ",
+        );
+
+        let mut invocation = script.prepare_invoke();
+        for (target, _) in entries {
+            invocation.key(format!("reaction_counts:{}", target.to_key()));
+        }
+        invocation
+            .key(REACTION_DIRTY_KEY)
+            .arg(REACTION_COUNTS_TTL_SECS)
+            .arg(ReactionType::ALL.len());
+        for reaction in ReactionType::ALL.iter() {
+            invocation.arg(reaction.to_str());
+        }
+        for (target, counts) in entries {
+            invocation.arg(target.to_key());
+            for reaction in ReactionType::ALL.iter() {
+                let count = counts
+                    .iter()
+                    .find(|(r, _)| r == reaction)
+                    .map(|(_, c)| *c)
+                    .unwrap_or(0);
+                invocation.arg(count);
+            }
+        }
+
+        let mut conn = self.conn.clone();
+        invocation.invoke_async(&mut conn).await
+    }
+
+    /// Adjust a cached reaction counter by `delta` (+1 / -1)
+    ///
+    /// Only touches counters that are cached: if the counter isn't in Redis,
+    /// the next read loads the exact value from Postgres anyway, and creating
+    /// a partial hash here would make it look cached with wrong numbers.
+    /// Runs as a Lua script so the existence check and increment are atomic.
+    pub async fn incr_reaction_count(
+        &self,
+        target: ReactionTarget,
+        reaction: ReactionType,
+        delta: i64,
+    ) -> redis::RedisResult<()> {
+        let script = redis::Script::new(
+            r"
+            if redis.call('EXISTS', KEYS[1]) == 1 then
+                redis.call('HINCRBY', KEYS[1], ARGV[1], ARGV[2])
+                redis.call('EXPIRE', KEYS[1], ARGV[3])
+                redis.call('SADD', KEYS[2], ARGV[4])
+            end
+            return 0
+            ",
+        );
+        let mut conn = self.conn.clone();
+
+        script
+            .key(format!("reaction_counts:{}", target.to_key()))
+            .key(REACTION_DIRTY_KEY)
+            .arg(reaction.to_str())
+            .arg(delta)
+            .arg(REACTION_COUNTS_TTL_SECS)
+            .arg(target.to_key())
+            .invoke_async(&mut conn)
+            .await
+    }
+
+    /// Take up to `count` targets whose counters changed since the last flush
+    ///
+    /// SPOP removes them from the dirty set; call `mark_reactions_dirty`
+    /// to put them back if the flush fails.
+    pub async fn pop_dirty_reaction_targets(
+        &self,
+        count: usize,
+    ) -> redis::RedisResult<Vec<String>> {
+        let mut conn = self.conn.clone();
+        redis::cmd("SPOP")
+            .arg(REACTION_DIRTY_KEY)
+            .arg(count)
+            .query_async(&mut conn)
+            .await
+    }
+
+    /// Mark targets as needing a flush again
+    pub async fn mark_reactions_dirty(&self, target_keys: &[String]) -> redis::RedisResult<()> {
+        if target_keys.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn.clone();
+        conn.sadd(REACTION_DIRTY_KEY, target_keys).await
+    }
 }
@@ -4,8 +4,10 @@ use tower_http::trace::TraceLayer;
 use crate::{
     AppState,
     handler::{
-        auth::auth_handler, comment::comment_handler, newsletter::newsletter_handler,
-        post::post_handler, search::search_handler, users::users_handler,
+        auth::auth_handler, comment::comment_handler, media::media_handler,
+        moderation::moderation_handler, newsletter::newsletter_handler,
+        notification::notification_handler, post::post_handler, search::search_handler,
+        users::users_handler, well_known::well_known_handler,
     },
     middleware::auth,
 };
@@ -21,7 +23,11 @@ use crate::{
 /// - `/api/users/*` - User management (protected by auth middleware)
 /// - `/api/posts/*` - Blog post operations (CRUD)
 /// - `/api/comments/*` - Comment operations
+/// - `/api/media/*` - Media library management (admin only)
+/// - `/api/moderation/*` - Comment moderation queue, audit log and report triage (admin only)
+/// - `/api/notifications/*` - One-click unsubscribe from notification emails
 /// - `/api/newsletter/*` - Newsletter subscription management
+/// - `/.well-known/jwks.json` - Public keys verifying our tokens (outside "/api", where verifiers look)
 /// Key methods:
 /// - `.nest(path, router)`: Groups routes under a path prefix. Nests an entire Router.
 ///   Example: `.nest("/users", user_router)` makes routes like "/users/profile", "/users/:id"
@@ -42,10 +48,12 @@ use crate::{
 /// # Parameters
 /// - `app_state`: Shared application state (database, Redis, config, etc.)
 pub fn create_router(app_state: AppState) -> Router {
+    let well_known_route = well_known_handler().with_state(app_state.clone());
+
     let api_route = Router::new()
-        // Search routes - public access
+        // Search routes - public access (signed-in users also get their own reactions)
         // Handles both full-text search and vector similarity search
-        .nest("/search", search_handler())
+        .nest("/search", search_handler(app_state.clone()))
         // Authentication routes - public access (login, register, token refresh)
         // Pass app_state for database and Redis access
         .nest("/auth", auth_handler(app_state.clone()))
@@ -68,6 +76,12 @@ pub fn create_router(app_state: AppState) -> Router {
         .nest("/posts", post_handler(app_state.clone()))
         // Comment routes - typically public read, protected write
         .nest("/comments", comment_handler(app_state.clone()))
+        // Media library routes - admin only (auth + role check applied inside)
+        .nest("/media", media_handler(app_state.clone()))
+        // Comment moderation routes - admin only (auth + role check applied inside)
+        .nest("/moderation", moderation_handler(app_state.clone()))
+        // Notification routes - public access (signed unsubscribe links)
+        .nest("/notifications", notification_handler())
         // Newsletter subscription routes - public access
         .nest("/newsletter", newsletter_handler())
         // Apply TraceLayer middleware to ALL routes
@@ -90,5 +104,7 @@ pub fn create_router(app_state: AppState) -> Router {
     // - Easier versioning (could add /api/v2 later)
     // - Clear distinction between API and other routes (e.g., static files, webhooks)
     // - Simplified reverse proxy configuration (forward all /api/* to backend)
-    Router::new().nest("/api", api_route)
+    Router::new()
+        .nest("/api", api_route)
+        .nest("/.well-known", well_known_route)
 }
@@ -0,0 +1,163 @@
+pub mod bayes;
+pub mod heuristics;
+
+use std::str::FromStr;
+
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::db::{CommentExt, SpamFilterExt};
+use crate::models::CommentStatus;
+
+/// Spam score from which a comment is held in the moderation queue
+pub const REVIEW_THRESHOLD: f32 = 0.5;
+
+/// Spam score from which a comment is marked as spam straight away
+pub const SPAM_THRESHOLD: f32 = 0.9;
+
+/// Classifier backends that can be enabled with `SPAM_CLASSIFIERS`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpamBackend {
+    Heuristic, // Link count and posting rate
+    Bayes,     // Naive Bayes filter trained from moderation decisions
+    Llm,       // Judgment of the LLM service (slow, opt-in)
+}
+
+impl SpamBackend {
+    pub fn to_str(&self) -> &str {
+        match self {
+            SpamBackend::Heuristic => "heuristic",
+            SpamBackend::Bayes => "bayes",
+            SpamBackend::Llm => "llm",
+        }
+    }
+}
+
+impl FromStr for SpamBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "heuristic" => Ok(SpamBackend::Heuristic),
+            "bayes" => Ok(SpamBackend::Bayes),
+            "llm" => Ok(SpamBackend::Llm),
+            other => Err(format!("unknown spam classifier '{}'", other)),
+        }
+    }
+}
+
+/// Score given by a single backend
+#[derive(Debug)]
+pub struct SpamSignal {
+    pub backend: SpamBackend,
+    pub score: f32,
+    pub detail: Option<String>,
+}
+
+/// Combined result of all enabled backends
+#[derive(Debug)]
+pub struct SpamVerdict {
+    pub score: f32,
+    pub status: CommentStatus,
+    pub signals: Vec<SpamSignal>,
+}
+
+impl SpamVerdict {
+    /// Summary of the signals for the moderation log, e.g. "heuristic 0.80 (3 link(s), ...); bayes 0.42"
+    pub fn reason(&self) -> String {
+        self.signals
+            .iter()
+            .map(|s| match &s.detail {
+                Some(detail) => format!("{} {:.2} ({})", s.backend.to_str(), s.score, detail),
+                None => format!("{} {:.2}", s.backend.to_str(), s.score),
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Run a new or edited comment through the enabled spam classifiers
+///
+/// The highest score of all backends decides:
+/// - `>= SPAM_THRESHOLD`: Spam (hidden, shows up in the spam queue)
+/// - `>= REVIEW_THRESHOLD`: Pending (borderline, a moderator decides)
+/// - otherwise: Approved
+///
+/// A backend that fails (DB/LLM error) or has no opinion (untrained filter)
+/// is skipped, so classifier trouble never blocks commenting.
+/// The LLM is asked last, and only if the cheap backends haven't already flagged spam.
+///
+/// # Parameters
+/// - `user_id`: Author, for the posting-rate heuristic
+/// - `content`: Raw comment text
+pub async fn classify_comment(app_state: &AppState, user_id: Uuid, content: &str) -> SpamVerdict {
+    let backends = &app_state.env.spam_classifiers;
+    let mut signals = Vec::new();
+
+    if backends.contains(&SpamBackend::Heuristic) {
+        match app_state
+            .db_client
+            .get_user_recent_comment_count(user_id, heuristics::RATE_WINDOW_MINUTES)
+            .await
+        {
+            Ok(recent) => {
+                let (score, detail) = heuristics::score(content, recent);
+                signals.push(SpamSignal {
+                    backend: SpamBackend::Heuristic,
+                    score,
+                    detail: Some(detail),
+                });
+            }
+            Err(e) => tracing::warn!("Spam heuristic skipped, DB error: {}", e),
+        }
+    }
+
+    if backends.contains(&SpamBackend::Bayes) {
+        let tokens = bayes::tokenize(content);
+        match app_state.db_client.get_spam_model(&tokens).await {
+            Ok((spam_docs, ham_docs, model)) => {
+                if let Some(score) = bayes::score(spam_docs, ham_docs, &model) {
+                    signals.push(SpamSignal {
+                        backend: SpamBackend::Bayes,
+                        score,
+                        detail: None,
+                    });
+                }
+            }
+            Err(e) => tracing::warn!("Spam filter skipped, DB error: {}", e),
+        }
+    }
+
+    let score_so_far = signals.iter().map(|s| s.score).fold(0.0, f32::max);
+
+    if backends.contains(&SpamBackend::Llm) && score_so_far < SPAM_THRESHOLD {
+        match app_state
+            .http_client
+            .get_spam_score(&app_state.env.llm_url, &app_state.env.model_name, content)
+            .await
+        {
+            Ok(score) => signals.push(SpamSignal {
+                backend: SpamBackend::Llm,
+                score,
+                detail: None,
+            }),
+            Err(e) => tracing::warn!("LLM spam check skipped: {}", e.message),
+        }
+    }
+
+    let score = signals.iter().map(|s| s.score).fold(0.0, f32::max);
+
+    let status = if score >= SPAM_THRESHOLD {
+        CommentStatus::Spam
+    } else if score >= REVIEW_THRESHOLD {
+        CommentStatus::Pending
+    } else {
+        CommentStatus::Approved
+    };
+
+    SpamVerdict {
+        score,
+        status,
+        signals,
+    }
+}
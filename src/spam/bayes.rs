@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+
+use super::heuristics::link_host;
+use crate::models::SpamToken;
+
+/// Minimum number of spam and of ham comments before the filter gives an opinion
+///
+/// With only a handful of examples a single word decides everything,
+/// so the backend abstains until both classes have some history.
+const MIN_TRAINING_DOCS: i64 = 20;
+
+/// Tokens seen fewer times than this carry no information and are ignored
+const MIN_TOKEN_OCCURRENCES: i32 = 3;
+
+/// Number of most decisive tokens combined into the final score (Graham's "interesting" tokens)
+const INTERESTING_TOKENS: usize = 15;
+
+/// How strongly rarely-seen tokens are pulled towards a neutral 0.5 (Robinson's `s`)
+const SMOOTHING_STRENGTH: f64 = 1.0;
+
+/// Split a comment into the set of tokens used by the filter
+///
+/// - Lowercase words of 2 to 30 characters (any script, so Korean works too)
+/// - `host:example.com` for every linked host, spam domains are very telling
+///
+/// Duplicates are removed - the model counts documents, not occurrences.
+pub fn tokenize(content: &str) -> Vec<String> {
+    let mut tokens = HashSet::new();
+
+    for word in content.split_whitespace() {
+        if let Some(host) = link_host(word) {
+            tokens.insert(format!("host:{}", host));
+        }
+    }
+
+    for word in content.split(|c: char| !c.is_alphanumeric()) {
+        if (2..=30).contains(&word.chars().count()) {
+            tokens.insert(word.to_lowercase());
+        }
+    }
+
+    tokens.into_iter().collect()
+}
+
+/// Compute the spam probability of a comment from its known tokens
+///
+/// Each token gets a spam probability from how often it appeared in spam vs
+/// ham comments (smoothed towards 0.5 when rarely seen). The tokens furthest
+/// from 0.5 are then combined with the naive Bayes assumption.
+///
+/// # Parameters
+/// - `spam_docs`, `ham_docs`: Corpus size the model was trained on
+/// - `tokens`: Model statistics for the comment's tokens (unknown tokens are simply absent)
+///
+/// # Returns
+/// Spam probability between 0.0 and 1.0, or None if the model can't tell (not enough data)
+pub fn score(spam_docs: i64, ham_docs: i64, tokens: &[SpamToken]) -> Option<f32> {
+    if spam_docs < MIN_TRAINING_DOCS || ham_docs < MIN_TRAINING_DOCS {
+        return None;
+    }
+
+    let mut probabilities: Vec<f64> = tokens
+        .iter()
+        .filter(|t| t.spam_count + t.ham_count >= MIN_TOKEN_OCCURRENCES)
+        .map(|t| {
+            // Relative frequencies, so the (usually much larger) ham corpus doesn't dominate
+            let spam_freq = (t.spam_count as f64 / spam_docs as f64).min(1.0);
+            let ham_freq = (t.ham_count as f64 / ham_docs as f64).min(1.0);
+            let p = spam_freq / (spam_freq + ham_freq);
+
+            let n = (t.spam_count + t.ham_count) as f64;
+            let p = (SMOOTHING_STRENGTH * 0.5 + n * p) / (SMOOTHING_STRENGTH + n);
+            p.clamp(0.01, 0.99)
+        })
+        .collect();
+
+    if probabilities.is_empty() {
+        return None;
+    }
+
+    probabilities.sort_by(|a, b| (b - 0.5).abs().total_cmp(&(a - 0.5).abs()));
+    probabilities.truncate(INTERESTING_TOKENS);
+
+    // Combine in log space to avoid underflow: P = 1 / (1 + prod(1-p) / prod(p))
+    let (ln_spam, ln_ham) = probabilities
+        .iter()
+        .fold((0.0, 0.0), |(s, h), p| (s + p.ln(), h + (1.0 - p).ln()));
+
+    Some((1.0 / (1.0 + (ln_ham - ln_spam).exp())) as f32)
+}
@@ -0,0 +1,70 @@
+/// Window (in minutes) the comment rate of a user is measured over
+pub const RATE_WINDOW_MINUTES: i32 = 10;
+
+/// Score a comment using link-count and posting-rate heuristics
+///
+/// Link spam is by far the most common kind of comment spam, and spam bots
+/// post much faster than people type, so both signals are strong on their own.
+/// The higher of the two scores wins.
+///
+/// # Parameters
+/// - `content`: Raw comment text
+/// - `recent_comments`: Comments the author posted in the last `RATE_WINDOW_MINUTES`
+///
+/// # Returns
+/// Spam score between 0.0 and 1.0, and a short human-readable explanation
+pub fn score(content: &str, recent_comments: i64) -> (f32, String) {
+    let links = count_links(content);
+
+    let link_score = match links {
+        0 => 0.0,
+        1 => 0.1,
+        2 => 0.5,
+        3..=4 => 0.8,
+        _ => 0.95,
+    };
+
+    let rate_score = match recent_comments {
+        0..=3 => 0.0,
+        4..=6 => 0.6,
+        _ => 0.9,
+    };
+
+    let detail = format!(
+        "{} link(s), {} comment(s) in {} min",
+        links, recent_comments, RATE_WINDOW_MINUTES
+    );
+
+    (f32::max(link_score, rate_score), detail)
+}
+
+/// Count the links in a comment (one per whitespace-separated word)
+pub fn count_links(content: &str) -> usize {
+    content
+        .split_whitespace()
+        .filter(|word| link_host(word).is_some())
+        .count()
+}
+
+/// Extract the host of a link-looking word ("https://x.com/a", "www.x.com", "[a](http://x.com)")
+///
+/// Returns the lowercase host without a leading "www.", or None if the word is not a link.
+pub fn link_host(word: &str) -> Option<String> {
+    let rest = match word.find("://") {
+        Some(i) => &word[i + 3..],
+        None => &word[word.find("www.")?..],
+    };
+
+    let host: String = rest
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '.' || *c == '-')
+        .collect::<String>()
+        .to_lowercase();
+    let host = host.trim_start_matches("www.").trim_end_matches('.');
+
+    if host.contains('.') {
+        Some(host.to_string())
+    } else {
+        None
+    }
+}
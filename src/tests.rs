@@ -0,0 +1,243 @@
+//! End-to-end tests of the API
+//!
+//! Requests go through the full router, like from a browser or API client.
+//! Every test gets its own database from `#[sqlx::test]` (created from
+//! `DATABASE_URL`, migrated, dropped afterwards) and uses the Redis at
+//! `REDIS_URL` (default `redis://127.0.0.1:6379`). Keys are random per test,
+//! so tests can share it.
+
+mod oauth;
+mod passkey;
+
+use crate::AppState;
+use crate::config::Config;
+use crate::db::{DBClient, UserExt};
+use crate::embed::embed_service_client::EmbedServiceClient;
+use crate::grpc::GRPCClient;
+use crate::http::HttpClient;
+use crate::models::User;
+use crate::oauth::OAuthProvider;
+use crate::redisdb::RedisClient;
+use crate::routes;
+use crate::utils::jwt_keys::JwtKeys;
+use crate::utils::password;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderMap, Request, StatusCode, header};
+use axum_client_ip::ClientIpSource;
+use axum_extra::extract::cookie::Cookie;
+use chrono::{Duration, Utc};
+use serde_json::{Value, json};
+use sqlx::PgPool;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tower::ServiceExt;
+use webauthn_rs::prelude::{Url, WebauthnBuilder};
+
+/// Origin of the frontend, passkey ceremonies run on it
+pub(crate) const FRONTEND_URL: &str = "http://localhost:3000";
+
+/// Public URL of the API, social login callbacks point at it
+pub(crate) const API_URL: &str = "http://localhost:8000";
+
+/// Password of the users created with `create_user`
+pub(crate) const TEST_PASSWORD: &str = "correct horse battery staple";
+
+/// Configuration of the app under test, nothing is read from the environment
+fn test_config(oauth_providers: Vec<OAuthProvider>) -> Config {
+    Config {
+        database_url: String::new(), // The pool comes from #[sqlx::test]
+        jwt_secret: "test-jwt-secret-at-least-32-bytes-long".to_string(),
+        jwt_signing_key: None,
+        jwt_retired_keys: Vec::new(),
+        jwt_secret_retired_at: None,
+        jwt_issuer: API_URL.to_string(),
+        jwt_audience: API_URL.to_string(),
+        jwt_maxage: 900,
+        refresh_token_maxage: 86400,
+        redis_url: redis_url(),
+        port: 8000,
+        llm_url: "http://127.0.0.1:1".to_string(),
+        model_name: "test".to_string(),
+        grpc_url: "http://127.0.0.1:1".to_string(),
+        frontend_url: FRONTEND_URL.to_string(),
+        api_url: API_URL.to_string(),
+        comment_premoderation: false,
+        spam_classifiers: Vec::new(),
+        report_hide_threshold: 3,
+        totp_issuer: "Blog".to_string(),
+        webauthn_rp_id: "localhost".to_string(),
+        webauthn_rp_origin: FRONTEND_URL.to_string(),
+        oauth_providers,
+    }
+}
+
+fn redis_url() -> String {
+    std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1:6379".to_string())
+}
+
+/// Router of the app under test and its state, for setting up data directly
+pub(crate) async fn test_app(
+    pool: PgPool,
+    oauth_providers: Vec<OAuthProvider>,
+) -> (Router, AppState) {
+    let config = test_config(oauth_providers);
+
+    let manager = redis::Client::open(config.redis_url.clone())
+        .unwrap()
+        .get_connection_manager()
+        .await
+        .expect("Redis must be running at REDIS_URL");
+
+    // None of the tested endpoints embed text, the channel never connects
+    let channel = tonic::transport::Endpoint::from_static("http://127.0.0.1:1").connect_lazy();
+
+    let rp_origin = Url::parse(&config.webauthn_rp_origin).unwrap();
+    let webauthn = WebauthnBuilder::new(&config.webauthn_rp_id, &rp_origin)
+        .unwrap()
+        .rp_name(&config.totp_issuer)
+        .build()
+        .unwrap();
+
+    let app_state = AppState {
+        jwt_keys: Arc::new(JwtKeys::init(&config)),
+        env: Arc::new(config),
+        db_client: DBClient::new(pool),
+        redis_client: RedisClient::new(manager),
+        grpc_client: GRPCClient {
+            embed_client: EmbedServiceClient::new(channel),
+        },
+        http_client: HttpClient {
+            conn: reqwest::Client::new(),
+        },
+        ip_extraction: ClientIpSource::ConnectInfo,
+        webauthn: Arc::new(webauthn),
+    };
+
+    (routes::create_router(app_state.clone()), app_state)
+}
+
+/// Response of the app, with the body parsed as JSON (Null if it isn't)
+pub(crate) struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+impl TestResponse {
+    /// Value of a cookie set by the response
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| Cookie::parse(v.to_str().ok()?.to_string()).ok())
+            .find(|c| c.name() == name)
+            .map(|c| c.value().to_string())
+    }
+
+    /// Target of a redirect
+    pub fn location(&self) -> &str {
+        self.headers
+            .get(header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .expect("Response is not a redirect")
+    }
+}
+
+/// Send a request to the app, from 127.0.0.1
+pub(crate) async fn send(app: &Router, mut request: Request<Body>) -> TestResponse {
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    TestResponse {
+        status,
+        headers,
+        body: serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    }
+}
+
+/// GET, with a bearer token and/or a Cookie header
+pub(crate) fn get(uri: &str, access_token: Option<&str>, cookie: Option<&str>) -> Request<Body> {
+    let mut request = Request::get(uri);
+    if let Some(token) = access_token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    if let Some(cookie) = cookie {
+        request = request.header(header::COOKIE, cookie);
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+/// POST a JSON body, with a bearer token
+pub(crate) fn post_json(uri: &str, access_token: Option<&str>, body: Value) -> Request<Body> {
+    let mut request = Request::post(uri).header(header::CONTENT_TYPE, "application/json");
+    if let Some(token) = access_token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    request.body(Body::from(body.to_string())).unwrap()
+}
+
+/// Create a user with `TEST_PASSWORD`, as if they had registered
+pub(crate) async fn create_user(
+    app_state: &AppState,
+    username: &str,
+    email: &str,
+    verified: bool,
+) -> User {
+    let hashed_password = password::hash(TEST_PASSWORD).unwrap();
+    let verification_token = uuid::Uuid::new_v4().to_string();
+    let user = app_state
+        .db_client
+        .save_user(
+            username,
+            email,
+            hashed_password.as_str(),
+            verification_token.as_str(),
+            Utc::now() + Duration::hours(24),
+        )
+        .await
+        .unwrap();
+
+    if verified {
+        app_state
+            .db_client
+            .verifed_token(&verification_token)
+            .await
+            .unwrap();
+    }
+
+    app_state
+        .db_client
+        .get_user(Some(user.id), None, None, None)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+/// Log in with `TEST_PASSWORD`, returns the access token
+pub(crate) async fn login(app: &Router, identifier: &str) -> String {
+    let response = send(
+        app,
+        post_json(
+            "/api/auth/login",
+            None,
+            json!({ "identifier": identifier, "password": TEST_PASSWORD }),
+        ),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    response.body["access_token"]
+        .as_str()
+        .expect("Login response without access token")
+        .to_string()
+}
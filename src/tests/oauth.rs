@@ -0,0 +1,464 @@
+use super::{
+    API_URL, FRONTEND_URL, TEST_PASSWORD, TestResponse, create_user, get, login, send, test_app,
+};
+use crate::db::{IdentityExt, UserExt};
+use crate::oauth::{OAuthProvider, ProviderKind};
+use crate::utils::password;
+use axum::Router;
+use axum::extract::{Form, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Json};
+use axum::routing::{self, post};
+use data_encoding::BASE64URL_NOPAD;
+use reqwest::Url;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const PROVIDER: &str = "mock";
+const CLIENT_ID: &str = "test-client";
+const CLIENT_SECRET: &str = "test-client-secret";
+
+/// Code handed out by the mock authorization endpoint
+struct Grant {
+    code_challenge: String,
+    redirect_uri: String,
+    claims: Value, // UserInfo response for the access token of this code
+}
+
+#[derive(Default)]
+struct IssuerState {
+    grants: HashMap<String, Grant>,
+    access_tokens: HashMap<String, Value>,
+}
+
+/// OpenID Connect issuer on a random local port
+///
+/// Serves discovery, token and UserInfo endpoints. The authorization
+/// endpoint is played by the test: `authorize` reads the URL the app
+/// redirected to and hands out a code, as if the user had signed in.
+/// The token endpoint checks the client credentials, the redirect URI
+/// and the PKCE verifier, and codes are single use.
+#[derive(Clone)]
+struct MockIssuer {
+    url: String,
+    state: Arc<Mutex<IssuerState>>,
+}
+
+impl MockIssuer {
+    async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = MockIssuer {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            state: Arc::new(Mutex::new(IssuerState::default())),
+        };
+
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", routing::get(discovery))
+            .route("/token", post(token))
+            .route("/userinfo", routing::get(userinfo))
+            .with_state(issuer.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        issuer
+    }
+
+    fn provider(&self) -> OAuthProvider {
+        OAuthProvider {
+            name: PROVIDER.to_string(),
+            kind: ProviderKind::Oidc {
+                issuer: self.url.clone(),
+            },
+            client_id: CLIENT_ID.to_string(),
+            client_secret: CLIENT_SECRET.to_string(),
+        }
+    }
+
+    /// Sign in at the provider: check the authorization request, return (code, state)
+    fn authorize(&self, authorize_url: &str, claims: Value) -> (String, String) {
+        let url = Url::parse(authorize_url).unwrap();
+        assert_eq!(url.path(), "/authorize");
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], CLIENT_ID);
+        assert_eq!(params["code_challenge_method"], "S256");
+        assert!(params["scope"].split(' ').any(|s| s == "openid"));
+
+        let code = uuid::Uuid::new_v4().to_string();
+        self.state.lock().unwrap().grants.insert(
+            code.clone(),
+            Grant {
+                code_challenge: params["code_challenge"].clone(),
+                redirect_uri: params["redirect_uri"].clone(),
+                claims,
+            },
+        );
+
+        (code, params["state"].clone())
+    }
+}
+
+async fn discovery(State(issuer): State<MockIssuer>) -> impl IntoResponse {
+    Json(json!({
+        "issuer": issuer.url,
+        "authorization_endpoint": format!("{}/authorize", issuer.url),
+        "token_endpoint": format!("{}/token", issuer.url),
+        "userinfo_endpoint": format!("{}/userinfo", issuer.url),
+    }))
+}
+
+async fn token(
+    State(issuer): State<MockIssuer>,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let mut state = issuer.state.lock().unwrap();
+    let invalid_grant = (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": "invalid_grant" })),
+    );
+
+    let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+    if field("grant_type") != "authorization_code"
+        || field("client_id") != CLIENT_ID
+        || field("client_secret") != CLIENT_SECRET
+    {
+        return invalid_grant.into_response();
+    }
+
+    let Some(grant) = state.grants.remove(field("code")) else {
+        return invalid_grant.into_response();
+    };
+    let challenge = BASE64URL_NOPAD.encode(&Sha256::digest(field("code_verifier").as_bytes()));
+    if challenge != grant.code_challenge || field("redirect_uri") != grant.redirect_uri {
+        return invalid_grant.into_response();
+    }
+
+    let access_token = uuid::Uuid::new_v4().to_string();
+    state
+        .access_tokens
+        .insert(access_token.clone(), grant.claims);
+
+    Json(json!({ "access_token": access_token, "token_type": "Bearer" })).into_response()
+}
+
+async fn userinfo(State(issuer): State<MockIssuer>, headers: HeaderMap) -> impl IntoResponse {
+    let access_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    match issuer.state.lock().unwrap().access_tokens.get(access_token) {
+        Some(claims) => Json(claims.clone()).into_response(),
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Outcome of a social login, from the fragment of the redirect to the frontend
+fn outcome(response: &TestResponse) -> HashMap<String, String> {
+    assert_eq!(response.status, StatusCode::SEE_OTHER);
+    let location = response.location();
+    let (page, fragment) = location.split_once('#').unwrap();
+    assert_eq!(page, format!("{}/auth/oauth/callback", FRONTEND_URL));
+
+    fragment
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn callback_uri(code: &str, state: &str) -> String {
+    format!(
+        "/api/auth/oauth/{}/callback?code={}&state={}",
+        PROVIDER, code, state
+    )
+}
+
+/// Redirect to the provider and sign in there, returns (code, state, state cookie)
+async fn authorize(
+    app: &Router,
+    issuer: &MockIssuer,
+    start_uri: &str,
+    access_token: Option<&str>,
+    claims: Value,
+) -> (String, String, String) {
+    let start = send(app, get(start_uri, access_token, None)).await;
+    assert_eq!(start.status, StatusCode::SEE_OTHER, "{}", start.body);
+    let state_cookie = start.cookie("oauth_state").expect("No state cookie");
+
+    let (code, state) = issuer.authorize(start.location(), claims);
+    assert_eq!(state, state_cookie);
+    (code, state, state_cookie)
+}
+
+/// A complete social login with the claims the provider asserts
+async fn sign_in(app: &Router, issuer: &MockIssuer, claims: Value) -> TestResponse {
+    let start_uri = format!("/api/auth/oauth/{}", PROVIDER);
+    let (code, state, state_cookie) = authorize(app, issuer, &start_uri, None, claims).await;
+
+    let cookie = format!("oauth_state={}", state_cookie);
+    send(app, get(&callback_uri(&code, &state), None, Some(&cookie))).await
+}
+
+#[sqlx::test]
+async fn oidc_login_creates_account_and_signs_in_again(pool: PgPool) {
+    let issuer = MockIssuer::start().await;
+    let (app, app_state) = test_app(pool, vec![issuer.provider()]).await;
+    let claims = json!({
+        "sub": "subject-1",
+        "email": "carol@example.com",
+        "email_verified": true,
+        "preferred_username": "carol",
+    });
+
+    let first = sign_in(&app, &issuer, claims.clone()).await;
+    assert_eq!(outcome(&first)["status"], "success");
+    assert!(first.cookie("access_token").is_some());
+
+    let user = app_state
+        .db_client
+        .get_identity_user(PROVIDER, "subject-1")
+        .await
+        .unwrap()
+        .expect("Identity not linked");
+    assert_eq!(user.username, "carol");
+    assert_eq!(user.email, "carol@example.com");
+    assert!(user.verified);
+
+    // The redirect URI registered at the provider points at this API
+    let start = send(&app, get("/api/auth/oauth/mock", None, None)).await;
+    let authorize_url = Url::parse(start.location()).unwrap();
+    let redirect_uri = authorize_url
+        .query_pairs()
+        .find(|(k, _)| k == "redirect_uri")
+        .unwrap()
+        .1
+        .to_string();
+    assert_eq!(
+        redirect_uri,
+        format!("{}/api/auth/oauth/{}/callback", API_URL, PROVIDER)
+    );
+
+    // Signing in again uses the linked account, even with another email
+    let mut claims = claims;
+    claims["email"] = json!("carol@other.example");
+    let second = sign_in(&app, &issuer, claims).await;
+    assert_eq!(outcome(&second)["status"], "success");
+
+    let again = app_state
+        .db_client
+        .get_identity_user(PROVIDER, "subject-1")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(again.id, user.id);
+    assert_eq!(again.email, "carol@example.com");
+}
+
+#[sqlx::test]
+async fn oidc_callback_requires_state_of_this_browser(pool: PgPool) {
+    let issuer = MockIssuer::start().await;
+    let (app, app_state) = test_app(pool, vec![issuer.provider()]).await;
+    let claims =
+        json!({ "sub": "subject-1", "email": "carol@example.com", "email_verified": true });
+
+    let start_uri = format!("/api/auth/oauth/{}", PROVIDER);
+    let (code, state, state_cookie) = authorize(&app, &issuer, &start_uri, None, claims).await;
+    let cookie = format!("oauth_state={}", state_cookie);
+
+    // Another browser (no state cookie) can't finish the login
+    let no_cookie = send(&app, get(&callback_uri(&code, &state), None, None)).await;
+    assert_eq!(outcome(&no_cookie)["error"], "invalid_state");
+
+    // Nor can a state that doesn't match the cookie
+    let other_state = send(
+        &app,
+        get(&callback_uri(&code, "other-state"), None, Some(&cookie)),
+    )
+    .await;
+    assert_eq!(outcome(&other_state)["error"], "invalid_state");
+    assert!(
+        app_state
+            .db_client
+            .get_identity_user(PROVIDER, "subject-1")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let callback = send(&app, get(&callback_uri(&code, &state), None, Some(&cookie))).await;
+    assert_eq!(outcome(&callback)["status"], "success");
+
+    // The state is used up by the login
+    let replay = send(&app, get(&callback_uri(&code, &state), None, Some(&cookie))).await;
+    assert_eq!(outcome(&replay)["error"], "invalid_state");
+}
+
+#[sqlx::test]
+async fn oidc_code_exchange_sends_pkce_verifier(pool: PgPool) {
+    let issuer = MockIssuer::start().await;
+    let (app, app_state) = test_app(pool, vec![issuer.provider()]).await;
+    let claims =
+        json!({ "sub": "subject-1", "email": "carol@example.com", "email_verified": true });
+
+    let start_uri = format!("/api/auth/oauth/{}", PROVIDER);
+    let (code, state, state_cookie) = authorize(&app, &issuer, &start_uri, None, claims).await;
+
+    // A code issued for another challenge (e.g. intercepted from another login) is refused
+    issuer
+        .state
+        .lock()
+        .unwrap()
+        .grants
+        .get_mut(&code)
+        .unwrap()
+        .code_challenge = BASE64URL_NOPAD.encode(&Sha256::digest(b"another verifier"));
+
+    let cookie = format!("oauth_state={}", state_cookie);
+    let callback = send(&app, get(&callback_uri(&code, &state), None, Some(&cookie))).await;
+    assert_eq!(outcome(&callback)["error"], "provider_error");
+    assert!(callback.cookie("access_token").is_none());
+    assert!(
+        app_state
+            .db_client
+            .get_identity_user(PROVIDER, "subject-1")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[sqlx::test]
+async fn oidc_links_existing_account_only_with_verified_email(pool: PgPool) {
+    let issuer = MockIssuer::start().await;
+    let (app, app_state) = test_app(pool, vec![issuer.provider()]).await;
+    let alice = create_user(&app_state, "alice", "alice@example.com", true).await;
+
+    let unverified = sign_in(
+        &app,
+        &issuer,
+        json!({ "sub": "subject-1", "email": "alice@example.com", "email_verified": false }),
+    )
+    .await;
+    assert_eq!(outcome(&unverified)["error"], "email_not_verified");
+    assert!(unverified.cookie("access_token").is_none());
+    assert!(
+        app_state
+            .db_client
+            .get_identity_user(PROVIDER, "subject-1")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Some issuers send the flag as a string
+    let verified = sign_in(
+        &app,
+        &issuer,
+        json!({ "sub": "subject-1", "email": "alice@example.com", "email_verified": "true" }),
+    )
+    .await;
+    assert_eq!(outcome(&verified)["status"], "success");
+
+    let linked = app_state
+        .db_client
+        .get_identity_user(PROVIDER, "subject-1")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(linked.id, alice.id);
+
+    // Alice proved owning the address when registering, her password still works
+    login(&app, "alice").await;
+}
+
+#[sqlx::test]
+async fn oidc_takes_over_unverified_account_from_its_registrant(pool: PgPool) {
+    let issuer = MockIssuer::start().await;
+    let (app, app_state) = test_app(pool, vec![issuer.provider()]).await;
+    let squatter = create_user(&app_state, "squatter", "dave@example.com", false).await;
+
+    let response = sign_in(
+        &app,
+        &issuer,
+        json!({ "sub": "subject-1", "email": "dave@example.com", "email_verified": true }),
+    )
+    .await;
+    assert_eq!(outcome(&response)["status"], "success");
+
+    let user = app_state
+        .db_client
+        .get_user(Some(squatter.id), None, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(user.verified);
+    assert!(user.token_version > squatter.token_version);
+    assert!(!password::compare(TEST_PASSWORD, &user.password).unwrap());
+}
+
+#[sqlx::test]
+async fn oidc_link_refuses_identity_of_another_user(pool: PgPool) {
+    let issuer = MockIssuer::start().await;
+    let (app, app_state) = test_app(pool, vec![issuer.provider()]).await;
+
+    let carol = sign_in(
+        &app,
+        &issuer,
+        json!({ "sub": "subject-1", "email": "carol@example.com", "email_verified": true }),
+    )
+    .await;
+    assert_eq!(outcome(&carol)["status"], "success");
+
+    let bob = create_user(&app_state, "bob", "bob@example.com", true).await;
+    let access_token = login(&app, "bob").await;
+    let link_uri = format!("/api/auth/oauth/{}/link", PROVIDER);
+
+    // Carol's account at the provider can't be attached to Bob
+    let (code, state, state_cookie) = authorize(
+        &app,
+        &issuer,
+        &link_uri,
+        Some(&access_token),
+        json!({ "sub": "subject-1", "email": "carol@example.com", "email_verified": true }),
+    )
+    .await;
+    let cookie = format!("oauth_state={}", state_cookie);
+    let taken = send(&app, get(&callback_uri(&code, &state), None, Some(&cookie))).await;
+    assert_eq!(outcome(&taken)["error"], "identity_in_use");
+
+    // An account nobody uses yet is linked, whatever its email
+    let (code, state, state_cookie) = authorize(
+        &app,
+        &issuer,
+        &link_uri,
+        Some(&access_token),
+        json!({ "sub": "subject-2", "email": "bob@elsewhere.example", "email_verified": false }),
+    )
+    .await;
+    let cookie = format!("oauth_state={}", state_cookie);
+    let linked = send(&app, get(&callback_uri(&code, &state), None, Some(&cookie))).await;
+    let fields = outcome(&linked);
+    assert_eq!(fields["status"], "linked");
+    assert_eq!(fields["provider"], PROVIDER);
+
+    let identities = app_state
+        .db_client
+        .get_user_identities(bob.id)
+        .await
+        .unwrap();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(
+        app_state
+            .db_client
+            .get_identity_user(PROVIDER, "subject-1")
+            .await
+            .unwrap()
+            .unwrap()
+            .email,
+        "carol@example.com"
+    );
+}
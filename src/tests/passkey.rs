@@ -0,0 +1,335 @@
+use super::{FRONTEND_URL, create_user, get, login, post_json, send, test_app};
+use axum::http::StatusCode;
+use data_encoding::BASE64URL_NOPAD;
+use ring::rand::SystemRandom;
+use ring::signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+// Authenticator data flags (WebAuthn Level 2, section 6.1)
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// Software authenticator holding one ES256 passkey
+///
+/// Answers ceremonies like a platform authenticator would: "none"
+/// attestation, user presence and verification always asserted, and the
+/// user handle returned on login so the credential is discoverable.
+struct SoftAuthenticator {
+    rng: SystemRandom,
+    key: EcdsaKeyPair,
+    credential_id: Vec<u8>,
+    user_handle: Option<String>, // Base64url, as received in the registration options
+    counter: u32,
+}
+
+impl SoftAuthenticator {
+    fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+
+        SoftAuthenticator {
+            rng,
+            key,
+            credential_id: uuid::Uuid::new_v4().as_bytes().to_vec(),
+            user_handle: None,
+            counter: 0,
+        }
+    }
+
+    /// Answer to the options of `navigator.credentials.create()`
+    fn register(&mut self, options: &Value) -> Value {
+        let options = &options["publicKey"];
+        let rp_id = options["rp"]["id"].as_str().unwrap();
+        self.user_handle = Some(options["user"]["id"].as_str().unwrap().to_string());
+        let client_data = client_data("webauthn.create", options);
+
+        // COSE_Key of the public key: {1: 2 (EC2), 3: -7 (ES256), -1: 1 (P-256), -2: x, -3: y}
+        let point = self.key.public_key().as_ref(); // 0x04 || x || y
+        let mut cose_key = vec![0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21];
+        cose_key.extend(cbor_bytes(&point[1..33]));
+        cose_key.push(0x22);
+        cose_key.extend(cbor_bytes(&point[33..65]));
+
+        let mut auth_data = self.auth_data(
+            rp_id,
+            FLAG_USER_PRESENT | FLAG_USER_VERIFIED | FLAG_ATTESTED_CREDENTIAL,
+        );
+        auth_data.extend([0u8; 16]); // AAGUID
+        auth_data.extend((self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend(&self.credential_id);
+        auth_data.extend(cose_key);
+
+        // {"fmt": "none", "attStmt": {}, "authData": auth_data}
+        let mut attestation_object = vec![0xa3];
+        attestation_object.extend(cbor_text("fmt"));
+        attestation_object.extend(cbor_text("none"));
+        attestation_object.extend(cbor_text("attStmt"));
+        attestation_object.push(0xa0);
+        attestation_object.extend(cbor_text("authData"));
+        attestation_object.extend(cbor_bytes(&auth_data));
+
+        json!({
+            "id": BASE64URL_NOPAD.encode(&self.credential_id),
+            "rawId": BASE64URL_NOPAD.encode(&self.credential_id),
+            "response": {
+                "attestationObject": BASE64URL_NOPAD.encode(&attestation_object),
+                "clientDataJSON": BASE64URL_NOPAD.encode(client_data.as_bytes()),
+            },
+            "type": "public-key",
+            "extensions": {},
+        })
+    }
+
+    /// Answer to the options of `navigator.credentials.get()`
+    fn authenticate(&mut self, options: &Value) -> Value {
+        let options = &options["publicKey"];
+        let rp_id = options["rpId"].as_str().unwrap();
+        let client_data = client_data("webauthn.get", options);
+
+        self.counter += 1;
+        let auth_data = self.auth_data(rp_id, FLAG_USER_PRESENT | FLAG_USER_VERIFIED);
+
+        let mut signed = auth_data.clone();
+        signed.extend(Sha256::digest(client_data.as_bytes()));
+        let signature = self.key.sign(&self.rng, &signed).unwrap();
+
+        json!({
+            "id": BASE64URL_NOPAD.encode(&self.credential_id),
+            "rawId": BASE64URL_NOPAD.encode(&self.credential_id),
+            "response": {
+                "authenticatorData": BASE64URL_NOPAD.encode(&auth_data),
+                "clientDataJSON": BASE64URL_NOPAD.encode(client_data.as_bytes()),
+                "signature": BASE64URL_NOPAD.encode(signature.as_ref()),
+                "userHandle": self.user_handle,
+            },
+            "type": "public-key",
+            "extensions": {},
+        })
+    }
+
+    /// RP ID hash, flags and signature counter
+    fn auth_data(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+        let mut auth_data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        auth_data.push(flags);
+        auth_data.extend(self.counter.to_be_bytes());
+        auth_data
+    }
+}
+
+/// clientDataJSON the browser would send for these options
+fn client_data(ceremony: &str, options: &Value) -> String {
+    let challenge = options["challenge"].as_str().unwrap().trim_end_matches('=');
+    json!({
+        "type": ceremony,
+        "challenge": challenge,
+        "origin": FRONTEND_URL,
+        "crossOrigin": false,
+    })
+    .to_string()
+}
+
+/// CBOR head of a byte or text string
+fn cbor_head(major_type: u8, len: usize) -> Vec<u8> {
+    let major_type = major_type << 5;
+    match len {
+        0..24 => vec![major_type | len as u8],
+        24..256 => vec![major_type | 24, len as u8],
+        _ => {
+            let mut head = vec![major_type | 25];
+            head.extend((len as u16).to_be_bytes());
+            head
+        }
+    }
+}
+
+fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = cbor_head(2, bytes.len());
+    encoded.extend(bytes);
+    encoded
+}
+
+fn cbor_text(text: &str) -> Vec<u8> {
+    let mut encoded = cbor_head(3, text.len());
+    encoded.extend(text.as_bytes());
+    encoded
+}
+
+/// Register a passkey for the signed-in user
+async fn register_passkey(
+    app: &axum::Router,
+    access_token: &str,
+    authenticator: &mut SoftAuthenticator,
+) {
+    let start = send(
+        app,
+        post_json(
+            "/api/auth/passkey/register/start",
+            Some(access_token),
+            json!({}),
+        ),
+    )
+    .await;
+    assert_eq!(start.status, StatusCode::OK, "{}", start.body);
+
+    let credential = authenticator.register(&start.body["options"]);
+    let finish = send(
+        app,
+        post_json(
+            "/api/auth/passkey/register/finish",
+            Some(access_token),
+            json!({ "name": "Laptop", "credential": credential }),
+        ),
+    )
+    .await;
+    assert_eq!(finish.status, StatusCode::CREATED, "{}", finish.body);
+}
+
+/// Options of a passwordless login: (ceremony id, options)
+async fn start_login(app: &axum::Router) -> (String, Value) {
+    let start = send(
+        app,
+        post_json("/api/auth/passkey/login/start", None, json!({})),
+    )
+    .await;
+    assert_eq!(start.status, StatusCode::OK, "{}", start.body);
+
+    let ceremony_id = start.body["ceremonyId"].as_str().unwrap().to_string();
+    (ceremony_id, start.body["options"].clone())
+}
+
+#[sqlx::test]
+async fn passkey_registration_and_login(pool: PgPool) {
+    let (app, app_state) = test_app(pool, Vec::new()).await;
+    let user = create_user(&app_state, "alice", "alice@example.com", true).await;
+    let access_token = login(&app, "alice").await;
+
+    let mut authenticator = SoftAuthenticator::new();
+    register_passkey(&app, &access_token, &mut authenticator).await;
+
+    let passkeys = send(&app, get("/api/users/passkeys", Some(&access_token), None)).await;
+    assert_eq!(passkeys.status, StatusCode::OK);
+    assert_eq!(passkeys.body["data"].as_array().unwrap().len(), 1);
+    assert_eq!(passkeys.body["data"][0]["name"], "Laptop");
+    assert!(passkeys.body["data"][0]["lastUsedAt"].is_null());
+
+    let (ceremony_id, options) = start_login(&app).await;
+    let credential = authenticator.authenticate(&options);
+    let finish = send(
+        &app,
+        post_json(
+            "/api/auth/passkey/login/finish",
+            None,
+            json!({ "ceremonyId": ceremony_id, "credential": credential }),
+        ),
+    )
+    .await;
+    assert_eq!(finish.status, StatusCode::OK, "{}", finish.body);
+    assert!(finish.cookie("refresh_token").is_some());
+
+    // The new session belongs to the owner of the passkey
+    let passkey_token = finish.body["access_token"].as_str().unwrap();
+    let me = send(&app, get("/api/users/me", Some(passkey_token), None)).await;
+    assert_eq!(me.status, StatusCode::OK, "{}", me.body);
+    assert_eq!(me.body["data"]["user"]["id"], user.id.to_string());
+
+    // The signature counter and last use were stored
+    let passkeys = send(&app, get("/api/users/passkeys", Some(passkey_token), None)).await;
+    assert!(!passkeys.body["data"][0]["lastUsedAt"].is_null());
+}
+
+#[sqlx::test]
+async fn passkey_registration_rejects_another_challenge(pool: PgPool) {
+    let (app, app_state) = test_app(pool, Vec::new()).await;
+    create_user(&app_state, "alice", "alice@example.com", true).await;
+    let access_token = login(&app, "alice").await;
+
+    let start = send(
+        &app,
+        post_json(
+            "/api/auth/passkey/register/start",
+            Some(&access_token),
+            json!({}),
+        ),
+    )
+    .await;
+    let mut options = start.body["options"].clone();
+    options["publicKey"]["challenge"] = json!(BASE64URL_NOPAD.encode(&[0u8; 32]));
+
+    let credential = SoftAuthenticator::new().register(&options);
+    let finish = send(
+        &app,
+        post_json(
+            "/api/auth/passkey/register/finish",
+            Some(&access_token),
+            json!({ "name": "Laptop", "credential": credential }),
+        ),
+    )
+    .await;
+    assert_eq!(finish.status, StatusCode::BAD_REQUEST);
+
+    let passkeys = send(&app, get("/api/users/passkeys", Some(&access_token), None)).await;
+    assert!(passkeys.body["data"].as_array().unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn passkey_login_answer_is_single_use(pool: PgPool) {
+    let (app, app_state) = test_app(pool, Vec::new()).await;
+    create_user(&app_state, "alice", "alice@example.com", true).await;
+    let access_token = login(&app, "alice").await;
+
+    let mut authenticator = SoftAuthenticator::new();
+    register_passkey(&app, &access_token, &mut authenticator).await;
+
+    let (ceremony_id, options) = start_login(&app).await;
+    let body = json!({
+        "ceremonyId": ceremony_id,
+        "credential": authenticator.authenticate(&options),
+    });
+
+    let first = send(
+        &app,
+        post_json("/api/auth/passkey/login/finish", None, body.clone()),
+    )
+    .await;
+    assert_eq!(first.status, StatusCode::OK, "{}", first.body);
+
+    let replay = send(
+        &app,
+        post_json("/api/auth/passkey/login/finish", None, body),
+    )
+    .await;
+    assert_eq!(replay.status, StatusCode::UNAUTHORIZED);
+}
+
+#[sqlx::test]
+async fn passkey_login_rejects_unknown_key(pool: PgPool) {
+    let (app, app_state) = test_app(pool, Vec::new()).await;
+    create_user(&app_state, "alice", "alice@example.com", true).await;
+    let access_token = login(&app, "alice").await;
+
+    let mut authenticator = SoftAuthenticator::new();
+    register_passkey(&app, &access_token, &mut authenticator).await;
+
+    // Same credential id and user handle, but signed with another key
+    let mut impostor = SoftAuthenticator::new();
+    impostor.credential_id = authenticator.credential_id.clone();
+    impostor.user_handle = authenticator.user_handle.clone();
+
+    let (ceremony_id, options) = start_login(&app).await;
+    let finish = send(
+        &app,
+        post_json(
+            "/api/auth/passkey/login/finish",
+            None,
+            json!({ "ceremonyId": ceremony_id, "credential": impostor.authenticate(&options) }),
+        ),
+    )
+    .await;
+    assert_eq!(finish.status, StatusCode::UNAUTHORIZED);
+    assert!(finish.cookie("refresh_token").is_none());
+}
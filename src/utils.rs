@@ -1,2 +1,11 @@
+pub mod api_token;
+pub mod comment_content;
+pub mod email_change;
+pub mod image_processing;
+pub mod jwt_keys;
 pub mod password;
-pub mod token;
\ No newline at end of file
+pub mod signature;
+pub mod token;
+pub mod token_hash;
+pub mod totp;
+pub mod uploads;
\ No newline at end of file
@@ -0,0 +1,27 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+/// Start of every personal access token
+///
+/// Tells tokens apart from JWTs in the `Authorization` header, and makes
+/// leaked tokens easy to find for secret scanners.
+pub const TOKEN_PREFIX: &str = "blog_pat_";
+
+/// Characters of a token kept for display (prefix plus 4 random ones)
+const DISPLAY_PREFIX_CHARS: usize = TOKEN_PREFIX.len() + 4;
+
+/// Generate a new token: the prefix and 32 random bytes, hex encoded
+pub fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, hex::encode(bytes))
+}
+
+/// Whether a bearer token is a personal access token (and not a JWT)
+pub fn is_api_token(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
+
+/// Start of a token shown in the token list, e.g. "blog_pat_3f9a"
+pub fn display_prefix(token: &str) -> String {
+    token.chars().take(DISPLAY_PREFIX_CHARS).collect()
+}
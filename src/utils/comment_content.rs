@@ -0,0 +1,219 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use pulldown_cmark::{Event, Parser, Tag, TagEnd, TextMergeStream, html};
+
+/// Maximum number of different users a single comment may mention, and
+/// notify over its lifetime (edits included)
+///
+/// Every mention sends an email, so without a cap one comment could be used
+/// to spam a large part of the user base.
+pub const MAX_MENTIONS_PER_COMMENT: usize = 5;
+
+/// Characters allowed in a mention (`@name`)
+///
+/// Usernames are free-form, but only these can be mentioned:
+/// letters and digits of any script, '_', '-' and '.'.
+fn is_mention_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+/// Find the `@name` mentions in a piece of text
+///
+/// An '@' only starts a mention at the beginning of the text or after a
+/// character that can't be part of a name, so e-mail addresses
+/// ("me@example.com") are not mentions. Trailing '.' and '-' are dropped,
+/// so "thanks @theo." mentions "theo".
+///
+/// # Returns
+/// (byte range of "@name" in `text`, name) for each mention, in order
+fn mention_spans(text: &str) -> Vec<(Range<usize>, &str)> {
+    let mut spans = Vec::new();
+    let mut prev: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c == '@' && !prev.is_some_and(is_mention_char) {
+            let start = i + 1;
+            let mut end = start;
+            while let Some(&(j, next)) = chars.peek() {
+                if !is_mention_char(next) {
+                    break;
+                }
+                end = j + next.len_utf8();
+                chars.next();
+            }
+
+            let name = text[start..end].trim_end_matches(['.', '-']);
+            if !name.is_empty() {
+                spans.push((i..start + name.len(), name));
+            }
+            prev = text[..end].chars().next_back();
+        } else {
+            prev = Some(c);
+        }
+    }
+
+    spans
+}
+
+/// Parse a comment as Markdown (CommonMark without extensions)
+///
+/// Consecutive text events are merged, the parser splits text around
+/// characters like '@' and '_' which would otherwise cut mentions in half.
+fn parse(content: &str) -> TextMergeStream<'_, Parser<'_>> {
+    TextMergeStream::new(Parser::new(content))
+}
+
+/// Usernames mentioned in a comment, in order of first appearance, without duplicates
+///
+/// `@name` inside code or link text is not a mention.
+pub fn extract_mentions(content: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut literal_depth = 0;
+
+    for event in parse(content) {
+        match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::Link { .. }) => literal_depth += 1,
+            Event::End(TagEnd::CodeBlock | TagEnd::Link) => literal_depth -= 1,
+            Event::Text(text) if literal_depth == 0 => {
+                for (_, name) in mention_spans(&text) {
+                    if !names.iter().any(|n| n == name) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    names
+}
+
+/// Escape text for use in HTML (content and attribute values)
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape a text run and turn mentions of existing users into profile links
+fn link_mentions(text: &str, mentioned: &[String]) -> String {
+    let mut html = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for (range, name) in mention_spans(text) {
+        if !mentioned.iter().any(|m| m == name) {
+            continue;
+        }
+        html.push_str(&escape_html(&text[last..range.start]));
+        // Names only contain letters, digits, '_', '-' and '.', nothing to escape
+        html.push_str(&format!(
+            r#"<a href="/users/{name}" class="mention">@{name}</a>"#
+        ));
+        last = range.end;
+    }
+    html.push_str(&escape_html(&text[last..]));
+
+    html
+}
+
+/// Render a comment (restricted Markdown) to HTML
+///
+/// Supported: paragraphs, line breaks, *emphasis*, **strong**, `inline code`,
+/// fenced/indented code blocks and links. Everything else is flattened:
+/// headings become paragraphs, list items become lines, images are replaced
+/// by their alt text, and raw HTML is shown as text.
+/// Single line breaks are kept (as in chat and GitHub comments).
+///
+/// Mentions of existing users become profile links:
+/// `<a href="/users/theo" class="mention">@theo</a>`.
+/// Mentions of names that don't exist stay plain text.
+///
+/// The result is sanitized with `sanitize_comment_html` as a second line of defense.
+///
+/// # Parameters
+/// - `content`: Markdown source as written
+/// - `mentioned`: Usernames that were resolved to existing users
+pub fn render_html(content: &str, mentioned: &[String]) -> String {
+    let mut events: Vec<Event> = Vec::new();
+    let mut literal_depth = 0;
+
+    for event in parse(content) {
+        match event {
+            Event::Start(tag) => match tag {
+                Tag::Paragraph | Tag::Emphasis | Tag::Strong => events.push(Event::Start(tag)),
+                Tag::CodeBlock(_) | Tag::Link { .. } => {
+                    literal_depth += 1;
+                    events.push(Event::Start(tag));
+                }
+                Tag::Heading { .. } => events.push(Event::Start(Tag::Paragraph)),
+                // Lists, quotes, images, ...: only their text is kept
+                _ => {}
+            },
+            Event::End(tag) => match tag {
+                TagEnd::Paragraph | TagEnd::Emphasis | TagEnd::Strong => {
+                    events.push(Event::End(tag))
+                }
+                TagEnd::CodeBlock | TagEnd::Link => {
+                    literal_depth -= 1;
+                    events.push(Event::End(tag));
+                }
+                TagEnd::Heading(_) => events.push(Event::End(TagEnd::Paragraph)),
+                TagEnd::Item => events.push(Event::HardBreak),
+                _ => {}
+            },
+            Event::Text(text) if literal_depth == 0 => {
+                events.push(Event::InlineHtml(link_mentions(&text, mentioned).into()));
+            }
+            Event::Text(_) | Event::Code(_) | Event::HardBreak => events.push(event),
+            Event::SoftBreak => events.push(Event::HardBreak),
+            Event::Html(raw) | Event::InlineHtml(raw) => events.push(Event::Text(raw)),
+            // Rules, footnotes, math, ...
+            _ => {}
+        }
+    }
+
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, events.into_iter());
+
+    sanitize_comment_html(&rendered)
+}
+
+/// Only mention links may be relative, every other link must be absolute
+fn allow_mention_links(url: &str) -> Option<Cow<'_, str>> {
+    url.starts_with("/users/").then_some(Cow::Borrowed(url))
+}
+
+/// Sanitize rendered comment HTML
+///
+/// Much stricter than the policy for posts (`secure_content`), which are
+/// written by admins and may use tables, images and inline styles.
+/// Comments are written by anyone, so only the tags the Markdown subset
+/// produces are allowed, no styles and no classes except `mention`.
+/// Links must be http(s)/mailto and get `rel="nofollow ugc"`, so comment
+/// spam gains no search ranking.
+fn sanitize_comment_html(html: &str) -> String {
+    ammonia::Builder::empty()
+        .tags(HashSet::from([
+            "p", "br", "em", "strong", "code", "pre", "a",
+        ]))
+        .generic_attributes(HashSet::new())
+        .tag_attributes(HashMap::from([("a", HashSet::from(["href"]))]))
+        .allowed_classes(HashMap::from([("a", HashSet::from(["mention"]))]))
+        .url_schemes(HashSet::from(["http", "https", "mailto"]))
+        .url_relative(ammonia::UrlRelative::Custom(Box::new(allow_mention_links)))
+        .link_rel(Some("nofollow ugc noopener noreferrer"))
+        .clean(html)
+        .to_string()
+}
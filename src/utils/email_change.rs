@@ -0,0 +1,8 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+/// Generate the token of a confirmation or revert link: 32 random bytes, hex encoded
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
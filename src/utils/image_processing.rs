@@ -0,0 +1,281 @@
+use std::io::Cursor;
+
+use image::{
+    AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits,
+    codecs::{
+        avif::AvifEncoder,
+        gif::{GifDecoder, GifEncoder, Repeat},
+        jpeg::JpegEncoder,
+        png::PngEncoder,
+    },
+    error::{LimitError, LimitErrorKind},
+    imageops::FilterType,
+};
+
+/// Widths (in pixels) of the responsive variants generated for every upload
+///
+/// Only widths smaller than the original are produced - we never upscale.
+/// These line up with the breakpoints the frontend uses in its `sizes` attribute.
+const VARIANT_WIDTHS: [u32; 3] = [480, 960, 1600];
+
+/// Largest dimension we are willing to decode
+///
+/// Protects against decompression bombs: a tiny PNG can declare a
+/// 100000x100000 canvas and make the decoder allocate tens of gigabytes.
+const MAX_DIMENSION: u32 = 10_000;
+
+/// Upper bound on memory the decoder may allocate (256 MB)
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+const JPEG_QUALITY: u8 = 82;
+const WEBP_QUALITY: f32 = 80.0;
+const AVIF_QUALITY: u8 = 70;
+const AVIF_SPEED: u8 = 8; // 1 (slowest, smallest) ..= 10 (fastest)
+const GIF_SPEED: i32 = 10; // Color quantization, 1 (slowest, best colors) ..= 30 (fastest)
+
+/// Most frames of an animated GIF we re-encode
+const MAX_GIF_FRAMES: usize = 500;
+
+/// A single encoded file produced from an upload
+///
+/// `suffix` is appended to the upload's base name when writing to disk,
+/// e.g. "" for the full-size file, "-480w" for the 480px variant.
+pub struct EncodedVariant {
+    pub suffix: String,
+    pub ext: &'static str,
+    pub mime_type: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// Result of running an upload through the processing pipeline
+pub struct ProcessedImage {
+    pub width: u32,
+    pub height: u32,
+    pub blurhash: String,
+    /// Full-size file in the original format (metadata stripped)
+    pub original: EncodedVariant,
+    /// Resized and alternative-format variants (WebP/AVIF)
+    pub variants: Vec<EncodedVariant>,
+}
+
+/// Decode an uploaded image, strip its metadata and generate responsive variants
+///
+/// Pipeline:
+/// 1. Decode with size limits (decompression bomb protection)
+/// 2. Apply the EXIF orientation so rotated phone photos display correctly
+/// 3. Re-encode the full-size image - re-encoding drops EXIF/XMP/ICC chunks,
+///    which removes GPS coordinates, camera serial numbers, etc.
+/// 4. Generate downscaled variants in the original format, WebP and AVIF
+/// 5. Compute a blurhash placeholder for progressive loading
+///
+/// GIFs are re-encoded frame by frame, keeping the animation: only the frames
+/// and their delays are carried over, so comment, application and plain text
+/// extension blocks (where metadata or other payloads hide) are dropped. They
+/// loop forever and get no resized variants.
+///
+/// This is CPU heavy (AVIF encoding especially) - call it from
+/// `tokio::task::spawn_blocking`, never directly on the async runtime.
+///
+/// # Parameters
+/// - `bytes`: Raw upload bytes (already checked by `verify_image_signature`)
+/// - `ext`: Lowercase file extension of the upload ("jpg", "png", "gif", "webp")
+pub fn process_image(bytes: &[u8], ext: &str) -> Result<ProcessedImage, image::ImageError> {
+    let format = match ext {
+        "jpg" | "jpeg" => ImageFormat::Jpeg,
+        "png" => ImageFormat::Png,
+        "gif" => ImageFormat::Gif,
+        _ => ImageFormat::WebP,
+    };
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits_for_upload());
+
+    // Read orientation before decoding - it lives in the EXIF block we are about to discard
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+
+    let (width, height) = (img.width(), img.height());
+    let blurhash = compute_blurhash(&img)?;
+
+    if format == ImageFormat::Gif {
+        let bytes = reencode_gif(bytes, limits_for_upload())?;
+        return Ok(ProcessedImage {
+            width,
+            height,
+            blurhash,
+            original: EncodedVariant {
+                suffix: String::new(),
+                ext: "gif",
+                mime_type: "image/gif",
+                width,
+                height,
+                bytes,
+            },
+            variants: Vec::new(),
+        });
+    }
+
+    let original = encode_variant(&img, format, String::new())?;
+
+    let mut variants = Vec::new();
+    for target_width in VARIANT_WIDTHS.into_iter().filter(|w| *w < width) {
+        // resize() keeps the aspect ratio; height bound is just "large enough"
+        let resized = img.resize(target_width, u32::MAX, FilterType::Lanczos3);
+        let suffix = format!("-{}w", target_width);
+
+        // For WebP uploads the variant in the original format is the WebP one
+        variants.push(encode_variant(&resized, format, suffix.clone())?);
+        if format != ImageFormat::WebP {
+            variants.push(encode_webp(&resized, suffix.clone())?);
+        }
+        variants.push(encode_avif(&resized, suffix)?);
+    }
+
+    // Full-size modern formats, so the largest srcset entry has WebP/AVIF too
+    if format != ImageFormat::WebP {
+        variants.push(encode_webp(&img, String::new())?);
+    }
+    variants.push(encode_avif(&img, String::new())?);
+
+    Ok(ProcessedImage {
+        width,
+        height,
+        blurhash,
+        original,
+        variants,
+    })
+}
+
+/// Decoder limits for untrusted uploads
+fn limits_for_upload() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    limits
+}
+
+/// Rebuild a GIF from its decoded frames
+///
+/// Frames are decoded and encoded one at a time, so memory stays bounded
+/// by one frame however long the animation is.
+fn reencode_gif(bytes: &[u8], limits: Limits) -> Result<Vec<u8>, image::ImageError> {
+    let mut decoder = GifDecoder::new(Cursor::new(bytes))?;
+    decoder.set_limits(limits)?;
+
+    let mut buf = Vec::new();
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut buf, GIF_SPEED);
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        for (i, frame) in decoder.into_frames().enumerate() {
+            if i == MAX_GIF_FRAMES {
+                return Err(image::ImageError::Limits(LimitError::from_kind(
+                    LimitErrorKind::InsufficientMemory,
+                )));
+            }
+            encoder.encode_frame(frame?)?;
+        }
+    }
+
+    Ok(buf)
+}
+
+/// Re-encode an image in its original format (JPEG, PNG or WebP)
+fn encode_variant(
+    img: &DynamicImage,
+    format: ImageFormat,
+    suffix: String,
+) -> Result<EncodedVariant, image::ImageError> {
+    match format {
+        ImageFormat::Jpeg => {
+            let mut buf = Vec::new();
+            // JPEG has no alpha channel
+            DynamicImage::ImageRgb8(img.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut buf, JPEG_QUALITY))?;
+            Ok(EncodedVariant {
+                suffix,
+                ext: "jpg",
+                mime_type: "image/jpeg",
+                width: img.width(),
+                height: img.height(),
+                bytes: buf,
+            })
+        }
+        ImageFormat::Png => {
+            let mut buf = Vec::new();
+            img.write_with_encoder(PngEncoder::new(&mut buf))?;
+            Ok(EncodedVariant {
+                suffix,
+                ext: "png",
+                mime_type: "image/png",
+                width: img.width(),
+                height: img.height(),
+                bytes: buf,
+            })
+        }
+        _ => encode_webp(img, suffix),
+    }
+}
+
+/// Encode a lossy WebP variant
+///
+/// The `image` crate only ships a lossless WebP encoder, which produces files
+/// larger than the JPEG we started from, so libwebp (via the `webp` crate) is used instead.
+fn encode_webp(img: &DynamicImage, suffix: String) -> Result<EncodedVariant, image::ImageError> {
+    let rgba = DynamicImage::ImageRgba8(img.to_rgba8());
+    let encoder = webp::Encoder::from_image(&rgba).map_err(|e| {
+        image::ImageError::Unsupported(image::error::UnsupportedError::from_format_and_kind(
+            ImageFormat::WebP.into(),
+            image::error::UnsupportedErrorKind::GenericFeature(e.to_string()),
+        ))
+    })?;
+
+    Ok(EncodedVariant {
+        suffix,
+        ext: "webp",
+        mime_type: "image/webp",
+        width: img.width(),
+        height: img.height(),
+        bytes: encoder.encode(WEBP_QUALITY).to_vec(),
+    })
+}
+
+/// Encode an AVIF variant
+fn encode_avif(img: &DynamicImage, suffix: String) -> Result<EncodedVariant, image::ImageError> {
+    let mut buf = Vec::new();
+    DynamicImage::ImageRgba8(img.to_rgba8()).write_with_encoder(
+        AvifEncoder::new_with_speed_quality(&mut buf, AVIF_SPEED, AVIF_QUALITY),
+    )?;
+
+    Ok(EncodedVariant {
+        suffix,
+        ext: "avif",
+        mime_type: "image/avif",
+        width: img.width(),
+        height: img.height(),
+        bytes: buf,
+    })
+}
+
+/// Compute a blurhash placeholder string
+///
+/// Blurhash encodes a blurred version of the image in ~30 characters.
+/// The frontend decodes it into a tiny canvas shown while the real image loads.
+///
+/// The image is shrunk to 32px first - blurhash only keeps a handful of
+/// low-frequency components, so encoding the full image is wasted work.
+fn compute_blurhash(img: &DynamicImage) -> Result<String, image::ImageError> {
+    let small = img.thumbnail(32, 32).to_rgba8();
+
+    blurhash::encode(4, 3, small.width(), small.height(), small.as_raw()).map_err(|e| {
+        image::ImageError::Unsupported(image::error::UnsupportedError::from_format_and_kind(
+            image::error::ImageFormatHint::Unknown,
+            image::error::UnsupportedErrorKind::GenericFeature(e.to_string()),
+        ))
+    })
+}
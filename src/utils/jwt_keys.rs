@@ -0,0 +1,372 @@
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::utils::token::TokenType;
+
+/// Keys access and refresh tokens are signed and verified with
+///
+/// Without `JWT_SIGNING_KEY`, tokens are signed with HS256 and `JWT_SECRET_KEY`
+/// (no `kid`, nothing to publish). With it, tokens are signed with the private
+/// key in that PEM file: RS256 for RSA keys, EdDSA for Ed25519 keys. The `kid`
+/// in the token header tells which key signed it, and the public keys are
+/// published at `/.well-known/jwks.json` so other services can verify tokens.
+///
+/// **Rotating keys:**
+/// 1. Generate a new key (`openssl genpkey -algorithm ed25519 -out 2026-10.pem`)
+/// 2. Make it `JWT_SIGNING_KEY`, and move the old one to `JWT_RETIRED_KEYS`
+///    with the time it was retired
+/// 3. Tokens signed by the old key keep working until the rotation window
+///    (`REFRESH_TOKEN_MAXAGE`, the longest a token lives) has passed since then
+///
+/// Switching from HS256 works the same way: `JWT_SECRET_KEY` stays a retired
+/// verifier (no `kid`) from `JWT_SECRET_RETIRED_AT`, which must be set with
+/// `JWT_SIGNING_KEY`, so nobody is signed out by the switch.
+///
+/// A retired key only verifies tokens issued before it was retired that expire
+/// within a token lifetime (`JWT_MAXAGE` or `REFRESH_TOKEN_MAXAGE`) of it. A
+/// leaked old key can still mint such tokens, but only until the rotation
+/// window has passed, after which the key verifies nothing.
+pub struct JwtKeys {
+    signing: SigningKey,
+    verifying: Vec<VerifyingKey>,
+    rotation_window: Duration, // Longest a token lives (`REFRESH_TOKEN_MAXAGE`)
+    access_lifetime: Duration, // `JWT_MAXAGE`
+    pub issuer: String,        // `iss` of our tokens (`JWT_ISSUER`)
+    pub audience: String,      // `aud` of our tokens (`JWT_AUDIENCE`)
+}
+
+struct SigningKey {
+    kid: Option<String>, // None for the HS256 secret
+    algorithm: Algorithm,
+    key: EncodingKey,
+}
+
+/// Key a token can be verified with
+pub struct VerifyingKey {
+    kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub key: DecodingKey,
+    pub retired_at: Option<DateTime<Utc>>, // None for the signing key
+    jwk: Option<Jwk>,                      // Public key as published in the JWKS (asymmetric keys)
+}
+
+/// Public key in JSON Web Key format (RFC 7517)
+#[derive(Debug, Clone, Serialize)]
+pub struct Jwk {
+    pub kty: &'static str, // "RSA" or "OKP" (Ed25519)
+    #[serde(rename = "use")]
+    pub use_: &'static str, // Always "sig"
+    pub alg: &'static str,
+    pub kid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>, // RSA modulus
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>, // RSA public exponent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<&'static str>, // "Ed25519"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>, // Ed25519 public key
+}
+
+/// JSON Web Key Set served at `/.well-known/jwks.json`
+#[derive(Debug, Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+impl JwtKeys {
+    /// Load the keys configured in `JWT_SIGNING_KEY` and `JWT_RETIRED_KEYS`
+    /// (plus the HS256 secret, see `JWT_SECRET_RETIRED_AT`), and the issuer
+    /// and audience of our tokens
+    ///
+    /// # Panics
+    /// If a key file can't be read or isn't an RSA or Ed25519 private key,
+    /// like the rest of `Config::init`
+    pub fn init(config: &Config) -> Self {
+        let rotation_window = Duration::seconds(config.refresh_token_maxage);
+        let access_lifetime = Duration::seconds(config.jwt_maxage);
+
+        let (signing, mut verifying) = match &config.jwt_signing_key {
+            Some(spec) => {
+                let (kid, path) = spec
+                    .split_once('=')
+                    .expect("JWT_SIGNING_KEY must look like kid=/path/to/key.pem");
+                let (key, verifying) = load_key(kid.trim(), path.trim(), None);
+
+                // Not defaulted to startup: the window would restart with every deploy
+                let secret_retired_at = config
+                    .jwt_secret_retired_at
+                    .as_deref()
+                    .expect("JWT_SECRET_RETIRED_AT must be set with JWT_SIGNING_KEY");
+                let secret_retired_at = DateTime::parse_from_rfc3339(secret_retired_at.trim())
+                    .unwrap_or_else(|e| {
+                        panic!("JWT_SECRET_RETIRED_AT must be an RFC 3339 time: {}", e)
+                    })
+                    .with_timezone(&Utc);
+                let secret = VerifyingKey {
+                    kid: None,
+                    algorithm: Algorithm::HS256,
+                    key: DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+                    retired_at: Some(secret_retired_at),
+                    jwk: None,
+                };
+
+                (key, vec![verifying, secret])
+            }
+            None => (
+                SigningKey {
+                    kid: None,
+                    algorithm: Algorithm::HS256,
+                    key: EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+                },
+                vec![VerifyingKey {
+                    kid: None,
+                    algorithm: Algorithm::HS256,
+                    key: DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+                    retired_at: None,
+                    jwk: None,
+                }],
+            ),
+        };
+
+        for spec in &config.jwt_retired_keys {
+            let (kid, path, retired_at) = parse_retired_key(spec).unwrap_or_else(|| {
+                panic!(
+                    "JWT_RETIRED_KEYS entry '{}' must look like kid=/path/to/key.pem@2026-01-31T00:00:00Z",
+                    spec
+                )
+            });
+
+            assert!(
+                verifying.iter().all(|k| k.kid.as_deref() != Some(kid)),
+                "JWT key id '{}' is used more than once",
+                kid
+            );
+
+            let (_, key) = load_key(kid, path, Some(retired_at));
+            verifying.push(key);
+        }
+
+        JwtKeys {
+            signing,
+            verifying,
+            rotation_window,
+            access_lifetime,
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+        }
+    }
+
+    /// Header for new tokens, naming the algorithm and key that signs them
+    pub fn header(&self) -> Header {
+        Header {
+            kid: self.signing.kid.clone(),
+            ..Header::new(self.signing.algorithm)
+        }
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.signing.key
+    }
+
+    /// Key that verifies a token with this `kid`
+    ///
+    /// None if the key is unknown, or retired longer ago than the rotation window.
+    pub fn verifying_key(&self, kid: Option<&str>) -> Option<&VerifyingKey> {
+        self.verifying
+            .iter()
+            .find(|k| k.kid.as_deref() == kid)
+            .filter(|k| self.in_rotation_window(k))
+    }
+
+    /// Public keys of the signing key and the retired keys still in their
+    /// rotation window (empty with HS256)
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .verifying
+                .iter()
+                .filter(|k| self.in_rotation_window(k))
+                .filter_map(|k| k.jwk.clone())
+                .collect(),
+        }
+    }
+
+    /// Longest a token of this type lives
+    pub fn lifetime(&self, typ: TokenType) -> Duration {
+        match typ {
+            TokenType::Access => self.access_lifetime,
+            TokenType::Refresh => self.rotation_window,
+        }
+    }
+
+    fn in_rotation_window(&self, key: &VerifyingKey) -> bool {
+        key.retired_at
+            .is_none_or(|retired_at| retired_at + self.rotation_window > Utc::now())
+    }
+}
+
+/// Split a `JWT_RETIRED_KEYS` entry into key id, path and retirement time
+fn parse_retired_key(spec: &str) -> Option<(&str, &str, DateTime<Utc>)> {
+    let (kid, rest) = spec.split_once('=')?;
+    let (path, retired_at) = rest.rsplit_once('@')?;
+    let retired_at = DateTime::parse_from_rfc3339(retired_at.trim()).ok()?;
+    Some((kid.trim(), path.trim(), retired_at.with_timezone(&Utc)))
+}
+
+/// Read a private key from a PEM file
+///
+/// Ed25519 keys must be PKCS#8 (`BEGIN PRIVATE KEY`), RSA keys can also be
+/// PKCS#1 (`BEGIN RSA PRIVATE KEY`).
+fn load_key(
+    kid: &str,
+    path: &str,
+    retired_at: Option<DateTime<Utc>>,
+) -> (SigningKey, VerifyingKey) {
+    assert!(!kid.is_empty(), "JWT key id for {} must not be empty", path);
+
+    let pem = std::fs::read(path)
+        .unwrap_or_else(|e| panic!("Can't read JWT key '{}' from {}: {}", kid, path, e));
+    let (label, der) = pem_to_der(&pem)
+        .unwrap_or_else(|| panic!("JWT key '{}' ({}) is not a PEM file", kid, path));
+
+    let (algorithm, encoding, decoding, jwk) = if let Ok(pair) =
+        Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+    {
+        let x = BASE64URL_NOPAD.encode(pair.public_key().as_ref());
+        let jwk = Jwk {
+            kty: "OKP",
+            use_: "sig",
+            alg: "EdDSA",
+            kid: kid.to_string(),
+            n: None,
+            e: None,
+            crv: Some("Ed25519"),
+            x: Some(x.clone()),
+        };
+        (
+            Algorithm::EdDSA,
+            EncodingKey::from_ed_pem(&pem),
+            DecodingKey::from_ed_components(&x),
+            jwk,
+        )
+    } else {
+        let pair = match label.as_str() {
+            "RSA PRIVATE KEY" => RsaKeyPair::from_der(&der),
+            _ => RsaKeyPair::from_pkcs8(&der),
+        }
+        .unwrap_or_else(|e| {
+            panic!(
+                "JWT key '{}' ({}) must be an Ed25519 or RSA private key: {}",
+                kid, path, e
+            )
+        });
+        let (n, e) = rsa_public_components(pair.public_key().as_ref())
+            .unwrap_or_else(|| panic!("JWT key '{}' ({}) has an invalid public key", kid, path));
+        let (n, e) = (BASE64URL_NOPAD.encode(n), BASE64URL_NOPAD.encode(e));
+        let decoding = DecodingKey::from_rsa_components(&n, &e);
+        let jwk = Jwk {
+            kty: "RSA",
+            use_: "sig",
+            alg: "RS256",
+            kid: kid.to_string(),
+            n: Some(n),
+            e: Some(e),
+            crv: None,
+            x: None,
+        };
+        (
+            Algorithm::RS256,
+            EncodingKey::from_rsa_pem(&pem),
+            decoding,
+            jwk,
+        )
+    };
+
+    let encoding =
+        encoding.unwrap_or_else(|e| panic!("Invalid JWT key '{}' ({}): {}", kid, path, e));
+    let decoding =
+        decoding.unwrap_or_else(|e| panic!("Invalid JWT key '{}' ({}): {}", kid, path, e));
+
+    (
+        SigningKey {
+            kid: Some(kid.to_string()),
+            algorithm,
+            key: encoding,
+        },
+        VerifyingKey {
+            kid: Some(kid.to_string()),
+            algorithm,
+            key: decoding,
+            retired_at,
+            jwk: Some(jwk),
+        },
+    )
+}
+
+/// Label and DER contents of the first block of a PEM file
+fn pem_to_der(pem: &[u8]) -> Option<(String, Vec<u8>)> {
+    let pem = std::str::from_utf8(pem).ok()?;
+    let mut lines = pem
+        .lines()
+        .map(str::trim)
+        .skip_while(|l| !l.starts_with("-----BEGIN "));
+
+    let label = lines
+        .next()?
+        .strip_prefix("-----BEGIN ")?
+        .strip_suffix("-----")?
+        .to_string();
+    let body: String = lines.take_while(|l| !l.starts_with("-----END ")).collect();
+
+    let der = BASE64.decode(body.as_bytes()).ok()?;
+    Some((label, der))
+}
+
+/// Modulus and exponent of a DER encoded RSAPublicKey (RFC 8017, appendix A.1.1)
+///
+/// JWKs carry them as unsigned big-endian numbers, so the sign byte DER
+/// adds to integers with the high bit set is dropped.
+fn rsa_public_components(der: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (0x30, sequence, _) = der_element(der)? else {
+        return None;
+    };
+    let (0x02, n, rest) = der_element(sequence)? else {
+        return None;
+    };
+    let (0x02, e, _) = der_element(rest)? else {
+        return None;
+    };
+    Some((strip_leading_zeros(n), strip_leading_zeros(e)))
+}
+
+/// Tag, contents and the remaining input of the first DER element
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        // Long form: the low bits give the number of length bytes
+        let count = (first & 0x7f) as usize;
+        if count > 4 || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count]
+            .iter()
+            .fold(0usize, |len, &b| (len << 8) | b as usize);
+        (len, &rest[count..])
+    };
+
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+fn strip_leading_zeros(int: &[u8]) -> &[u8] {
+    let start = int.iter().position(|&b| b != 0).unwrap_or(int.len());
+    &int[start..]
+}
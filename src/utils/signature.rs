@@ -0,0 +1,137 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Sign the one-click unsubscribe link of a user
+///
+/// **Why sign the link?**
+/// Unsubscribe links must work without logging in (mail clients call them
+/// directly, see RFC 8058), so the user id in the link can't be trusted by itself.
+/// Anyone could otherwise turn off notifications for any user id.
+/// An HMAC over the user id proves the link was generated by this server.
+///
+/// The purpose ("unsubscribe:") is part of the signed message, so a signature
+/// can never be reused for another kind of signed link.
+///
+/// # Parameters
+/// - `user_id`: User the link belongs to
+/// - `secret`: Server secret (the JWT secret)
+///
+/// # Returns
+/// Hex-encoded HMAC-SHA256 signature
+pub fn sign_unsubscribe(user_id: Uuid, secret: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("unsubscribe:{}", user_id).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Check an unsubscribe signature
+///
+/// Uses a constant-time comparison (`verify_slice`) so the signature
+/// can't be guessed byte by byte from response timings.
+pub fn verify_unsubscribe(user_id: Uuid, signature: &str, secret: &[u8]) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("unsubscribe:{}", user_id).as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Sign a magic login link
+///
+/// The link carries the user id, a random nonce and the expiry, all covered
+/// by the signature, so neither can be changed. The nonce is also kept in
+/// Redis until the link is used, which makes the link single use.
+///
+/// # Parameters
+/// - `user_id`: User the link signs in
+/// - `nonce`: Random value identifying this link
+/// - `expires_at`: Unix time in seconds after which the link is refused
+/// - `secret`: Server secret (the JWT secret)
+///
+/// # Returns
+/// Hex-encoded HMAC-SHA256 signature
+pub fn sign_magic_link(user_id: Uuid, nonce: &str, expires_at: i64, secret: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("magic-link:{}:{}:{}", user_id, nonce, expires_at).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Check a magic link signature (constant-time, like `verify_unsubscribe`)
+///
+/// The expiry is checked by the caller.
+pub fn verify_magic_link(
+    user_id: Uuid,
+    nonce: &str,
+    expires_at: i64,
+    signature: &str,
+    secret: &[u8],
+) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("magic-link:{}:{}:{}", user_id, nonce, expires_at).as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// CSRF token of a login session
+///
+/// Browsers send the `access_token` cookie with every request to the API,
+/// including requests a malicious site triggers. Such a site can't read the
+/// CSRF token though, so requiring it in a header (`X-CSRF-Token`) proves the
+/// request was made by our frontend. The token is bound to the session: a
+/// token from another session (or a cookie planted by a sibling subdomain)
+/// doesn't match.
+///
+/// # Parameters
+/// - `session_id`: Session (`sid` claim) the token belongs to
+/// - `secret`: Server secret (the JWT secret)
+///
+/// # Returns
+/// Hex-encoded HMAC-SHA256 signature
+pub fn sign_csrf_token(session_id: &str, secret: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("csrf:{}", session_id).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Check the CSRF token of a session (constant-time, like `verify_unsubscribe`)
+pub fn verify_csrf_token(session_id: &str, token: &str, secret: &[u8]) -> bool {
+    let Ok(token) = hex::decode(token) else {
+        return false;
+    };
+
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("csrf:{}", session_id).as_bytes());
+    mac.verify_slice(&token).is_ok()
+}
+
+/// Sign the unlock link emailed when an account gets locked
+///
+/// The nonce is also stored with the lock, so the link only lifts the
+/// lock it was sent for, and stops working when that lock ends.
+///
+/// # Returns
+/// Hex-encoded HMAC-SHA256 signature
+pub fn sign_account_unlock(user_id: Uuid, nonce: &str, secret: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("account-unlock:{}:{}", user_id, nonce).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Check an unlock link signature (constant-time, like `verify_unsubscribe`)
+pub fn verify_account_unlock(user_id: Uuid, nonce: &str, signature: &str, secret: &[u8]) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("account-unlock:{}:{}", user_id, nonce).as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
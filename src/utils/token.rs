@@ -6,12 +6,23 @@
 // - We still query the database to verify the user exists
 // - "Stateless" refers to the authentication mechanism, not the entire application
 
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 use axum::http::StatusCode;
 use chrono::{Duration, Utc};
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use jsonwebtoken::{Validation, decode, decode_header, encode};
 use serde::{Deserialize, Serialize};
 
 use crate::error::{ErrorMessage, HttpError};
+use crate::models::User;
+use crate::utils::jwt_keys::JwtKeys;
+
+/// What a token is for, so one kind can't be used in place of the other
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,  // Sent with API requests, checked by the `auth` middleware
+    Refresh, // Only accepted by the refresh endpoint
+}
 
 /// JWT token claims (payload)
 ///
@@ -29,54 +40,71 @@ use crate::error::{ErrorMessage, HttpError};
 /// ```
 ///
 /// **Three Parts:**
-/// 1. **Header**: Algorithm and token type
+/// 1. **Header**: Algorithm, token type and the key that signed it (see `JwtKeys`)
 ///    ```
-///    {"alg": "HS256", "typ": "JWT"}
+///    {"alg": "EdDSA", "typ": "JWT", "kid": "2026-10"}
 ///    ```
 ///
 /// 2. **Payload** (this struct): The claims (actual data)
 ///    ```
-///    {"sub": "user_id", "iat": 1234567890, "exp": 1234571490}
+///    {"sub": "user_id", "iat": 1234567890, "exp": 1234571490, "typ": "access", ...}
 ///    ```
 ///
 /// 3. **Signature**: Cryptographic signature to verify integrity
 ///    ```
-///    HMACSHA256(base64(header) + "." + base64(payload), secret)
+///    Ed25519(base64(header) + "." + base64(payload), private_key)
 ///    ```
 ///
 /// **Standard JWT Claims:**
 /// - `sub` (subject): Identifies the principal (usually user ID)
 /// - `iat` (issued at): Timestamp when token was created
 /// - `exp` (expiration): Timestamp when token expires
+/// - `iss` (issuer): Who issued the token (`JWT_ISSUER`)
+/// - `aud` (audience): Who the token is intended for (`JWT_AUDIENCE`)
+/// - `jti` (JWT ID): Unique identifier for the token, used to revoke a single
+///   access token (see `RedisClient::revoke_access_token`)
+///
+/// Our own claims:
+/// - `typ`: Access or refresh token, checked by whoever accepts it
+/// - `sid`: The login session the token belongs to (see `RedisClient::save_session`)
+/// - `ver`: Token version of the user when the token was issued, tokens with an
+///   older version were invalidated (see `UserExt::bump_token_version`)
+/// - `role`: Role of the user when the token was issued (access tokens), for
+///   other services verifying our tokens. This API checks the current role in the database.
 ///
-/// Other standard claims not used here:
-/// - `iss` (issuer): Who issued the token
-/// - `aud` (audience): Who the token is intended for
+/// Standard claim not used here:
 /// - `nbf` (not before): Token is not valid before this time
-/// - `jti` (JWT ID): Unique identifier for the token
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenClaims {
     pub sub: String, // Subject: User ID (UUID as string)
     pub iat: usize,  // Issued At: Unix timestamp when token was created
     pub exp: usize,  // Expiration: Unix timestamp when token expires
+    pub iss: String, // Issuer
+    pub aud: String, // Audience
+    pub jti: String, // Token ID: random, unique per token
+    pub typ: TokenType,
+    pub sid: String, // Session ID
+    pub ver: i32,    // User's token version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>, // "admin" or "user" (access tokens only)
 }
 
-/// Create a signed JWT token
+/// Create a signed access token
 ///
 /// **How JWT Signing Works:**
 /// 1. Create the payload (claims) with user data and timestamps
 /// 2. Encode header and payload as base64
-/// 3. Create signature: HMAC-SHA256(header.payload, secret)
+/// 3. Create signature with the current signing key (HS256, RS256 or EdDSA)
 /// 4. Combine all three parts: header.payload.signature
 ///
 /// **Why Sign Tokens?**
 /// Signing ensures:
 /// - **Integrity**: Token hasn't been tampered with
-/// - **Authenticity**: Token was issued by our server (only we have the secret)
+/// - **Authenticity**: Token was issued by our server (only we have the secret/private key)
 /// - **Non-repudiation**: Can prove the token came from us
 ///
 /// **Security Notes:**
-/// - The secret MUST be kept secure (environment variable, never in code)
+/// - The secret or private key MUST be kept secure (environment/key file, never in code)
 /// - Use a strong secret (at least 32 random bytes)
 /// - Tokens are NOT encrypted - anyone can read the payload (base64 decode)
 /// - Never put sensitive data in tokens (passwords, credit cards, etc.)
@@ -88,11 +116,10 @@ pub struct TokenClaims {
 /// - **Refresh Token**: Long-lived (7-30 days), used to get new access tokens
 ///
 /// # Parameters
-/// - `data`: User ID or other identifier to embed in the token (usually UUID)
-/// - `secret`: Secret key for signing (from environment variable)
-/// - `expires_in_seconds`: How long until the token expires
-///   - Access tokens: 900-3600 seconds (15-60 minutes)
-///   - Refresh tokens: 604800-2592000 seconds (7-30 days)
+/// - `user`: User the token authenticates (`sub`, `role` and `ver`)
+/// - `session_id`: Login session the token was issued for
+/// - `keys`: Signing keys (`AppState::jwt_keys`)
+/// - `expires_in_seconds`: How long until the token expires (900-3600 seconds, 15-60 minutes)
 ///
 /// # Returns
 /// - `Ok(String)`: The complete JWT token (header.payload.signature)
@@ -101,22 +128,49 @@ pub struct TokenClaims {
 /// # Example
 /// ```
 /// // Create 15-minute access token
-/// let access_token = create_token(&user.id.to_string(), secret.as_bytes(), 900)?;
-///
-/// // Create 7-day refresh token
-/// let refresh_token = create_token(&user.id.to_string(), secret.as_bytes(), 604800)?;
+/// let access_token = create_access_token(&user, &session_id, &app_state.jwt_keys, 900)?;
 /// ```
-pub fn create_token(
-    data: &str,
-    secret: &[u8],
+pub fn create_access_token(
+    user: &User,
+    session_id: &str,
+    keys: &JwtKeys,
     expires_in_seconds: i64,
 ) -> Result<String, jsonwebtoken::errors::Error> {
-    // Validation: Reject empty subjects
-    // The subject (user ID) is critical - without it, we can't identify who the token belongs to
-    if data.is_empty() {
-        return Err(jsonwebtoken::errors::ErrorKind::InvalidSubject.into());
-    }
+    sign(
+        user,
+        TokenType::Access,
+        session_id,
+        keys,
+        expires_in_seconds,
+    )
+}
 
+/// Create a refresh token bound to a login session
+///
+/// The session ID lets the refresh endpoint find the session in Redis,
+/// so every device keeps its own refresh token and can be signed out on its own.
+pub fn create_refresh_token(
+    user: &User,
+    session_id: &str,
+    keys: &JwtKeys,
+    expires_in_seconds: i64,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    sign(
+        user,
+        TokenType::Refresh,
+        session_id,
+        keys,
+        expires_in_seconds,
+    )
+}
+
+fn sign(
+    user: &User,
+    typ: TokenType,
+    session_id: &str,
+    keys: &JwtKeys,
+    expires_in_seconds: i64,
+) -> Result<String, jsonwebtoken::errors::Error> {
     // Get current timestamp
     let now = Utc::now();
 
@@ -128,55 +182,71 @@ pub fn create_token(
     // Add the specified duration to current time
     let exp = (now + Duration::seconds(expires_in_seconds)).timestamp() as usize;
 
+    // Random token ID, so every token can be told apart (and revoked) even if
+    // two are issued for the same session in the same second
+    let mut jti = [0u8; 16];
+    OsRng.fill_bytes(&mut jti);
+
     // Build the token claims (payload)
     let claims = TokenClaims {
-        sub: data.to_string(),
+        sub: user.id.to_string(),
         iat,
         exp,
+        iss: keys.issuer.clone(),
+        aud: keys.audience.clone(),
+        jti: hex::encode(jti),
+        typ,
+        sid: session_id.to_string(),
+        ver: user.token_version,
+        // Refresh tokens only lead to a new access token, which gets the role of then
+        role: (typ == TokenType::Access).then(|| user.role.to_str().to_string()),
     };
 
     // Encode and sign the JWT
     //
-    // Header::default() creates:
-    // {"alg": "HS256", "typ": "JWT"}
+    // The header names the algorithm and the key id, e.g.:
+    // {"alg": "EdDSA", "typ": "JWT", "kid": "2026-10"}
     //
-    // Algorithm HS256 (HMAC-SHA256):
-    // - Symmetric algorithm (same key for signing and verification)
-    // - Fast and secure for server-to-server or server-to-client tokens
-    // - Not suitable if clients need to verify tokens (use RS256 for that)
+    // Algorithms:
+    // - HS256 (HMAC-SHA256, default): symmetric, the same secret signs and verifies,
+    //   so nobody else can verify tokens without being able to create them
+    // - RS256/EdDSA: asymmetric, the private key signs and the public keys
+    //   published at /.well-known/jwks.json verify
     //
     // Process:
     // 1. Serialize header and claims to JSON
     // 2. Base64-encode both
-    // 3. Create signature: HMAC-SHA256(base64(header).base64(claims), secret)
+    // 3. Sign base64(header).base64(claims) with the signing key
     // 4. Base64-encode signature
     // 5. Concatenate: base64(header).base64(claims).base64(signature)
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret),
-    )
+    encode(&keys.header(), &claims, keys.encoding_key())
 }
 
-/// Decode and verify a JWT token
+/// Decode and verify an access token
 ///
 /// **How JWT Verification Works:**
 /// 1. Split token into header, payload, signature
 /// 2. Decode header and payload from base64
-/// 3. Recompute signature using the secret
+/// 3. Find the key named by `kid` in the header and check the signature with it
 /// 4. Compare computed signature with token signature (constant-time)
 /// 5. Check expiration time (exp) against current time
 /// 6. If all checks pass, return the claims
 ///
 /// **What Gets Validated:**
 /// - Signature is valid (token hasn't been tampered with)
-/// - Token was signed with our secret (authentication)
+/// - Token was signed with one of our keys (authentication)
+/// - A retired key only signed it before it was retired
 /// - Token hasn't expired (exp < now)
-/// - Token format is correct (proper JWT structure)
+/// - Token was issued by us (iss) for us (aud)
+/// - Token is an access token (typ), a refresh token isn't accepted as a bearer
+///
+/// The caller also checks `ver` against the user's current token version,
+/// and `jti` against the denylist.
 ///
 /// **Why Validation Fails:**
 /// - Token was modified (signature won't match)
 /// - Token was signed with wrong secret (signature won't match)
+/// - Token was signed with a key retired longer ago than the rotation window
 /// - Token has expired (exp is in the past)
 /// - Token is malformed (invalid base64, missing parts)
 ///
@@ -188,48 +258,104 @@ pub fn create_token(
 ///
 /// # Parameters
 /// - `token`: The JWT token string to verify
-/// - `secret`: Secret key used to sign the token (must match the one used in create_token)
+/// - `keys`: Verification keys (`AppState::jwt_keys`)
 ///
 /// # Returns
-/// - `Ok(String)`: The subject (user ID) extracted from the token
-/// - `Err(HttpError)`: If token is invalid, expired, or signature doesn't match
+/// - `Ok(TokenClaims)`: The claims, `sub` is the user ID
+/// - `Err(HttpError)`: If token is invalid, expired, not an access token, or signature doesn't match
 ///
 /// # Example
 /// ```
 /// // In authentication middleware:
 /// let token = extract_token_from_header(req)?;
-/// let user_id_str = decode_token(token, secret.as_bytes())?;
-/// let user_id = Uuid::parse_str(&user_id_str)?;
+/// let claims = decode_access_token(token, &app_state.jwt_keys)?;
+/// let user_id = Uuid::parse_str(&claims.sub)?;
 ///
 /// // Now fetch user from database
 /// let user = db.get_user(user_id).await?;
 /// ```
-pub fn decode_token<T: Into<String>>(token: T, secret: &[u8]) -> Result<String, HttpError> {
+pub fn decode_access_token<T: Into<String>>(
+    token: T,
+    keys: &JwtKeys,
+) -> Result<TokenClaims, HttpError> {
+    decode_claims(token, keys, TokenType::Access)
+}
+
+/// Decode and verify a refresh token
+///
+/// The caller checks `ver` against the user's current token version.
+///
+/// # Returns
+/// - `Ok(TokenClaims)`: The claims, `sub` is the user ID and `sid` the session ID
+/// - `Err(HttpError)`: 401 if the token is invalid, expired or not a refresh token
+///   (including tokens issued before token types were introduced)
+pub fn decode_refresh_token<T: Into<String>>(
+    token: T,
+    keys: &JwtKeys,
+) -> Result<TokenClaims, HttpError> {
+    decode_claims(token, keys, TokenType::Refresh)
+}
+
+fn decode_claims<T: Into<String>>(
+    token: T,
+    keys: &JwtKeys,
+    expected_type: TokenType,
+) -> Result<TokenClaims, HttpError> {
+    let invalid_token = || {
+        HttpError::new(
+            ErrorMessage::InvalidToken.to_string(),
+            StatusCode::UNAUTHORIZED,
+        )
+    };
+    let token = token.into();
+
+    // Pick the key by the (unverified) kid in the header
+    // An unknown kid, or one retired too long ago, is rejected before any crypto
+    let header = decode_header(&token).map_err(|_| invalid_token())?;
+    let key = keys
+        .verifying_key(header.kid.as_deref())
+        .ok_or_else(invalid_token)?;
+
     // Decode and verify the token
     //
-    // Validation::new(Algorithm::HS256) creates a validator that:
-    // - Verifies the signature using HMAC-SHA256
+    // Validation::new(key.algorithm) creates a validator that:
+    // - Verifies the signature with the algorithm of the key
     // - Checks that exp (expiration) hasn't passed
     // - Validates the token structure
     //
-    // Why use the same algorithm (HS256)?
-    // - Must match the algorithm used to create the token
-    // - Prevents algorithm substitution attacks (attacker changes "HS256" to "none")
+    // Why take the algorithm from the key and not the header?
+    // - Prevents algorithm substitution attacks (attacker changes "EdDSA" to "HS256"
+    //   and signs with the public key, or to "none")
     // - The validation ensures the algorithm in the header matches what we expect
-    let decode = decode::<TokenClaims>(
-        &token.into(),
-        &DecodingKey::from_secret(secret),
-        &Validation::new(Algorithm::HS256), // Also validates expiration automatically
-    );
-
-    // Handle the result
-    // - If successful: Extract and return the subject (user ID)
-    // - If failed: Return 401 Unauthorized error
-    match decode {
-        Ok(token) => Ok(token.claims.sub),
-        Err(_) => Err(HttpError::new(
-            ErrorMessage::InvalidToken.to_string(),
-            StatusCode::UNAUTHORIZED,
-        )),
+    //
+    // The issuer and audience must be ours too, so tokens another service
+    // signed with a shared key aren't accepted here
+    let mut validation = Validation::new(key.algorithm); // Also validates expiration automatically
+    validation.set_issuer(&[&keys.issuer]);
+    validation.set_audience(&[&keys.audience]);
+    validation.set_required_spec_claims(&["exp", "sub", "iss", "aud"]);
+
+    let claims = decode::<TokenClaims>(&token, &key.key, &validation)
+        .map_err(|_| invalid_token())?
+        .claims;
+
+    // An access token can't be used to refresh, and a refresh token can't be
+    // sent as a bearer (it lives much longer and is meant to stay in its cookie)
+    if claims.typ != expected_type {
+        return Err(invalid_token());
+    }
+
+    // A retired key only vouches for tokens it could have signed while it was
+    // the signing key: issued before it was retired, and expiring within a
+    // token lifetime of that. iat and exp are signed by whoever holds the key,
+    // so checking iat alone would let a leaked key backdate a long-lived token
+    if let Some(retired_at) = key.retired_at {
+        let latest_exp = retired_at + keys.lifetime(claims.typ);
+        if claims.iat as i64 > retired_at.timestamp() || claims.exp as i64 > latest_exp.timestamp()
+        {
+            return Err(invalid_token());
+        }
     }
+
+    Ok(claims)
 }
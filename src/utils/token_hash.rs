@@ -0,0 +1,11 @@
+use sha2::{Digest, Sha256};
+
+/// Hash a random secret for storage and lookup (hex encoded SHA-256)
+///
+/// For server generated secrets only: personal access tokens, email change
+/// links and MFA recovery codes. They are random with enough entropy that
+/// guessing is hopeless, so no salt or slow hash is needed (unlike passwords,
+/// see `password::hash`), and the hash can be looked up directly.
+pub fn hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
@@ -0,0 +1,149 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use sha1::Sha1;
+
+use crate::utils::token_hash;
+
+type HmacSha1 = Hmac<Sha1>;
+
+/// Seconds a code is valid for (RFC 6238 default, the only value most authenticator apps support)
+const STEP_SECS: i64 = 30;
+
+/// Digits of a code
+const DIGITS: u32 = 6;
+
+/// Steps before/after the current one that are accepted too, for clock drift
+const SKEW_STEPS: i64 = 1;
+
+/// Secret size, 160 bits as recommended by RFC 4226
+const SECRET_BYTES: usize = 20;
+
+/// Recovery codes handed out per enrolment
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Generate a new TOTP secret
+///
+/// # Returns
+/// Base32 encoded secret (no padding), the format authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// Build the `otpauth://` URI that authenticator apps read from a QR code
+///
+/// Example: `otpauth://totp/Blog:theo?secret=...&issuer=Blog&algorithm=SHA1&digits=6&period=30`
+///
+/// # Parameters
+/// - `secret`: Base32 secret from `generate_secret`
+/// - `issuer`: Name of the service shown in the app
+/// - `account`: Account name shown in the app (the username)
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> String {
+    let mut url = Url::parse("otpauth://totp/").expect("static URL is valid");
+    url.path_segments_mut()
+        .expect("otpauth URL has a path")
+        .pop_if_empty()
+        .push(&format!("{}:{}", issuer, account));
+    url.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &STEP_SECS.to_string());
+    url.to_string()
+}
+
+/// HOTP value (RFC 4226) of a time step
+fn code_at(key: &[u8], step: i64) -> String {
+    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&(step as u64).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation: the last nibble picks 4 bytes of the hash
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// Check a code from an authenticator app
+///
+/// Codes of steps at or before `last_used_step` are refused, so a code
+/// can't be used twice (e.g. by someone looking over the user's shoulder).
+///
+/// # Parameters
+/// - `secret`: Base32 secret of the user
+/// - `code`: Code as typed, spaces are ignored
+/// - `now`: Current unix time in seconds
+/// - `last_used_step`: Step of the last accepted code (0 if none)
+///
+/// # Returns
+/// Step the code belongs to (store it as the new `last_used_step`),
+/// None if the code is wrong or was already used
+pub fn verify_code(secret: &str, code: &str, now: i64, last_used_step: i64) -> Option<i64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let current = now / STEP_SECS;
+
+    // Every step of the window is compared, in constant time, so the timing
+    // doesn't tell how close a guess was
+    let mut matched = None;
+    for step in (current - SKEW_STEPS..=current + SKEW_STEPS).filter(|step| *step > last_used_step)
+    {
+        if constant_time_eq(code_at(&key, step).as_bytes(), code.as_bytes()) && matched.is_none() {
+            matched = Some(step);
+        }
+    }
+    matched
+}
+
+/// Compare two byte strings without stopping at the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Generate a set of single-use recovery codes
+///
+/// Format `xxxxx-xxxxx` (lowercase base32, 50 bits each), short enough to
+/// type from paper. Only their hashes are stored, see `hash_recovery_code`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            OsRng.fill_bytes(&mut bytes);
+            let encoded = BASE32_NOPAD.encode(&bytes).to_lowercase();
+            format!("{}-{}", &encoded[..5], &encoded[5..10])
+        })
+        .collect()
+}
+
+/// Hash a recovery code for storage and lookup, see `token_hash::hash`
+///
+/// Case, spaces and dashes are ignored, so "ABCDE FGHIJ" matches "abcde-fghij".
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    token_hash::hash(&normalized)
+}
@@ -0,0 +1,45 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Directory uploaded files are written to (served by the reverse proxy)
+pub const UPLOAD_DIR: &str = "./uploads";
+
+/// Public base URL the upload directory is served from
+pub const PUBLIC_UPLOAD_URL: &str = "https://theolee.net/static/uploads";
+
+/// Build the public URL of an uploaded file
+pub fn public_url(file_name: &str) -> String {
+    format!("{}/{}", PUBLIC_UPLOAD_URL, file_name)
+}
+
+/// Write a file into the upload directory, creating the directory if needed
+pub fn write_upload_file(file_name: &str, bytes: &[u8]) -> std::io::Result<()> {
+    let upload_dir = PathBuf::from(UPLOAD_DIR);
+    fs::create_dir_all(&upload_dir)?;
+
+    let mut file = fs::File::create(upload_dir.join(file_name))?;
+    file.write_all(bytes)
+}
+
+/// Remove files from the upload directory
+///
+/// Missing files are ignored (already cleaned up, or removed by hand),
+/// other errors are logged and skipped so one bad file doesn't stop the rest.
+///
+/// # Returns
+/// Number of files actually removed
+pub fn remove_upload_files(file_names: &[String]) -> usize {
+    let upload_dir = PathBuf::from(UPLOAD_DIR);
+    let mut removed = 0;
+
+    for file_name in file_names {
+        match fs::remove_file(upload_dir.join(file_name)) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to remove upload {}: {}", file_name, e),
+        }
+    }
+
+    removed
+}